@@ -1,30 +1,1012 @@
-use crate::stream::Command;
+use std::convert::Infallible;
+use std::ffi::{OsStr, OsString};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
-pub fn start_api_task(port: u16, command_tx: flume::Sender<Command>) {
-    let server = tiny_http::Server::http(("0.0.0.0", port)).expect("Failed to start server");
+use axum::extract::multipart::Field;
+use axum::extract::{DefaultBodyLimit, Multipart, Path, Query, Request, State};
+use axum::http::{HeaderMap, Method, StatusCode, header};
+use axum::middleware::Next;
+use axum::response::sse::{self, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post};
+use axum::{Json, Router};
+use futures_util::{Stream, StreamExt};
+use tokio::io::AsyncWriteExt;
 
-    std::thread::spawn(move || {
-        loop {
-            let request = match server.recv() {
-                Ok(request) => request,
+use crate::download::Downloader;
+use crate::events::EventLog;
+use crate::health::HealthCheck;
+use crate::history::{History, program_log_csv};
+use crate::random_files::LibraryRoots;
+use crate::stats::SessionStats;
+use crate::status::StatusTracker;
+use crate::stream::{Command, Event, MjpegFeed, Quarantine, SlateKind, parse_gain};
+use crate::thumbnail::PreviewFormat;
+use crate::viewer_tokens::{AuthRequest, ViewerTokens};
+
+/// A running HTTP control API, see [`start_api_task`].
+#[derive(Debug)]
+pub struct ApiHandle {
+    shutdown_tx: tokio::sync::watch::Sender<bool>,
+    thread: std::thread::JoinHandle<()>,
+}
+
+impl ApiHandle {
+    /// Stops accepting connections and waits for in-flight requests to finish.
+    /// Open event streams are closed.
+    pub fn shutdown(self) {
+        _ = self.shutdown_tx.send(true);
+        _ = self.thread.join();
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ApiState {
+    command_tx: flume::Sender<Command>,
+    status: StatusTracker,
+    stats: SessionStats,
+    event_log: EventLog,
+    health: HealthCheck,
+    history: Option<History>,
+    quarantine: Quarantine,
+    mjpeg: Option<MjpegFeed>,
+    upload_dir: Option<PathBuf>,
+    downloader: Option<Downloader>,
+    event_tx: flume::Sender<Event>,
+    roots: LibraryRoots,
+    viewer_tokens: Option<ViewerTokens>,
+    tokens: Arc<[String]>,
+    shutdown_rx: tokio::sync::watch::Receiver<bool>,
+}
+
+/// What the API reports on and controls.
+#[derive(Debug, Clone)]
+pub struct ApiContext {
+    pub command_tx: flume::Sender<Command>,
+    pub status: StatusTracker,
+    pub stats: SessionStats,
+    pub event_log: EventLog,
+    /// Reported at `GET /healthz`.
+    pub health: HealthCheck,
+    pub history: Option<History>,
+    pub quarantine: Quarantine,
+    pub mjpeg: Option<MjpegFeed>,
+    /// Where `POST /upload` saves files, uploads are refused if there's none.
+    pub upload_dir: Option<PathBuf>,
+    /// Uploads bigger than this are refused.
+    pub upload_max_bytes: u64,
+    /// Fetches the web videos sent to `POST /download`, they're refused if there's none.
+    pub downloader: Option<Downloader>,
+    /// Where events that don't come from the stream itself, e.g. download progress, are sent.
+    pub event_tx: flume::Sender<Event>,
+    /// The root directories files are picked from, changed by `POST` and `DELETE /roots`.
+    pub roots: LibraryRoots,
+    /// Tokens for viewers of mediamtx's HLS and WebRTC, managed at `/viewer-tokens`. mediamtx
+    /// checks them at `POST /mediamtx/auth`.
+    pub viewer_tokens: Option<ViewerTokens>,
+}
+
+/// Starts the HTTP control API on its own thread and async runtime.
+/// If `tokens` isn't empty, requests that change anything need an `Authorization: Bearer <token>`
+/// header with one of them.
+pub fn start_api_task(port: u16, context: ApiContext, tokens: Vec<String>) -> ApiHandle {
+    // Bind straight away, so a port that's in use fails on startup
+    let listener = std::net::TcpListener::bind(("0.0.0.0", port)).expect("Failed to start server");
+    listener.set_nonblocking(true).expect("Failed to start server");
+
+    let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+    let ApiContext {
+        command_tx,
+        status,
+        stats,
+        event_log,
+        health,
+        history,
+        quarantine,
+        mjpeg,
+        upload_dir,
+        upload_max_bytes,
+        downloader,
+        event_tx,
+        roots,
+        viewer_tokens,
+    } = context;
+    let upload_limit = usize::try_from(upload_max_bytes).unwrap_or(usize::MAX);
+    let state = ApiState {
+        command_tx,
+        status,
+        stats,
+        event_log,
+        health,
+        history,
+        quarantine,
+        mjpeg,
+        upload_dir,
+        downloader,
+        event_tx,
+        roots,
+        viewer_tokens,
+        tokens: tokens.into(),
+        shutdown_rx: shutdown_rx.clone(),
+    };
+    let app = Router::new()
+        .route("/skip", get(skip).post(skip))
+        .route("/status", get(status_json))
+        .route("/stats", get(stats_json))
+        .route("/healthz", get(healthz))
+        .route("/history", get(history_json))
+        .route("/history/export", get(history_export))
+        .route("/history/{id}/still", get(history_still))
+        .route("/events", get(events))
+        .route("/gain", post(set_gain).delete(clear_gain))
+        .route("/next", post(play_next))
+        .route("/enqueue", post(enqueue))
+        .route("/download", post(download))
+        .route("/roots", get(list_roots).post(add_root).delete(remove_root))
+        // Media files are far bigger than the default limit
+        .route("/upload", post(upload).layer(DefaultBodyLimit::max(upload_limit)))
+        .route("/media-cache", delete(invalidate_media_cache))
+        .route("/freeze", post(freeze))
+        .route("/unfreeze", post(unfreeze))
+        .route("/hold", post(hold))
+        .route("/resume", post(resume))
+        .route("/video-profile", post(set_video_profile))
+        .route("/title-template", post(set_title_template).delete(clear_title_template))
+        .route("/approvals", get(approvals))
+        .route("/approvals/thumbnail", get(approval_thumbnail))
+        .route("/preview", get(preview))
+        .route("/approvals/approve", post(approve))
+        .route("/approvals/reject", post(reject))
+        .route("/quarantine", get(quarantine))
+        .route("/quarantine/{id}", delete(unquarantine))
+        .route("/mjpeg", get(mjpeg))
+        .route("/viewer-tokens", get(list_viewer_tokens).post(mint_viewer_token))
+        .route("/viewer-tokens/{token}", delete(revoke_viewer_token))
+        .route("/mediamtx/auth", post(mediamtx_auth))
+        .layer(axum::middleware::from_fn_with_state(state.clone(), authorize))
+        .with_state(state);
+
+    let thread = std::thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .thread_name("z-stream-api")
+            .enable_all()
+            .build()
+            .expect("Failed to start API runtime");
+
+        runtime.block_on(async move {
+            let listener = match tokio::net::TcpListener::from_std(listener) {
+                Ok(listener) => listener,
                 Err(error) => {
                     eprintln!("Error: {error}");
-                    break;
+                    return;
                 }
             };
+            let shutdown = shutdown_signal(shutdown_rx);
+            if let Err(error) = axum::serve(listener, app).with_graceful_shutdown(shutdown).await {
+                eprintln!("Error: {error}");
+            }
+        });
+    });
+
+    ApiHandle { shutdown_tx, thread }
+}
+
+async fn authorize(State(state): State<ApiState>, request: Request, next: Next) -> Response {
+    eprintln!("Request: {} {}", request.method(), request.uri());
+
+    if needs_token(request.method(), request.uri().path())
+        && !is_authorized(request.headers(), &state.tokens)
+    {
+        return (StatusCode::UNAUTHORIZED, [(header::WWW_AUTHENTICATE, "Bearer")]).into_response();
+    }
+    next.run(request).await
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct SkipQuery {
+    #[serde(default)]
+    graceful: bool,
+    /// Seconds to keep playing before a graceful skip.
+    delay: Option<f64>,
+}
+
+/// `?graceful=true` lets the current file play on for a moment (`delay`, 2 seconds by default),
+/// then cuts to the next one on the file's next keyframe.
+async fn skip(State(state): State<ApiState>, Query(query): Query<SkipQuery>) -> StatusCode {
+    const DEFAULT_GRACEFUL_DELAY: f64 = 2.0;
+
+    if !query.graceful {
+        return send_command(&state, Command::Skip).await;
+    }
+    let delay = query.delay.unwrap_or(DEFAULT_GRACEFUL_DELAY);
+    match Duration::try_from_secs_f64(delay) {
+        Ok(delay) => send_command(&state, Command::SoftSkip { delay }).await,
+        Err(_) => StatusCode::BAD_REQUEST,
+    }
+}
+
+async fn status_json(State(state): State<ApiState>) -> impl IntoResponse {
+    Json(state.status.status())
+}
+
+async fn stats_json(State(state): State<ApiState>) -> impl IntoResponse {
+    Json(state.stats.summary())
+}
+
+/// 200 with a [`HealthReport`](crate::health::HealthReport) when healthy, 503 with one saying
+/// what's wrong when not.
+async fn healthz(State(state): State<ApiState>) -> Response {
+    let report = state.health.report();
+    let status = if report.healthy { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(report)).into_response()
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct HistoryQuery {
+    limit: Option<usize>,
+}
+
+/// The most recently played files, newest first. 404 unless a history database is configured.
+async fn history_json(
+    State(state): State<ApiState>,
+    Query(query): Query<HistoryQuery>,
+) -> Response {
+    const DEFAULT_LIMIT: usize = 50;
+
+    let Some(history) = state.history else { return StatusCode::NOT_FOUND.into_response() };
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+    match tokio::task::spawn_blocking(move || history.recent(limit)).await {
+        Ok(Ok(entries)) => Json(entries).into_response(),
+        Ok(Err(error)) => {
+            eprintln!("Failed to read history: {error}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ExportFormat {
+    #[default]
+    Csv,
+    Json,
+}
 
-            handle_request(request, command_tx.clone());
+#[derive(Debug, serde::Deserialize)]
+struct ExportQuery {
+    #[serde(default)]
+    format: ExportFormat,
+    /// Unix timestamps, everything that started between them is exported.
+    from: Option<f64>,
+    to: Option<f64>,
+}
+
+/// The program log, what aired and when, oldest first: `?format=csv` (the default) or `json`,
+/// optionally only `from` and `to` some Unix timestamps. 404 unless a history database is
+/// configured.
+async fn history_export(
+    State(state): State<ApiState>,
+    Query(query): Query<ExportQuery>,
+) -> Response {
+    let Some(history) = state.history else { return StatusCode::NOT_FOUND.into_response() };
+    let entries = match tokio::task::spawn_blocking(move || {
+        history.program_log(query.from, query.to)
+    })
+    .await
+    {
+        Ok(Ok(entries)) => entries,
+        Ok(Err(error)) => {
+            eprintln!("Failed to read history: {error}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+    match query.format {
+        ExportFormat::Csv => {
+            let headers = [
+                (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+                (header::CONTENT_DISPOSITION, "attachment; filename=\"program-log.csv\""),
+            ];
+            (headers, program_log_csv(&entries)).into_response()
+        }
+        ExportFormat::Json => Json(entries).into_response(),
+    }
+}
+
+/// A JPEG of a recently played file, see [`crate::history::HistoryEntry::has_still`].
+async fn history_still(State(state): State<ApiState>, Path(id): Path<i64>) -> Response {
+    let Some(history) = state.history else { return StatusCode::NOT_FOUND.into_response() };
+    match tokio::task::spawn_blocking(move || history.still(id)).await {
+        Ok(Ok(Some(jpeg))) => ([(header::CONTENT_TYPE, "image/jpeg")], jpeg).into_response(),
+        Ok(Ok(None)) => StatusCode::NOT_FOUND.into_response(),
+        Ok(Err(error)) => {
+            eprintln!("Failed to read still: {error}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct EventsQuery {
+    #[serde(default = "default_follow")]
+    follow: bool,
+}
+
+fn default_follow() -> bool {
+    true
+}
+
+/// Streams events as Server-Sent Events.
+/// Clients that send `Last-Event-ID` when reconnecting get any events they missed first.
+/// `?follow=false` ends the stream after those, instead of going on with new events.
+async fn events(
+    State(state): State<ApiState>,
+    Query(query): Query<EventsQuery>,
+    headers: HeaderMap,
+) -> Sse<impl Stream<Item = Result<sse::Event, Infallible>>> {
+    let last_id = headers
+        .get("Last-Event-ID")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok());
+    let (missed, event_rx) = state.event_log.subscribe(last_id);
+
+    let live = futures_util::stream::iter(query.follow.then(|| event_rx.into_stream())).flatten();
+    let events = futures_util::stream::iter(missed).chain(live);
+    let stream = events.filter_map(|(id, event)| async move {
+        match sse::Event::default().id(id.to_string()).json_data(&event) {
+            Ok(event) => Some(Ok(event)),
+            Err(error) => {
+                eprintln!("Failed to serialize event: {error}");
+                None
+            }
         }
     });
+    let retry = futures_util::stream::once(async {
+        Ok(sse::Event::default().retry(Duration::from_secs(3)))
+    });
+    // Graceful shutdown waits for every connection, so event streams have to end by themselves
+    let stream = retry.chain(stream).take_until(shutdown_signal(state.shutdown_rx.clone()));
+    Sse::new(stream).keep_alive(KeepAlive::new().interval(Duration::from_secs(15)))
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct GainQuery {
+    db: String,
+}
+
+/// The body is the path of the file, `db` is the gain to apply to it.
+async fn set_gain(
+    State(state): State<ApiState>,
+    Query(query): Query<GainQuery>,
+    body: String,
+) -> StatusCode {
+    match (body_path(&body), parse_gain(&query.db)) {
+        (Some(path), Some(gain_db)) => {
+            send_command(&state, Command::SetGain { path, gain_db: Some(gain_db) }).await
+        }
+        _ => StatusCode::BAD_REQUEST,
+    }
+}
+
+async fn clear_gain(State(state): State<ApiState>, body: String) -> StatusCode {
+    match body_path(&body) {
+        Some(path) => send_command(&state, Command::SetGain { path, gain_db: None }).await,
+        None => StatusCode::BAD_REQUEST,
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct PathRequest {
+    path: PathBuf,
 }
 
-fn handle_request(request: tiny_http::Request, command_tx: flume::Sender<Command>) {
-    let method = request.method();
-    let path = request.url();
-    eprintln!("Request: {method} {path}");
-    if *method == tiny_http::Method::Get && path == "/skip" {
-        _ = command_tx.send(Command::Skip);
+/// `{"path": "..."}`, replaces the queued file.
+async fn play_next(
+    State(state): State<ApiState>,
+    Json(PathRequest { path }): Json<PathRequest>,
+) -> StatusCode {
+    if !tokio::fs::metadata(&path).await.is_ok_and(|metadata| metadata.is_file()) {
+        return StatusCode::NOT_FOUND;
+    }
+    send_command(&state, Command::PlayNext { path }).await
+}
+
+/// `{"path": "..."}`, adds the file to the end of the queue.
+async fn enqueue(
+    State(state): State<ApiState>,
+    Json(PathRequest { path }): Json<PathRequest>,
+) -> StatusCode {
+    if !tokio::fs::metadata(&path).await.is_ok_and(|metadata| metadata.is_file()) {
+        return StatusCode::NOT_FOUND;
+    }
+    send_command(&state, Command::Enqueue { path }).await
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct DownloadRequest {
+    url: String,
+}
+
+/// `{"url": "..."}`, downloads a web video and plays it next once it's there, see the
+/// `download_*` events for how it's going. 404 unless there's a downloader.
+async fn download(
+    State(state): State<ApiState>,
+    Json(DownloadRequest { url }): Json<DownloadRequest>,
+) -> StatusCode {
+    let Some(downloader) = &state.downloader else { return StatusCode::NOT_FOUND };
+    // Nothing that could pass for one of the downloader's options
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        return StatusCode::BAD_REQUEST;
+    }
+    downloader.start(url, state.command_tx.clone(), state.event_tx.clone());
+    StatusCode::ACCEPTED
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct RootRequest {
+    path: PathBuf,
+}
+
+async fn list_roots(State(state): State<ApiState>) -> Json<Vec<String>> {
+    let roots = state.roots.list();
+    Json(roots.iter().map(|root| root.to_string_lossy().into_owned()).collect())
+}
+
+/// Adds a root directory (or file) to pick from, `{"path": "..."}`. It's indexed in the
+/// background, so it isn't picked from straight away. 409 if it already is one.
+async fn add_root(
+    State(state): State<ApiState>,
+    Json(RootRequest { path }): Json<RootRequest>,
+) -> Response {
+    // Relative to whatever the server was started in otherwise
+    if !path.is_absolute() {
+        return (StatusCode::BAD_REQUEST, "The path has to be absolute").into_response();
+    }
+    if tokio::fs::metadata(&path).await.is_err() {
+        return (StatusCode::BAD_REQUEST, "The path doesn't exist").into_response();
+    }
+    println!("Adding root {}", path.display());
+    if state.roots.add(path) { StatusCode::ACCEPTED } else { StatusCode::CONFLICT }.into_response()
+}
+
+/// Stops picking from a root, `{"path": "..."}`. What's playing or queued from it still plays.
+async fn remove_root(
+    State(state): State<ApiState>,
+    Json(RootRequest { path }): Json<RootRequest>,
+) -> StatusCode {
+    if state.roots.remove(&path) {
+        println!("Removed root {}", path.display());
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct UploadQuery {
+    #[serde(default)]
+    play_next: bool,
+}
+
+/// Saves the `file` field of a multipart form into the upload directory under its own name, with
+/// a number added if that's taken, and responds with `{"path": "..."}`. `?play_next=true` also
+/// queues it to play next. 404 unless there's an upload directory, 413 if it's over the limit.
+async fn upload(
+    State(state): State<ApiState>,
+    Query(query): Query<UploadQuery>,
+    mut multipart: Multipart,
+) -> Response {
+    let Some(upload_dir) = &state.upload_dir else { return StatusCode::NOT_FOUND.into_response() };
+    let field = loop {
+        match multipart.next_field().await {
+            Ok(Some(field)) if field.name() == Some("file") => break field,
+            Ok(Some(_)) => continue,
+            Ok(None) => return (StatusCode::BAD_REQUEST, "No file field").into_response(),
+            Err(error) => return error.into_response(),
+        }
+    };
+    // Only the name, so it can't end up outside the upload directory
+    let file_name = field
+        .file_name()
+        .and_then(|name| std::path::Path::new(name).file_name())
+        .map(OsStr::to_os_string);
+    let Some(file_name) = file_name else {
+        return (StatusCode::BAD_REQUEST, "The file has no name").into_response();
+    };
+
+    let path = match save_upload(upload_dir, &file_name, field).await {
+        Ok(path) => path,
+        Err(response) => return response,
+    };
+    println!("Uploaded {}", path.display());
+    if query.play_next {
+        let status = send_command(&state, Command::PlayNext { path: path.clone() }).await;
+        if status != StatusCode::OK {
+            return status.into_response();
+        }
+    }
+    let body = serde_json::json!({ "path": path.to_string_lossy() });
+    (StatusCode::CREATED, Json(body)).into_response()
+}
+
+/// Writes the upload to a hidden temporary file first, so the library never sees half of it, and
+/// moves it to the first free name once it's all there.
+async fn save_upload(
+    dir: &std::path::Path,
+    file_name: &OsStr,
+    mut field: Field<'_>,
+) -> Result<PathBuf, Response> {
+    const MAX_NUMBER: u32 = 1000;
+
+    let internal_error = |error: std::io::Error| {
+        eprintln!("Failed to save an upload: {error}");
+        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    };
+
+    tokio::fs::create_dir_all(dir).await.map_err(internal_error)?;
+    let mut temp_file = tempfile::Builder::new()
+        .prefix(".upload-")
+        .tempfile_in(dir)
+        .map_err(internal_error)?;
+    let mut file = tokio::fs::File::from_std(temp_file.reopen().map_err(internal_error)?);
+    while let Some(chunk) = field.chunk().await.map_err(IntoResponse::into_response)? {
+        file.write_all(&chunk).await.map_err(internal_error)?;
+    }
+    file.flush().await.map_err(internal_error)?;
+    drop(file);
+
+    for number in 0..MAX_NUMBER {
+        let path = dir.join(numbered_file_name(file_name, number));
+        match temp_file.persist_noclobber(&path) {
+            Ok(_) => return Ok(path),
+            Err(error) if error.error.kind() == std::io::ErrorKind::AlreadyExists => {
+                temp_file = error.file;
+            }
+            Err(error) => return Err(internal_error(error.error)),
+        }
+    }
+    Err((StatusCode::CONFLICT, "Too many files with that name").into_response())
+}
+
+/// `name.ext`, then `name (1).ext`, `name (2).ext` and so on.
+fn numbered_file_name(file_name: &OsStr, number: u32) -> OsString {
+    if number == 0 {
+        return file_name.to_os_string();
+    }
+    let path = std::path::Path::new(file_name);
+    let mut numbered = path.file_stem().unwrap_or(file_name).to_os_string();
+    numbered.push(format!(" ({number})"));
+    if let Some(extension) = path.extension() {
+        numbered.push(".");
+        numbered.push(extension);
+    }
+    numbered
+}
+
+/// The body is the path of the file to forget, or empty to clear the whole cache.
+async fn invalidate_media_cache(State(state): State<ApiState>, body: String) -> StatusCode {
+    send_command(&state, Command::InvalidateMediaCache { path: body_path(&body) }).await
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct FreezeQuery {
+    #[serde(default)]
+    mute: bool,
+}
+
+/// Holds the current frame, `?mute=true` silences the audio as well.
+async fn freeze(State(state): State<ApiState>, Query(query): Query<FreezeQuery>) -> StatusCode {
+    send_command(&state, Command::Freeze { mute_audio: query.mute }).await
+}
+
+async fn unfreeze(State(state): State<ApiState>) -> StatusCode {
+    send_command(&state, Command::Unfreeze).await
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct HoldQuery {
+    slate: Option<SlateKind>,
+}
+
+/// Cuts to a slate until `/resume`, `?slate=standby|sign-off|pause` (the default).
+async fn hold(State(state): State<ApiState>, Query(query): Query<HoldQuery>) -> StatusCode {
+    let slate = query.slate.unwrap_or(SlateKind::Pause);
+    send_command(&state, Command::Hold { slate }).await
+}
+
+async fn resume(State(state): State<ApiState>) -> StatusCode {
+    send_command(&state, Command::Resume).await
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct VideoProfileQuery {
+    width: Option<u32>,
+    height: Option<u32>,
+    bitrate: Option<u32>,
+}
+
+/// Re-encodes the output at a different size (`width`, `height`) and/or `bitrate` (kbit/s),
+/// without restarting it. Anything left out stays as it is.
+async fn set_video_profile(
+    State(state): State<ApiState>,
+    Query(query): Query<VideoProfileQuery>,
+) -> StatusCode {
+    let command = Command::SetVideoProfile {
+        width: query.width,
+        height: query.height,
+        bitrate_kbps: query.bitrate,
+    };
+    send_command(&state, command).await
+}
+
+/// The body is the new template for the title overlay, see
+/// [`TitleTemplate`](crate::stream::TitleTemplate).
+async fn set_title_template(State(state): State<ApiState>, body: String) -> StatusCode {
+    if body.trim().is_empty() {
+        return StatusCode::BAD_REQUEST;
+    }
+    send_command(&state, Command::SetTitleTemplate { template: Some(body) }).await
+}
+
+/// Goes back to the default title, the file's path or the sidecar's title.
+async fn clear_title_template(State(state): State<ApiState>) -> StatusCode {
+    send_command(&state, Command::SetTitleTemplate { template: None }).await
+}
+
+/// Files that were picked, but can't play until they're approved.
+async fn approvals(State(state): State<ApiState>) -> impl IntoResponse {
+    Json(state.status.status().awaiting_approval)
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct ThumbnailQuery {
+    path: PathBuf,
+    width: Option<u32>,
+}
+
+/// A JPEG of a file waiting for approval. Other files are a 404, so this can't be used to look at
+/// anything else on the machine.
+async fn approval_thumbnail(
+    State(state): State<ApiState>,
+    Query(query): Query<ThumbnailQuery>,
+) -> Response {
+    const DEFAULT_WIDTH: u32 = 320;
+    const MAX_WIDTH: u32 = 1920;
+
+    if !state.status.status().awaiting_approval.contains(&query.path) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let width = query.width.unwrap_or(DEFAULT_WIDTH).clamp(16, MAX_WIDTH);
+    let path = query.path;
+    match tokio::task::spawn_blocking(move || crate::thumbnail::thumbnail(&path, width)).await {
+        Ok(Ok(jpeg)) => ([(header::CONTENT_TYPE, "image/jpeg")], jpeg).into_response(),
+        Ok(Err(error)) => {
+            eprintln!("Failed to create thumbnail: {error}");
+            StatusCode::UNPROCESSABLE_ENTITY.into_response()
+        }
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct PreviewQuery {
+    /// The file playing now if not given.
+    path: Option<PathBuf>,
+    #[serde(default)]
+    format: PreviewFormat,
+    width: Option<u32>,
+    seconds: Option<f64>,
+}
+
+/// A few seconds of a file as an animated GIF or WebP, e.g. for chat bots announcing what's on.
+/// Defaults to what's playing right now. Only files that are playing, coming up or waiting for
+/// approval can be previewed.
+async fn preview(State(state): State<ApiState>, Query(query): Query<PreviewQuery>) -> Response {
+    const DEFAULT_WIDTH: u32 = 320;
+    const MAX_WIDTH: u32 = 640;
+    const DEFAULT_SECONDS: f64 = 3.0;
+    const MAX_SECONDS: f64 = 10.0;
+
+    let status = state.status.status();
+    let (path, start) = match (query.path, status.playing) {
+        (None, Some(playing)) => {
+            (playing.path, gstreamer::ClockTime::try_from_seconds_f64(playing.elapsed_secs).ok())
+        }
+        (None, None) => return StatusCode::NOT_FOUND.into_response(),
+        (Some(path), playing) => {
+            let known = playing.is_some_and(|playing| playing.path == path)
+                || status.upcoming.contains(&path)
+                || status.awaiting_approval.contains(&path);
+            if !known {
+                return StatusCode::NOT_FOUND.into_response();
+            }
+            (path, None)
+        }
+    };
+    let width = query.width.unwrap_or(DEFAULT_WIDTH).clamp(16, MAX_WIDTH);
+    let Ok(length) = gstreamer::ClockTime::try_from_seconds_f64(
+        query.seconds.unwrap_or(DEFAULT_SECONDS).clamp(0.5, MAX_SECONDS),
+    ) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    let format = query.format;
+    let preview = move || crate::thumbnail::preview(&path, start, length, width, format);
+    match tokio::task::spawn_blocking(preview).await {
+        Ok(Ok(image)) => ([(header::CONTENT_TYPE, format.content_type())], image).into_response(),
+        Ok(Err(error)) => {
+            eprintln!("Failed to create preview: {error}");
+            StatusCode::UNPROCESSABLE_ENTITY.into_response()
+        }
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// `{"path": "..."}`, lets a file waiting for approval play. It's played next.
+async fn approve(
+    State(state): State<ApiState>,
+    Json(PathRequest { path }): Json<PathRequest>,
+) -> StatusCode {
+    review(&state, path, true).await
+}
+
+/// `{"path": "..."}`, keeps a file waiting for approval from ever playing.
+async fn reject(
+    State(state): State<ApiState>,
+    Json(PathRequest { path }): Json<PathRequest>,
+) -> StatusCode {
+    review(&state, path, false).await
+}
+
+async fn review(state: &ApiState, path: PathBuf, approved: bool) -> StatusCode {
+    if !state.status.status().awaiting_approval.contains(&path) {
+        return StatusCode::NOT_FOUND;
+    }
+    send_command(state, Command::Review { path, approved }).await
+}
+
+/// Files that failed to play and won't be picked again.
+async fn quarantine(State(state): State<ApiState>) -> impl IntoResponse {
+    Json(state.quarantine.entries())
+}
+
+/// Lets a quarantined file be picked again.
+async fn unquarantine(State(state): State<ApiState>, Path(id): Path<i64>) -> StatusCode {
+    match tokio::task::spawn_blocking(move || state.quarantine.remove(id)).await {
+        Ok(Ok(true)) => StatusCode::OK,
+        Ok(Ok(false)) => StatusCode::NOT_FOUND,
+        Ok(Err(error)) => {
+            eprintln!("Failed to remove quarantine entry: {error}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// The program as `multipart/x-mixed-replace` JPEGs, which most browsers and small devices can
+/// show without any video decoding. 404 unless the MJPEG feed is enabled.
+async fn mjpeg(State(state): State<ApiState>) -> Response {
+    const BOUNDARY: &str = "frame";
+
+    let Some(mjpeg) = state.mjpeg else { return StatusCode::NOT_FOUND.into_response() };
+    let frames = futures_util::stream::unfold(mjpeg.subscribe(), |mut frames| async move {
+        frames.changed().await.ok()?;
+        let frame = frames.borrow_and_update().clone();
+        Some((frame, frames))
+    });
+    let parts = frames
+        .filter_map(|frame| async move {
+            let jpeg = frame?;
+            let mut part = format!(
+                "--{BOUNDARY}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+                jpeg.len()
+            )
+            .into_bytes();
+            part.extend_from_slice(&jpeg);
+            part.extend_from_slice(b"\r\n");
+            Some(Ok::<_, Infallible>(part))
+        })
+        .take_until(shutdown_signal(state.shutdown_rx.clone()));
+
+    let content_type = format!("multipart/x-mixed-replace; boundary={BOUNDARY}");
+    (
+        [(header::CONTENT_TYPE, content_type), (header::CACHE_CONTROL, "no-cache".to_string())],
+        axum::body::Body::from_stream(parts),
+    )
+        .into_response()
+}
+
+/// The viewer tokens that haven't expired. 404 unless viewer tokens are enabled.
+async fn list_viewer_tokens(State(state): State<ApiState>) -> Response {
+    let Some(viewer_tokens) = state.viewer_tokens else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    Json(viewer_tokens.list()).into_response()
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+struct MintRequest {
+    /// Seconds the token works for.
+    ttl: Option<u64>,
+    label: Option<String>,
+}
+
+/// `{"ttl": 3600, "label": "..."}`, both optional, makes a token for viewing over HLS and WebRTC.
+/// The token goes into their URLs as `?token=...`. 403 if the API has no tokens of its own,
+/// anyone could mint one then.
+async fn mint_viewer_token(State(state): State<ApiState>, body: String) -> Response {
+    const DEFAULT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+    let Some(viewer_tokens) = state.viewer_tokens else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if state.tokens.is_empty() {
+        return (StatusCode::FORBIDDEN, "Viewer tokens need an API token").into_response();
+    }
+    let request: MintRequest = if body.trim().is_empty() {
+        MintRequest::default()
+    } else {
+        match serde_json::from_str(&body) {
+            Ok(request) => request,
+            Err(error) => return (StatusCode::BAD_REQUEST, error.to_string()).into_response(),
+        }
+    };
+    let ttl = request.ttl.map_or(DEFAULT_TTL, Duration::from_secs);
+    (StatusCode::CREATED, Json(viewer_tokens.mint(ttl, request.label))).into_response()
+}
+
+async fn revoke_viewer_token(
+    State(state): State<ApiState>,
+    Path(token): Path<String>,
+) -> StatusCode {
+    let Some(viewer_tokens) = state.viewer_tokens else { return StatusCode::NOT_FOUND };
+    if viewer_tokens.revoke(&token) {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+/// mediamtx's `authHTTPAddress`, asked about every client. Anything but 200 turns it away.
+async fn mediamtx_auth(
+    State(state): State<ApiState>,
+    Json(request): Json<AuthRequest>,
+) -> StatusCode {
+    let allowed = state.viewer_tokens.as_ref().is_none_or(|tokens| tokens.allows(&request));
+    if allowed { StatusCode::OK } else { StatusCode::UNAUTHORIZED }
+}
+
+async fn shutdown_signal(mut shutdown_rx: tokio::sync::watch::Receiver<bool>) {
+    _ = shutdown_rx.wait_for(|shutdown| *shutdown).await;
+}
+
+/// Hands a command over to the GStreamer side, without blocking the runtime if it's backed up.
+async fn send_command(state: &ApiState, command: Command) -> StatusCode {
+    match state.command_tx.send_async(command).await {
+        Ok(()) => StatusCode::OK,
+        Err(_) => StatusCode::SERVICE_UNAVAILABLE,
+    }
+}
+
+/// Whether a request needs one of the API tokens: anything that changes something, and the
+/// viewer tokens, which are as good as access to the stream. mediamtx can't send a token when it
+/// checks a viewer, and only gets an answer.
+fn needs_token(method: &Method, path: &str) -> bool {
+    if path == "/mediamtx/auth" {
+        return false;
+    }
+    path.starts_with("/viewer-tokens") || is_mutating(method, path)
+}
+
+fn is_mutating(method: &Method, path: &str) -> bool {
+    // `/skip` is a GET for the sake of simple clients, but it still changes what's playing
+    path == "/skip" || !matches!(*method, Method::GET | Method::HEAD)
+}
+
+fn is_authorized(headers: &HeaderMap, tokens: &[String]) -> bool {
+    if tokens.is_empty() {
+        return true;
+    }
+
+    let Some(value) = headers.get(header::AUTHORIZATION).and_then(|v| v.to_str().ok()) else {
+        return false;
+    };
+    let Some(token) = value.strip_prefix("Bearer ") else { return false };
+    let token = token.trim();
+    tokens
+        .iter()
+        .any(|expected| constant_time_eq(expected.as_bytes(), token.as_bytes()))
+}
+
+/// Compares without bailing out early, so timing doesn't leak how much of a token matched.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+fn body_path(body: &str) -> Option<PathBuf> {
+    let body = body.trim();
+    if body.is_empty() { None } else { Some(PathBuf::from(body)) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bearer(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, value.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn reads_are_open_and_changes_need_a_token() {
+        assert!(!needs_token(&Method::GET, "/status"));
+        assert!(!needs_token(&Method::HEAD, "/status"));
+        assert!(needs_token(&Method::POST, "/enqueue"));
+        assert!(needs_token(&Method::DELETE, "/queue"));
+        // Listing viewer tokens is a GET, but the tokens are secrets
+        assert!(needs_token(&Method::GET, "/viewer-tokens"));
+    }
+
+    #[test]
+    fn skip_is_mutating_even_as_a_get() {
+        assert!(is_mutating(&Method::GET, "/skip"));
+        assert!(needs_token(&Method::GET, "/skip"));
+        assert!(!is_mutating(&Method::GET, "/status"));
+        assert!(is_mutating(&Method::POST, "/pause"));
+    }
+
+    #[test]
+    fn mediamtx_auth_is_exempt() {
+        // mediamtx can't send a token, it's asking about its viewers' tokens
+        assert!(!needs_token(&Method::POST, "/mediamtx/auth"));
+    }
+
+    #[test]
+    fn no_tokens_means_no_auth() {
+        assert!(is_authorized(&HeaderMap::new(), &[]));
+        assert!(is_authorized(&bearer("Bearer anything"), &[]));
+    }
+
+    #[test]
+    fn checks_the_bearer_token() {
+        let tokens = ["secret".to_string(), "other".to_string()];
+        assert!(is_authorized(&bearer("Bearer secret"), &tokens));
+        assert!(is_authorized(&bearer("Bearer other"), &tokens));
+        assert!(is_authorized(&bearer("Bearer  secret "), &tokens));
+        assert!(!is_authorized(&bearer("Bearer wrong"), &tokens));
+        assert!(!is_authorized(&bearer("Bearer secre"), &tokens));
+        assert!(!is_authorized(&HeaderMap::new(), &tokens));
+    }
+
+    #[test]
+    fn rejects_malformed_authorization_headers() {
+        let tokens = ["secret".to_string()];
+        assert!(!is_authorized(&bearer("secret"), &tokens));
+        assert!(!is_authorized(&bearer("Basic secret"), &tokens));
+        assert!(!is_authorized(&bearer("bearer secret"), &tokens));
+        assert!(!is_authorized(&bearer("Bearer"), &tokens));
+        assert!(!is_authorized(&bearer("Bearer "), &tokens));
+
+        let mut headers = HeaderMap::new();
+        let not_utf8 = header::HeaderValue::from_bytes(b"Bearer \xffsecret").unwrap();
+        headers.insert(header::AUTHORIZATION, not_utf8);
+        assert!(!is_authorized(&headers, &tokens));
+    }
+
+    #[test]
+    fn constant_time_eq_compares_whole_values() {
+        assert!(constant_time_eq(b"", b""));
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+        assert!(!constant_time_eq(b"secret", b""));
     }
-    let response = tiny_http::Response::empty(200);
-    _ = request.respond(response);
 }
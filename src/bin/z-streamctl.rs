@@ -0,0 +1,157 @@
+//! A small client for the z-stream control API, so scripts don't have to hand-craft requests.
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::{Parser, Subcommand};
+use z_stream::client::ApiClient;
+use z_stream::stream::SlateKind;
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Controls a running z-stream instance")]
+struct Cli {
+    /// Base URL of the control API.
+    #[arg(long, env = "Z_STREAM_URL", default_value = "http://127.0.0.1:18080")]
+    url: String,
+
+    /// Bearer token, needed for commands that change anything if the server requires one.
+    #[arg(long, env = "Z_STREAM_API_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+
+    #[command(subcommand)]
+    command: CtlCommand,
+}
+
+#[derive(Debug, Subcommand)]
+enum CtlCommand {
+    /// Skip the current file.
+    Skip {
+        /// Let the current file play on for a couple of seconds, then cut on a keyframe.
+        #[arg(long)]
+        graceful: bool,
+    },
+    /// Print what's playing and what's coming up, as JSON.
+    Status,
+    /// Print the files coming up next.
+    Queue,
+    /// Play this file after the current one, instead of whatever is queued.
+    Next {
+        file: PathBuf,
+    },
+    /// Add this file to the end of the queue.
+    Enqueue {
+        file: PathBuf,
+    },
+    /// Hold the current video frame.
+    Freeze {
+        /// Silence the audio while frozen.
+        #[arg(long)]
+        mute: bool,
+    },
+    Unfreeze,
+    /// Show a slate instead of files until `resume`.
+    Hold {
+        /// `standby`, `sign-off` or `pause`.
+        #[arg(default_value = "pause")]
+        slate: SlateKind,
+    },
+    Resume,
+    /// Re-encode the output at a different size and/or bitrate, without restarting it.
+    VideoProfile {
+        /// `WIDTHxHEIGHT`, e.g. `854x480`.
+        #[arg(long, value_parser = parse_size)]
+        size: Option<(u32, u32)>,
+        /// In kbit/s.
+        #[arg(long)]
+        bitrate: Option<u32>,
+    },
+    /// Print the files waiting for approval.
+    Approvals,
+    /// Let a file waiting for approval play.
+    Approve {
+        file: PathBuf,
+    },
+    /// Keep a file waiting for approval from ever playing.
+    Reject {
+        file: PathBuf,
+    },
+    /// Print the files that failed to play and won't be picked again, with their ids.
+    Quarantine,
+    /// Let a quarantined file be picked again.
+    Unquarantine {
+        id: i64,
+    },
+    /// Print the recent events the server still remembers, one JSON object per line.
+    Events {
+        /// Keep printing events as they happen.
+        #[arg(long)]
+        follow: bool,
+    },
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(&cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("Error: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(cli: &Cli) -> Result<(), ureq::Error> {
+    let client = ApiClient::new(&cli.url, cli.token.clone());
+    match &cli.command {
+        CtlCommand::Skip { graceful } => client.skip(*graceful)?,
+        CtlCommand::Status => {
+            let status = client.status()?;
+            println!("{}", serde_json::to_string_pretty(&status).unwrap_or_default());
+        }
+        CtlCommand::Queue => {
+            let status = client.status()?;
+            let upcoming = status["upcoming"].as_array().into_iter().flatten();
+            for path in upcoming.filter_map(|path| path.as_str()) {
+                println!("{path}");
+            }
+        }
+        CtlCommand::Next { file } => client.play_next(file)?,
+        CtlCommand::Enqueue { file } => client.enqueue(file)?,
+        CtlCommand::Freeze { mute } => client.freeze(*mute)?,
+        CtlCommand::Unfreeze => client.unfreeze()?,
+        CtlCommand::Hold { slate } => client.hold(*slate)?,
+        CtlCommand::Resume => client.resume()?,
+        CtlCommand::VideoProfile { size, bitrate } => client.set_video_profile(*size, *bitrate)?,
+        CtlCommand::Approvals => {
+            let approvals = client.approvals()?;
+            for path in approvals.as_array().into_iter().flatten().filter_map(|p| p.as_str()) {
+                println!("{path}");
+            }
+        }
+        CtlCommand::Approve { file } => client.approve(file)?,
+        CtlCommand::Reject { file } => client.reject(file)?,
+        CtlCommand::Quarantine => {
+            let quarantine = client.quarantine()?;
+            for entry in quarantine.as_array().into_iter().flatten() {
+                let path = entry["path"].as_str().unwrap_or_default();
+                let reason = entry["reason"].as_str().unwrap_or_default();
+                println!("{}\t{path}\t{reason}", entry["id"]);
+            }
+        }
+        CtlCommand::Unquarantine { id } => client.unquarantine(*id)?,
+        CtlCommand::Events { follow: true } => {
+            client.follow_events(true, |event| println!("{event}"))?;
+        }
+        CtlCommand::Events { follow: false } => {
+            client.recent_events(|event| println!("{event}"))?
+        }
+    }
+    Ok(())
+}
+
+fn parse_size(s: &str) -> Result<(u32, u32), String> {
+    let (width, height) =
+        s.split_once('x').ok_or_else(|| format!("Expected WIDTHxHEIGHT, got {s:?}"))?;
+    let parse = |n: &str| n.parse::<u32>().map_err(|error| format!("Invalid size {s:?}: {error}"));
+    Ok((parse(width)?, parse(height)?))
+}
@@ -0,0 +1,948 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::{Args, Parser, Subcommand};
+use z_stream::health::HealthOptions;
+use z_stream::hooks::EventHook;
+use z_stream::mediamtx;
+use z_stream::random_files::{Cooldown, FileFilter, RandomFiles};
+use z_stream::resources::{CpuList, ResourceLimits};
+use z_stream::stream::{
+    AppSrcFormat, AppSrcPolicy, AspectPolicy, AudioOptions, ContentClassifier, DataOverlayOptions,
+    DataSource, DeinterlaceMode, EncoderOptions, EncoderProperty, KenBurnsOptions,
+    KeyframeInterval, LiveInputOptions, LiveSource, LiveTransition, LoudnessOptions, MjpegOptions,
+    MonitorOptions, OutputProfile, OverlaySlot, PlayDurationPolicy, PreparePolicy, RateControl,
+    RatingPolicy, RatingSlot, RenditionOptions, SecondaryAudio, Shuffle, SlateOptions,
+    SrtEncryption, SrtKeyLength, StingOptions, StreamOptions, SubtitleOptions, TransitionKind,
+    TransitionOptions, VideoOptions, Visualizer, WhepOptions,
+};
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Streams random files from a library as a continuous channel")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: CliCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CliCommand {
+    /// Start streaming files from the given root directories.
+    Serve(ServeArgs),
+    /// Print the detected media info of a file.
+    Probe { file: PathBuf },
+    /// Check which files in a directory can be played.
+    Validate { dir: PathBuf },
+    /// Print the playlist the file selection would produce, without playing anything.
+    Simulate(SimulateArgs),
+    /// Scan the library and hand out files to streamers started with `--leader`.
+    Leader {
+        #[arg(required = true)]
+        root_dirs: Vec<PathBuf>,
+
+        #[arg(long, default_value_t = 18081)]
+        port: u16,
+
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Remove entries from a media info cache, all of them unless a file is given.
+    ClearMediaCache { database: PathBuf, file: Option<PathBuf> },
+    /// Check a `serve --config` file, and the files and ports it refers to.
+    CheckConfig { file: PathBuf },
+    /// Bundle a `serve --config` file with the files its quarantine database keeps out, to move
+    /// the channel to another machine with `import-config`.
+    ExportConfig { file: PathBuf, bundle: PathBuf },
+    /// Write the config file of an `export-config` bundle to `file`, and add the bundle's
+    /// quarantined files to the quarantine database it names. The config is then checked like
+    /// `check-config` does, as the files it names aren't in the bundle.
+    ImportConfig { bundle: PathBuf, file: PathBuf },
+    /// Monitor and control a running instance through its API.
+    Tui {
+        /// Base URL of the control API.
+        #[arg(long, env = "Z_STREAM_URL", default_value = "http://127.0.0.1:18080")]
+        url: String,
+
+        /// Bearer token, if the server requires one.
+        #[arg(long, env = "Z_STREAM_API_TOKEN", hide_env_values = true)]
+        token: Option<String>,
+    },
+}
+
+#[derive(Debug, Args)]
+pub struct SimulateArgs {
+    #[arg(required = true)]
+    pub root_dirs: Vec<PathBuf>,
+
+    #[command(flatten)]
+    pub filter: FilterArgs,
+
+    /// How many hours of playlist to generate.
+    #[arg(long, default_value_t = 24.0)]
+    pub hours: f64,
+
+    /// Minutes to assume for videos and audio, unless `--probe` is used.
+    #[arg(long, default_value_t = 30)]
+    pub assumed_duration: u64,
+
+    /// Seconds to show images for.
+    #[arg(long, default_value_t = 5)]
+    pub image_duration: u64,
+
+    /// Discover the real duration of each file (slow on large libraries).
+    #[arg(long)]
+    pub probe: bool,
+}
+
+#[derive(Debug, Args)]
+#[command(args_override_self = true)]
+pub struct ServeArgs {
+    /// Directories (or files) to pick content from. Without any (and no `--leader`), a test card
+    /// is shown until some are added through the API.
+    pub root_dirs: Vec<PathBuf>,
+
+    /// Read options from this TOML file, with the flag names as keys (e.g. `video-bitrate =
+    /// 4000`) and `root-dirs` for the directories. Options given here win over the file's.
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Get files from a `z-stream leader` at this URL instead of scanning the library here.
+    #[arg(long, value_name = "URL")]
+    pub leader: Option<String>,
+
+    #[command(flatten)]
+    pub filter: FilterArgs,
+
+    /// Play every file once, in a random order, before repeating any.
+    #[arg(long)]
+    pub no_repeat: bool,
+
+    /// Remember which files `--no-repeat` has played in this SQLite database, so a restart
+    /// carries on where it left off.
+    #[arg(long, value_name = "PATH", requires = "no_repeat")]
+    pub shuffle_state: Option<PathBuf>,
+
+    /// Port of the internal RTSP server that mediamtx restreams from.
+    #[arg(long, default_value_t = 18554)]
+    pub rtsp_port: u16,
+
+    /// Port of the HTTP control API.
+    #[arg(long, default_value_t = 18080)]
+    pub api_port: u16,
+
+    /// Don't start the bundled mediamtx. Clients have to play the internal RTSP server (or WHEP)
+    /// directly then.
+    #[arg(long, conflicts_with = "rtmp_path")]
+    pub no_mediamtx: bool,
+
+    /// Port mediamtx serves RTSP clients on.
+    #[arg(long, default_value_t = mediamtx::RTSP_PORT)]
+    pub mediamtx_rtsp_port: u16,
+
+    /// Port mediamtx serves RTMP clients on.
+    #[arg(long, default_value_t = mediamtx::RTMP_PORT)]
+    pub mediamtx_rtmp_port: u16,
+
+    /// Port mediamtx serves HLS on.
+    #[arg(long, default_value_t = mediamtx::HLS_PORT)]
+    pub mediamtx_hls_port: u16,
+
+    /// Port mediamtx serves WebRTC on.
+    #[arg(long, default_value_t = mediamtx::WEBRTC_PORT)]
+    pub mediamtx_webrtc_port: u16,
+
+    /// Port mediamtx serves SRT clients on.
+    #[arg(long, default_value_t = mediamtx::SRT_PORT)]
+    pub mediamtx_srt_port: u16,
+
+    /// Also write the addresses clients can connect to as JSON to this file (`-` for stdout),
+    /// for scripts to pick up. It includes the API tokens.
+    #[arg(long, value_name = "FILE")]
+    pub endpoints_json: Option<PathBuf>,
+
+    /// Bearer tokens required by API requests that change anything. Open access if none are set.
+    #[arg(
+        long = "api-token",
+        env = "Z_STREAM_API_TOKENS",
+        value_delimiter = ',',
+        hide_env_values = true
+    )]
+    pub api_tokens: Vec<String>,
+
+    /// Save files uploaded to `POST /upload` here. Put it inside a root directory for them to join
+    /// the rotation too, rather than only playing when queued. Needs an API token, or anyone who
+    /// can reach the API could fill the disk.
+    #[arg(long, value_name = "DIR", requires = "api_tokens")]
+    pub upload_dir: Option<PathBuf>,
+
+    /// Largest upload accepted, in megabytes.
+    #[arg(long, value_name = "MB", default_value_t = 4000, requires = "upload_dir")]
+    pub upload_max_mb: u64,
+
+    /// yt-dlp (or a compatible downloader), to play web videos sent to `POST /download` with.
+    #[arg(long, value_name = "PATH", requires = "download_dir")]
+    pub downloader: Option<PathBuf>,
+
+    /// Where the downloader saves web videos. They're deleted a day later.
+    #[arg(long, value_name = "DIR", requires = "downloader")]
+    pub download_dir: Option<PathBuf>,
+
+    #[arg(long, default_value = "my_stream")]
+    pub stream_key: String,
+
+    /// Also serve the stream under this key, e.g. to move players over to a new one without
+    /// breaking the old URLs.
+    #[arg(long = "stream-key-alias", value_name = "KEY", value_delimiter = ',')]
+    pub stream_key_aliases: Vec<String>,
+
+    /// Output canvas as `WIDTHxHEIGHT` or `WIDTHxHEIGHT@FPS`.
+    #[arg(long, default_value_t = VideoOptions::default())]
+    pub video: VideoOptions,
+
+    /// Target bitrate of the encoded video, in kbit/s.
+    #[arg(long, value_name = "KBPS", default_value_t = VideoOptions::default().bitrate_kbps)]
+    pub video_bitrate: u32,
+
+    /// How the H.264 encoder spends bits: `cbr`, `vbr`, `cqp:QP` or `crf:QUALITY` (0-51, lower
+    /// is better), e.g. `crf:23` for archival outputs.
+    #[arg(long, value_name = "MODE", default_value_t = RateControl::default())]
+    pub rate_control: RateControl,
+
+    /// Time (`2s`) or number of frames (`60f`) between keyframes, the same for every encoder so
+    /// HLS segments line up.
+    #[arg(long, value_name = "INTERVAL", default_value_t = KeyframeInterval::default())]
+    pub keyframe_interval: KeyframeInterval,
+
+    /// Sample rate of the output audio, in Hz.
+    #[arg(long, value_name = "HZ", default_value_t = AudioOptions::default().sample_rate)]
+    pub audio_rate: u32,
+
+    /// Number of output audio channels.
+    #[arg(long, value_name = "N", default_value_t = AudioOptions::default().channels)]
+    pub audio_channels: u32,
+
+    /// Target bitrate of the encoded audio, in kbit/s.
+    #[arg(long, value_name = "KBPS", default_value_t = AudioOptions::default().bitrate_kbps)]
+    pub audio_bitrate: u32,
+
+    /// Push audio to the output in chunks of this many milliseconds (e.g. 20-40), cutting the
+    /// per-buffer overhead of inputs that decode to tiny buffers.
+    #[arg(long, value_name = "MS")]
+    pub audio_chunk_ms: Option<u32>,
+
+    /// `h264-aac`, or `mpeg2-ts` (MPEG-2 video and MP2 audio in a transport stream) for old
+    /// set-top boxes that can't decode H.264.
+    #[arg(long, default_value_t = OutputProfile::default())]
+    pub output_profile: OutputProfile,
+
+    /// Keep the inputs' own timestamps in the output, rather than stamping buffers with the time
+    /// they're pushed at.
+    #[arg(long)]
+    pub appsrc_source_timestamps: bool,
+
+    /// Segment format of the output's appsrcs: `time`, `bytes` or `default`.
+    #[arg(long, value_name = "FORMAT", default_value_t = AppSrcFormat::default())]
+    pub appsrc_format: AppSrcFormat,
+
+    /// Don't mark the output's appsrcs as live sources.
+    #[arg(long)]
+    pub appsrc_not_live: bool,
+
+    /// Extra latency for the output's appsrcs to report, in milliseconds.
+    #[arg(long, value_name = "MS")]
+    pub appsrc_min_latency: Option<u64>,
+
+    /// H.264 encoders to try, in order, e.g. `vah264enc,x264enc`. Defaults to the hardware
+    /// encoders, then x264.
+    #[arg(long = "video-encoder", value_name = "FACTORY", value_delimiter = ',')]
+    pub video_encoders: Vec<String>,
+
+    /// Set a property on an H.264 encoder if it's the one that's used, overriding the defaults,
+    /// e.g. `x264enc.speed-preset=veryfast`. Can be given more than once.
+    #[arg(long = "encoder-property", value_name = "FACTORY.PROPERTY=VALUE")]
+    pub encoder_properties: Vec<EncoderProperty>,
+
+    /// Threads each software encoder may use. Defaults to the encoder's own choice, usually one
+    /// per CPU.
+    #[arg(long, value_name = "N")]
+    pub encode_threads: Option<u32>,
+
+    /// Niceness of the whole process, from -20 to 19 (the nicest).
+    #[arg(long, value_name = "N", allow_negative_numbers = true)]
+    pub nice: Option<i32>,
+
+    /// CPUs the whole process runs on, e.g. `0-3,6`.
+    #[arg(long, value_name = "LIST")]
+    pub cpus: Option<CpuList>,
+
+    /// Niceness of the threads scanning the root directories and discovering files ahead of time.
+    #[arg(long, value_name = "N", allow_negative_numbers = true)]
+    pub scan_nice: Option<i32>,
+
+    /// CPUs the scanning threads run on, e.g. `4-5`.
+    #[arg(long, value_name = "LIST")]
+    pub scan_cpus: Option<CpuList>,
+
+    /// Number of threads walking the root directories. Defaults to one per CPU.
+    #[arg(long, value_name = "N")]
+    pub scan_threads: Option<usize>,
+
+    /// When to deinterlace video files: `auto` (the ones that are interlaced), `always` or
+    /// `never`.
+    #[arg(long, value_name = "MODE", default_value_t = DeinterlaceMode::default())]
+    pub deinterlace: DeinterlaceMode,
+
+    /// How files with a different aspect ratio than the output are fitted to it: `letterbox`
+    /// (black borders), `crop` (fill, cutting off the edges) or `stretch`.
+    #[arg(long, value_name = "POLICY", default_value_t = AspectPolicy::default())]
+    pub aspect: AspectPolicy,
+
+    /// Carry each file's second audio track as a second audio program.
+    #[arg(long)]
+    pub secondary_audio: bool,
+
+    /// Preferred audio languages (ISO 639), most preferred first.
+    #[arg(long = "audio-language", value_delimiter = ',')]
+    pub audio_languages: Vec<String>,
+
+    /// Burn each file's embedded subtitles into the picture, if it has any.
+    #[arg(long)]
+    pub burn_subtitles: bool,
+
+    /// Subtitle languages (ISO 639) for `--burn-subtitles`, most preferred first. Files without
+    /// any of them play without subtitles. Any track is used if not given.
+    #[arg(long = "subtitle-language", value_delimiter = ',', requires = "burn_subtitles")]
+    pub subtitle_languages: Vec<String>,
+
+    /// Burn in `.srt` files next to the files instead of their embedded subtitles, e.g.
+    /// `movie.srt` or `movie.en.srt` for `movie.mkv`.
+    #[arg(long, requires = "burn_subtitles")]
+    pub subtitle_sidecars: bool,
+
+    /// Seconds to show images for, unless they have a duration of their own.
+    #[arg(long, default_value_t = 5)]
+    pub image_duration: u64,
+
+    /// Maximum seconds to play videos and audio with an unknown duration (e.g. live sources) for.
+    /// By default they play until they end.
+    #[arg(long)]
+    pub max_unknown_duration: Option<u64>,
+
+    /// Seconds to wait for a file's media info before giving up on it.
+    #[arg(long, default_value_t = 5)]
+    pub discovery_timeout: u64,
+
+    /// Seconds a file may take to get ready to play (discovery included) before it's quarantined.
+    #[arg(long, default_value_t = 15)]
+    pub prepare_budget: u64,
+
+    /// Also write the session summary printed on shutdown to this file, as JSON.
+    #[arg(long)]
+    pub stats_file: Option<PathBuf>,
+
+    /// Record every file played in this SQLite database, served at `GET /history`. Instances can
+    /// share one, e.g. on a network path, each recording its plays under its stream key.
+    #[arg(long)]
+    pub history_db: Option<PathBuf>,
+
+    /// Don't pick files that any instance sharing the `--history-db` started within this many
+    /// minutes.
+    #[arg(long, value_name = "MINUTES", requires = "history_db")]
+    pub shared_repeat_window: Option<u64>,
+
+    /// Keep files that failed to play out of rotation across restarts, in this SQLite database.
+    /// Entries are served at `GET /quarantine` and can be removed again.
+    #[arg(long)]
+    pub quarantine_db: Option<PathBuf>,
+
+    /// Cache discovered media info in this SQLite database, so files are only probed once.
+    /// Instances sharing a library can share one too, e.g. on a network path.
+    #[arg(long)]
+    pub media_cache: Option<PathBuf>,
+
+    /// Control API URL of another instance sharing the same library (mounted at the same path).
+    /// Files it's playing or has queued aren't picked here.
+    #[arg(long = "peer", value_name = "URL", value_delimiter = ',')]
+    pub peers: Vec<String>,
+
+    /// Listen for an SRT caller on this port. While one is connected, it takes over the output
+    /// from the files.
+    #[arg(long, value_name = "PORT")]
+    pub srt_listen: Option<u16>,
+
+    /// Only accept SRT callers on `--srt-listen` that encrypt with this passphrase (10 to 79
+    /// characters).
+    #[arg(
+        long,
+        env = "Z_STREAM_SRT_LISTEN_PASSPHRASE",
+        hide_env_values = true,
+        value_parser = parse_srt_passphrase
+    )]
+    pub srt_listen_passphrase: Option<String>,
+
+    /// Key length `--srt-listen` callers have to encrypt with, in bytes: 16, 24 or 32. Left to
+    /// the caller if not set.
+    #[arg(long, value_name = "BYTES", requires = "srt_listen_passphrase")]
+    pub srt_listen_pbkeylen: Option<SrtKeyLength>,
+
+    /// Encrypt the SRT output of the bundled mediamtx with this passphrase (10 to 79
+    /// characters), which players then need to read it. The key length is theirs to pick.
+    #[arg(
+        long,
+        env = "Z_STREAM_SRT_PASSPHRASE",
+        hide_env_values = true,
+        value_parser = parse_srt_passphrase,
+        conflicts_with = "no_mediamtx"
+    )]
+    pub srt_passphrase: Option<String>,
+
+    /// Require a token to watch mediamtx's HLS and WebRTC, added to their URLs as `?token=...`.
+    /// Tokens are made at the API's `POST /viewer-tokens` and revoked at
+    /// `DELETE /viewer-tokens/TOKEN`, which need an API token, or anyone could mint their own.
+    /// The other protocols stay open.
+    #[arg(long, conflicts_with = "no_mediamtx", requires = "api_tokens")]
+    pub viewer_tokens: bool,
+
+    /// Accept RTMP publishes to this path of the bundled mediamtx (e.g. `rtmp://host/live` for
+    /// `live`). While something is published there, it takes over the output from the files.
+    #[arg(long, value_name = "PATH", conflicts_with = "srt_listen")]
+    pub rtmp_path: Option<String>,
+
+    /// When a live input takes over: `cut` off the current file, or wait until `after-current`
+    /// has ended.
+    #[arg(long, default_value = "cut")]
+    pub live_transition: LiveTransition,
+
+    /// Only play picked files once they've been approved through the API. Files waiting for
+    /// approval are skipped.
+    #[arg(long)]
+    pub require_approval: bool,
+
+    /// Run every picked file past this shell command first, with the file's path as `$1` and a
+    /// JPEG thumbnail as `$2`. Exiting with 1 vetoes the file. Verdicts are cached in the
+    /// `--media-cache` database.
+    #[arg(long, value_name = "COMMAND")]
+    pub classifier_command: Option<String>,
+
+    /// Like `--classifier-command`, but POSTs the thumbnail to this URL with the file's path in
+    /// the `path` query parameter, expecting `{"allow": bool}` back.
+    #[arg(long, value_name = "URL", conflicts_with = "classifier_command")]
+    pub classifier_url: Option<String>,
+
+    /// Give a file, or every file in a directory, a content rating (e.g. `family`). Files without
+    /// one are `unrated`.
+    #[arg(long = "rating", value_name = "PATH=RATING", value_parser = parse_rating)]
+    pub ratings: Vec<(PathBuf, String)>,
+
+    /// Only play files with these ratings during a daily time window, in local time, e.g.
+    /// `06:00-21:00=family,unrated`. The first matching slot applies, outside of every slot
+    /// anything can play. Slots can be named, e.g. `Movie night@21:00-23:30=adult`.
+    #[arg(long = "rating-slot", value_name = "[NAME@]START-END=RATINGS")]
+    pub rating_slots: Vec<RatingSlot>,
+
+    /// Play this clip first thing when the named rating slot begins, e.g.
+    /// `Movie night=/media/idents/movie-night.mp4`.
+    #[arg(long = "slot-jingle", value_name = "NAME=FILE", value_parser = parse_slot_jingle)]
+    pub slot_jingles: Vec<(String, PathBuf)>,
+
+    /// Channel name shown on slates, as `{channel}`.
+    #[arg(long, default_value = "z-stream")]
+    pub channel_name: String,
+
+    /// Image to render slate text over, instead of black.
+    #[arg(long, value_name = "PATH")]
+    pub slate_background: Option<PathBuf>,
+
+    /// Font of the slate text, as a Pango font description.
+    #[arg(long, default_value = "Sans Bold, 32")]
+    pub slate_font: String,
+
+    /// Text of the slate shown while there's nothing to play. Can use `{channel}`, `{time}` and
+    /// `{next}` (the file coming up), `\n` starts a new line.
+    #[arg(long, value_name = "TEMPLATE")]
+    pub standby_text: Option<String>,
+
+    /// Text of the sign-off slate, see `--standby-text`.
+    #[arg(long, value_name = "TEMPLATE")]
+    pub sign_off_text: Option<String>,
+
+    /// Text of the pause slate, see `--standby-text`.
+    #[arg(long, value_name = "TEMPLATE")]
+    pub pause_text: Option<String>,
+
+    /// Count down to named rating slots on a slate between files, once they begin within this
+    /// many seconds.
+    #[arg(long, value_name = "SECONDS")]
+    pub countdown: Option<u64>,
+
+    /// Show the output of this shell command in a corner of the frame, e.g.
+    /// `top-left=curl -s wttr.in?format=3`. Slots are top-left, top, top-right, bottom-left,
+    /// bottom and bottom-right.
+    #[arg(long = "data-overlay", value_name = "SLOT=COMMAND", value_parser = parse_data_overlay)]
+    pub data_overlay_commands: Vec<(OverlaySlot, String)>,
+
+    /// Like `--data-overlay`, but shows what this URL returns.
+    #[arg(long = "data-overlay-url", value_name = "SLOT=URL", value_parser = parse_data_overlay)]
+    pub data_overlay_urls: Vec<(OverlaySlot, String)>,
+
+    /// Seconds between data overlay updates.
+    #[arg(long, default_value_t = 300)]
+    pub data_overlay_interval: u64,
+
+    /// Show the capture date and location of photos in this corner, taken from their EXIF tags.
+    #[arg(long, value_name = "SLOT")]
+    pub photo_info: Option<OverlaySlot>,
+
+    /// What the title overlay shows, e.g. "{title} ({year}) - {elapsed}/{duration}". It can use
+    /// {title}, {year}, {artist}, {file}, {path}, {elapsed} and {duration}, and can be changed
+    /// through the API. Shows the file's path by default.
+    #[arg(long, value_name = "TEMPLATE")]
+    pub title_template: Option<String>,
+
+    /// Zoom and pan slowly over photos, like a slideshow, instead of showing them still.
+    #[arg(long)]
+    pub ken_burns: bool,
+
+    /// How far `--ken-burns` zooms in, in percent.
+    #[arg(long, value_name = "PERCENT", default_value_t = 15, requires = "ken_burns")]
+    pub ken_burns_zoom: u32,
+
+    /// Draw the sound of audio-only files as their video, instead of showing their cover art:
+    /// `wave` or `spectrum`.
+    #[arg(long, value_name = "KIND")]
+    pub visualizer: Option<Visualizer>,
+
+    /// Bring every file to about the same loudness, so quiet and loud files play at a
+    /// consistent volume. Uses `audioloudnorm` if it's installed, ReplayGain tags otherwise.
+    #[arg(long)]
+    pub normalize_loudness: bool,
+
+    /// The loudness `--normalize-loudness` aims for, in LUFS.
+    #[arg(
+        long,
+        value_name = "LUFS",
+        default_value_t = -23,
+        allow_negative_numbers = true,
+        requires = "normalize_loudness"
+    )]
+    pub loudness_target: i32,
+
+    /// Sound to play over the start of every item.
+    #[arg(long, value_name = "FILE")]
+    pub sting: Option<PathBuf>,
+
+    /// Volume of the program audio while a sting plays, in percent.
+    #[arg(long, value_name = "PERCENT", default_value_t = 30)]
+    pub sting_duck: u8,
+
+    /// Change the picture between items with a transition instead of a cut: `dissolve`,
+    /// `fade-black` or `wipe`.
+    #[arg(long, value_name = "KIND")]
+    pub transition: Option<TransitionKind>,
+
+    /// How long a `--transition` takes, in milliseconds.
+    #[arg(long, value_name = "MS", default_value_t = 1000, requires = "transition")]
+    pub transition_duration: u64,
+
+    /// Also serve the output at this size and bitrate, as `WIDTHxHEIGHT:KBPS`, at the stream key
+    /// with `_HEIGHT` appended, e.g. `1280x720:3000` at `my_stream_720`. Can be given more than
+    /// once.
+    #[arg(long = "rendition", value_name = "WIDTHxHEIGHT:KBPS", value_delimiter = ',')]
+    pub renditions: Vec<RenditionOptions>,
+
+    /// Also serve a low framerate MJPEG copy of the program at `/mjpeg` on the API port, for
+    /// devices that can't play anything else.
+    #[arg(long)]
+    pub mjpeg: bool,
+
+    /// Frames per second of the MJPEG feed.
+    #[arg(long, value_name = "FPS", default_value_t = 2, requires = "mjpeg")]
+    pub mjpeg_fps: u32,
+
+    /// Width of the MJPEG feed, the height keeps the canvas' aspect ratio.
+    #[arg(long, value_name = "PIXELS", default_value_t = 640, requires = "mjpeg")]
+    pub mjpeg_width: u32,
+
+    /// Also serve the program over WHEP on this port, for browsers to play with sub-second
+    /// latency without mediamtx. Needs the `whepserversink` element (gst-plugins-rs).
+    #[arg(long, value_name = "PORT")]
+    pub whep_port: Option<u16>,
+
+    /// Play the output over RTSP in the background, and report when its video or audio stops
+    /// coming through.
+    #[arg(long)]
+    pub monitor: bool,
+
+    /// How long the monitor waits for video or audio before reporting the output as unhealthy, in
+    /// seconds.
+    #[arg(long, value_name = "SECS", default_value_t = 10, requires = "monitor")]
+    pub monitor_stall_timeout: u64,
+
+    /// Report the server as unhealthy at `GET /healthz` when more files than this fail to
+    /// preroll within a minute. With `--monitor`, its video or audio stalling does as well.
+    #[arg(long, value_name = "COUNT")]
+    pub max_preroll_failures: Option<u32>,
+
+    /// POST the health report as JSON to this URL whenever the server turns unhealthy, or
+    /// healthy again.
+    #[arg(long, value_name = "URL")]
+    pub alert_webhook: Option<String>,
+
+    /// Warn when the disk holding the download, upload or database directories runs low, and
+    /// delete the oldest downloads to make room.
+    #[arg(long)]
+    pub disk_monitor: bool,
+
+    /// Free space the disk monitor warns below, in megabytes.
+    #[arg(long, value_name = "MB", default_value_t = 2000, requires = "disk_monitor")]
+    pub disk_warn_below: u64,
+
+    /// Free space the disk monitor deletes the oldest downloads to get back to, in megabytes.
+    #[arg(long, value_name = "MB", default_value_t = 1000, requires = "disk_monitor")]
+    pub disk_prune_below: u64,
+
+    /// Text of the countdown slate, see `--standby-text`. Can also use `{show}` and
+    /// `{countdown}`.
+    #[arg(long, value_name = "TEMPLATE")]
+    pub countdown_text: Option<String>,
+
+    /// Show a desktop notification when a file starts playing or fails, the output goes
+    /// unhealthy, the disk runs low or a root directory can't be read.
+    #[arg(long)]
+    pub notify: bool,
+
+    /// Run this shell command when a file starts playing or fails, the output goes unhealthy, the
+    /// disk runs low or a root directory can't be read, with the event JSON on stdin.
+    #[arg(long, value_name = "COMMAND")]
+    pub on_event: Option<String>,
+
+    /// Launch ffplay against the stream and exit after it closes (development helper).
+    #[arg(long, hide = true)]
+    pub test: bool,
+}
+
+fn parse_rating(value: &str) -> Result<(PathBuf, String), String> {
+    let (path, rating) = value.rsplit_once('=').ok_or("expected PATH=RATING")?;
+    let rating = rating.trim();
+    if rating.is_empty() {
+        return Err("empty rating".to_string());
+    }
+    Ok((PathBuf::from(path), rating.to_string()))
+}
+
+fn parse_slot_jingle(value: &str) -> Result<(String, PathBuf), String> {
+    let (name, file) = value.split_once('=').ok_or("expected NAME=FILE")?;
+    Ok((name.trim().to_string(), PathBuf::from(file)))
+}
+
+fn parse_srt_passphrase(value: &str) -> Result<String, String> {
+    let length = value.chars().count();
+    if !SrtEncryption::PASSPHRASE_LENGTH.contains(&length) {
+        return Err(format!("expected 10 to 79 characters, got {length}"));
+    }
+    Ok(value.to_string())
+}
+
+fn parse_data_overlay(value: &str) -> Result<(OverlaySlot, String), String> {
+    let (slot, source) = value.split_once('=').ok_or("expected SLOT=SOURCE")?;
+    Ok((slot.trim().parse()?, source.to_string()))
+}
+
+impl ServeArgs {
+    pub fn resource_limits(&self) -> ResourceLimits {
+        ResourceLimits {
+            nice: self.nice,
+            cpus: self.cpus.clone(),
+            scan_nice: self.scan_nice,
+            scan_cpus: self.scan_cpus.clone(),
+            scan_threads: self.scan_threads,
+        }
+    }
+
+    pub fn mediamtx_ports(&self) -> mediamtx::Ports {
+        mediamtx::Ports {
+            rtsp: self.mediamtx_rtsp_port,
+            rtmp: self.mediamtx_rtmp_port,
+            hls: self.mediamtx_hls_port,
+            webrtc: self.mediamtx_webrtc_port,
+            srt: self.mediamtx_srt_port,
+        }
+    }
+
+    pub fn event_hook(&self) -> EventHook {
+        EventHook { desktop_notifications: self.notify, command: self.on_event.clone() }
+    }
+
+    pub fn health(&self) -> HealthOptions {
+        HealthOptions {
+            max_preroll_failures_per_minute: self.max_preroll_failures,
+            alert_webhook: self.alert_webhook.clone(),
+        }
+    }
+
+    pub fn live_source(&self) -> Option<LiveSource> {
+        if let Some(port) = self.srt_listen {
+            let encryption = self.srt_listen_passphrase.clone().map(|passphrase| SrtEncryption {
+                passphrase,
+                key_length: self.srt_listen_pbkeylen,
+            });
+            return Some(LiveSource::Srt { port, encryption });
+        }
+        let mediamtx_port = self.mediamtx_rtsp_port;
+        self.rtmp_path.clone().map(|path| LiveSource::Rtmp { path, mediamtx_port })
+    }
+
+    pub fn classifier(&self) -> Option<ContentClassifier> {
+        let command = self.classifier_command.clone().map(ContentClassifier::Command);
+        command.or_else(|| self.classifier_url.clone().map(ContentClassifier::Http))
+    }
+
+    /// Whether there's nothing to play from, so a test card is shown instead.
+    pub fn is_demo(&self) -> bool {
+        self.root_dirs.is_empty() && self.leader.is_none()
+    }
+
+    pub fn slate_options(&self) -> SlateOptions {
+        const DEMO_STANDBY: &str = "{channel}\nNo root directories given\n\
+            Add some to the command line or with POST /roots\n{time}";
+
+        let defaults = SlateOptions::default();
+        let default_standby =
+            if self.is_demo() { DEMO_STANDBY.to_string() } else { defaults.standby };
+        let template = |text: &Option<String>, default: String| {
+            text.as_ref().map_or(default, |text| text.replace("\\n", "\n"))
+        };
+        SlateOptions {
+            channel_name: self.channel_name.clone(),
+            background: self.slate_background.clone(),
+            test_card: self.is_demo(),
+            font: self.slate_font.clone(),
+            standby: template(&self.standby_text, default_standby),
+            sign_off: template(&self.sign_off_text, defaults.sign_off),
+            pause: template(&self.pause_text, defaults.pause),
+            countdown: template(&self.countdown_text, defaults.countdown),
+            countdown_lead: self.countdown.map(Duration::from_secs),
+        }
+    }
+
+    pub fn rating_policy(&self) -> RatingPolicy {
+        let mut slots = self.rating_slots.clone();
+        for (name, file) in &self.slot_jingles {
+            let slot = slots.iter_mut().find(|slot| slot.name.as_ref() == Some(name));
+            match slot {
+                Some(slot) => slot.jingle = Some(file.clone()),
+                None => eprintln!("Warning: no rating slot named {name:?} for its jingle"),
+            }
+        }
+        RatingPolicy { ratings: self.ratings.clone(), slots }
+    }
+
+    pub fn data_overlays(&self) -> Vec<DataOverlayOptions> {
+        let commands = self
+            .data_overlay_commands
+            .iter()
+            .map(|(slot, command)| (*slot, DataSource::Command(command.clone())));
+        let urls = self
+            .data_overlay_urls
+            .iter()
+            .map(|(slot, url)| (*slot, DataSource::Url(url.clone())));
+        let interval = Duration::from_secs(self.data_overlay_interval);
+        commands
+            .chain(urls)
+            .map(|(slot, source)| DataOverlayOptions { slot, source, interval })
+            .collect()
+    }
+
+    pub fn stream_options(&self) -> StreamOptions {
+        StreamOptions {
+            video: VideoOptions {
+                bitrate_kbps: self.video_bitrate,
+                rate_control: self.rate_control,
+                keyframe_interval: self.keyframe_interval,
+                ..self.video
+            },
+            audio: AudioOptions {
+                sample_rate: self.audio_rate,
+                channels: self.audio_channels,
+                bitrate_kbps: self.audio_bitrate,
+                chunk_ms: self.audio_chunk_ms,
+            },
+            output_profile: self.output_profile,
+            appsrc: AppSrcPolicy {
+                do_timestamp: !self.appsrc_source_timestamps,
+                format: self.appsrc_format,
+                is_live: !self.appsrc_not_live,
+                min_latency: self.appsrc_min_latency.map(gstreamer::ClockTime::from_mseconds),
+            },
+            encoders: EncoderOptions {
+                priority: if self.video_encoders.is_empty() {
+                    EncoderOptions::default().priority
+                } else {
+                    self.video_encoders.clone()
+                },
+                properties: self.encoder_properties.clone(),
+                threads: self.encode_threads,
+            },
+            deinterlace: self.deinterlace,
+            aspect: self.aspect,
+            secondary_audio: if self.secondary_audio {
+                SecondaryAudio::SecondTrack
+            } else {
+                SecondaryAudio::Disabled
+            },
+            audio_languages: self.audio_languages.clone(),
+            play_duration: PlayDurationPolicy {
+                image_hold: gstreamer::ClockTime::from_seconds(self.image_duration),
+                unknown_max: self.max_unknown_duration.map(gstreamer::ClockTime::from_seconds),
+            },
+            prepare: PreparePolicy {
+                discovery_timeout: gstreamer::ClockTime::from_seconds(self.discovery_timeout),
+                budget: gstreamer::ClockTime::from_seconds(self.prepare_budget),
+            },
+            peers: self.peers.clone(),
+            shared_repeat_window: self
+                .shared_repeat_window
+                .map(|minutes| Duration::from_secs(minutes * 60)),
+            leader: self.leader.clone(),
+            files: self.filter.file_filter(),
+            root_weights: self.filter.root_weights.clone(),
+            cooldown: self.filter.cooldown(),
+            shuffle: if self.no_repeat {
+                Shuffle::NoRepeat { state_db: self.shuffle_state.clone() }
+            } else {
+                Shuffle::Random
+            },
+            live_input: self
+                .live_source()
+                .map(|source| LiveInputOptions { source, transition: self.live_transition }),
+            require_approval: self.require_approval,
+            classifier: self.classifier(),
+            ratings: self.rating_policy(),
+            slates: self.slate_options(),
+            data_overlays: self.data_overlays(),
+            photo_info: self.photo_info,
+            title_template: self.title_template.clone(),
+            ken_burns: self
+                .ken_burns
+                .then_some(KenBurnsOptions { zoom_percent: self.ken_burns_zoom }),
+            visualizer: self.visualizer,
+            subtitles: self.burn_subtitles.then(|| SubtitleOptions {
+                languages: self.subtitle_languages.clone(),
+                sidecars: self.subtitle_sidecars,
+            }),
+            loudness: self
+                .normalize_loudness
+                .then_some(LoudnessOptions { target_lufs: self.loudness_target }),
+            sting: self
+                .sting
+                .clone()
+                .map(|file| StingOptions { file, duck_percent: self.sting_duck }),
+            transition: self.transition.map(|kind| TransitionOptions {
+                kind,
+                duration: Duration::from_millis(self.transition_duration),
+            }),
+            mjpeg: self.mjpeg.then(|| self.mjpeg_options()),
+            renditions: self.renditions.clone(),
+            monitor: self.monitor.then(|| MonitorOptions {
+                stall_timeout: Duration::from_secs(self.monitor_stall_timeout),
+            }),
+            whep: self.whep_port.map(|port| WhepOptions { port }),
+        }
+    }
+
+    fn mjpeg_options(&self) -> MjpegOptions {
+        // JPEG encoders want even sizes
+        let width = self.mjpeg_width.max(2) & !1;
+        let height = (u64::from(width) * u64::from(self.video.height) / u64::from(self.video.width))
+            as u32
+            & !1;
+        MjpegOptions {
+            width,
+            height: height.max(2),
+            framerate: self.mjpeg_fps.max(1),
+            ..MjpegOptions::default()
+        }
+    }
+}
+
+/// Which files in the root directories can be picked, and how often.
+#[derive(Debug, Args)]
+pub struct FilterArgs {
+    /// Only pick files with these extensions.
+    #[arg(long = "include-ext", value_name = "EXT", value_delimiter = ',')]
+    pub include_extensions: Vec<String>,
+
+    /// Never pick files with these extensions. Replaces the built-in list of non-media extensions
+    /// (subtitles, .nfo, .txt, archives, ...).
+    #[arg(long = "exclude-ext", value_name = "EXT", value_delimiter = ',')]
+    pub exclude_extensions: Option<Vec<String>>,
+
+    /// Only pick files whose path matches one of these globs, e.g. `**/Season */*`.
+    #[arg(long = "include-glob", value_name = "GLOB")]
+    pub include_globs: Vec<String>,
+
+    /// Never pick files whose path matches one of these globs, e.g. `**/*sample*`.
+    #[arg(long = "exclude-glob", value_name = "GLOB")]
+    pub exclude_globs: Vec<String>,
+
+    /// Don't look inside directories (or at files) matching this glob, relative to the root
+    /// directory, e.g. `**/extras/**`. Adds to the built-in list (`@eaDir`, recycle bins, ...).
+    #[arg(long = "ignore", value_name = "GLOB")]
+    pub ignore_paths: Vec<String>,
+
+    /// Also pick files in hidden directories, and hidden files.
+    #[arg(long)]
+    pub include_hidden: bool,
+
+    /// Weight of a root directory as `PATH=WEIGHT`, e.g. `/media/bumpers=5`. Once any root has a
+    /// weight, roots are picked from in proportion to their weights (1 if not given) rather than
+    /// to how many files they have.
+    #[arg(long = "root-weight", value_name = "PATH=WEIGHT", value_parser = parse_root_weight)]
+    pub root_weights: Vec<(PathBuf, u32)>,
+
+    /// Don't pick a file again until this many others have been picked.
+    #[arg(long, value_name = "COUNT")]
+    pub cooldown: Option<usize>,
+
+    /// Don't pick a file again until this many seconds after it was picked.
+    #[arg(long, value_name = "SECONDS", conflicts_with = "cooldown")]
+    pub cooldown_secs: Option<u64>,
+}
+
+fn parse_root_weight(value: &str) -> Result<(PathBuf, u32), String> {
+    let (path, weight) = value.rsplit_once('=').ok_or("expected PATH=WEIGHT")?;
+    let weight = weight.trim().parse().map_err(|_| format!("invalid weight {weight:?}"))?;
+    Ok((PathBuf::from(path), weight))
+}
+
+impl FilterArgs {
+    pub fn file_filter(&self) -> FileFilter {
+        let mut ignore_paths = FileFilter::default().ignore_paths;
+        ignore_paths.extend(self.ignore_paths.iter().cloned());
+        FileFilter {
+            include_extensions: self.include_extensions.clone(),
+            exclude_extensions: self
+                .exclude_extensions
+                .clone()
+                .unwrap_or_else(|| FileFilter::default().exclude_extensions),
+            include_globs: self.include_globs.clone(),
+            exclude_globs: self.exclude_globs.clone(),
+            ignore_paths,
+            skip_hidden: !self.include_hidden,
+        }
+    }
+
+    pub fn cooldown(&self) -> Option<Cooldown> {
+        let duration = self.cooldown_secs.map(Duration::from_secs).map(Cooldown::Duration);
+        self.cooldown.map(Cooldown::Count).or(duration)
+    }
+
+    pub fn random_files(&self, root_dirs: &[PathBuf]) -> Result<RandomFiles, globset::Error> {
+        let mut files = RandomFiles::new(root_dirs)
+            .with_matcher(self.file_filter().compile()?)
+            .with_weights(self.root_weights.clone());
+        if let Some(cooldown) = self.cooldown() {
+            files = files.with_cooldown(cooldown);
+        }
+        Ok(files)
+    }
+}
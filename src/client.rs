@@ -0,0 +1,173 @@
+use std::io::BufRead;
+use std::path::Path;
+
+use crate::stream::SlateKind;
+
+/// A blocking client for the HTTP control API, for `z-streamctl` and the TUI.
+#[derive(Debug, Clone)]
+pub struct ApiClient {
+    base_url: String,
+    token: Option<String>,
+}
+
+impl ApiClient {
+    /// `token` is only sent with requests that change anything.
+    pub fn new(base_url: impl Into<String>, token: Option<String>) -> Self {
+        let base_url = base_url.into().trim_end_matches('/').to_string();
+        Self { base_url, token }
+    }
+
+    pub fn status(&self) -> Result<serde_json::Value, ureq::Error> {
+        self.get_json("/status")
+    }
+
+    pub fn stats(&self) -> Result<serde_json::Value, ureq::Error> {
+        self.get_json("/stats")
+    }
+
+    pub fn skip(&self, graceful: bool) -> Result<(), ureq::Error> {
+        self.post(&format!("/skip?graceful={graceful}")).send_empty()?;
+        Ok(())
+    }
+
+    pub fn play_next(&self, path: &Path) -> Result<(), ureq::Error> {
+        self.post_path("/next", path)
+    }
+
+    /// Adds a file to the end of the queue.
+    pub fn enqueue(&self, path: &Path) -> Result<(), ureq::Error> {
+        self.post_path("/enqueue", path)
+    }
+
+    /// Files waiting for approval.
+    pub fn approvals(&self) -> Result<serde_json::Value, ureq::Error> {
+        self.get_json("/approvals")
+    }
+
+    pub fn approve(&self, path: &Path) -> Result<(), ureq::Error> {
+        self.post_path("/approvals/approve", path)
+    }
+
+    pub fn reject(&self, path: &Path) -> Result<(), ureq::Error> {
+        self.post_path("/approvals/reject", path)
+    }
+
+    pub fn freeze(&self, mute_audio: bool) -> Result<(), ureq::Error> {
+        self.post(&format!("/freeze?mute={mute_audio}")).send_empty()?;
+        Ok(())
+    }
+
+    pub fn unfreeze(&self) -> Result<(), ureq::Error> {
+        self.post("/unfreeze").send_empty()?;
+        Ok(())
+    }
+
+    /// Cuts to a slate until [`resume`](Self::resume).
+    pub fn hold(&self, slate: SlateKind) -> Result<(), ureq::Error> {
+        self.post("/hold").query("slate", slate.to_string()).send_empty()?;
+        Ok(())
+    }
+
+    pub fn resume(&self) -> Result<(), ureq::Error> {
+        self.post("/resume").send_empty()?;
+        Ok(())
+    }
+
+    /// Re-encodes the output at a different size and/or bitrate (in kbit/s), `None` keeps what's
+    /// in use now.
+    pub fn set_video_profile(
+        &self,
+        size: Option<(u32, u32)>,
+        bitrate_kbps: Option<u32>,
+    ) -> Result<(), ureq::Error> {
+        let mut request = self.post("/video-profile");
+        if let Some((width, height)) = size {
+            request = request.query("width", width.to_string()).query("height", height.to_string());
+        }
+        if let Some(bitrate_kbps) = bitrate_kbps {
+            request = request.query("bitrate", bitrate_kbps.to_string());
+        }
+        request.send_empty()?;
+        Ok(())
+    }
+
+    /// Files that failed to play and won't be picked again.
+    pub fn quarantine(&self) -> Result<serde_json::Value, ureq::Error> {
+        self.get_json("/quarantine")
+    }
+
+    /// Lets a quarantined file be picked again, `id` is from [`quarantine`](Self::quarantine).
+    pub fn unquarantine(&self, id: i64) -> Result<(), ureq::Error> {
+        self.delete(&format!("/quarantine/{id}")).call()?;
+        Ok(())
+    }
+
+    /// Follows the event stream, calling `on_event` with the JSON of each event until the
+    /// connection drops. With `history`, the recent events the server still keeps come first.
+    pub fn follow_events(
+        &self,
+        history: bool,
+        on_event: impl FnMut(&str),
+    ) -> Result<(), ureq::Error> {
+        self.read_events(true, history, on_event)
+    }
+
+    /// Calls `on_event` with the JSON of each of the recent events the server still keeps.
+    pub fn recent_events(&self, on_event: impl FnMut(&str)) -> Result<(), ureq::Error> {
+        self.read_events(false, true, on_event)
+    }
+
+    fn read_events(
+        &self,
+        follow: bool,
+        history: bool,
+        mut on_event: impl FnMut(&str),
+    ) -> Result<(), ureq::Error> {
+        let mut request = ureq::get(self.url("/events"))
+            .query("follow", follow.to_string())
+            .header("Accept", "text/event-stream");
+        if history {
+            // Ids start at 1, so this asks for everything that's still kept
+            request = request.header("Last-Event-ID", "0");
+        }
+        let response = request.call()?;
+        let reader = std::io::BufReader::new(response.into_body().into_reader());
+        for line in reader.lines() {
+            if let Some(data) = line?.strip_prefix("data:") {
+                on_event(data.trim());
+            }
+        }
+        Ok(())
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.base_url)
+    }
+
+    fn get_json(&self, path: &str) -> Result<serde_json::Value, ureq::Error> {
+        ureq::get(self.url(path)).call()?.body_mut().read_json()
+    }
+
+    /// Posts `{"path": ...}`.
+    fn post_path(&self, endpoint: &str, path: &Path) -> Result<(), ureq::Error> {
+        let body = serde_json::json!({ "path": path.to_string_lossy() }).to_string();
+        self.post(endpoint).header("Content-Type", "application/json").send(body)?;
+        Ok(())
+    }
+
+    fn post(&self, path: &str) -> ureq::RequestBuilder<ureq::typestate::WithBody> {
+        let request = ureq::post(self.url(path));
+        match &self.token {
+            Some(token) => request.header("Authorization", format!("Bearer {token}")),
+            None => request,
+        }
+    }
+
+    fn delete(&self, path: &str) -> ureq::RequestBuilder<ureq::typestate::WithoutBody> {
+        let request = ureq::delete(self.url(path));
+        match &self.token {
+            Some(token) => request.header("Authorization", format!("Bearer {token}")),
+            None => request,
+        }
+    }
+}
@@ -0,0 +1,379 @@
+//! Config files for `serve`: TOML tables of its command line options. Keys are the long flag
+//! names, e.g. `video-bitrate = 4000` for `--video-bitrate 4000`, flags that can be given more
+//! than once take arrays, and `root-dirs` holds the directories.
+//!
+//! `export-config` and `import-config` move a config file to another machine together with the
+//! quarantine, see [`Bundle`].
+
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+use z_stream::stream::Quarantine;
+
+use crate::cli::{Cli, CliCommand, ServeArgs};
+
+/// Options that are still accepted, but shouldn't be in a config file any more, and why.
+const DEPRECATED: &[(&str, &str)] =
+    &[("test", "it's a development helper, it kills every mediamtx and exits once ffplay does")];
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Failed to read {}: {error}", path.display())]
+    Read { path: PathBuf, error: std::io::Error },
+
+    #[error("Invalid TOML: {0}")]
+    Toml(#[from] toml::de::Error),
+
+    #[error("{key}: expected a string, number or boolean, or an array of them")]
+    InvalidValue { key: String },
+
+    #[error("{}", .0.to_string().trim_start_matches("error: ").trim())]
+    Options(#[from] clap::Error),
+
+    #[error("Failed to write {}: {error}", path.display())]
+    Write { path: PathBuf, error: std::io::Error },
+
+    #[error("Failed to write TOML: {0}")]
+    Serialize(#[from] toml::ser::Error),
+
+    #[error("Quarantine database: {0}")]
+    Quarantine(#[from] rusqlite::Error),
+}
+
+/// What `export-config` writes: a config file and the files its quarantine database keeps out,
+/// as one TOML file. Ratings and rating slots are options, so they're part of the config, and
+/// per-file settings are sidecars that move with the media.
+///
+/// Files the config names, like the sting, the slate background or the root directories, aren't
+/// in it. `import-config` checks the imported config, so paths that don't exist on the new machine
+/// are reported.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct Bundle {
+    config: toml::Table,
+    #[serde(default)]
+    quarantine: Vec<QuarantinedFile>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct QuarantinedFile {
+    path: PathBuf,
+    reason: String,
+}
+
+/// The command line with the config file of `serve --config FILE` spliced in ahead of the other
+/// options, so they win over the file.
+pub fn expand_args(args: Vec<OsString>) -> Result<Vec<OsString>, Error> {
+    if args.get(1).is_none_or(|command| command != "serve") {
+        return Ok(args);
+    }
+    let config = args.iter().enumerate().find_map(|(index, arg)| {
+        let arg = arg.to_str()?;
+        match arg.strip_prefix("--config=") {
+            Some(path) => Some(PathBuf::from(path)),
+            None if arg == "--config" => args.get(index + 1).map(PathBuf::from),
+            None => None,
+        }
+    });
+    let Some(config) = config else { return Ok(args) };
+
+    let mut expanded = args[..2].to_vec();
+    expanded.extend(to_args(&read(&config)?)?);
+    expanded.extend_from_slice(&args[2..]);
+    Ok(expanded)
+}
+
+fn read(path: &Path) -> Result<toml::Table, Error> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|error| Error::Read { path: path.to_path_buf(), error })?;
+    Ok(text.parse()?)
+}
+
+/// `serve` arguments for the options in `table`.
+fn to_args(table: &toml::Table) -> Result<Vec<OsString>, Error> {
+    let mut args: Vec<OsString> = Vec::new();
+    let mut root_dirs: Vec<OsString> = Vec::new();
+    for (key, value) in table {
+        // Also takes the field names, e.g. `video_bitrate`
+        let name = key.replace('_', "-");
+        let values = match value {
+            toml::Value::Array(values) => values.as_slice(),
+            value => std::slice::from_ref(value),
+        };
+        for value in values {
+            let value = match value {
+                toml::Value::String(value) => value.clone(),
+                toml::Value::Integer(value) => value.to_string(),
+                toml::Value::Float(value) => value.to_string(),
+                toml::Value::Boolean(true) if name != "root-dirs" => {
+                    args.push(format!("--{name}").into());
+                    continue;
+                }
+                toml::Value::Boolean(false) if name != "root-dirs" => continue,
+                _ => return Err(Error::InvalidValue { key: key.clone() }),
+            };
+            if name == "root-dirs" {
+                root_dirs.push(value.into());
+            } else {
+                args.push(format!("--{name}={value}").into());
+            }
+        }
+    }
+    args.extend(root_dirs);
+    Ok(args)
+}
+
+/// Parses the options in `table` the way `serve` would.
+fn serve_args(table: &toml::Table) -> Result<ServeArgs, Error> {
+    let command_line = ["z-stream".into(), "serve".into()].into_iter().chain(to_args(table)?);
+    let Cli { command: CliCommand::Serve(args) } = Cli::try_parse_from(command_line)? else {
+        unreachable!("the command line is for serve");
+    };
+    Ok(args)
+}
+
+/// Bundles the config file at `path` with the files its `quarantine-db` keeps out, see
+/// [`Bundle`].
+pub fn export(path: &Path, bundle_path: &Path) -> Result<(), Error> {
+    let config = read(path)?;
+    let quarantine = match serve_args(&config)?.quarantine_db {
+        Some(database) if database.exists() => Quarantine::open(&database)?
+            .entries()
+            .into_iter()
+            .map(|entry| QuarantinedFile { path: entry.path, reason: entry.reason })
+            .collect(),
+        _ => Vec::new(),
+    };
+    let text = toml::to_string(&Bundle { config, quarantine })?;
+    std::fs::write(bundle_path, text)
+        .map_err(|error| Error::Write { path: bundle_path.to_path_buf(), error })
+}
+
+/// Writes the config file of the bundle at `bundle_path` to `path`, which mustn't exist yet, and
+/// adds the bundle's quarantined files to the config's `quarantine-db`.
+pub fn import(bundle_path: &Path, path: &Path) -> Result<(), Error> {
+    let text = std::fs::read_to_string(bundle_path)
+        .map_err(|error| Error::Read { path: bundle_path.to_path_buf(), error })?;
+    let bundle: Bundle = toml::from_str(&text)?;
+    // Before writing anything, so a config `serve` won't take isn't imported
+    let args = serve_args(&bundle.config)?;
+
+    let text = toml::to_string(&bundle.config)?;
+    std::fs::File::create_new(path)
+        .and_then(|mut file| file.write_all(text.as_bytes()))
+        .map_err(|error| Error::Write { path: path.to_path_buf(), error })?;
+
+    match args.quarantine_db {
+        Some(database) => {
+            let quarantine = Quarantine::open(&database)?;
+            for file in bundle.quarantine {
+                quarantine.add(file.path, &file.reason);
+            }
+        }
+        None if !bundle.quarantine.is_empty() => {
+            let count = bundle.quarantine.len();
+            eprintln!(
+                "Warning: the config has no quarantine-db, {count} quarantined files left out"
+            );
+        }
+        None => (),
+    }
+    Ok(())
+}
+
+/// Counts what `check` finds wrong.
+#[derive(Default)]
+struct Report {
+    errors: usize,
+    warnings: usize,
+}
+
+impl Report {
+    fn error(&mut self, message: impl std::fmt::Display) {
+        eprintln!("error: {message}");
+        self.errors += 1;
+    }
+
+    fn warning(&mut self, message: impl std::fmt::Display) {
+        eprintln!("warning: {message}");
+        self.warnings += 1;
+    }
+}
+
+/// Checks the config file at `path` the way `serve` would read it, plus what `serve` would only
+/// notice once it's running: missing files, invalid output settings and ports used twice.
+/// Prints what it finds, and returns whether there were no errors.
+pub fn check(path: &Path) -> bool {
+    let mut report = Report::default();
+    check_file(path, &mut report);
+    match (report.errors, report.warnings) {
+        (0, 0) => println!("{}: OK", path.display()),
+        (errors, warnings) => {
+            println!("{}: {errors} error(s), {warnings} warning(s)", path.display())
+        }
+    }
+    report.errors == 0
+}
+
+fn check_file(path: &Path, report: &mut Report) {
+    let table = match read(path) {
+        Ok(table) => table,
+        Err(error) => return report.error(error),
+    };
+    for (key, reason) in DEPRECATED {
+        if table.contains_key(*key) || table.contains_key(&key.replace('-', "_")) {
+            report.warning(format!("{key} is deprecated, {reason}"));
+        }
+    }
+
+    // The command line parser is the schema, so the file can't accept anything `serve` doesn't
+    let args = match serve_args(&table) {
+        Ok(args) => args,
+        Err(error) => return report.error(error),
+    };
+
+    check_options(&args, report);
+    check_paths(&args, report);
+    check_ports(&args, report);
+}
+
+fn check_options(args: &ServeArgs, report: &mut Report) {
+    let options = args.stream_options();
+    if let Err(error) = options.video.validate() {
+        report.error(error);
+    }
+    if let Err(error) = options.audio.validate() {
+        report.error(error);
+    }
+    for rendition in &options.renditions {
+        if let Err(error) = rendition.video(options.video).validate() {
+            report.error(format!("rendition {rendition}: {error}"));
+        }
+    }
+}
+
+fn check_paths(args: &ServeArgs, report: &mut Report) {
+    let mut inputs: Vec<(&str, &Path)> = Vec::new();
+    inputs.extend(args.root_dirs.iter().map(|path| ("root-dirs", path.as_path())));
+    inputs.extend(args.filter.root_weights.iter().map(|(path, _)| ("root-weight", path.as_path())));
+    inputs.extend(args.ratings.iter().map(|(path, _)| ("rating", path.as_path())));
+    inputs.extend(args.slot_jingles.iter().map(|(_, path)| ("slot-jingle", path.as_path())));
+    inputs.extend(args.slate_background.as_deref().map(|path| ("slate-background", path)));
+    inputs.extend(args.sting.as_deref().map(|path| ("sting", path)));
+    for (key, path) in inputs {
+        if !path.exists() {
+            report.error(format!("{key}: {} doesn't exist", path.display()));
+        }
+    }
+
+    // Created if they don't exist, but not their directories
+    let outputs = [
+        ("history-db", &args.history_db),
+        ("quarantine-db", &args.quarantine_db),
+        ("media-cache", &args.media_cache),
+        ("shuffle-state", &args.shuffle_state),
+        ("stats-file", &args.stats_file),
+    ];
+    for (key, path) in outputs {
+        let Some(path) = path else { continue };
+        let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty());
+        if let Some(parent) = parent
+            && !parent.is_dir()
+        {
+            report.error(format!("{key}: directory {} doesn't exist", parent.display()));
+        }
+    }
+}
+
+fn check_ports(args: &ServeArgs, report: &mut Report) {
+    let mut ports = vec![("rtsp-port", args.rtsp_port), ("api-port", args.api_port)];
+    if !args.no_mediamtx {
+        let mediamtx = args.mediamtx_ports();
+        ports.extend([
+            ("mediamtx-rtsp-port", mediamtx.rtsp),
+            ("mediamtx-rtmp-port", mediamtx.rtmp),
+            ("mediamtx-hls-port", mediamtx.hls),
+            ("mediamtx-webrtc-port", mediamtx.webrtc),
+            ("mediamtx-srt-port", mediamtx.srt),
+        ]);
+    }
+    ports.extend(args.srt_listen.map(|port| ("srt-listen", port)));
+    ports.extend(args.whep_port.map(|port| ("whep-port", port)));
+
+    let mut used: HashMap<u16, &str> = HashMap::new();
+    for (name, port) in ports {
+        match used.get(&port) {
+            Some(other) => report.error(format!("{name} and {other} both use port {port}")),
+            None => _ = used.insert(port, name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(toml: &str) -> Result<Vec<String>, Error> {
+        let args = to_args(&toml.parse().unwrap())?;
+        Ok(args.into_iter().map(|arg| arg.into_string().unwrap()).collect())
+    }
+
+    #[test]
+    fn turns_keys_into_flags() {
+        assert_eq!(args("video-bitrate = 2500").unwrap(), ["--video-bitrate=2500"]);
+        assert_eq!(args("video_bitrate = 2500").unwrap(), ["--video-bitrate=2500"]);
+        assert_eq!(args("gain = -1.5").unwrap(), ["--gain=-1.5"]);
+        assert_eq!(args("title-template = \"{title}\"").unwrap(), ["--title-template={title}"]);
+        assert_eq!(args("api-token = [\"a\", \"b\"]").unwrap(), ["--api-token=a", "--api-token=b"]);
+    }
+
+    #[test]
+    fn booleans_are_plain_flags() {
+        assert_eq!(args("shuffle = true").unwrap(), ["--shuffle"]);
+        assert!(args("shuffle = false").unwrap().is_empty());
+    }
+
+    #[test]
+    fn root_dirs_come_last() {
+        let toml = "root-dirs = [\"/media/a\", \"/media/b\"]\nzoom = 2";
+        assert_eq!(args(toml).unwrap(), ["--zoom=2", "/media/a", "/media/b"]);
+        assert_eq!(args("root_dirs = \"/media/a\"").unwrap(), ["/media/a"]);
+    }
+
+    #[test]
+    fn bundles_the_quarantine() {
+        let dir = tempfile::tempdir().unwrap();
+        let database = dir.path().join("quarantine.db");
+        let config = format!("video-bitrate = 2500\nquarantine-db = {database:?}\n");
+        std::fs::write(dir.path().join("serve.toml"), config).unwrap();
+        Quarantine::open(&database)
+            .unwrap()
+            .add("/media/broken.mkv".into(), "no streams");
+
+        let bundle = dir.path().join("bundle.toml");
+        export(&dir.path().join("serve.toml"), &bundle).unwrap();
+        std::fs::remove_file(&database).unwrap();
+        import(&bundle, &dir.path().join("imported.toml")).unwrap();
+
+        let imported = read(&dir.path().join("imported.toml")).unwrap();
+        assert_eq!(imported, read(&dir.path().join("serve.toml")).unwrap());
+        let entries = Quarantine::open(&database).unwrap().entries();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].path, Path::new("/media/broken.mkv"));
+        assert_eq!(entries[0].reason, "no streams");
+        // Never over an existing config
+        assert!(matches!(
+            import(&bundle, &dir.path().join("imported.toml")),
+            Err(Error::Write { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_other_values() {
+        for toml in ["root-dirs = true", "video = { width = 1280 }", "ports = [[1, 2]]"] {
+            assert!(matches!(args(toml), Err(Error::InvalidValue { .. })), "{toml:?}");
+        }
+    }
+}
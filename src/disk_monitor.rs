@@ -0,0 +1,83 @@
+//! Keeps the directories the server writes to from filling up the disk: it warns when free space
+//! runs low, and deletes the oldest files from directories that only hold temporary ones (e.g.
+//! downloads) to make room.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::stream::Event;
+
+/// How often free space is checked.
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct DiskMonitorOptions {
+    /// Directories whose oldest files are deleted when space runs low, e.g. downloads.
+    pub prune_dirs: Vec<PathBuf>,
+    /// Directories that are only watched, e.g. the ones holding the databases.
+    pub watch_dirs: Vec<PathBuf>,
+    /// Warn when less than this many bytes are free.
+    pub warn_below: u64,
+    /// Delete files from `prune_dirs`, oldest first, until at least this many bytes are free.
+    pub prune_below: u64,
+}
+
+/// Checks free space every so often on a thread of its own, sending [`Event::DiskSpaceLow`] when
+/// a directory drops below the warning threshold and [`Event::DiskSpaceOk`] once it's back above.
+pub fn start_disk_monitor(options: DiskMonitorOptions, event_tx: flume::Sender<Event>) {
+    std::thread::spawn(move || {
+        let mut low = HashSet::new();
+        loop {
+            for dir in &options.prune_dirs {
+                prune(dir, options.prune_below);
+            }
+            for dir in options.prune_dirs.iter().chain(&options.watch_dirs) {
+                // Directories that aren't there yet have nothing in them to fill the disk
+                let Ok(free_bytes) = fs2::available_space(dir) else { continue };
+                if free_bytes < options.warn_below {
+                    if low.insert(dir.clone()) {
+                        let free_mb = free_bytes / 1_000_000;
+                        eprintln!("Low disk space: {free_mb} MB free for {}", dir.display());
+                        _ = event_tx.send(Event::DiskSpaceLow { dir: dir.clone(), free_bytes });
+                    }
+                } else if low.remove(dir) {
+                    println!("Enough disk space for {} again", dir.display());
+                    _ = event_tx.send(Event::DiskSpaceOk { dir: dir.clone() });
+                }
+            }
+            std::thread::sleep(CHECK_INTERVAL);
+        }
+    });
+}
+
+/// Deletes the oldest files in `dir` until `min_free` bytes are free, or there are none left.
+fn prune(dir: &Path, min_free: u64) {
+    let has_room = || !fs2::available_space(dir).is_ok_and(|free| free < min_free);
+    if has_room() {
+        return;
+    }
+
+    let Ok(entries) = std::fs::read_dir(dir) else { return };
+    let mut files: Vec<(SystemTime, PathBuf)> = entries
+        .flatten()
+        // Hidden and partial files are still being written
+        .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+        .filter(|entry| entry.path().extension().is_none_or(|extension| extension != "part"))
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok().filter(|metadata| metadata.is_file())?;
+            Some((metadata.modified().ok()?, entry.path()))
+        })
+        .collect();
+    files.sort();
+
+    for (_, path) in files {
+        match std::fs::remove_file(&path) {
+            Ok(()) => println!("Removed {} to free up disk space", path.display()),
+            Err(error) => eprintln!("Failed to remove {}: {error}", path.display()),
+        }
+        if has_room() {
+            break;
+        }
+    }
+}
@@ -0,0 +1,155 @@
+//! Playing videos from the web: a downloader like yt-dlp fetches them into a directory of their
+//! own, and they're queued to play next once they're there.
+
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Command as Process, Stdio};
+use std::time::{Duration, SystemTime};
+
+use crate::stream::{Command, Event};
+
+/// How long downloads are kept, older ones are deleted when the next download starts.
+const KEEP_FOR: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    #[error("The downloader failed: {0}")]
+    Failed(String),
+
+    #[error("The downloader didn't say where it saved the file")]
+    NoFile,
+}
+
+/// Where and how web videos are downloaded.
+#[derive(Debug, Clone)]
+pub struct Downloader {
+    /// yt-dlp, or something that takes the same options.
+    pub program: PathBuf,
+    /// Where downloads go, created if it doesn't exist.
+    pub dir: PathBuf,
+}
+
+impl Downloader {
+    /// Downloads `url` on a thread of its own, with [`Event::DownloadProgress`] along the way,
+    /// and queues the file to play next once it's there.
+    pub fn start(
+        &self,
+        url: String,
+        command_tx: flume::Sender<Command>,
+        event_tx: flume::Sender<Event>,
+    ) {
+        let downloader = self.clone();
+        std::thread::spawn(move || {
+            println!("Downloading {url}");
+            let progress = Progress { event_tx: event_tx.clone(), url: url.clone(), shown: None };
+            match downloader.download(&url, progress) {
+                Ok(path) => {
+                    println!("Downloaded {url} to {}", path.display());
+                    _ = event_tx.send(Event::Downloaded { url, path: path.clone() });
+                    _ = command_tx.send(Command::PlayNext { path });
+                }
+                Err(error) => {
+                    eprintln!("Failed to download {url}: {error}");
+                    _ = event_tx.send(Event::DownloadFailed { url, reason: error.to_string() });
+                }
+            }
+        });
+    }
+
+    fn download(&self, url: &str, mut progress: Progress) -> Result<PathBuf, Error> {
+        std::fs::create_dir_all(&self.dir)?;
+        remove_old_downloads(&self.dir);
+
+        let mut child = Process::new(&self.program)
+            .args(["--no-playlist", "--no-mtime", "--newline", "--progress"])
+            .arg("--paths")
+            .arg(&self.dir)
+            .args(["--output", "%(title).100B [%(id)s].%(ext)s"])
+            // Where the file ended up, once it's finished
+            .args(["--print", "after_move:filepath"])
+            .arg("--")
+            .arg(url)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        // Progress can be on either, depending on the version
+        progress.report(0);
+        let stderr = BufReader::new(child.stderr.take().unwrap());
+        let mut stderr_progress = progress.clone();
+        let stderr_thread = std::thread::spawn(move || {
+            let mut last_line = None;
+            for line in stderr.lines().map_while(Result::ok) {
+                if let Some(percent) = progress_percent(&line) {
+                    stderr_progress.report(percent);
+                } else if !line.trim().is_empty() {
+                    last_line = Some(line);
+                }
+            }
+            last_line
+        });
+
+        let mut path = None;
+        for line in BufReader::new(child.stdout.take().unwrap()).lines() {
+            let line = line?;
+            match progress_percent(&line) {
+                Some(percent) => progress.report(percent),
+                None if Path::new(line.trim()).is_file() => path = Some(PathBuf::from(line.trim())),
+                None => (),
+            }
+        }
+
+        let status = child.wait()?;
+        let last_error = stderr_thread.join().ok().flatten();
+        if !status.success() {
+            return Err(Error::Failed(last_error.unwrap_or_else(|| status.to_string())));
+        }
+        path.ok_or(Error::NoFile)
+    }
+}
+
+/// Sends [`Event::DownloadProgress`] when the percentage changes, only whole percents so there
+/// aren't more events than anyone wants to see.
+#[derive(Debug, Clone)]
+struct Progress {
+    event_tx: flume::Sender<Event>,
+    url: String,
+    shown: Option<u8>,
+}
+
+impl Progress {
+    fn report(&mut self, percent: u8) {
+        if self.shown == Some(percent) {
+            return;
+        }
+        self.shown = Some(percent);
+        _ = self
+            .event_tx
+            .try_send(Event::DownloadProgress { url: self.url.clone(), percent });
+    }
+}
+
+/// The percentage of a `[download]  42.3% of ...` progress line.
+fn progress_percent(line: &str) -> Option<u8> {
+    let rest = line.strip_prefix("[download]")?;
+    let percent = rest.split_whitespace().next()?.strip_suffix('%')?;
+    let percent = percent.parse::<f64>().ok()?;
+    Some(percent.clamp(0.0, 100.0) as u8)
+}
+
+fn remove_old_downloads(dir: &Path) {
+    let Ok(entries) = std::fs::read_dir(dir) else { return };
+    for entry in entries.flatten() {
+        let is_old =
+            entry.metadata().and_then(|metadata| metadata.modified()).is_ok_and(|modified| {
+                SystemTime::now().duration_since(modified).is_ok_and(|age| age > KEEP_FOR)
+            });
+        if is_old && let Err(error) = std::fs::remove_file(entry.path()) {
+            eprintln!("Failed to remove old download {}: {error}", entry.path().display());
+        }
+    }
+}
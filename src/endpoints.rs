@@ -0,0 +1,146 @@
+//! Where clients can find the stream, printed at startup and optionally written as JSON.
+
+use std::path::Path;
+
+use serde::Serialize;
+use z_stream::mediamtx;
+
+use crate::cli::ServeArgs;
+
+/// The host put into the URLs, as everything listens on all interfaces.
+const HOST: &str = "127.0.0.1";
+
+#[derive(Debug, Serialize)]
+pub struct Endpoints {
+    pub stream_key: String,
+    /// From the internal RTSP server if mediamtx isn't running, from mediamtx otherwise.
+    pub rtsp: String,
+    /// The rest are only there with mediamtx.
+    pub rtmp: Option<String>,
+    pub srt: Option<String>,
+    pub webrtc: Option<String>,
+    pub hls: Option<String>,
+    /// RTSP URLs of the other names the stream is available under.
+    pub aliases: Vec<String>,
+    /// RTSP URLs of the lower resolution copies.
+    pub renditions: Vec<String>,
+    pub mjpeg: Option<String>,
+    /// Where WHEP players post their offers.
+    pub whep: Option<String>,
+    /// Where a live input can be sent to take over the output.
+    pub live_input: Option<String>,
+    pub api: Api,
+    pub ports: Ports,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Api {
+    pub url: String,
+    /// Needed by requests that change anything, open access if empty.
+    pub tokens: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Ports {
+    /// mediamtx's, if it's running.
+    pub mediamtx: Option<mediamtx::Ports>,
+    pub api: u16,
+    /// The internal RTSP server mediamtx restreams from.
+    pub internal_rtsp: u16,
+    pub srt_listen: Option<u16>,
+    pub whep: Option<u16>,
+}
+
+impl Endpoints {
+    /// The endpoints `args` make, with `mediamtx` serving on these ports if it's running.
+    pub fn new(args: &ServeArgs, mediamtx: Option<mediamtx::Ports>) -> Self {
+        let key = &args.stream_key;
+        let rtsp_port = mediamtx.map_or(args.rtsp_port, |ports| ports.rtsp);
+        let rtsp_url = |path: &str| format!("rtsp://{HOST}:{rtsp_port}/{path}");
+        let api_url = format!("http://{HOST}:{}", args.api_port);
+        let live_input = match (args.srt_listen, &args.rtmp_path, mediamtx) {
+            (Some(port), _, _) => Some(format!("srt://{HOST}:{port}")),
+            (None, Some(path), Some(ports)) => Some(format!("rtmp://{HOST}:{}/{path}", ports.rtmp)),
+            _ => None,
+        };
+        Self {
+            stream_key: key.clone(),
+            rtsp: rtsp_url(key),
+            rtmp: mediamtx.map(|ports| format!("rtmp://{HOST}:{}/{key}", ports.rtmp)),
+            srt: mediamtx.map(|ports| format!("srt://{HOST}:{}?streamid=read:{key}", ports.srt)),
+            webrtc: mediamtx.map(|ports| format!("http://{HOST}:{}/{key}", ports.webrtc)),
+            hls: mediamtx.map(|ports| format!("http://{HOST}:{}/{key}/index.m3u8", ports.hls)),
+            aliases: args.stream_key_aliases.iter().map(|alias| rtsp_url(alias)).collect(),
+            renditions: args
+                .renditions
+                .iter()
+                .map(|rendition| rtsp_url(&format!("{key}{}", rendition.mount_suffix())))
+                .collect(),
+            mjpeg: args.mjpeg.then(|| format!("{api_url}/mjpeg")),
+            whep: args.whep_port.map(|port| format!("http://{HOST}:{port}/whep/endpoint")),
+            live_input,
+            api: Api { url: api_url, tokens: args.api_tokens.clone() },
+            ports: Ports {
+                mediamtx,
+                api: args.api_port,
+                internal_rtsp: args.rtsp_port,
+                srt_listen: args.srt_listen,
+                whep: args.whep_port,
+            },
+        }
+    }
+
+    /// Writes the endpoints as JSON to `path`, or to stdout if it's `-`.
+    pub fn write_json(&self, path: &Path) -> std::io::Result<()> {
+        if path == Path::new("-") {
+            println!("{}", serde_json::to_string(self)?);
+            return Ok(());
+        }
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, json)?;
+
+        // It has the API tokens in it
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Endpoints {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Clients can connect to:")?;
+        if let Some(rtmp) = &self.rtmp {
+            writeln!(f, "  RTMP: {rtmp}")?;
+        }
+        writeln!(f, "  RTSP: {}", self.rtsp)?;
+        if let Some(srt) = &self.srt {
+            writeln!(f, "  SRT: {srt}")?;
+        }
+        if let Some(webrtc) = &self.webrtc {
+            writeln!(f, "  WebRTC: {webrtc}")?;
+        }
+        if let Some(hls) = &self.hls {
+            writeln!(f, "  HLS:  {hls}")?;
+        }
+        for alias in &self.aliases {
+            writeln!(f, "  Also as {alias}")?;
+        }
+        for rendition in &self.renditions {
+            writeln!(f, "  Rendition: {rendition}")?;
+        }
+        if let Some(mjpeg) = &self.mjpeg {
+            writeln!(f, "  MJPEG: {mjpeg}")?;
+        }
+        if let Some(whep) = &self.whep {
+            writeln!(f, "  WHEP: {whep}")?;
+        }
+        if let Some(live_input) = &self.live_input {
+            writeln!(f, "Live input: {live_input}")?;
+        }
+        write!(f, "API: {}", self.api.url)
+    }
+}
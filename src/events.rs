@@ -0,0 +1,58 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use crate::stream::Event;
+
+/// How many past events are kept for clients that reconnect.
+const HISTORY_LEN: usize = 100;
+/// Subscribers that fall this far behind are dropped, they can catch up by reconnecting.
+const SUBSCRIBER_BUFFER: usize = 100;
+
+/// Numbers the stream [`Event`]s and fans them out to any number of subscribers.
+/// Recent events are kept, so a subscriber that reconnects with the last id it saw doesn't miss
+/// anything.
+#[derive(Debug, Clone, Default)]
+pub struct EventLog {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    last_id: u64,
+    history: VecDeque<(u64, Event)>,
+    subscribers: Vec<flume::Sender<(u64, Event)>>,
+}
+
+impl EventLog {
+    pub fn handle_event(&self, event: &Event) {
+        let mut state = self.state.lock();
+        state.last_id += 1;
+        let id = state.last_id;
+
+        if state.history.len() == HISTORY_LEN {
+            state.history.pop_front();
+        }
+        state.history.push_back((id, event.clone()));
+
+        state.subscribers.retain(|tx| tx.try_send((id, event.clone())).is_ok());
+    }
+
+    /// Returns the kept events after `last_id` (none if `None`) and a receiver for new ones.
+    pub fn subscribe(
+        &self,
+        last_id: Option<u64>,
+    ) -> (Vec<(u64, Event)>, flume::Receiver<(u64, Event)>) {
+        let mut state = self.state.lock();
+        let missed = match last_id {
+            Some(last_id) => {
+                state.history.iter().filter(|(id, _)| *id > last_id).cloned().collect()
+            }
+            None => Vec::new(),
+        };
+        let (tx, rx) = flume::bounded(SUBSCRIBER_BUFFER);
+        state.subscribers.push(tx);
+        (missed, rx)
+    }
+}
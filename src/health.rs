@@ -0,0 +1,125 @@
+//! Health checks that run in the server itself, for deployments without a monitoring stack:
+//! crossing a threshold turns `GET /healthz` unhealthy, and posts an alert to a webhook.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use serde::Serialize;
+
+use crate::stream::Event;
+
+/// How often the thresholds are checked, so problems that pass by themselves (e.g. failures
+/// dropping out of the last minute) are noticed without waiting for an event.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// Preroll failures are counted over this long.
+const FAILURE_WINDOW: Duration = Duration::from_secs(60);
+
+/// When the server counts as unhealthy. Video or audio not reaching the output only counts with
+/// the output monitor running, its stall timeout is the threshold for that.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub struct HealthOptions {
+    /// More files failing to preroll than this within a minute is unhealthy.
+    pub max_preroll_failures_per_minute: Option<u32>,
+    /// POST a [`HealthReport`] as JSON to this URL whenever the server turns unhealthy or
+    /// healthy again.
+    pub alert_webhook: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HealthReport {
+    pub healthy: bool,
+    /// What's wrong, empty when healthy.
+    pub problems: Vec<String>,
+}
+
+/// Checks the [`Event`]s against the [`HealthOptions`], for `/healthz` to report.
+#[derive(Debug, Clone)]
+pub struct HealthCheck {
+    options: HealthOptions,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    output_problem: Option<String>,
+    preroll_failures: VecDeque<Instant>,
+    /// Whether the last check found it healthy, to alert on changes.
+    healthy: bool,
+}
+
+impl HealthCheck {
+    /// Starts a thread checking the thresholds every so often, and alerting the webhook.
+    pub fn start(options: HealthOptions) -> Self {
+        let state = State { healthy: true, ..State::default() };
+        let this = Self { options, state: Arc::new(Mutex::new(state)) };
+        let alert_tx = this.options.alert_webhook.clone().map(start_alerts);
+        let check = this.clone();
+        std::thread::spawn(move || {
+            loop {
+                let report = check.report();
+                let changed = {
+                    let mut state = check.state.lock();
+                    std::mem::replace(&mut state.healthy, report.healthy) != report.healthy
+                };
+                if changed {
+                    if report.healthy {
+                        println!("Healthy again");
+                    } else {
+                        eprintln!("Unhealthy: {}", report.problems.join(", "));
+                    }
+                    if let Some(alert_tx) = &alert_tx {
+                        _ = alert_tx.send(report);
+                    }
+                }
+                std::thread::sleep(CHECK_INTERVAL);
+            }
+        });
+        this
+    }
+
+    pub fn handle_event(&self, event: &Event) {
+        let mut state = self.state.lock();
+        match event {
+            Event::OutputUnhealthy { reason } => state.output_problem = Some(reason.clone()),
+            Event::OutputHealthy => state.output_problem = None,
+            Event::PrerollFailed { .. } => state.preroll_failures.push_back(Instant::now()),
+            _ => (),
+        }
+    }
+
+    pub fn report(&self) -> HealthReport {
+        let mut state = self.state.lock();
+        let mut problems = Vec::new();
+        if let Some(output_problem) = &state.output_problem {
+            problems.push(output_problem.clone());
+        }
+
+        while state.preroll_failures.front().is_some_and(|at| at.elapsed() > FAILURE_WINDOW) {
+            state.preroll_failures.pop_front();
+        }
+        let failures = state.preroll_failures.len();
+        if let Some(max) = self.options.max_preroll_failures_per_minute
+            && failures > max as usize
+        {
+            problems.push(format!("{failures} files failed to preroll in the last minute"));
+        }
+
+        HealthReport { healthy: problems.is_empty(), problems }
+    }
+}
+
+/// Starts a thread posting the reports sent to the returned channel to `webhook`, so a slow
+/// webhook doesn't hold up the checks.
+fn start_alerts(webhook: String) -> flume::Sender<HealthReport> {
+    let (alert_tx, alert_rx) = flume::unbounded::<HealthReport>();
+    std::thread::spawn(move || {
+        for report in alert_rx {
+            if let Err(error) = ureq::post(&webhook).send_json(&report) {
+                eprintln!("Failed to send health alert: {error}");
+            }
+        }
+    });
+    alert_tx
+}
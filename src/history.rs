@@ -0,0 +1,320 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use parking_lot::Mutex;
+use rusqlite::OptionalExtension;
+use serde::Serialize;
+
+use crate::stream::{EndReason, Event, ItemOverrides, parse_file_name};
+use crate::thumbnail::thumbnail;
+
+/// Width of the stills kept of every file played.
+const STILL_WIDTH: u32 = 320;
+/// How many stills to keep, older ones are dropped.
+const MAX_STILLS: usize = 500;
+/// How long to wait for another instance sharing the database to finish writing.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A record of every file played, kept in a SQLite database so it survives restarts.
+///
+/// A still of every recent file with video is kept alongside it, see [`History::still`].
+///
+/// Several instances can share the database, e.g. on a network path, each recording its plays
+/// under its own channel name. Each one only reports its own plays, but
+/// [`History::played_since`] sees everyone's, so they can keep from repeating each other.
+#[derive(Debug, Clone)]
+pub struct History {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug)]
+struct State {
+    connection: rusqlite::Connection,
+    channel: String,
+    /// Row and path of the file that's playing, to fill in when it ends.
+    playing: Option<(i64, PathBuf)>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryEntry {
+    pub id: i64,
+    #[serde(serialize_with = "crate::paths::serialize_lossy")]
+    pub path: PathBuf,
+    pub media_type: String,
+    /// Unix timestamps, in seconds.
+    pub started_at: f64,
+    pub ended_at: Option<f64>,
+    /// The length of the file itself, if known.
+    pub duration_secs: Option<f64>,
+    pub skipped: bool,
+    pub error: Option<String>,
+    /// Whether there's a still of the file, see [`History::still`]. The API serves it at
+    /// `/history/{id}/still`.
+    pub has_still: bool,
+}
+
+/// A line of the program log, what aired and when, see [`History::program_log`].
+#[derive(Debug, Clone, Serialize)]
+pub struct LogEntry {
+    /// UTC, as RFC 3339.
+    pub start_time: String,
+    /// Missing for the file that's playing, or one that was playing when the server stopped.
+    pub end_time: Option<String>,
+    #[serde(serialize_with = "crate::paths::serialize_lossy")]
+    pub path: PathBuf,
+    /// From the file's `.zstream.toml` sidecar if it still has one, else its name.
+    pub title: String,
+    /// How long it aired for.
+    pub duration_secs: Option<f64>,
+    pub skipped: bool,
+}
+
+impl History {
+    /// Opens the database at `path`, recording plays under `channel` (e.g. the stream key).
+    pub fn open(path: &Path, channel: &str) -> Result<Self, rusqlite::Error> {
+        let connection = rusqlite::Connection::open(path)?;
+        connection.busy_timeout(BUSY_TIMEOUT)?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS plays (
+                id INTEGER PRIMARY KEY,
+                path TEXT NOT NULL,
+                media_type TEXT NOT NULL,
+                started_at REAL NOT NULL,
+                ended_at REAL,
+                duration_secs REAL,
+                skipped INTEGER NOT NULL DEFAULT 0,
+                error TEXT,
+                channel TEXT
+            );
+            CREATE INDEX IF NOT EXISTS plays_started_at ON plays (started_at);
+            CREATE TABLE IF NOT EXISTS stills (
+                play_id INTEGER PRIMARY KEY,
+                jpeg BLOB NOT NULL
+            );",
+        )?;
+        // Databases from before plays were recorded per channel
+        let has_channel: bool = connection.query_row(
+            "SELECT EXISTS (SELECT 1 FROM pragma_table_info('plays') WHERE name = 'channel')",
+            [],
+            |row| row.get(0),
+        )?;
+        if !has_channel {
+            connection.execute_batch("ALTER TABLE plays ADD COLUMN channel TEXT;")?;
+        }
+        connection.execute_batch("CREATE INDEX IF NOT EXISTS plays_path ON plays (path);")?;
+        let state = State { connection, channel: channel.to_string(), playing: None };
+        Ok(Self { state: Arc::new(Mutex::new(state)) })
+    }
+
+    pub fn handle_event(&self, event: &Event) {
+        if let Err(error) = self.record(event) {
+            eprintln!("Failed to record history: {error}");
+        }
+    }
+
+    fn record(&self, event: &Event) -> Result<(), rusqlite::Error> {
+        let mut state = self.state.lock();
+        match event {
+            Event::Playing { path, media_type, duration } => {
+                // A file without an end is left without one, rather than given this one's
+                state.playing = None;
+                let media_type = serde_json::to_value(media_type).unwrap_or_default();
+                state.connection.execute(
+                    "INSERT INTO plays (path, media_type, started_at, duration_secs, channel)
+                    VALUES (?1, ?2, ?3, ?4, ?5)",
+                    rusqlite::params![
+                        path.to_string_lossy(),
+                        media_type.as_str().unwrap_or_default(),
+                        unix_now(),
+                        duration.map(|duration| duration.seconds_f64()),
+                        state.channel,
+                    ],
+                )?;
+                let id = state.connection.last_insert_rowid();
+                state.playing = Some((id, path.clone()));
+
+                // Grabbing a frame takes a moment, don't hold up the other events for it
+                let history = self.clone();
+                let path = path.clone();
+                std::thread::spawn(move || history.capture_still(id, &path));
+            }
+            Event::Ended { path, reason, .. } => {
+                let Some((id, playing_path)) = state.playing.take() else { return Ok(()) };
+                if playing_path != *path {
+                    return Ok(());
+                }
+                let error = match reason {
+                    EndReason::Error(error) => Some(error.as_str()),
+                    _ => None,
+                };
+                state.connection.execute(
+                    "UPDATE plays SET ended_at = ?1, skipped = ?2, error = ?3 WHERE id = ?4",
+                    rusqlite::params![unix_now(), *reason == EndReason::Skipped, error, id],
+                )?;
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn capture_still(&self, id: i64, path: &Path) {
+        let jpeg = match thumbnail(path, STILL_WIDTH) {
+            Ok(jpeg) => jpeg,
+            // Nothing to see in audio files
+            Err(crate::thumbnail::Error::NoVideo) => return,
+            Err(error) => {
+                eprintln!("Failed to capture a still of {}: {error}", path.display());
+                return;
+            }
+        };
+        if let Err(error) = self.store_still(id, &jpeg) {
+            eprintln!("Failed to record still: {error}");
+        }
+    }
+
+    fn store_still(&self, id: i64, jpeg: &[u8]) -> Result<(), rusqlite::Error> {
+        let state = self.state.lock();
+        state.connection.execute(
+            "INSERT OR REPLACE INTO stills (play_id, jpeg) VALUES (?1, ?2)",
+            rusqlite::params![id, jpeg],
+        )?;
+        state.connection.execute(
+            "DELETE FROM stills WHERE play_id NOT IN
+            (SELECT play_id FROM stills ORDER BY play_id DESC LIMIT ?1)",
+            [MAX_STILLS as i64],
+        )?;
+        Ok(())
+    }
+
+    /// A JPEG from the history entry with this `id`, if there is one. Only the most recent
+    /// files have one.
+    pub fn still(&self, id: i64) -> Result<Option<Vec<u8>>, rusqlite::Error> {
+        let state = self.state.lock();
+        state
+            .connection
+            .query_row("SELECT jpeg FROM stills WHERE play_id = ?1", [id], |row| row.get(0))
+            .optional()
+    }
+
+    /// Whether any channel sharing the database started `path` within the last `window`.
+    pub fn played_since(&self, path: &Path, window: Duration) -> Result<bool, rusqlite::Error> {
+        let state = self.state.lock();
+        state.connection.query_row(
+            "SELECT EXISTS (SELECT 1 FROM plays WHERE path = ?1 AND started_at >= ?2)",
+            rusqlite::params![path.to_string_lossy(), unix_now() - window.as_secs_f64()],
+            |row| row.get(0),
+        )
+    }
+
+    /// The most recently started files on this channel, newest first.
+    pub fn recent(&self, limit: usize) -> Result<Vec<HistoryEntry>, rusqlite::Error> {
+        let state = self.state.lock();
+        let mut statement = state.connection.prepare(
+            "SELECT id, path, media_type, started_at, ended_at, duration_secs, skipped, error,
+                EXISTS (SELECT 1 FROM stills WHERE play_id = plays.id)
+            FROM plays WHERE channel IS NULL OR channel = ?2
+            ORDER BY started_at DESC LIMIT ?1",
+        )?;
+        let rows = statement.query_map(rusqlite::params![limit as i64, state.channel], |row| {
+            Ok(HistoryEntry {
+                id: row.get(0)?,
+                path: PathBuf::from(row.get::<_, String>(1)?),
+                media_type: row.get(2)?,
+                started_at: row.get(3)?,
+                ended_at: row.get(4)?,
+                duration_secs: row.get(5)?,
+                skipped: row.get(6)?,
+                error: row.get(7)?,
+                has_still: row.get(8)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// What aired on this channel between the Unix timestamps `from` and `to`, oldest first.
+    pub fn program_log(
+        &self,
+        from: Option<f64>,
+        to: Option<f64>,
+    ) -> Result<Vec<LogEntry>, rusqlite::Error> {
+        let mut entries: Vec<LogEntry> = {
+            let state = self.state.lock();
+            let mut statement = state.connection.prepare(
+                "SELECT strftime('%Y-%m-%dT%H:%M:%SZ', started_at, 'unixepoch'),
+                    strftime('%Y-%m-%dT%H:%M:%SZ', ended_at, 'unixepoch'),
+                    path, ended_at - started_at, skipped
+                FROM plays
+                WHERE (?1 IS NULL OR started_at >= ?1) AND (?2 IS NULL OR started_at < ?2)
+                    AND (channel IS NULL OR channel = ?3)
+                ORDER BY started_at",
+            )?;
+            let rows = statement.query_map(rusqlite::params![from, to, state.channel], |row| {
+                Ok(LogEntry {
+                    start_time: row.get(0)?,
+                    end_time: row.get(1)?,
+                    path: PathBuf::from(row.get::<_, String>(2)?),
+                    title: String::new(),
+                    duration_secs: row.get(3)?,
+                    skipped: row.get(4)?,
+                })
+            })?;
+            rows.collect::<Result<_, _>>()?
+        };
+        // Reading the sidecars can take a while, so it's done without holding the database
+        for entry in &mut entries {
+            entry.title = log_title(&entry.path);
+        }
+        Ok(entries)
+    }
+}
+
+/// `entries` as CSV, with a header row.
+pub fn program_log_csv(entries: &[LogEntry]) -> String {
+    let mut csv = String::from("start_time,end_time,path,title,duration_secs,skipped\r\n");
+    for entry in entries {
+        let fields = [
+            csv_field(&entry.start_time),
+            csv_field(entry.end_time.as_deref().unwrap_or_default()),
+            csv_field(&entry.path.to_string_lossy()),
+            csv_field(&entry.title),
+            entry.duration_secs.map(|secs| format!("{secs:.1}")).unwrap_or_default(),
+            entry.skipped.to_string(),
+        ];
+        csv.push_str(&fields.join(","));
+        csv.push_str("\r\n");
+    }
+    csv
+}
+
+/// Quotes `field` if it needs it.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn log_title(path: &Path) -> String {
+    ItemOverrides::load(path).title.unwrap_or_else(|| parse_file_name(path).0)
+}
+
+fn unix_now() -> f64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_csv_fields_that_need_it() {
+        assert_eq!(csv_field("Plain title"), "Plain title");
+        assert_eq!(csv_field(""), "");
+        assert_eq!(csv_field("Live, at last"), "\"Live, at last\"");
+        assert_eq!(csv_field("The \"best\" bits"), "\"The \"\"best\"\" bits\"");
+        assert_eq!(csv_field("Two\nlines"), "\"Two\nlines\"");
+        assert_eq!(csv_field("Two\r\nlines"), "\"Two\r\nlines\"");
+    }
+}
@@ -0,0 +1,90 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use crate::stream::{EndReason, Event};
+
+/// Local reactions to what's playing, for running the channel on a workstation.
+/// Only files starting to play, files failing, the output going unhealthy and the disk filling up
+/// trigger them.
+#[derive(Debug, Clone, Default)]
+pub struct EventHook {
+    /// Show a desktop notification (`notify-send` on Linux, `osascript` on macOS).
+    pub desktop_notifications: bool,
+    /// Run this through the shell with the event as JSON on stdin.
+    pub command: Option<String>,
+}
+
+impl EventHook {
+    pub fn is_enabled(&self) -> bool {
+        self.desktop_notifications || self.command.is_some()
+    }
+
+    /// Starts a thread that runs the hook for events sent to the returned channel, so slow
+    /// commands don't hold up anything else.
+    pub fn start(self) -> flume::Sender<Event> {
+        let (event_tx, event_rx) = flume::unbounded::<Event>();
+        std::thread::spawn(move || {
+            for event in event_rx {
+                self.handle_event(&event);
+            }
+        });
+        event_tx
+    }
+
+    fn handle_event(&self, event: &Event) {
+        let (title, body) = match event {
+            Event::Playing { path, .. } => ("Now playing", path.display().to_string()),
+            Event::Ended { path, reason: EndReason::Error(error), .. } => {
+                ("Playback failed", format!("{}: {error}", path.display()))
+            }
+            Event::OutputUnhealthy { reason } => ("Output unhealthy", reason.clone()),
+            Event::DiskSpaceLow { dir, free_bytes } => {
+                let free_mb = free_bytes / 1_000_000;
+                ("Low disk space", format!("{free_mb} MB free for {}", dir.display()))
+            }
+            Event::RootUnavailable { root, reason } => {
+                ("Library unavailable", format!("{}: {reason}", root.display()))
+            }
+            _ => return,
+        };
+
+        if self.desktop_notifications
+            && let Err(error) = notify(title, &body)
+        {
+            eprintln!("Failed to show notification: {error}");
+        }
+        if let Some(command) = &self.command
+            && let Err(error) = run_command(command, event)
+        {
+            eprintln!("Failed to run event hook: {error}");
+        }
+    }
+}
+
+fn notify(title: &str, body: &str) -> std::io::Result<()> {
+    let mut command = if cfg!(target_os = "macos") {
+        let script = format!("display notification {body:?} with title {title:?}");
+        let mut command = Command::new("osascript");
+        command.arg("-e").arg(script);
+        command
+    } else {
+        let mut command = Command::new("notify-send");
+        command.args(["--app-name", "z-stream", title, body]);
+        command
+    };
+    command.stdout(Stdio::null()).stderr(Stdio::null()).status()?;
+    Ok(())
+}
+
+fn run_command(command: &str, event: &Event) -> std::io::Result<()> {
+    let json = serde_json::to_vec(event)?;
+    let mut child = Command::new("sh").arg("-c").arg(command).stdin(Stdio::piped()).spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(&json)?;
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        eprintln!("Event hook exited with {status}");
+    }
+    Ok(())
+}
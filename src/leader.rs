@@ -0,0 +1,91 @@
+//! Splitting library scanning from streaming: a leader process walks the library and hands out
+//! files that look playable, and streamers ask it for their next file instead of scanning.
+//!
+//! The library has to be mounted at the same path on the leader and every streamer.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use axum::Json;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+
+use crate::media_type::TypeFinder;
+use crate::random_files::RandomFiles;
+
+/// How many random files to look at for one that's probably media before giving up.
+const MAX_ATTEMPTS: usize = 20;
+
+/// The file as a `file://` URI, which (unlike a JSON string) can hold any path.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct Candidate {
+    uri: String,
+}
+
+/// Serves random picks from `files` at `GET /candidate` until the process exits.
+/// GStreamer has to be initialised, typefind is used to skip files that aren't media.
+pub fn run_candidate_server(files: RandomFiles, port: u16) -> std::io::Result<()> {
+    let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build()?;
+    runtime.block_on(async move {
+        let listener = tokio::net::TcpListener::bind(("0.0.0.0", port)).await?;
+        let app = axum::Router::new().route("/candidate", get(candidate)).with_state(files);
+        axum::serve(listener, app).await
+    })
+}
+
+async fn candidate(State(files): State<RandomFiles>) -> Response {
+    let pick = tokio::task::spawn_blocking(move || {
+        let mut type_finder = TypeFinder::default();
+        files
+            .take(MAX_ATTEMPTS)
+            .find(|path| type_finder.is_probably_media(path).unwrap_or(false))
+    });
+    match pick.await {
+        Ok(Some(path)) => match glib::filename_to_uri(&path, None) {
+            Ok(uri) => Json(Candidate { uri: uri.into() }).into_response(),
+            Err(error) => {
+                eprintln!("Can't hand out {}: {error}", path.display());
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        },
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// Files handed out by a leader, see [`run_candidate_server`].
+/// Never ends, if the leader can't be reached it keeps retrying.
+#[derive(Debug, Clone)]
+pub struct RemoteCandidates {
+    url: String,
+}
+
+impl RemoteCandidates {
+    pub fn new(leader_url: &str) -> Self {
+        Self { url: format!("{}/candidate", leader_url.trim_end_matches('/')) }
+    }
+}
+
+impl Iterator for RemoteCandidates {
+    type Item = PathBuf;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        const RETRY_INTERVAL: Duration = Duration::from_secs(2);
+
+        loop {
+            let result = ureq::get(&self.url)
+                .call()
+                .and_then(|mut response| response.body_mut().read_json::<Candidate>());
+            match result {
+                Ok(candidate) => match glib::filename_from_uri(&candidate.uri) {
+                    Ok((path, _)) => return Some(path),
+                    Err(error) => eprintln!("The leader sent an invalid file URI: {error}"),
+                },
+                Err(error) => eprintln!("Failed to get a file from the leader: {error}"),
+            }
+            std::thread::sleep(RETRY_INTERVAL);
+        }
+    }
+}
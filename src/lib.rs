@@ -0,0 +1,31 @@
+//! A continuous-stream engine: picks random files from a set of directories and plays them back to
+//! back as a single live RTSP stream.
+//!
+//! The main entry point is [`Server::builder()`].
+
+#![deny(unused_imports, unsafe_code, clippy::all)]
+
+pub mod api;
+pub mod client;
+pub mod disk_monitor;
+pub mod download;
+pub mod events;
+pub mod health;
+pub mod history;
+pub mod hooks;
+pub mod leader;
+pub mod media_cache;
+pub mod media_info;
+pub mod media_type;
+pub mod mediamtx;
+pub mod paths;
+pub mod random_files;
+pub mod resources;
+mod server;
+pub mod stats;
+pub mod status;
+pub mod stream;
+pub mod thumbnail;
+pub mod viewer_tokens;
+
+pub use self::server::{Server, ServerBuilder};
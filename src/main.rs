@@ -1,26 +1,125 @@
 #![deny(unused_imports, unsafe_code, clippy::all)]
 
-mod api;
-mod media_info;
-mod media_type;
-mod mediamtx;
-mod random_files;
-mod stream;
+mod cli;
+mod config;
+mod endpoints;
+mod tui;
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use gstreamer_rtsp_server::prelude::RTSPServerExtManual;
+use clap::Parser;
+use z_stream::client::ApiClient;
+use z_stream::disk_monitor::DiskMonitorOptions;
+use z_stream::download::Downloader;
+use z_stream::media_cache::MediaInfoCache;
+use z_stream::media_info::MediaInfo;
+use z_stream::media_type::MediaType;
+use z_stream::viewer_tokens::ViewerTokens;
+use z_stream::{Server, mediamtx};
 
-const STREAM_KEY: &str = "my_stream";
-const RTSP_PORT: u16 = 18554;
-const API_PORT: u16 = 18080;
+use crate::cli::{Cli, CliCommand, ServeArgs, SimulateArgs};
+use crate::endpoints::Endpoints;
 
 fn main() {
-    gstreamer::init().expect("Failed to initialize GStreamer");
+    let args = config::expand_args(std::env::args_os().collect()).unwrap_or_else(|error| {
+        eprintln!("Error: {error}");
+        std::process::exit(1);
+    });
+    let cli = Cli::parse_from(args);
+
+    match cli.command {
+        CliCommand::Serve(args) => {
+            gstreamer::init().expect("Failed to initialize GStreamer");
+            serve(args)
+        }
+        CliCommand::Probe { file } => {
+            gstreamer::init().expect("Failed to initialize GStreamer");
+            probe(&file)
+        }
+        CliCommand::Validate { dir } => {
+            gstreamer::init().expect("Failed to initialize GStreamer");
+            validate(&dir)
+        }
+        CliCommand::Simulate(args) => {
+            if args.probe {
+                gstreamer::init().expect("Failed to initialize GStreamer");
+            }
+            simulate(args)
+        }
+        CliCommand::Leader { root_dirs, port, filter } => {
+            gstreamer::init().expect("Failed to initialize GStreamer");
+            let files = filter.random_files(&root_dirs).unwrap_or_else(|error| {
+                eprintln!("Error: {error}");
+                std::process::exit(1);
+            });
+            println!("Handing out files at http://0.0.0.0:{port}/candidate");
+            if let Err(error) = z_stream::leader::run_candidate_server(files, port) {
+                eprintln!("Error: {error}");
+                std::process::exit(1);
+            }
+        }
+        CliCommand::ClearMediaCache { database, file } => {
+            let removed = MediaInfoCache::open(&database)
+                .and_then(|media_cache| media_cache.invalidate(file.as_deref()));
+            match removed {
+                Ok(count) => println!("Removed {count} entries"),
+                Err(error) => {
+                    eprintln!("Error: {error}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        CliCommand::CheckConfig { file } => {
+            if !config::check(&file) {
+                std::process::exit(1);
+            }
+        }
+        CliCommand::ExportConfig { file, bundle } => {
+            if let Err(error) = config::export(&file, &bundle) {
+                eprintln!("Error: {error}");
+                std::process::exit(1);
+            }
+        }
+        CliCommand::ImportConfig { bundle, file } => {
+            if let Err(error) = config::import(&bundle, &file) {
+                eprintln!("Error: {error}");
+                std::process::exit(1);
+            }
+            // The bundle only has the config, not the files it names, e.g. the sting
+            if !config::check(&file) {
+                std::process::exit(1);
+            }
+        }
+        CliCommand::Tui { url, token } => {
+            if let Err(error) = tui::run(ApiClient::new(url, token)) {
+                eprintln!("Error: {error}");
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+fn serve(args: ServeArgs) {
+    if let Err(error) = args.resource_limits().apply() {
+        eprintln!("Error: {error}");
+        std::process::exit(1);
+    }
+
+    if args.is_demo() {
+        println!("No root directories given, showing a test card until some are added");
+    }
+    let missing: Vec<&PathBuf> = args.root_dirs.iter().filter(|root| !root.exists()).collect();
+    if !args.root_dirs.is_empty() && missing.len() == args.root_dirs.len() {
+        eprintln!("Error: none of the root directories exist");
+        std::process::exit(1);
+    }
+    for root in missing {
+        eprintln!("Warning: {} doesn't exist, it's skipped until it does", root.display());
+    }
 
-    let mut args = std::env::args_os().skip(1).peekable();
-    if args.peek().is_some_and(|v| v == "--test") {
-        args.next();
+    let mediamtx_ports = (!args.no_mediamtx).then(|| args.mediamtx_ports());
+    if args.test {
         std::process::Command::new("pkill")
             .arg("mediamtx")
             .spawn()
@@ -28,10 +127,12 @@ fn main() {
             .wait()
             .unwrap();
 
+        let stream_key = args.stream_key.clone();
+        let rtsp_port = mediamtx_ports.map_or(args.rtsp_port, |ports| ports.rtsp);
         std::thread::spawn(move || {
             std::thread::sleep(std::time::Duration::from_millis(100));
             std::process::Command::new("ffplay")
-                .args(["-v", "info", "rtsp://127.0.0.1:8554/my_stream"])
+                .args(["-v", "info", &format!("rtsp://127.0.0.1:{rtsp_port}/{stream_key}")])
                 .spawn()
                 .unwrap()
                 .wait()
@@ -48,45 +149,223 @@ fn main() {
         });
     }
 
-    let root_dirs = std::env::args_os().skip(1).map(PathBuf::from).collect::<Vec<_>>();
-
-    let (command_tx, command_rx) = flume::bounded(20);
-    let (event_tx, _event_rx) = flume::bounded(20);
-    api::start_api_task(API_PORT, command_tx);
-
-    let rtmp_port: u16 = 1935;
-    let hls_port: u16 = 8888;
-    let rtsp_port: u16 = 8554;
-    let srt_port: u16 = 8890;
-    let webrtc_port: u16 = 8889;
+    let stream_key = args.stream_key.clone();
 
-    std::thread::spawn(move || {
-        let mut mediamtx = mediamtx::start().expect("Failed to start mediamtx");
+    if let Some(ports) = mediamtx_ports {
+        let config = mediamtx::Config {
+            ports,
+            rtsp_port: args.rtsp_port,
+            stream_key: stream_key.clone(),
+            aliases: args.stream_key_aliases.clone(),
+            live_path: args.rtmp_path.clone(),
+            rendition_suffixes: args
+                .renditions
+                .iter()
+                .map(|rendition| rendition.mount_suffix())
+                .collect(),
+            srt_passphrase: args.srt_passphrase.clone(),
+            auth_url: args
+                .viewer_tokens
+                .then(|| format!("http://127.0.0.1:{}/mediamtx/auth", args.api_port)),
+        };
+        std::thread::spawn(move || {
+            let mut mediamtx = mediamtx::start(&config).expect("Failed to start mediamtx");
 
-        let exit_status = mediamtx.wait().expect("Failed to wait for mediamtx to exit");
-        println!("Exit status: {}", exit_status);
-        if !exit_status.success() {
-            std::process::exit(1);
-        }
-    });
+            let exit_status = mediamtx.wait().expect("Failed to wait for mediamtx to exit");
+            println!("Exit status: {}", exit_status);
+            if !exit_status.success() {
+                std::process::exit(1);
+            }
+        });
+    }
 
     let main_loop = glib::MainLoop::new(None, false);
 
-    let server = stream::create_server(root_dirs, command_rx, event_tx, RTSP_PORT, STREAM_KEY)
-        .expect("Failed to start RTSP server");
+    let mut builder = Server::builder()
+        .root_dirs(&args.root_dirs)
+        .rtsp_port(args.rtsp_port)
+        .api_port(args.api_port)
+        .api_tokens(&args.api_tokens)
+        .stream_key(&stream_key)
+        .stream_key_aliases(&args.stream_key_aliases)
+        .event_hook(args.event_hook())
+        .health(args.health())
+        .options(args.stream_options());
+    if let Some(history_db) = &args.history_db {
+        builder = builder.history_db(history_db);
+    }
+    if let Some(quarantine_db) = &args.quarantine_db {
+        builder = builder.quarantine_db(quarantine_db);
+    }
+    if let Some(upload_dir) = &args.upload_dir {
+        builder = builder.upload_dir(upload_dir, args.upload_max_mb * 1_000_000);
+    }
+    if let Some(program) = &args.downloader
+        && let Some(dir) = &args.download_dir
+    {
+        builder = builder.downloader(Downloader { program: program.clone(), dir: dir.clone() });
+    }
+    if args.disk_monitor {
+        builder = builder.disk_monitor(DiskMonitorOptions {
+            prune_dirs: Vec::new(),
+            watch_dirs: Vec::new(),
+            warn_below: args.disk_warn_below * 1_000_000,
+            prune_below: args.disk_prune_below * 1_000_000,
+        });
+    }
+    if let Some(media_cache) = &args.media_cache {
+        builder = builder.media_cache_db(media_cache);
+    }
+    if args.viewer_tokens {
+        builder = builder.viewer_tokens(ViewerTokens::default());
+    }
+    let server = builder.build().expect("Failed to start RTSP server");
 
     let context = main_loop.context();
     server
         .attach(Some(&context))
         .expect("Failed to attach RTSP server to main loop");
 
-    println!("Clients can connect to:");
-    println!("  RTMP: rtmp://127.0.0.1:{rtmp_port}/{STREAM_KEY}");
-    println!("  RTSP: rtsp://127.0.0.1:{rtsp_port}/{STREAM_KEY}");
-    println!("  SRT: srt://127.0.0.1:{srt_port}?streamid=read:{STREAM_KEY}");
-    println!("  WebRTC: http://127.0.0.1:{webrtc_port}/{STREAM_KEY}");
-    println!("  HLS:  http://127.0.0.1:{hls_port}/{STREAM_KEY}/index.m3u8");
+    let endpoints = Endpoints::new(&args, mediamtx_ports);
+    println!("{endpoints}");
+    if let Some(path) = &args.endpoints_json
+        && let Err(error) = endpoints.write_json(path)
+    {
+        eprintln!("Failed to write {}: {error}", path.display());
+    }
     println!("\nPress Ctrl+C to shut down.");
 
+    #[cfg(unix)]
+    for signal in [libc_signal::SIGINT, libc_signal::SIGTERM] {
+        let main_loop = main_loop.clone();
+        glib::unix_signal_add_local_once(signal, move || main_loop.quit());
+    }
+
     main_loop.run();
+    server.shutdown();
+
+    let summary = server.stats().summary();
+    println!("\n{summary}");
+    if let Some(stats_file) = &args.stats_file
+        && let Err(error) = summary.write_json(stats_file)
+    {
+        eprintln!("Failed to write {}: {error}", stats_file.display());
+    }
+}
+
+/// Signal numbers, glib takes them as plain integers.
+#[cfg(unix)]
+mod libc_signal {
+    pub const SIGINT: i32 = 2;
+    pub const SIGTERM: i32 = 15;
+}
+
+fn probe(file: &Path) {
+    match MediaInfo::detect(file) {
+        Ok(media_info) => {
+            println!("{}: {:?}", file.display(), media_info.media_type());
+            println!("{media_info:#?}");
+        }
+        Err(error) => {
+            eprintln!("Failed to probe {}: {error}", file.display());
+            std::process::exit(1);
+        }
+    }
+}
+
+fn validate(dir: &Path) {
+    let mut playable = 0usize;
+    let mut unplayable = 0usize;
+
+    for entry in jwalk::WalkDir::new(dir) {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(error) => {
+                eprintln!("{error}");
+                continue;
+            }
+        };
+        if entry.file_type().is_dir() {
+            continue;
+        }
+
+        let path = entry.path();
+        match MediaInfo::detect(&path) {
+            Ok(media_info) if !media_info.is_empty() => {
+                println!("OK      {:?} {}", media_info.media_type(), path.display());
+                playable += 1;
+            }
+            Ok(_) => {
+                println!("SKIPPED {}", path.display());
+                unplayable += 1;
+            }
+            Err(error) => {
+                println!("FAILED  {} ({error})", path.display());
+                unplayable += 1;
+            }
+        }
+    }
+
+    println!("\n{playable} playable, {unplayable} unplayable");
+    if playable == 0 {
+        std::process::exit(1);
+    }
+}
+
+fn simulate(args: SimulateArgs) {
+    let total = Duration::from_secs_f64(args.hours * 3600.0);
+    let assumed_duration = Duration::from_secs(args.assumed_duration * 60);
+    let image_duration = Duration::from_secs(args.image_duration);
+
+    const MAX_SKIPPED_IN_A_ROW: u32 = 100;
+
+    let mut elapsed = Duration::ZERO;
+    let mut skipped_in_a_row = 0;
+    let mut files = args.filter.random_files(&args.root_dirs).unwrap_or_else(|error| {
+        eprintln!("Error: {error}");
+        std::process::exit(1);
+    });
+    while elapsed < total {
+        let Some(path) = files.next() else {
+            eprintln!("No files found");
+            std::process::exit(1);
+        };
+
+        let (media_type, known_duration) = if args.probe {
+            match MediaInfo::detect(&path) {
+                Ok(media_info) if !media_info.is_empty() => {
+                    (media_info.media_type(), media_info.known_duration().map(Duration::from))
+                }
+                _ => (MediaType::Unknown, None),
+            }
+        } else {
+            (MediaType::guess_from_extension(&path), None)
+        };
+        let duration = match (media_type, known_duration) {
+            (MediaType::Unknown, _) => {
+                // Give up if nothing in the roots looks playable, rather than looping forever
+                skipped_in_a_row += 1;
+                if skipped_in_a_row >= MAX_SKIPPED_IN_A_ROW {
+                    eprintln!("No playable files found");
+                    std::process::exit(1);
+                }
+                continue;
+            }
+            (MediaType::Image, _) => image_duration,
+            (_, Some(duration)) => duration,
+            (_, None) => assumed_duration,
+        };
+        skipped_in_a_row = 0;
+
+        let secs = elapsed.as_secs();
+        println!(
+            "{:02}:{:02}:{:02}  {:<17} {}",
+            secs / 3600,
+            (secs / 60) % 60,
+            secs % 60,
+            format!("{media_type:?}"),
+            path.display(),
+        );
+        elapsed += duration;
+    }
 }
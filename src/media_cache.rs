@@ -0,0 +1,131 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use parking_lot::Mutex;
+
+use crate::media_info::{Error, MediaInfo};
+
+/// Remembers [`MediaInfo`] in a SQLite database, so files don't go through discovery every time
+/// they're played. Entries are only used while the file's size and modification time still match.
+///
+/// Instances sharing a library can share the database too, so each file is only probed once.
+#[derive(Debug, Clone)]
+pub struct MediaInfoCache {
+    connection: Arc<Mutex<rusqlite::Connection>>,
+}
+
+impl MediaInfoCache {
+    pub fn open(path: &Path) -> Result<Self, rusqlite::Error> {
+        let connection = rusqlite::Connection::open(path)?;
+        // Another instance sharing the database may be writing to it
+        connection.busy_timeout(std::time::Duration::from_secs(5))?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS media_info (
+                path TEXT PRIMARY KEY,
+                size INTEGER NOT NULL,
+                modified_ns INTEGER NOT NULL,
+                info TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS classifications (
+                path TEXT PRIMARY KEY,
+                size INTEGER NOT NULL,
+                modified_ns INTEGER NOT NULL,
+                allowed INTEGER NOT NULL
+            );",
+        )?;
+        Ok(Self { connection: Arc::new(Mutex::new(connection)) })
+    }
+
+    /// Returns the cached info, or runs discovery and caches the result.
+    /// Failed discoveries (e.g. timeouts) aren't cached, so they're tried again next time.
+    pub fn detect(&self, path: &Path, timeout: gstreamer::ClockTime) -> Result<MediaInfo, Error> {
+        let Some(key) = FileKey::of(path) else {
+            return MediaInfo::detect_with_timeout(path, timeout);
+        };
+        if let Some(media_info) = self.get(path, key) {
+            return Ok(media_info);
+        }
+
+        let media_info = MediaInfo::detect_with_timeout(path, timeout)?;
+        if let Err(error) = self.insert(path, key, &media_info) {
+            eprintln!("Failed to cache media info for {}: {error}", path.display());
+        }
+        Ok(media_info)
+    }
+
+    fn get(&self, path: &Path, key: FileKey) -> Option<MediaInfo> {
+        let connection = self.connection.lock();
+        let info: String = connection
+            .query_row(
+                "SELECT info FROM media_info WHERE path = ?1 AND size = ?2 AND modified_ns = ?3",
+                rusqlite::params![path.to_string_lossy(), key.size, key.modified_ns],
+                |row| row.get(0),
+            )
+            .ok()?;
+        serde_json::from_str(&info).ok()
+    }
+
+    fn insert(&self, path: &Path, key: FileKey, media_info: &MediaInfo) -> rusqlite::Result<()> {
+        let info = serde_json::to_string(media_info)
+            .map_err(|error| rusqlite::Error::ToSqlConversionFailure(error.into()))?;
+        self.connection.lock().execute(
+            "INSERT OR REPLACE INTO media_info (path, size, modified_ns, info)
+            VALUES (?1, ?2, ?3, ?4)",
+            rusqlite::params![path.to_string_lossy(), key.size, key.modified_ns, info],
+        )?;
+        Ok(())
+    }
+
+    /// The cached verdict of the content classifier on `path`, see
+    /// [`ContentFilter`](crate::stream::ContentFilter).
+    pub fn classification(&self, path: &Path) -> Option<bool> {
+        let key = FileKey::of(path)?;
+        self.connection
+            .lock()
+            .query_row(
+                "SELECT allowed FROM classifications
+                WHERE path = ?1 AND size = ?2 AND modified_ns = ?3",
+                rusqlite::params![path.to_string_lossy(), key.size, key.modified_ns],
+                |row| row.get(0),
+            )
+            .ok()
+    }
+
+    pub fn set_classification(&self, path: &Path, allowed: bool) -> rusqlite::Result<()> {
+        let Some(key) = FileKey::of(path) else { return Ok(()) };
+        self.connection.lock().execute(
+            "INSERT OR REPLACE INTO classifications (path, size, modified_ns, allowed)
+            VALUES (?1, ?2, ?3, ?4)",
+            rusqlite::params![path.to_string_lossy(), key.size, key.modified_ns, allowed],
+        )?;
+        Ok(())
+    }
+
+    /// Forgets `path`, or everything if `None`. Returns how many entries were removed.
+    pub fn invalidate(&self, path: Option<&Path>) -> rusqlite::Result<usize> {
+        let connection = self.connection.lock();
+        match path {
+            Some(path) => connection
+                .execute("DELETE FROM media_info WHERE path = ?1", [path.to_string_lossy()]),
+            None => connection.execute("DELETE FROM media_info", []),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct FileKey {
+    size: i64,
+    modified_ns: i64,
+}
+
+impl FileKey {
+    fn of(path: &Path) -> Option<Self> {
+        let metadata = std::fs::metadata(path).ok()?;
+        let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        Some(Self {
+            size: i64::try_from(metadata.len()).ok()?,
+            modified_ns: i64::try_from(modified.as_nanos()).ok()?,
+        })
+    }
+}
@@ -1,3 +1,4 @@
+use std::io::{BufRead, Read};
 use std::path::Path;
 use std::sync::Arc;
 
@@ -5,8 +6,10 @@ use gstreamer::prelude::*;
 use gstreamer_pbutils::prelude::DiscovererStreamInfoExt;
 use gstreamer_pbutils::{
     Discoverer, DiscovererContainerInfo, DiscovererResult, DiscovererStreamInfo,
+    DiscovererVideoInfo,
 };
 use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
 
 use crate::media_type::MediaType;
 
@@ -16,31 +19,63 @@ pub enum Error {
     Glib(#[from] glib::Error),
     #[error(transparent)]
     GlibBool(#[from] glib::BoolError),
+    #[error("Discovery timed out")]
+    Timeout,
 }
 
-#[derive(Default, Debug, Copy, Clone, PartialEq, PartialOrd)]
+/// How long discovery waits for a file by default.
+pub const DEFAULT_DISCOVERY_TIMEOUT: gstreamer::ClockTime = gstreamer::ClockTime::from_seconds(5);
+
+#[derive(Default, Debug, Copy, Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub struct ImageInfo {
     pub horizontal_ppi: Option<f64>,
     pub vertical_ppi: Option<f64>,
 }
 
-#[derive(Default, Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
 pub struct StreamInfo {
     pub max_bitrate: Option<u32>,
     pub bitrate: Option<u32>,
 }
 
-#[derive(Default, Debug, Copy, Clone, PartialEq, PartialOrd)]
+#[derive(Default, Debug, Copy, Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub struct MediaInfo {
     pub duration: Option<gstreamer::ClockTime>,
     pub image: Option<ImageInfo>,
     pub video: Option<StreamInfo>,
     pub audio: Option<StreamInfo>,
+    /// Number of audio streams, `audio` only describes the first one.
+    pub audio_streams: usize,
+    /// Whether it's a live source, one that keeps producing data with no known end.
+    pub is_live: bool,
+    /// Whether the video is interlaced, or has interlaced parts.
+    #[serde(default)]
+    pub interlaced: bool,
+    /// Degrees clockwise the picture has to be turned to be upright, from its orientation tag.
+    #[serde(default)]
+    pub rotation: u16,
+    /// Whether it's an image with more than one frame, an animated GIF or an APNG. The discoverer
+    /// only looks at the first one.
+    #[serde(default)]
+    pub animated: bool,
+    /// Number of subtitle streams.
+    #[serde(default)]
+    pub subtitle_streams: usize,
 }
 
 impl MediaInfo {
     pub fn detect(path: &Path) -> Result<Self, Error> {
-        detect_media(path)
+        detect_media(path, DEFAULT_DISCOVERY_TIMEOUT)
+    }
+
+    pub fn detect_with_timeout(path: &Path, timeout: gstreamer::ClockTime) -> Result<Self, Error> {
+        detect_media(path, timeout)
+    }
+
+    /// The duration, if it is actually known.
+    /// Live sources, images and some containers report zero (or nothing) instead.
+    pub fn known_duration(&self) -> Option<gstreamer::ClockTime> {
+        self.duration.filter(|duration| *duration != gstreamer::ClockTime::ZERO)
     }
 
     pub fn is_empty(&self) -> bool {
@@ -56,6 +91,8 @@ impl MediaInfo {
             }
         } else if self.image.is_some() {
             MediaType::Image
+        } else if self.audio.is_some() {
+            MediaType::AudioOnly
         } else {
             MediaType::Unknown
         }
@@ -85,6 +122,11 @@ fn add_stream_info(info: &DiscovererStreamInfo, media_info: &Mutex<MediaInfo>) {
     let is_video = stream_nick == "video";
     let is_audio = stream_nick == "audio";
 
+    if stream_nick == "subtitles" {
+        media_info.subtitle_streams += 1;
+        return;
+    }
+
     if is_image {
         if media_info.image.is_some() {
             eprintln!("Image already set");
@@ -97,7 +139,11 @@ fn add_stream_info(info: &DiscovererStreamInfo, media_info: &Mutex<MediaInfo>) {
             return;
         }
         media_info.video = Some(StreamInfo::default());
+        media_info.interlaced = info
+            .downcast_ref::<DiscovererVideoInfo>()
+            .is_some_and(|video_info| video_info.is_interlaced());
     } else if is_audio {
+        media_info.audio_streams += 1;
         if media_info.audio.is_some() {
             eprintln!("Audio already set");
             return;
@@ -110,6 +156,12 @@ fn add_stream_info(info: &DiscovererStreamInfo, media_info: &Mutex<MediaInfo>) {
 
     let Some(tags) = info.tags() else { return };
 
+    if (is_image || is_video)
+        && let Some(orientation) = tags.get::<gstreamer::tags::ImageOrientation>()
+    {
+        media_info.rotation = orientation_rotation(orientation.get());
+    }
+
     if is_image {
         let image = media_info.image.as_mut().unwrap();
 
@@ -153,6 +205,15 @@ fn add_stream_info(info: &DiscovererStreamInfo, media_info: &Mutex<MediaInfo>) {
     }
 }
 
+/// Degrees clockwise of an `image-orientation` tag, e.g. `rotate-90` or `flip-rotate-270`.
+fn orientation_rotation(orientation: &str) -> u16 {
+    orientation
+        .trim_start_matches("flip-")
+        .strip_prefix("rotate-")
+        .and_then(|degrees| degrees.parse().ok())
+        .unwrap_or(0)
+}
+
 fn add_topology(info: &DiscovererStreamInfo, media_info: &Mutex<MediaInfo>) {
     add_stream_info(info, media_info);
 
@@ -165,16 +226,19 @@ fn add_topology(info: &DiscovererStreamInfo, media_info: &Mutex<MediaInfo>) {
     }
 }
 
-fn detect_media(path: &Path) -> Result<MediaInfo, Error> {
-    let loop_ = glib::MainLoop::new(None, false);
-    let timeout = 5 * gstreamer::ClockTime::SECOND;
+fn detect_media(path: &Path, timeout: gstreamer::ClockTime) -> Result<MediaInfo, Error> {
+    // A context of its own, so discoveries on different threads don't fight over the default one
+    let context = glib::MainContext::new();
+    let loop_ = glib::MainLoop::new(Some(&context), false);
 
     let uri = glib::filename_to_uri(path, None)?;
     let discoverer = Discoverer::new(timeout)?;
 
     let media_info = Arc::new(Mutex::new(MediaInfo::default()));
+    let timed_out = Arc::new(Mutex::new(false));
 
     let media_info_clone = media_info.clone();
+    let timed_out_clone = timed_out.clone();
     discoverer.connect_discovered(move |_discoverer, info, error| {
         let uri = info.uri();
         match info.result() {
@@ -189,7 +253,10 @@ fn detect_media(path: &Path) -> Result<MediaInfo, Error> {
                     eprintln!("Unknown error")
                 }
             }
-            DiscovererResult::Timeout => eprintln!("Timeout"),
+            DiscovererResult::Timeout => {
+                eprintln!("Timeout");
+                *timed_out_clone.lock() = true;
+            }
             DiscovererResult::Busy => eprintln!("Busy"),
             DiscovererResult::MissingPlugins => {
                 if let Some(s) = info.misc() {
@@ -203,7 +270,11 @@ fn detect_media(path: &Path) -> Result<MediaInfo, Error> {
             return;
         }
 
-        media_info_clone.lock().duration = info.duration();
+        {
+            let mut media_info = media_info_clone.lock();
+            media_info.duration = info.duration();
+            media_info.is_live = info.is_live();
+        }
         if let Some(stream_info) = info.stream_info() {
             add_topology(&stream_info, &media_info_clone);
         }
@@ -211,12 +282,121 @@ fn detect_media(path: &Path) -> Result<MediaInfo, Error> {
 
     let loop_clone = loop_.clone();
     discoverer.connect_finished(move |_| loop_clone.quit());
-    discoverer.start();
-    discoverer.discover_uri_async(&uri)?;
-
-    loop_.run();
-    discoverer.stop();
+    context.with_thread_default(|| -> Result<(), Error> {
+        // The discoverer attaches to the thread default context when started
+        discoverer.start();
+        discoverer.discover_uri_async(&uri)?;
+        loop_.run();
+        discoverer.stop();
+        Ok(())
+    })??;
+
+    if *timed_out.lock() {
+        return Err(Error::Timeout);
+    }
 
-    let media_info = *media_info.lock();
+    let mut media_info = *media_info.lock();
+    media_info.animated = media_info.audio.is_none() && is_animated_image(path);
     Ok(media_info)
 }
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Whether `path` is a GIF or PNG with more than one frame, from the file's own structure.
+fn is_animated_image(path: &Path) -> bool {
+    let Ok(file) = std::fs::File::open(path) else { return false };
+    let mut reader = std::io::BufReader::new(file);
+    let Ok(start) = reader.fill_buf() else { return false };
+    let (is_gif, is_png) = (start.starts_with(b"GIF8"), start.starts_with(PNG_SIGNATURE));
+    let animated = if is_gif {
+        is_animated_gif(&mut reader)
+    } else if is_png {
+        is_apng(&mut reader)
+    } else {
+        Ok(false)
+    };
+    animated.unwrap_or(false)
+}
+
+/// Walks a GIF's blocks until it finds a second image, or the end.
+fn is_animated_gif(reader: &mut impl Read) -> std::io::Result<bool> {
+    // Signature, version and logical screen descriptor
+    let mut header = [0; 13];
+    reader.read_exact(&mut header)?;
+    skip_color_table(reader, header[10])?;
+
+    let mut images = 0;
+    loop {
+        let mut introducer = [0];
+        reader.read_exact(&mut introducer)?;
+        match introducer[0] {
+            // Extension: label, then its data
+            0x21 => {
+                skip(reader, 1)?;
+                skip_sub_blocks(reader)?;
+            }
+            // Image: descriptor, local color table, LZW code size, then its data
+            0x2C => {
+                images += 1;
+                if images > 1 {
+                    return Ok(true);
+                }
+                let mut descriptor = [0; 9];
+                reader.read_exact(&mut descriptor)?;
+                skip_color_table(reader, descriptor[8])?;
+                skip(reader, 1)?;
+                skip_sub_blocks(reader)?;
+            }
+            // The trailer, or something that isn't a GIF after all
+            _ => return Ok(false),
+        }
+    }
+}
+
+/// Skips the color table that the packed `flags` byte of a GIF descriptor says follows it.
+fn skip_color_table(reader: &mut impl Read, flags: u8) -> std::io::Result<()> {
+    if flags & 0x80 == 0 {
+        return Ok(());
+    }
+    skip(reader, 3 << ((flags & 0x07) + 1))
+}
+
+fn skip_sub_blocks(reader: &mut impl Read) -> std::io::Result<()> {
+    loop {
+        let mut size = [0];
+        reader.read_exact(&mut size)?;
+        if size[0] == 0 {
+            return Ok(());
+        }
+        skip(reader, size[0].into())?;
+    }
+}
+
+/// Whether a PNG is an APNG of more than one frame, from its `acTL` chunk. That has to come before
+/// the image data, so nothing after it is read.
+fn is_apng(reader: &mut impl Read) -> std::io::Result<bool> {
+    skip(reader, PNG_SIGNATURE.len() as u64)?;
+    loop {
+        let mut header = [0; 8];
+        reader.read_exact(&mut header)?;
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+        match &header[4..] {
+            b"acTL" => {
+                let mut frames = [0; 4];
+                reader.read_exact(&mut frames)?;
+                return Ok(u32::from_be_bytes(frames) > 1);
+            }
+            b"IDAT" | b"IEND" => return Ok(false),
+            // The data and its CRC
+            _ => skip(reader, u64::from(length) + 4)?,
+        }
+    }
+}
+
+fn skip(reader: &mut impl Read, count: u64) -> std::io::Result<()> {
+    let skipped = std::io::copy(&mut reader.take(count), &mut std::io::sink())?;
+    if skipped < count {
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
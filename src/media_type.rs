@@ -1,100 +1,126 @@
+use std::io::Read;
 use std::path::Path;
-use std::sync::Arc;
-
-use gstreamer::prelude::*;
-use parking_lot::Mutex;
 
 use crate::stream::Error;
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+/// How much of the start of a file typefind gets to look at.
+/// Most typefinders need a few KiB, MPEG-TS and MP3 sync detection want more.
+const TYPEFIND_READ_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum MediaType {
     VideoWithAudio,
     VideoWithoutAudio,
     Image,
+    AudioOnly,
     Unknown,
 }
 
-/// Uses GStreamer's typefind to check if a file is a video or image.
-pub fn get_media_type(path: &Path) -> Result<MediaType, Error> {
-    // println!("TypeFind: Checking file {:?}", path);
-    let context = glib::MainContext::new();
-    let typefind_loop = glib::MainLoop::new(Some(&context), false);
-
-    let result: Arc<Mutex<Option<MediaType>>> = Arc::new(Mutex::new(None));
-
-    let pipeline = gstreamer::Pipeline::builder().name("typefind-pipeline").build();
-    let pipeline_clone = pipeline.clone();
-    let run_result = context.block_on(async {
-        let filesrc = gstreamer::ElementFactory::make("filesrc")
-            .property("location", path.to_str().unwrap())
-            .build()?;
-        let typefind = gstreamer::ElementFactory::make("typefind").build()?;
-
-        pipeline.add_many([&filesrc, &typefind])?;
-        gstreamer::Element::link_many([&filesrc, &typefind])?;
-
-        let result_clone = result.clone();
-        let typefind_loop_clone = typefind_loop.clone();
-
-        // Connect to typefind's "have-type" signal
-        typefind.connect("have-type", false, move |values| {
-            // values[0] = &Element
-            // values[1] = &u32 (probability)
-            // values[2] = &gst::Caps
-
-            if let Some(caps) = values.get(2).and_then(|v| v.get::<gstreamer::Caps>().ok()) {
-                let name = caps.structure(0).map(|s| s.name().as_str()).unwrap_or("<unknown>");
-                // println!("TypeFind: Found caps: {}", name);
+impl MediaType {
+    /// Typefind only sees the container, so videos are always reported as `VideoWithAudio`.
+    pub fn from_caps(caps: &gstreamer::CapsRef) -> Self {
+        let name = caps.structure(0).map(|s| s.name().as_str()).unwrap_or("<unknown>");
+        if name.starts_with("video/") {
+            MediaType::VideoWithAudio
+        } else if name.starts_with("image/") {
+            MediaType::Image
+        } else if name.starts_with("audio/") {
+            MediaType::AudioOnly
+        } else {
+            MediaType::Unknown
+        }
+    }
+
+    /// A guess from the file extension alone, for when even typefind is too expensive.
+    /// Anything that isn't obviously an image or audio is assumed to be a video.
+    pub fn guess_from_extension(path: &Path) -> Self {
+        let Some(extension) = path.extension().and_then(|e| e.to_str()) else {
+            return MediaType::Unknown;
+        };
+        let extension = extension.to_ascii_lowercase();
+        if IMAGE_EXTENSIONS.contains(&extension.as_str()) {
+            MediaType::Image
+        } else if AUDIO_EXTENSIONS.contains(&extension.as_str()) {
+            MediaType::AudioOnly
+        } else {
+            MediaType::VideoWithAudio
+        }
+    }
+}
 
-                let media_type = if name.starts_with("video/") {
-                    MediaType::VideoWithAudio
-                } else if name.starts_with("image/") {
-                    MediaType::Image
-                } else {
-                    MediaType::Unknown
-                };
-                *result_clone.lock() = Some(media_type);
-                typefind_loop_clone.quit();
-            }
-            None
-        });
+const IMAGE_EXTENSIONS: &[&str] =
+    &["jpg", "jpeg", "png", "gif", "bmp", "webp", "tif", "tiff", "heic", "avif"];
+const AUDIO_EXTENSIONS: &[&str] =
+    &["mp3", "flac", "ogg", "oga", "opus", "m4a", "aac", "wav", "wma", "aiff"];
 
-        // Also handle bus messages for errors or premature EOS
-        let bus = pipeline.bus().unwrap();
-        let typefind_loop_clone = typefind_loop.clone();
-        let result_clone = result.clone();
+/// Runs GStreamer's typefinders over the start of files, without building a pipeline.
+/// The read buffer is reused between files, so keep one around when checking many of them.
+#[derive(Debug, Default)]
+pub struct TypeFinder {
+    buffer: Vec<u8>,
+}
 
-        let _bus_watch = bus.add_watch_local(move |_, msg| {
-            match msg.view() {
-                // EOS before typefind could find anything (e.g., empty file)
-                gstreamer::MessageView::Eos(_) => {
-                    if result_clone.lock().is_none() {
-                        *result_clone.lock() = Some(MediaType::Unknown);
-                    }
-                    typefind_loop_clone.quit();
-                }
-                gstreamer::MessageView::Error(_) => {
-                    *result_clone.lock() = Some(MediaType::Unknown);
-                    typefind_loop_clone.quit();
-                }
-                _ => {}
+impl TypeFinder {
+    /// Returns the caps of the file's format, or `None` if no typefinder recognised it.
+    pub fn find(&mut self, path: &Path) -> Result<Option<gstreamer::Caps>, Error> {
+        let file = std::fs::File::open(path)?;
+        self.buffer.clear();
+        file.take(TYPEFIND_READ_SIZE as u64).read_to_end(&mut self.buffer)?;
+        if self.buffer.is_empty() {
+            return Ok(None);
+        }
+
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        let result = gstreamer_base::type_find_helper_for_data_with_extension(
+            None::<&gstreamer::Object>,
+            &self.buffer,
+            extension,
+        );
+        Ok(result.ok().map(|(caps, _probability)| caps))
+    }
+
+    /// A cheap check to throw out obvious non-media (subtitles, text, archives, ...) before
+    /// running a full discovery on the file.
+    pub fn is_probably_media(&mut self, path: &Path) -> Result<bool, Error> {
+        let Some(caps) = self.find(path)? else { return Ok(false) };
+        let name = caps.structure(0).map(|s| s.name().as_str()).unwrap_or("");
+        let is_media = match MediaType::from_caps(&caps) {
+            MediaType::Unknown => {
+                // Some containers (Ogg, MXF, MP3 with ID3 tags, ...) have application/ caps
+                name.starts_with("application/")
+                    && !name.starts_with("application/x-subtitle")
+                    && !NON_MEDIA_APPLICATION_CAPS.contains(&name)
             }
-            glib::ControlFlow::Continue
-        })?;
-
-        pipeline.set_state(gstreamer::State::Playing)?;
-        Ok::<(), Error>(())
-    });
-
-    _ = pipeline_clone.set_state(gstreamer::State::Null);
-
-    // Check for GStreamer errors
-    run_result?;
-
-    // This blocks until the typefind_loop (running in `block_on`) is quit
-    typefind_loop.run();
-    // println!("TypeFind: Loop finished.");
+            _ => true,
+        };
+        Ok(is_media)
+    }
+}
 
-    // Return the found type
-    Ok(result.lock().take().unwrap_or(MediaType::Unknown))
+const NON_MEDIA_APPLICATION_CAPS: &[&str] = &[
+    "application/pdf",
+    "application/postscript",
+    "application/xml",
+    "application/x-executable",
+    "application/x-compress",
+    "application/x-bzip",
+    "application/x-gzip",
+    "application/x-xz",
+    "application/zip",
+    "application/x-7z-compressed",
+    "application/x-rar",
+    "application/x-tar",
+    "application/x-ssa",
+    "application/x-ass",
+    "application/x-subtitle-vtt",
+    "application/x-hls",
+    "application/smil",
+    "application/ttml+xml",
+];
+
+/// Uses GStreamer's typefind to check if a file is a video, image or audio.
+pub fn get_media_type(path: &Path) -> Result<MediaType, Error> {
+    let caps = TypeFinder::default().find(path)?;
+    Ok(caps.map(|caps| MediaType::from_caps(&caps)).unwrap_or(MediaType::Unknown))
 }
@@ -1,19 +1,134 @@
 use std::process::{Child, Command, Stdio};
 use std::sync::{Arc, OnceLock};
 
-use crate::{RTSP_PORT, STREAM_KEY};
+/// Ports mediamtx listens on by default, its own defaults.
+pub const RTSP_PORT: u16 = 8554;
+pub const RTMP_PORT: u16 = 1935;
+pub const HLS_PORT: u16 = 8888;
+pub const WEBRTC_PORT: u16 = 8889;
+pub const SRT_PORT: u16 = 8890;
 
-fn config_yaml() -> String {
-    format!(
+/// The ports mediamtx serves the stream to clients on.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, serde::Serialize)]
+pub struct Ports {
+    pub rtsp: u16,
+    pub rtmp: u16,
+    pub hls: u16,
+    pub webrtc: u16,
+    pub srt: u16,
+}
+
+impl Default for Ports {
+    fn default() -> Self {
+        Self {
+            rtsp: RTSP_PORT,
+            rtmp: RTMP_PORT,
+            hls: HLS_PORT,
+            webrtc: WEBRTC_PORT,
+            srt: SRT_PORT,
+        }
+    }
+}
+
+/// What mediamtx serves, and how.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub ports: Ports,
+    /// The internal RTSP server the stream is pulled from.
+    pub rtsp_port: u16,
+    pub stream_key: String,
+    pub aliases: Vec<String>,
+    /// Where a live input can be published, if anywhere.
+    pub live_path: Option<String>,
+    /// Mount suffixes of the renditions, e.g. `_720`.
+    pub rendition_suffixes: Vec<String>,
+    /// Needed to read over SRT, and to publish the live input over it.
+    pub srt_passphrase: Option<String>,
+    /// If set, mediamtx asks this URL about every client, see
+    /// [`ViewerTokens::allows`](crate::viewer_tokens::ViewerTokens::allows).
+    pub auth_url: Option<String>,
+}
+
+fn config_yaml(config: &Config) -> String {
+    let Config {
+        ports,
+        rtsp_port,
+        stream_key,
+        aliases,
+        live_path,
+        rendition_suffixes,
+        srt_passphrase,
+        auth_url,
+    } = config;
+    let mut yaml = format!(
         "\
- paths:
-   {STREAM_KEY}:
-     source: rtsp://127.0.0.1:{RTSP_PORT}/{STREAM_KEY}
+rtspAddress: :{}
+rtmpAddress: :{}
+hlsAddress: :{}
+webrtcAddress: :{}
+srtAddress: :{}
+",
+        ports.rtsp, ports.rtmp, ports.hls, ports.webrtc, ports.srt
+    );
+    if let Some(auth_url) = auth_url {
+        yaml.push_str(&format!(
+            "\
+authMethod: http
+authHTTPAddress: {}
+authHTTPExclude:
+  - action: api
+  - action: metrics
+  - action: pprof
+",
+            yaml_string(auth_url)
+        ));
+    }
+    yaml.push_str("paths:\n");
+    // Only affects SRT, the other protocols can't be encrypted this way
+    let srt_read = srt_passphrase.as_deref().map_or(String::new(), |passphrase| {
+        format!("     srtReadPassphrase: {}\n", yaml_string(passphrase))
+    });
+    // Renditions are made from the main output, so it has to keep playing for them
+    let on_demand = if rendition_suffixes.is_empty() { "yes" } else { "no" };
+    // Aliases pull from the same RTSP mount, so old and new URLs show the same thing
+    for path in std::iter::once(stream_key).chain(aliases) {
+        yaml.push_str(&format!(
+            "   {path}:
+     source: rtsp://127.0.0.1:{rtsp_port}/{stream_key}
+     sourceOnDemand: {on_demand}
+     sourceOnDemandStartTimeout: 1m
+     sourceOnDemandCloseAfter: 1m
+{srt_read}"
+        ));
+    }
+    for suffix in rendition_suffixes {
+        yaml.push_str(&format!(
+            "   {stream_key}{suffix}:
+     source: rtsp://127.0.0.1:{rtsp_port}/{stream_key}{suffix}
      sourceOnDemand: yes
      sourceOnDemandStartTimeout: 1m
      sourceOnDemandCloseAfter: 1m
+{srt_read}"
+        ));
+    }
+    // Somewhere for a live input to be published (e.g. over RTMP), which takes over the stream
+    if let Some(live_path) = live_path {
+        yaml.push_str(&format!(
+            "\
+   {live_path}:
+     source: publisher
 "
-    )
+        ));
+        if let Some(passphrase) = srt_passphrase.as_deref() {
+            yaml.push_str(&format!("     srtPublishPassphrase: {}\n", yaml_string(passphrase)));
+        }
+    }
+    yaml
+}
+
+/// `value` as a single quoted YAML string.
+fn yaml_string(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
 }
 
 const MEDIAMTX_BIN: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/mediamtx"));
@@ -40,16 +155,16 @@ fn get_mediamtx_dir() -> &'static Result<Arc<tempfile::TempDir>, Arc<std::io::Er
             std::fs::set_permissions(&mediamtx_bin, perms)?;
         }
 
-        let mediamtx_yml = dir.path().join("mediamtx.yml");
-        std::fs::write(&mediamtx_yml, config_yaml())?;
-
         Ok(Arc::new(dir))
     })
 }
 
-pub fn start() -> Result<Child, Arc<std::io::Error>> {
+pub fn start(config: &Config) -> Result<Child, Arc<std::io::Error>> {
     let dir = get_mediamtx_dir().as_ref().map_err(Arc::clone)?;
 
+    let mediamtx_yml = dir.path().join("mediamtx.yml");
+    std::fs::write(&mediamtx_yml, config_yaml(config)).map_err(Arc::new)?;
+
     let mut mediamtx_bin = dir.path().join("mediamtx");
     if cfg!(windows) {
         mediamtx_bin.set_extension("exe");
@@ -62,3 +177,38 @@ pub fn start() -> Result<Child, Arc<std::io::Error>> {
         .spawn()
         .map_err(Arc::new)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn yaml_string_escapes_quotes() {
+        assert_eq!(yaml_string("plain"), "'plain'");
+        assert_eq!(yaml_string("it's"), "'it''s'");
+        assert_eq!(yaml_string("a: #b"), "'a: #b'");
+        assert_eq!(yaml_string(""), "''");
+    }
+
+    #[test]
+    fn quotes_the_passphrase_and_auth_url() {
+        let config = Config {
+            ports: Ports::default(),
+            rtsp_port: 8654,
+            stream_key: "live".to_string(),
+            aliases: vec!["old".to_string()],
+            live_path: Some("input".to_string()),
+            rendition_suffixes: Vec::new(),
+            srt_passphrase: Some("it's: a #secret".to_string()),
+            auth_url: Some("http://127.0.0.1:3000/mediamtx/auth".to_string()),
+        };
+        let yaml = config_yaml(&config);
+        assert!(yaml.contains("authHTTPAddress: 'http://127.0.0.1:3000/mediamtx/auth'\n"));
+        assert!(yaml.contains("srtReadPassphrase: 'it''s: a #secret'\n"));
+        assert!(yaml.contains("srtPublishPassphrase: 'it''s: a #secret'\n"));
+        assert!(yaml.contains("source: rtsp://127.0.0.1:8654/live\n"));
+        assert!(yaml.contains("   old:\n"));
+        // Both the stream and its alias need it to read, the live input to publish
+        assert_eq!(yaml.matches("srtReadPassphrase").count(), 2);
+    }
+}
@@ -0,0 +1,20 @@
+//! Paths in JSON, which only has (UTF-8) strings: anything in a path that isn't valid UTF-8 is
+//! replaced, so they're for showing, not for opening.
+
+use std::path::{Path, PathBuf};
+
+/// For `#[serde(serialize_with = "...")]` on a path.
+pub fn serialize_lossy<S: serde::Serializer>(
+    path: &Path,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&path.to_string_lossy())
+}
+
+/// For `#[serde(serialize_with = "...")]` on a list of paths.
+pub fn serialize_all_lossy<S: serde::Serializer>(
+    paths: &[PathBuf],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(paths.iter().map(|path| path.to_string_lossy()))
+}
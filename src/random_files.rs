@@ -1,21 +1,448 @@
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use notify::Watcher;
+use parking_lot::Mutex;
 use rand::Rng;
-use rand::seq::SliceRandom;
+use rand::seq::{IndexedRandom, SliceRandom};
 use rayon::iter::{IntoParallelRefIterator, ParallelBridge, ParallelIterator};
 
+use crate::stream::Event;
+
+/// Extensions that are never media, skipped unless the filter says otherwise.
+const DEFAULT_EXCLUDED_EXTENSIONS: &[&str] = &[
+    "nfo", "txt", "srt", "ass", "ssa", "sub", "idx", "vtt", "xml", "json", "db", "ini", "log",
+    "md", "pdf", "zip", "rar", "7z", "part", "torrent", "sfv", "md5", "url", "lnk", "ds_store",
+    "toml",
+];
+
+/// Directories NAS boxes and operating systems litter libraries with, never walked.
+const DEFAULT_IGNORED_PATHS: &[&str] =
+    &["**/@eaDir", "**/#recycle", "**/$RECYCLE.BIN", "**/System Volume Information"];
+
+/// Which files in the root directories can be picked.
+/// Extensions are compared case-insensitively, without the dot. Globs are matched against the
+/// whole path.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct FileFilter {
+    /// If not empty, only files with one of these extensions are picked.
+    pub include_extensions: Vec<String>,
+    pub exclude_extensions: Vec<String>,
+    /// If not empty, only files matching one of these are picked.
+    pub include_globs: Vec<String>,
+    pub exclude_globs: Vec<String>,
+    /// Globs matched against paths relative to the root, e.g. `**/extras/**`.
+    /// Unlike `exclude_globs` these are checked while walking, so ignored directories aren't read.
+    pub ignore_paths: Vec<String>,
+    /// Skips files and directories whose name starts with a dot.
+    pub skip_hidden: bool,
+}
+
+impl Default for FileFilter {
+    fn default() -> Self {
+        Self {
+            include_extensions: Vec::new(),
+            exclude_extensions: DEFAULT_EXCLUDED_EXTENSIONS.iter().map(|e| e.to_string()).collect(),
+            include_globs: Vec::new(),
+            exclude_globs: Vec::new(),
+            ignore_paths: DEFAULT_IGNORED_PATHS.iter().map(|p| p.to_string()).collect(),
+            skip_hidden: true,
+        }
+    }
+}
+
+impl FileFilter {
+    pub fn compile(&self) -> Result<FileMatcher, globset::Error> {
+        let extensions = |extensions: &[String]| {
+            extensions
+                .iter()
+                .map(|extension| extension.trim_start_matches('.').to_ascii_lowercase())
+                .collect()
+        };
+        let include_globs = if self.include_globs.is_empty() {
+            None
+        } else {
+            Some(glob_set(&self.include_globs)?)
+        };
+        Ok(FileMatcher {
+            include_extensions: extensions(&self.include_extensions),
+            exclude_extensions: extensions(&self.exclude_extensions),
+            include_globs,
+            exclude_globs: glob_set(&self.exclude_globs)?,
+            ignored_paths: glob_set(&self.ignore_paths)?,
+            skip_hidden: self.skip_hidden,
+        })
+    }
+}
+
+fn glob_set(patterns: &[String]) -> Result<GlobSet, globset::Error> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern)?);
+    }
+    builder.build()
+}
+
+/// A compiled [`FileFilter`]. The default matches everything.
+#[derive(Debug, Clone, Default)]
+pub struct FileMatcher {
+    include_extensions: HashSet<String>,
+    exclude_extensions: HashSet<String>,
+    include_globs: Option<GlobSet>,
+    exclude_globs: GlobSet,
+    ignored_paths: GlobSet,
+    skip_hidden: bool,
+}
+
+impl FileMatcher {
+    pub fn is_match(&self, path: &Path) -> bool {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase())
+            .unwrap_or_default();
+        if !self.include_extensions.is_empty() && !self.include_extensions.contains(&extension) {
+            return false;
+        }
+        if self.exclude_extensions.contains(&extension) {
+            return false;
+        }
+        if let Some(include_globs) = &self.include_globs
+            && !include_globs.is_match(path)
+        {
+            return false;
+        }
+        !self.exclude_globs.is_match(path)
+    }
+
+    /// Whether the walk should leave out `relative_path` (and everything under it, if it's a
+    /// directory).
+    pub fn is_ignored(&self, relative_path: &Path, is_dir: bool) -> bool {
+        // With a trailing separator `**/extras/**` also matches the `extras` directory itself
+        self.ignored_paths.is_match(relative_path)
+            || (is_dir && self.ignored_paths.is_match(relative_path.join("")))
+    }
+}
+
+/// Weight of roots that weren't given one, when others were.
+const DEFAULT_WEIGHT: u32 = 1;
+
+/// How many times a file still cooling down is drawn again, before it's picked anyway.
+/// Every draw scans the library, and small libraries may not have anything else to pick.
+const MAX_REDRAWS: usize = 5;
+
+/// Most files a duration [`Cooldown`] remembers.
+const MAX_COOLDOWN_ENTRIES: usize = 10_000;
+
+/// How long a picked file is kept from being picked again.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Cooldown {
+    /// Until this many other files have been picked.
+    Count(usize),
+    /// Until this long after it was picked.
+    Duration(Duration),
+}
+
+/// The files picked within a [`Cooldown`], oldest first.
 #[derive(Debug, Clone)]
-pub struct RandomFiles {
+struct RecentlyPicked {
+    cooldown: Cooldown,
+    picks: VecDeque<(PathBuf, Instant)>,
+}
+
+impl RecentlyPicked {
+    fn contains(&mut self, path: &Path) -> bool {
+        if let Cooldown::Duration(duration) = self.cooldown {
+            while self.picks.front().is_some_and(|(_, at)| at.elapsed() >= duration) {
+                self.picks.pop_front();
+            }
+        }
+        self.picks.iter().any(|(picked, _)| picked == path)
+    }
+
+    fn push(&mut self, path: PathBuf) {
+        let capacity = match self.cooldown {
+            Cooldown::Count(count) => count,
+            Cooldown::Duration(_) => MAX_COOLDOWN_ENTRIES,
+        };
+        if capacity == 0 {
+            return;
+        }
+        if self.picks.len() >= capacity {
+            self.picks.pop_front();
+        }
+        self.picks.push_back((path, Instant::now()));
+    }
+}
+
+/// How often a root that couldn't be read is checked for whether it's back.
+const UNAVAILABLE_RECHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The root directories, shared so they can be added and removed while files are being picked.
+///
+/// Pickers catch up on their next pick: removed roots are dropped straight away, and added ones
+/// are listed in the background and picked from once that's done. Changes aren't saved anywhere.
+///
+/// Roots that stop being readable, e.g. an unmounted NAS, aren't picked from until they're back,
+/// rather than failing every file picked from them.
+#[derive(Debug, Clone, Default)]
+pub struct LibraryRoots(Arc<Mutex<RootList>>);
+
+#[derive(Debug, Default)]
+struct RootList {
     roots: Vec<PathBuf>,
+    /// Bumped on every change, so pickers can tell theirs are out of date.
+    generation: u64,
+    /// Roots that couldn't be read, and when that was last checked.
+    unavailable: HashMap<PathBuf, Instant>,
+    event_tx: Option<flume::Sender<Event>>,
+}
+
+impl LibraryRoots {
+    pub fn new<I>(root_dirs: I) -> Self
+    where
+        I: IntoIterator<Item: Into<PathBuf>>,
+    {
+        let roots = root_dirs.into_iter().map(Into::into).collect();
+        Self(Arc::new(Mutex::new(RootList { roots, ..RootList::default() })))
+    }
+
+    /// Sends [`Event::RootUnavailable`] and [`Event::RootAvailable`] as roots go and come back.
+    pub fn with_events(self, event_tx: flume::Sender<Event>) -> Self {
+        self.0.lock().event_tx = Some(event_tx);
+        self
+    }
+
+    pub fn list(&self) -> Vec<PathBuf> {
+        self.0.lock().roots.clone()
+    }
+
+    /// Adds `root`, a directory or a file, unless it already is one. Returns whether it was added.
+    pub fn add(&self, root: PathBuf) -> bool {
+        let mut list = self.0.lock();
+        if list.roots.contains(&root) {
+            return false;
+        }
+        list.roots.push(root);
+        list.generation += 1;
+        true
+    }
+
+    /// Removes `root`, returns whether it was one.
+    pub fn remove(&self, root: &Path) -> bool {
+        let mut list = self.0.lock();
+        let count = list.roots.len();
+        list.roots.retain(|other| other != root);
+        if list.roots.len() == count {
+            return false;
+        }
+        list.generation += 1;
+        true
+    }
+
+    /// Whether the root `path` is in can still be read, to tell a broken file from a root that
+    /// went away. A root that can't be read is marked unavailable, like when a pick from it finds
+    /// that. Paths outside the roots count as readable.
+    pub fn root_is_readable(&self, path: &Path) -> bool {
+        let root = self
+            .list()
+            .into_iter()
+            .filter(|root| path.starts_with(root))
+            .max_by_key(|root| root.as_os_str().len());
+        root.is_none_or(|root| !self.report_unreadable(&root))
+    }
+
+    fn snapshot(&self) -> (u64, Vec<PathBuf>) {
+        let list = self.0.lock();
+        (list.generation, list.roots.clone())
+    }
+
+    fn is_unavailable(&self, root: &Path) -> bool {
+        self.0.lock().unavailable.contains_key(root)
+    }
+
+    /// Marks `root` as unavailable if a file in it couldn't be read because the root itself can't
+    /// be. Returns whether it's unavailable.
+    fn report_unreadable(&self, root: &Path) -> bool {
+        // Not holding the lock, a dead mount can take a while to answer
+        let Err(reason) = probe_root(root) else { return false };
+        let mut list = self.0.lock();
+        if list.unavailable.insert(root.to_path_buf(), Instant::now()).is_none() {
+            eprintln!("Not picking from {} until it's back: {reason}", root.display());
+            if let Some(event_tx) = &list.event_tx {
+                _ = event_tx.send(Event::RootUnavailable { root: root.to_path_buf(), reason });
+            }
+        }
+        true
+    }
+
+    /// Checks whether an unavailable `root` is back, if it's been long enough since the last
+    /// check. Returns whether it just came back.
+    fn recheck(&self, root: &Path) -> bool {
+        {
+            let mut list = self.0.lock();
+            let Some(checked_at) = list.unavailable.get_mut(root) else { return false };
+            if checked_at.elapsed() < UNAVAILABLE_RECHECK_INTERVAL {
+                return false;
+            }
+            *checked_at = Instant::now();
+        }
+        if probe_root(root).is_err() {
+            return false;
+        }
+        let mut list = self.0.lock();
+        list.unavailable.remove(root);
+        println!("{} is back", root.display());
+        if let Some(event_tx) = &list.event_tx {
+            _ = event_tx.send(Event::RootAvailable { root: root.to_path_buf() });
+        }
+        true
+    }
+}
+
+/// Whether a root can be read, and isn't an empty directory, which is what a mount point usually
+/// looks like with nothing mounted on it.
+fn probe_root(root: &Path) -> Result<(), String> {
+    let metadata = std::fs::metadata(root).map_err(|error| error.to_string())?;
+    if !metadata.is_dir() {
+        return Ok(());
+    }
+    let mut entries = std::fs::read_dir(root).map_err(|error| error.to_string())?;
+    match entries.next() {
+        Some(Ok(_)) => Ok(()),
+        Some(Err(error)) => Err(error.to_string()),
+        None => Err("it's empty, it may not be mounted".to_string()),
+    }
+}
+
+/// How often the index is rebuilt from scratch, in case the watcher missed something (e.g. on
+/// network mounts, which don't report changes).
+const INDEX_REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Picks random files from the roots.
+///
+/// The roots are indexed on the first pick, and kept up to date by watching them and rebuilding
+/// the index every [`INDEX_REFRESH_INTERVAL`]. Clones share the index and the cooldown.
+#[derive(Debug, Clone)]
+pub struct RandomFiles {
+    roots: LibraryRoots,
+    matcher: Arc<FileMatcher>,
+    weights: Arc<HashMap<PathBuf, u32>>,
+    recent: Option<Arc<Mutex<RecentlyPicked>>>,
+    index: Arc<Mutex<Option<LibraryIndex>>>,
 }
 
 impl RandomFiles {
+    /// Picks from the root directories, skipping files excluded by the default [`FileFilter`].
     pub fn new<I>(root_dirs: I) -> Self
     where
         I: IntoIterator<Item: Into<PathBuf>>,
     {
-        let roots: Vec<_> = root_dirs.into_iter().map(Into::into).collect();
-        Self { roots }
+        Self::from_roots(LibraryRoots::new(root_dirs))
+    }
+
+    /// Picks from `roots`, following them as they're added and removed.
+    pub fn from_roots(roots: LibraryRoots) -> Self {
+        let matcher = FileFilter::default().compile().unwrap_or_default();
+        Self {
+            roots,
+            matcher: Arc::new(matcher),
+            weights: Arc::default(),
+            recent: None,
+            index: Arc::default(),
+        }
+    }
+
+    /// Only picks files `matcher` matches. Roots that are files are always picked.
+    pub fn with_matcher(mut self, matcher: FileMatcher) -> Self {
+        self.matcher = Arc::new(matcher);
+        self
+    }
+
+    /// Picks from each root in proportion to its weight, rather than to how many files it has.
+    /// Roots without a weight get 1, a weight of 0 means the root is never picked from.
+    pub fn with_weights<I>(mut self, weights: I) -> Self
+    where
+        I: IntoIterator<Item = (PathBuf, u32)>,
+    {
+        let weights: HashMap<_, _> = weights.into_iter().collect();
+        let roots = self.roots.list();
+        for root in weights.keys().filter(|root| !roots.contains(root)) {
+            eprintln!("Ignoring weight for {}, it isn't a root directory", root.display());
+        }
+        self.weights = Arc::new(weights);
+        self
+    }
+
+    /// Keeps files from being picked again within `cooldown`, unless the library is too small
+    /// to find anything else.
+    pub fn with_cooldown(mut self, cooldown: Cooldown) -> Self {
+        let recent = RecentlyPicked { cooldown, picks: VecDeque::new() };
+        self.recent = Some(Arc::new(Mutex::new(recent)));
+        self
+    }
+
+    /// How likely `root` is to be picked from, relative to the other roots.
+    fn share(&self, root: &Path, file_count: u64) -> u64 {
+        if self.roots.is_unavailable(root) {
+            return 0;
+        }
+        // Without weights every file is equally likely, so roots with more files come up more
+        if self.weights.is_empty() || file_count == 0 {
+            return file_count;
+        }
+        u64::from(self.weights.get(root).copied().unwrap_or(DEFAULT_WEIGHT))
+    }
+
+    /// Picks a random file, ignoring the cooldown.
+    fn draw(&self) -> Option<PathBuf> {
+        let mut index = self.index.lock();
+        if index
+            .as_ref()
+            .is_none_or(|index| index.built_at.elapsed() >= INDEX_REFRESH_INTERVAL)
+        {
+            *index = Some(LibraryIndex::build(&self.roots, &self.matcher));
+        }
+        let index = index.as_mut()?;
+        index.update_roots(&self.roots, &self.matcher);
+        index.apply_changes(&self.matcher);
+        index.recheck_unavailable(&self.roots, &self.matcher);
+
+        // Drawn again when the root turns out to be unavailable, which leaves it out of the shares
+        loop {
+            let (root, path) = self.draw_from(index)?;
+            if std::fs::metadata(&path).is_err() && self.roots.report_unreadable(root) {
+                continue;
+            }
+            return Some(path);
+        }
+    }
+
+    /// Picks a root by its share, then a file in it.
+    fn draw_from<'a>(&self, index: &'a LibraryIndex) -> Option<(&'a Path, PathBuf)> {
+        let shares: Vec<_> = index
+            .roots
+            .iter()
+            .map(|root| self.share(&root.root, root.files.len() as u64))
+            .collect();
+        let total_shares = shares.iter().sum();
+        if total_shares == 0 {
+            return None;
+        }
+
+        let mut rng = rand::rng();
+        let mut pick = rng.random_range(0..total_shares);
+        for (share, root) in shares.into_iter().zip(&index.roots) {
+            if pick < share {
+                return root.files.choose(&mut rng).map(|path| (root.root.as_path(), path.clone()));
+            }
+
+            pick -= share;
+        }
+        None
     }
 }
 
@@ -23,79 +450,531 @@ impl Iterator for RandomFiles {
     type Item = PathBuf;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.roots.shuffle(&mut rand::rng());
-        let results = self.roots.par_iter().map(|p| scan_root(p)).collect::<Vec<_>>();
+        let mut path = self.draw()?;
+        for _ in 0..MAX_REDRAWS {
+            if !self.recent.as_ref().is_some_and(|recent| recent.lock().contains(&path)) {
+                break;
+            }
+            path = self.draw()?;
+        }
+        if let Some(recent) = &self.recent {
+            recent.lock().push(path.clone());
+        }
+        Some(path)
+    }
+}
 
-        let total_files = results.iter().map(|r| r.count).sum();
-        if total_files == 0 {
-            return None;
+/// The files in every root, so picks don't have to walk the library.
+#[derive(Debug)]
+struct LibraryIndex {
+    roots: Vec<IndexedRoot>,
+    /// Roots added since the index was built, still being listed.
+    pending: Vec<PendingRoot>,
+    /// The [`LibraryRoots`] generation `roots` and `pending` are up to date with.
+    generation: u64,
+    built_at: Instant,
+    watcher: Option<LibraryWatcher>,
+}
+
+impl LibraryIndex {
+    fn build(roots: &LibraryRoots, matcher: &Arc<FileMatcher>) -> Self {
+        let started_at = Instant::now();
+        let (generation, roots) = roots.snapshot();
+        // Watch first, so nothing that changes during the scan is missed
+        let watcher = LibraryWatcher::start(&roots)
+            .inspect_err(|error| eprintln!("Failed to watch the library for changes: {error}"))
+            .ok();
+        let roots: Vec<_> = roots
+            .par_iter()
+            .map(|root| IndexedRoot::new(root.clone(), list_root(root, matcher)))
+            .collect();
+        let file_count: usize = roots.iter().map(|root| root.files.len()).sum();
+        println!("Indexed {file_count} files in {:.1?}", started_at.elapsed());
+        Self {
+            roots,
+            pending: Vec::new(),
+            generation,
+            built_at: Instant::now(),
+            watcher,
         }
+    }
 
-        let mut rng = rand::rng();
-        let mut index = rng.random_range(0..total_files);
-        for result in results {
-            if index < result.count {
-                return result.selected;
+    /// Catches up with roots that were added or removed, and takes in the ones done listing.
+    fn update_roots(&mut self, roots: &LibraryRoots, matcher: &Arc<FileMatcher>) {
+        let (generation, roots) = roots.snapshot();
+        if generation != self.generation {
+            self.generation = generation;
+            let indexed = self.roots.iter().map(|root| &root.root);
+            let pending = self.pending.iter().map(|pending| &pending.root);
+            let known: Vec<PathBuf> = indexed.chain(pending).cloned().collect();
+            for removed in known.iter().filter(|root| !roots.contains(root)) {
+                println!("No longer picking from {}", removed.display());
+                if let Some(watcher) = &mut self.watcher {
+                    watcher.unwatch(removed);
+                }
+            }
+            self.roots.retain(|root| roots.contains(&root.root));
+            self.pending.retain(|pending| roots.contains(&pending.root));
+            for added in roots.into_iter().filter(|root| !known.contains(root)) {
+                if let Some(watcher) = &mut self.watcher {
+                    watcher.watch(&added);
+                }
+                self.pending.push(PendingRoot::start(added, matcher));
             }
+        }
 
-            index -= result.count;
+        for pending in std::mem::take(&mut self.pending) {
+            match pending.files.try_recv() {
+                Ok(files) => {
+                    println!("Indexed {} files in {}", files.len(), pending.root.display());
+                    // Replacing the old listing of a root that came back
+                    self.roots.retain(|root| root.root != pending.root);
+                    self.roots.push(IndexedRoot::new(pending.root, files));
+                }
+                Err(flume::TryRecvError::Empty) => self.pending.push(pending),
+                Err(flume::TryRecvError::Disconnected) => {
+                    eprintln!("Failed to index {}", pending.root.display());
+                }
+            }
         }
-        None
+    }
+
+    /// Lists roots that are back after being unavailable again, the watcher may have missed what
+    /// changed while they were gone. The old listing is picked from until that's done.
+    fn recheck_unavailable(&mut self, roots: &LibraryRoots, matcher: &Arc<FileMatcher>) {
+        for root in &self.roots {
+            if roots.recheck(&root.root) {
+                self.pending.push(PendingRoot::start(root.root.clone(), matcher));
+            }
+        }
+    }
+
+    /// Brings the index up to date with what the watcher saw change.
+    fn apply_changes(&mut self, matcher: &Arc<FileMatcher>) {
+        let Some(watcher) = &self.watcher else { return };
+        for path in watcher.changes() {
+            let Some(root) = self.roots.iter_mut().find(|root| path.starts_with(&root.root)) else {
+                continue;
+            };
+            if path.exists() {
+                for file in files_at(&root.root, &path, matcher) {
+                    root.insert(file);
+                }
+            } else {
+                root.remove(&path);
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct IndexedRoot {
+    root: PathBuf,
+    files: Vec<PathBuf>,
+    /// Where each file is in `files`, so it can be removed without a search.
+    positions: HashMap<PathBuf, usize>,
+}
+
+impl IndexedRoot {
+    fn new(root: PathBuf, files: Vec<PathBuf>) -> Self {
+        let positions = files.iter().enumerate().map(|(i, file)| (file.clone(), i)).collect();
+        Self { root, files, positions }
+    }
+
+    fn insert(&mut self, file: PathBuf) {
+        if self.positions.contains_key(&file) {
+            return;
+        }
+        self.positions.insert(file.clone(), self.files.len());
+        self.files.push(file);
+    }
+
+    /// Removes a file, or everything in a directory.
+    fn remove(&mut self, path: &Path) {
+        if let Some(position) = self.positions.remove(path) {
+            self.files.swap_remove(position);
+            if let Some(moved) = self.files.get(position) {
+                self.positions.insert(moved.clone(), position);
+            }
+            return;
+        }
+        let count = self.files.len();
+        self.files.retain(|file| !file.starts_with(path));
+        if self.files.len() != count {
+            *self = Self::new(std::mem::take(&mut self.root), std::mem::take(&mut self.files));
+        }
+    }
+}
+
+/// Every file `matcher` lets through in `root`, which is either a directory or a file that's
+/// always included.
+fn list_root(root: &Path, matcher: &Arc<FileMatcher>) -> Vec<PathBuf> {
+    let Ok(metadata) = std::fs::metadata(root) else { return Vec::new() };
+    if metadata.is_dir() {
+        walk_files(root, matcher).collect()
+    } else {
+        vec![root.to_path_buf()]
+    }
+}
+
+/// The files a walk of `root` would find at `path`: the file itself, or the files in a directory.
+fn files_at(root: &Path, path: &Path, matcher: &Arc<FileMatcher>) -> Vec<PathBuf> {
+    if path == root {
+        return list_root(root, matcher);
+    }
+    if is_excluded(root, path, matcher) {
+        return Vec::new();
+    }
+    if path.is_dir() {
+        walk_files(path, matcher).collect()
+    } else if matcher.is_match(path) {
+        vec![path.to_path_buf()]
+    } else {
+        Vec::new()
+    }
+}
+
+/// Whether `path`, or a directory it's in, is hidden or ignored by the matcher, the way walking
+/// `root` would skip it.
+fn is_excluded(root: &Path, path: &Path, matcher: &FileMatcher) -> bool {
+    let Ok(relative_path) = path.strip_prefix(root) else { return true };
+    let mut ancestors = relative_path.ancestors();
+    ancestors.filter(|ancestor| !ancestor.as_os_str().is_empty()).any(|ancestor| {
+        let is_hidden =
+            ancestor.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.'));
+        let is_dir = ancestor != relative_path || path.is_dir();
+        (matcher.skip_hidden && is_hidden) || matcher.is_ignored(ancestor, is_dir)
+    })
+}
+
+/// Every file under the directory `root` that `matcher` lets through.
+fn walk_files(
+    root: &Path,
+    matcher: &Arc<FileMatcher>,
+) -> impl ParallelIterator<Item = PathBuf> + use<> {
+    let root_clone = root.to_path_buf();
+    let walk_matcher = matcher.clone();
+    let walk_dir = jwalk::WalkDir::new(root)
+        .parallelism(jwalk::Parallelism::RayonDefaultPool {
+            busy_timeout: std::time::Duration::from_secs(1),
+        })
+        .skip_hidden(matcher.skip_hidden)
+        // Entries removed here aren't yielded, and removed directories aren't read at all
+        .process_read_dir(move |_, _, _, children| {
+            children.retain(|entry| {
+                let Ok(entry) = entry else { return true };
+                let path = entry.path();
+                let relative_path = path.strip_prefix(&root_clone).unwrap_or(&path);
+                !walk_matcher.is_ignored(relative_path, entry.file_type().is_dir())
+            });
+        });
+
+    let matcher = matcher.clone();
+    walk_dir.into_iter().par_bridge().filter_map(move |entry| {
+        let entry = entry.ok()?;
+        if entry.file_type().is_dir() {
+            return None;
+        }
+        let path = entry.path();
+        matcher.is_match(&path).then_some(path)
+    })
+}
+
+/// A root that was added while running, being listed on a thread of its own so a big one doesn't
+/// hold up picks.
+#[derive(Debug)]
+struct PendingRoot {
+    root: PathBuf,
+    files: flume::Receiver<Vec<PathBuf>>,
+}
+
+impl PendingRoot {
+    fn start(root: PathBuf, matcher: &Arc<FileMatcher>) -> Self {
+        println!("Indexing {}", root.display());
+        let (files_tx, files) = flume::bounded(1);
+        let root_clone = root.clone();
+        let matcher = matcher.clone();
+        std::thread::spawn(move || _ = files_tx.send(list_root(&root_clone, &matcher)));
+        Self { root, files }
     }
 }
 
-struct ScanResult<T> {
-    selected: Option<T>,
-    count: u64,
+/// Watches the roots for files being added, removed or renamed.
+#[derive(Debug)]
+struct LibraryWatcher {
+    // Stops watching when dropped
+    watcher: notify::RecommendedWatcher,
+    changed_paths: flume::Receiver<PathBuf>,
 }
 
-fn scan_root(path: &Path) -> ScanResult<PathBuf> {
-    let identity = || ScanResult { selected: None, count: 0 };
+impl LibraryWatcher {
+    fn start(roots: &[PathBuf]) -> notify::Result<Self> {
+        use notify::EventKind;
+        use notify::event::ModifyKind;
 
-    let Ok(metadata) = std::fs::metadata(path) else { return identity() };
-    if !metadata.file_type().is_dir() {
-        return ScanResult { selected: Some(path.to_path_buf()), count: 1 };
+        let (changed_tx, changed_paths) = flume::unbounded();
+        let handle_event = move |event: notify::Result<notify::Event>| {
+            let Ok(event) = event else { return };
+            let is_change = match event.kind {
+                EventKind::Create(_) | EventKind::Remove(_) => true,
+                EventKind::Modify(kind) => matches!(kind, ModifyKind::Name(_)),
+                _ => false,
+            };
+            if !is_change {
+                return;
+            }
+            for path in event.paths {
+                _ = changed_tx.send(path);
+            }
+        };
+        let watcher = notify::recommended_watcher(handle_event)?;
+        let mut library_watcher = Self { watcher, changed_paths };
+        for root in roots {
+            library_watcher.watch(root);
+        }
+        Ok(library_watcher)
     }
 
-    let walk_dir = jwalk::WalkDir::new(path).parallelism(jwalk::Parallelism::RayonDefaultPool {
-        busy_timeout: std::time::Duration::from_secs(1),
-    });
+    fn watch(&mut self, root: &Path) {
+        if let Err(error) = self.watcher.watch(root, notify::RecursiveMode::Recursive) {
+            eprintln!("Failed to watch {} for changes: {error}", root.display());
+        }
+    }
 
-    let reduce = |mut a: ScanResult<PathBuf>, b: ScanResult<PathBuf>| -> ScanResult<PathBuf> {
-        let total_count = a.count.saturating_add(b.count);
+    fn unwatch(&mut self, root: &Path) {
+        // Fails if it wasn't being watched, e.g. because watching it failed
+        _ = self.watcher.unwatch(root);
+    }
 
-        // If one side is empty, just return the other
-        if total_count == 0 {
-            return identity();
+    /// Paths that were created, removed or renamed since the last call, deduplicated.
+    fn changes(&self) -> HashSet<PathBuf> {
+        self.changed_paths.try_iter().collect()
+    }
+}
+
+/// Every file in the roots, in a random order, before any of them comes up again.
+///
+/// The files picked in the current round are kept in a SQLite database, so a restart carries on
+/// with the round rather than starting over. Without a database the round starts over on every
+/// restart.
+///
+/// The roots are watched, so files added during a round are part of it straight away and removed
+/// ones are dropped from it. The same goes for roots that are added or removed.
+#[derive(Debug)]
+pub struct ShuffledFiles {
+    library_roots: LibraryRoots,
+    /// The roots as of `generation`.
+    roots: Vec<PathBuf>,
+    generation: u64,
+    /// Roots added during the round, still being listed.
+    pending: Vec<PendingRoot>,
+    matcher: Arc<FileMatcher>,
+    connection: rusqlite::Connection,
+    /// The rest of the round, picked from the end.
+    remaining: Vec<PathBuf>,
+    last: Option<PathBuf>,
+    watcher: Option<LibraryWatcher>,
+}
+
+impl ShuffledFiles {
+    pub fn new(
+        library_roots: LibraryRoots,
+        matcher: FileMatcher,
+        state_db: Option<&Path>,
+    ) -> Result<Self, rusqlite::Error> {
+        let connection = match state_db {
+            Some(path) => rusqlite::Connection::open(path)?,
+            None => rusqlite::Connection::open_in_memory()?,
+        };
+        connection
+            .execute_batch("CREATE TABLE IF NOT EXISTS shuffle_played (path TEXT PRIMARY KEY);")?;
+        let (generation, roots) = library_roots.snapshot();
+        let watcher = LibraryWatcher::start(&roots)
+            .inspect_err(|error| eprintln!("Failed to watch the library for changes: {error}"))
+            .ok();
+        Ok(Self {
+            library_roots,
+            roots,
+            generation,
+            pending: Vec::new(),
+            matcher: Arc::new(matcher),
+            connection,
+            remaining: Vec::new(),
+            last: None,
+            watcher,
+        })
+    }
+
+    /// Brings the rest of the round up to date with the roots that were added or removed.
+    fn update_roots(&mut self) {
+        let (generation, roots) = self.library_roots.snapshot();
+        if generation != self.generation {
+            self.generation = generation;
+            for removed in self.roots.iter().filter(|root| !roots.contains(root)) {
+                println!("Dropping {} from the shuffle", removed.display());
+                self.remaining.retain(|file| !file.starts_with(removed));
+                if let Some(watcher) = &mut self.watcher {
+                    watcher.unwatch(removed);
+                }
+            }
+            self.pending.retain(|pending| roots.contains(&pending.root));
+            for added in roots.iter().filter(|root| !self.roots.contains(root)) {
+                if let Some(watcher) = &mut self.watcher {
+                    watcher.watch(added);
+                }
+                self.pending.push(PendingRoot::start(added.clone(), &self.matcher));
+            }
+            self.roots = roots;
+        }
+
+        for pending in std::mem::take(&mut self.pending) {
+            match pending.files.try_recv() {
+                // Listing the next round picks them up anyway
+                Ok(_) if self.remaining.is_empty() => (),
+                Ok(files) => self.insert(files),
+                Err(flume::TryRecvError::Empty) => self.pending.push(pending),
+                Err(flume::TryRecvError::Disconnected) => {
+                    eprintln!("Failed to index {}", pending.root.display());
+                }
+            }
         }
-        if a.count == 0 {
-            return b;
+    }
+
+    /// Brings the rest of the round up to date with what the watcher saw change.
+    fn apply_changes(&mut self) {
+        self.update_roots();
+        let Some(watcher) = &self.watcher else { return };
+        let changes = watcher.changes();
+        // Listing the next round picks them up anyway
+        if self.remaining.is_empty() {
+            return;
+        }
+        for path in changes {
+            if path.exists() {
+                self.add(&path);
+            } else {
+                self.remove(&path);
+            }
         }
-        if b.count == 0 {
-            return a;
+    }
+
+    /// Adds a new file, or the files in a new directory, at random places in the round.
+    fn add(&mut self, path: &Path) {
+        let Some(root) = self.roots.iter().find(|root| path.starts_with(root)) else { return };
+        let files = files_at(root, path, &self.matcher);
+        self.insert(files);
+    }
+
+    /// Puts files that aren't in the round yet, or already played in it, at random places in it.
+    fn insert(&mut self, files: Vec<PathBuf>) {
+        if files.is_empty() {
+            return;
         }
 
-        // Weighted random choice to decide which "selected" item to keep.
-        // Choose 'a's sample with probability a.count / total_count
+        let played = self.played().unwrap_or_default();
         let mut rng = rand::rng();
-        if rng.random_range(0..total_count) < a.count {
-            a.count = total_count;
-            a
+        for file in files {
+            if played.contains(&file) || self.remaining.contains(&file) {
+                continue;
+            }
+            println!("Adding {} to the shuffle", file.display());
+            let index = rng.random_range(0..=self.remaining.len());
+            self.remaining.insert(index, file);
+        }
+    }
+
+    /// Drops a removed file, or the files in a removed directory, from the round.
+    fn remove(&mut self, path: &Path) {
+        self.remaining.retain(|file| !file.starts_with(path));
+        let result = self
+            .connection
+            .execute("DELETE FROM shuffle_played WHERE path = ?1", [path.to_string_lossy()]);
+        if let Err(error) = result {
+            eprintln!("Failed to save shuffle state: {error}");
+        }
+    }
+
+    fn list_files(&self) -> Vec<PathBuf> {
+        self.roots
+            .par_iter()
+            .flat_map_iter(|root| list_root(root, &self.matcher))
+            .collect()
+    }
+
+    fn played(&self) -> rusqlite::Result<HashSet<PathBuf>> {
+        let mut statement = self.connection.prepare("SELECT path FROM shuffle_played")?;
+        let rows = statement.query_map([], |row| row.get::<_, String>(0))?;
+        rows.map(|path| path.map(PathBuf::from)).collect()
+    }
+
+    /// Scans the roots for what's left of the round, or starts a new one if nothing is.
+    /// Files added since the round started are part of it.
+    fn refill(&mut self) -> rusqlite::Result<()> {
+        let mut files = self.list_files();
+        let played = self.played()?;
+        let unplayed: Vec<_> =
+            files.iter().filter(|path| !played.contains(*path)).cloned().collect();
+        if unplayed.is_empty() {
+            println!("Played all {} files, starting over", files.len());
+            self.connection.execute("DELETE FROM shuffle_played", [])?;
         } else {
-            // Need to create a new struct to take ownership of b.selected
-            ScanResult { selected: b.selected, count: total_count }
+            files = unplayed;
         }
-    };
 
-    walk_dir
-        .into_iter()
-        .par_bridge()
-        .filter_map(|entry| {
-            let entry = entry.ok()?;
-            if entry.file_type().is_dir() {
-                return None;
+        files.shuffle(&mut rand::rng());
+        // Don't start a new round with the file that ended the last one
+        if files.len() > 1 && files.last() == self.last.as_ref() {
+            files.swap(0, files.len() - 1);
+        }
+        self.remaining = files;
+        Ok(())
+    }
+
+    /// The next file in the round from a root that's available. Files from unavailable roots are
+    /// moved to the far end of the round, to wait for the root to come back.
+    fn pop_available(&mut self) -> Option<PathBuf> {
+        for root in &self.roots {
+            self.library_roots.recheck(root);
+        }
+        for _ in 0..self.remaining.len() {
+            let path = self.remaining.pop()?;
+            let Some(root) = self.roots.iter().find(|root| path.starts_with(root)) else {
+                return Some(path);
+            };
+            let unreadable = || std::fs::metadata(&path).is_err();
+            if !self.library_roots.is_unavailable(root)
+                && !(unreadable() && self.library_roots.report_unreadable(root))
+            {
+                return Some(path);
             }
-            Some(ScanResult { selected: Some(entry.path()), count: 1 })
-        })
-        .reduce(identity, reduce)
+            self.remaining.insert(0, path);
+        }
+        None
+    }
+}
+
+impl Iterator for ShuffledFiles {
+    type Item = PathBuf;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.apply_changes();
+        if self.remaining.is_empty()
+            && let Err(error) = self.refill()
+        {
+            eprintln!("Failed to read shuffle state: {error}");
+            self.remaining = self.list_files();
+            self.remaining.shuffle(&mut rand::rng());
+        }
+
+        let path = self.pop_available()?;
+        let result = self.connection.execute(
+            "INSERT OR IGNORE INTO shuffle_played (path) VALUES (?1)",
+            [path.to_string_lossy()],
+        );
+        if let Err(error) = result {
+            eprintln!("Failed to save shuffle state: {error}");
+        }
+        self.last = Some(path.clone());
+        Some(path)
+    }
 }
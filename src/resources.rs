@@ -0,0 +1,128 @@
+//! Limits on how much of the machine one channel takes, so several channels on one box slow down
+//! predictably under load instead of all fighting over every core.
+//!
+//! Encoding (everything the process does by default) and scanning (walking the root directories
+//! and discovering files ahead of time) are limited separately, since scanning can always wait.
+
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use rustix::process::CpuSet;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Invalid CPU list {0:?}, expected e.g. 0-3,6")]
+    InvalidCpuList(String),
+
+    #[error("Failed to set the niceness: {0}")]
+    Nice(std::io::Error),
+
+    #[error("Failed to set the CPU affinity: {0}")]
+    Affinity(std::io::Error),
+
+    #[error("Failed to start the scanning threads: {0}")]
+    ThreadPool(#[from] rayon::ThreadPoolBuildError),
+}
+
+/// The limits scanning threads apply to themselves as they start.
+static SCAN_LIMITS: OnceLock<ResourceLimits> = OnceLock::new();
+
+/// CPUs to run on, given as e.g. `0-3,6`.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub struct CpuList(Vec<usize>);
+
+impl CpuList {
+    fn cpu_set(&self) -> CpuSet {
+        let mut cpu_set = CpuSet::new();
+        for &cpu in &self.0 {
+            cpu_set.set(cpu);
+        }
+        cpu_set
+    }
+}
+
+impl FromStr for CpuList {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidCpuList(s.to_string());
+        let mut cpus = Vec::new();
+        for range in s.split(',').map(str::trim) {
+            let (first, last) = range.split_once('-').unwrap_or((range, range));
+            let first: usize = first.trim().parse().map_err(|_| invalid())?;
+            let last: usize = last.trim().parse().map_err(|_| invalid())?;
+            if first > last || last >= CpuSet::MAX_CPU {
+                return Err(invalid());
+            }
+            cpus.extend(first..=last);
+        }
+        Ok(Self(cpus))
+    }
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub struct ResourceLimits {
+    /// Niceness of the whole process, from -20 to 19 (the nicest). Only root can go below the
+    /// current one.
+    pub nice: Option<i32>,
+    /// CPUs the whole process runs on. Scanning can be moved onto others with `scan_cpus`.
+    pub cpus: Option<CpuList>,
+    /// Niceness of the scanning threads, usually higher than `nice` so playback wins.
+    pub scan_nice: Option<i32>,
+    /// CPUs the scanning threads run on.
+    pub scan_cpus: Option<CpuList>,
+    /// Number of threads walking the root directories, one per CPU by default.
+    pub scan_threads: Option<usize>,
+}
+
+impl ResourceLimits {
+    /// Applies the limits to this process.
+    ///
+    /// Has to be called before any other thread is started: threads only inherit the niceness and
+    /// affinity of the thread that starts them, and the scanning threads (rayon's global pool) can
+    /// only be set up once.
+    pub fn apply(&self) -> Result<(), Error> {
+        if let Some(nice) = self.nice {
+            set_nice(nice)?;
+        }
+        if let Some(cpus) = &self.cpus {
+            set_affinity(cpus)?;
+        }
+
+        _ = SCAN_LIMITS.set(self.clone());
+        let mut scan_pool = rayon::ThreadPoolBuilder::new()
+            .thread_name(|index| format!("scan-{index}"))
+            .start_handler(|_| limit_scan_thread());
+        if let Some(scan_threads) = self.scan_threads {
+            scan_pool = scan_pool.num_threads(scan_threads);
+        }
+        scan_pool.build_global()?;
+        Ok(())
+    }
+}
+
+/// Applies the scanning limits to the calling thread, for the start handlers of thread pools that
+/// scan. Does nothing if [`ResourceLimits::apply`] wasn't called.
+pub fn limit_scan_thread() {
+    let Some(limits) = SCAN_LIMITS.get() else { return };
+    let mut result = Ok(());
+    if let Some(scan_nice) = limits.scan_nice {
+        result = result.and(set_nice(scan_nice));
+    }
+    if let Some(scan_cpus) = &limits.scan_cpus {
+        result = result.and(set_affinity(scan_cpus));
+    }
+    if let Err(error) = result {
+        eprintln!("Failed to limit a scanning thread: {error}");
+    }
+}
+
+/// Sets the calling thread's niceness, which is per thread on Linux.
+fn set_nice(nice: i32) -> Result<(), Error> {
+    rustix::process::setpriority_process(None, nice).map_err(|error| Error::Nice(error.into()))
+}
+
+fn set_affinity(cpus: &CpuList) -> Result<(), Error> {
+    rustix::process::sched_setaffinity(None, &cpus.cpu_set())
+        .map_err(|error| Error::Affinity(error.into()))
+}
@@ -0,0 +1,396 @@
+use std::path::{Path, PathBuf};
+
+use gstreamer_rtsp_server::prelude::RTSPServerExtManual;
+use parking_lot::Mutex;
+
+use crate::api::{ApiContext, ApiHandle};
+use crate::disk_monitor::{DiskMonitorOptions, start_disk_monitor};
+use crate::download::Downloader;
+use crate::events::EventLog;
+use crate::health::{HealthCheck, HealthOptions};
+use crate::history::History;
+use crate::hooks::EventHook;
+use crate::media_cache::MediaInfoCache;
+use crate::random_files::LibraryRoots;
+use crate::stats::SessionStats;
+use crate::status::StatusTracker;
+use crate::stream::{
+    self, AudioOptions, Command, Error, Event, MjpegFeed, Quarantine, StreamOptions,
+    StreamServices, VideoOptions,
+};
+use crate::viewer_tokens::ViewerTokens;
+
+/// A continuous stream of random files from a set of root directories, served over RTSP.
+///
+/// The server only starts accepting clients once it is attached to a [`glib::MainContext`] that
+/// is being run, and files only start being fed once the first client connects.
+///
+/// ```no_run
+/// gstreamer::init().unwrap();
+///
+/// let server = z_stream::Server::builder()
+///     .root_dir("/media/videos")
+///     .stream_key("tv")
+///     .build()
+///     .unwrap();
+///
+/// let main_loop = glib::MainLoop::new(None, false);
+/// server.attach(Some(&main_loop.context())).unwrap();
+/// main_loop.run();
+/// ```
+pub struct Server {
+    rtsp_server: gstreamer_rtsp_server::RTSPServer,
+    rtsp_port: u16,
+    stream_key: String,
+    stream_key_aliases: Vec<String>,
+    command_tx: flume::Sender<Command>,
+    event_rx: flume::Receiver<Event>,
+    status: StatusTracker,
+    stats: SessionStats,
+    api: Mutex<Option<ApiHandle>>,
+}
+
+impl Server {
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::default()
+    }
+
+    /// Attaches the RTSP server to `context` (or the default context if `None`).
+    pub fn attach(&self, context: Option<&glib::MainContext>) -> Result<glib::SourceId, Error> {
+        Ok(self.rtsp_server.attach(context)?)
+    }
+
+    /// Sends commands (skip, gain changes, ...) to the running stream.
+    pub fn command_sender(&self) -> flume::Sender<Command> {
+        self.command_tx.clone()
+    }
+
+    /// Receives playback events.
+    /// Events are dropped rather than queued up if nobody is receiving them.
+    pub fn events(&self) -> &flume::Receiver<Event> {
+        &self.event_rx
+    }
+
+    /// What's currently playing and what's coming up next.
+    pub fn status(&self) -> &StatusTracker {
+        &self.status
+    }
+
+    /// Statistics for the whole session, e.g. to report on shutdown.
+    pub fn stats(&self) -> &SessionStats {
+        &self.stats
+    }
+
+    pub fn rtsp_server(&self) -> &gstreamer_rtsp_server::RTSPServer {
+        &self.rtsp_server
+    }
+
+    pub fn rtsp_port(&self) -> u16 {
+        self.rtsp_port
+    }
+
+    pub fn stream_key(&self) -> &str {
+        &self.stream_key
+    }
+
+    /// Other mount paths the same stream is served at.
+    pub fn stream_key_aliases(&self) -> &[String] {
+        &self.stream_key_aliases
+    }
+
+    /// Stops the HTTP control API, letting requests that are in flight finish.
+    pub fn shutdown(&self) {
+        if let Some(api) = self.api.lock().take() {
+            api.shutdown();
+        }
+    }
+}
+
+/// Builder for [`Server`].
+#[derive(Debug, Clone)]
+pub struct ServerBuilder {
+    root_dirs: Vec<PathBuf>,
+    rtsp_port: u16,
+    stream_key: String,
+    stream_key_aliases: Vec<String>,
+    api_port: Option<u16>,
+    api_tokens: Vec<String>,
+    event_hook: EventHook,
+    health: HealthOptions,
+    history_db: Option<PathBuf>,
+    media_cache_db: Option<PathBuf>,
+    quarantine_db: Option<PathBuf>,
+    upload_dir: Option<PathBuf>,
+    upload_max_bytes: u64,
+    downloader: Option<Downloader>,
+    disk_monitor: Option<DiskMonitorOptions>,
+    viewer_tokens: Option<ViewerTokens>,
+    options: StreamOptions,
+}
+
+impl Default for ServerBuilder {
+    fn default() -> Self {
+        Self {
+            root_dirs: Vec::new(),
+            rtsp_port: 18554,
+            stream_key: "my_stream".to_string(),
+            stream_key_aliases: Vec::new(),
+            api_port: None,
+            api_tokens: Vec::new(),
+            event_hook: EventHook::default(),
+            health: HealthOptions::default(),
+            history_db: None,
+            media_cache_db: None,
+            quarantine_db: None,
+            upload_dir: None,
+            upload_max_bytes: 0,
+            downloader: None,
+            disk_monitor: None,
+            viewer_tokens: None,
+            options: StreamOptions::default(),
+        }
+    }
+}
+
+impl ServerBuilder {
+    /// Adds a directory (or single file) to pick content from.
+    pub fn root_dir(mut self, root_dir: impl Into<PathBuf>) -> Self {
+        self.root_dirs.push(root_dir.into());
+        self
+    }
+
+    pub fn root_dirs<I>(mut self, root_dirs: I) -> Self
+    where
+        I: IntoIterator<Item: Into<PathBuf>>,
+    {
+        self.root_dirs.extend(root_dirs.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn rtsp_port(mut self, rtsp_port: u16) -> Self {
+        self.rtsp_port = rtsp_port;
+        self
+    }
+
+    /// The RTSP mount path, the stream is served at `rtsp://host:port/{stream_key}`.
+    pub fn stream_key(mut self, stream_key: impl Into<String>) -> Self {
+        self.stream_key = stream_key.into();
+        self
+    }
+
+    /// Also serves the stream at `rtsp://host:port/{alias}`, e.g. to move players over to a new
+    /// stream key without breaking the old one.
+    pub fn stream_key_alias(mut self, alias: impl Into<String>) -> Self {
+        self.stream_key_aliases.push(alias.into());
+        self
+    }
+
+    pub fn stream_key_aliases<I>(mut self, aliases: I) -> Self
+    where
+        I: IntoIterator<Item: Into<String>>,
+    {
+        self.stream_key_aliases.extend(aliases.into_iter().map(Into::into));
+        self
+    }
+
+    /// Also starts the HTTP control API on this port.
+    pub fn api_port(mut self, api_port: u16) -> Self {
+        self.api_port = Some(api_port);
+        self
+    }
+
+    /// Requires one of these bearer tokens for API requests that change anything.
+    pub fn api_tokens<I>(mut self, tokens: I) -> Self
+    where
+        I: IntoIterator<Item: Into<String>>,
+    {
+        self.api_tokens.extend(tokens.into_iter().map(Into::into));
+        self
+    }
+
+    /// Notifications or a command to run when files start playing or fail.
+    pub fn event_hook(mut self, event_hook: EventHook) -> Self {
+        self.event_hook = event_hook;
+        self
+    }
+
+    /// When `GET /healthz` reports the server unhealthy, and where to alert when it does.
+    pub fn health(mut self, health: HealthOptions) -> Self {
+        self.health = health;
+        self
+    }
+
+    /// Records every file played in this SQLite database, created if it doesn't exist.
+    pub fn history_db(mut self, path: impl Into<PathBuf>) -> Self {
+        self.history_db = Some(path.into());
+        self
+    }
+
+    /// Caches discovered media info in this SQLite database, created if it doesn't exist.
+    pub fn media_cache_db(mut self, path: impl Into<PathBuf>) -> Self {
+        self.media_cache_db = Some(path.into());
+        self
+    }
+
+    /// Keeps files that failed to play in this SQLite database, created if it doesn't exist, so
+    /// they stay out of rotation across restarts.
+    pub fn quarantine_db(mut self, path: impl Into<PathBuf>) -> Self {
+        self.quarantine_db = Some(path.into());
+        self
+    }
+
+    /// Accepts files of up to `max_bytes` uploaded to the API into this directory, created if it
+    /// doesn't exist. They only join the rotation if it's in one of the root directories.
+    pub fn upload_dir(mut self, path: impl Into<PathBuf>, max_bytes: u64) -> Self {
+        self.upload_dir = Some(path.into());
+        self.upload_max_bytes = max_bytes;
+        self
+    }
+
+    /// Lets the API play web videos, fetched with this.
+    pub fn downloader(mut self, downloader: Downloader) -> Self {
+        self.downloader = Some(downloader);
+        self
+    }
+
+    /// Watches free space, see [`start_disk_monitor`]. The download directory is pruned, and the
+    /// upload directory and the databases' directories are watched, along with the given ones.
+    pub fn disk_monitor(mut self, options: DiskMonitorOptions) -> Self {
+        self.disk_monitor = Some(options);
+        self
+    }
+
+    /// Lets the API mint and revoke tokens for viewers of mediamtx's HLS and WebRTC, and answer
+    /// mediamtx when it checks them. mediamtx has to be started with the API as its auth, see
+    /// [`mediamtx::Config::auth_url`](crate::mediamtx::Config::auth_url).
+    pub fn viewer_tokens(mut self, viewer_tokens: ViewerTokens) -> Self {
+        self.viewer_tokens = Some(viewer_tokens);
+        self
+    }
+
+    pub fn video(mut self, video: VideoOptions) -> Self {
+        self.options.video = video;
+        self
+    }
+
+    pub fn audio(mut self, audio: AudioOptions) -> Self {
+        self.options.audio = audio;
+        self
+    }
+
+    pub fn options(mut self, options: StreamOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn build(self) -> Result<Server, Error> {
+        let history = match &self.history_db {
+            Some(path) => Some(History::open(path, &self.stream_key)?),
+            None => None,
+        };
+        let media_cache = self.media_cache_db.as_deref().map(MediaInfoCache::open).transpose()?;
+        let quarantine = match &self.quarantine_db {
+            Some(path) => Quarantine::open(path)?,
+            None => Quarantine::default(),
+        };
+        let mjpeg = self.options.mjpeg.map(MjpegFeed::new);
+        let stream_keys: Vec<String> = std::iter::once(&self.stream_key)
+            .chain(&self.stream_key_aliases)
+            .cloned()
+            .collect();
+
+        let (command_tx, command_rx) = flume::bounded(20);
+        let (feeder_event_tx, feeder_event_rx) = flume::bounded(20);
+        let (event_tx, event_rx) = flume::bounded(20);
+        let api_event_tx = feeder_event_tx.clone();
+        if let Some(mut options) = self.disk_monitor.clone() {
+            options.prune_dirs.extend(self.downloader.as_ref().map(|d| d.dir.clone()));
+            options.watch_dirs.extend(self.upload_dir.clone());
+            let databases = [&self.history_db, &self.media_cache_db, &self.quarantine_db];
+            let database_dirs = databases
+                .into_iter()
+                .flatten()
+                .filter_map(|path| path.parent())
+                .map(|dir| if dir.as_os_str().is_empty() { Path::new(".") } else { dir });
+            options.watch_dirs.extend(database_dirs.map(Path::to_path_buf));
+            options.watch_dirs.sort();
+            options.watch_dirs.dedup();
+            start_disk_monitor(options, feeder_event_tx.clone());
+        }
+
+        let roots = LibraryRoots::new(self.root_dirs).with_events(feeder_event_tx.clone());
+        let rtsp_server = stream::create_server(
+            roots.clone(),
+            command_rx,
+            feeder_event_tx,
+            self.rtsp_port,
+            &stream_keys,
+            self.options,
+            StreamServices {
+                media_cache,
+                quarantine: quarantine.clone(),
+                mjpeg: mjpeg.clone(),
+                history: history.clone(),
+            },
+        )?;
+
+        // Keep the status and stats up to date, then pass the events on to whoever is listening
+        let status = StatusTracker::default();
+        let stats = SessionStats::default();
+        let event_log = EventLog::default();
+        let health = HealthCheck::start(self.health);
+        let status_clone = status.clone();
+        let stats_clone = stats.clone();
+        let event_log_clone = event_log.clone();
+        let health_clone = health.clone();
+        let history_clone = history.clone();
+        let hook_tx = self.event_hook.is_enabled().then(|| self.event_hook.start());
+        std::thread::spawn(move || {
+            for event in feeder_event_rx {
+                status_clone.handle_event(&event);
+                stats_clone.handle_event(&event);
+                event_log_clone.handle_event(&event);
+                health_clone.handle_event(&event);
+                if let Some(history) = &history_clone {
+                    history.handle_event(&event);
+                }
+                if let Some(hook_tx) = &hook_tx {
+                    _ = hook_tx.send(event.clone());
+                }
+                _ = event_tx.try_send(event);
+            }
+        });
+
+        let api = self.api_port.map(|api_port| {
+            let context = ApiContext {
+                command_tx: command_tx.clone(),
+                status: status.clone(),
+                stats: stats.clone(),
+                event_log,
+                health,
+                history,
+                quarantine,
+                mjpeg,
+                upload_dir: self.upload_dir,
+                upload_max_bytes: self.upload_max_bytes,
+                downloader: self.downloader,
+                event_tx: api_event_tx,
+                roots,
+                viewer_tokens: self.viewer_tokens,
+            };
+            crate::api::start_api_task(api_port, context, self.api_tokens)
+        });
+
+        Ok(Server {
+            rtsp_server,
+            rtsp_port: self.rtsp_port,
+            stream_key: self.stream_key,
+            stream_key_aliases: self.stream_key_aliases,
+            command_tx,
+            event_rx,
+            status,
+            stats,
+            api: Mutex::new(api),
+        })
+    }
+}
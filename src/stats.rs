@@ -0,0 +1,267 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use serde::Serialize;
+
+use crate::stream::{EndReason, Event};
+
+/// Upper bounds of the first buffer latency histogram's buckets, in milliseconds. Anything slower
+/// goes in one more bucket.
+const LATENCY_BUCKETS_MS: [u64; 8] = [25, 50, 100, 250, 500, 1000, 2500, 5000];
+
+/// Accumulates statistics over the lifetime of the process from the stream [`Event`]s.
+#[derive(Debug, Clone)]
+pub struct SessionStats {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug)]
+struct State {
+    started_at: Instant,
+    airtime: Duration,
+    files_played: u64,
+    skips: u64,
+    errors: u64,
+    quarantined: u64,
+    switch_count: u32,
+    switch_total: Duration,
+    first_buffer_latency: LatencyHistogram,
+    playing_since: Option<Instant>,
+    last_ended_at: Option<Instant>,
+    output_outages: u64,
+    output_downtime: Duration,
+    unhealthy_since: Option<Instant>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionSummary {
+    pub uptime_secs: f64,
+    pub airtime_secs: f64,
+    pub files_played: u64,
+    pub skips: u64,
+    pub errors: u64,
+    pub quarantined: u64,
+    /// Average time between one item ending and the next one starting.
+    pub average_switch_latency_ms: Option<f64>,
+    /// Time from one item ending to the next one's first frame reaching the output.
+    pub first_buffer_latency: LatencyHistogram,
+    /// How often, and for how long in total, the monitor found the output unhealthy.
+    pub output_outages: u64,
+    pub output_downtime_secs: f64,
+    pub peak_memory_bytes: Option<u64>,
+}
+
+impl Default for SessionStats {
+    fn default() -> Self {
+        let state = State {
+            started_at: Instant::now(),
+            airtime: Duration::ZERO,
+            files_played: 0,
+            skips: 0,
+            errors: 0,
+            quarantined: 0,
+            switch_count: 0,
+            switch_total: Duration::ZERO,
+            first_buffer_latency: LatencyHistogram::default(),
+            playing_since: None,
+            last_ended_at: None,
+            output_outages: 0,
+            output_downtime: Duration::ZERO,
+            unhealthy_since: None,
+        };
+        Self { state: Arc::new(Mutex::new(state)) }
+    }
+}
+
+impl SessionStats {
+    pub fn handle_event(&self, event: &Event) {
+        let mut state = self.state.lock();
+        let now = Instant::now();
+        match event {
+            Event::Queued { .. }
+            | Event::Dequeued { .. }
+            | Event::PrerollFailed { .. }
+            | Event::LiveStarted { .. }
+            | Event::LiveEnded { .. }
+            | Event::AwaitingApproval { .. }
+            | Event::Reviewed { .. }
+            | Event::SlateStarted { .. }
+            | Event::SlateEnded { .. }
+            | Event::Frozen { .. }
+            | Event::Unfrozen
+            | Event::EncodingChanged { .. }
+            | Event::DownloadProgress { .. }
+            | Event::Downloaded { .. }
+            | Event::DownloadFailed { .. }
+            | Event::DiskSpaceLow { .. }
+            | Event::DiskSpaceOk { .. }
+            | Event::RootUnavailable { .. }
+            | Event::RootAvailable { .. } => (),
+            Event::Switched { latency_ms } => state.first_buffer_latency.record(*latency_ms),
+            Event::Playing { .. } => {
+                if let Some(last_ended_at) = state.last_ended_at.take() {
+                    state.switch_count += 1;
+                    state.switch_total += now - last_ended_at;
+                }
+                state.playing_since = Some(now);
+                state.files_played += 1;
+            }
+            Event::Ended { reason, .. } => {
+                if let Some(playing_since) = state.playing_since.take() {
+                    state.airtime += now - playing_since;
+                }
+                state.last_ended_at = Some(now);
+                match reason {
+                    EndReason::Finished | EndReason::Interrupted => (),
+                    EndReason::Skipped => state.skips += 1,
+                    EndReason::Error(_) => state.errors += 1,
+                }
+            }
+            Event::Quarantined { .. } => state.quarantined += 1,
+            Event::OutputUnhealthy { .. } => {
+                // The reason can change while it's still unhealthy
+                if state.unhealthy_since.is_none() {
+                    state.unhealthy_since = Some(now);
+                    state.output_outages += 1;
+                }
+            }
+            Event::OutputHealthy => {
+                if let Some(unhealthy_since) = state.unhealthy_since.take() {
+                    state.output_downtime += now - unhealthy_since;
+                }
+            }
+        }
+    }
+
+    pub fn summary(&self) -> SessionSummary {
+        let state = self.state.lock();
+        let mut airtime = state.airtime;
+        if let Some(playing_since) = state.playing_since {
+            airtime += playing_since.elapsed();
+        }
+        let mut output_downtime = state.output_downtime;
+        if let Some(unhealthy_since) = state.unhealthy_since {
+            output_downtime += unhealthy_since.elapsed();
+        }
+
+        SessionSummary {
+            uptime_secs: state.started_at.elapsed().as_secs_f64(),
+            airtime_secs: airtime.as_secs_f64(),
+            files_played: state.files_played,
+            skips: state.skips,
+            errors: state.errors,
+            quarantined: state.quarantined,
+            average_switch_latency_ms: (state.switch_count > 0)
+                .then(|| (state.switch_total / state.switch_count).as_secs_f64() * 1000.0),
+            first_buffer_latency: state.first_buffer_latency.clone(),
+            output_outages: state.output_outages,
+            output_downtime_secs: output_downtime.as_secs_f64(),
+            peak_memory_bytes: peak_memory_bytes(),
+        }
+    }
+}
+
+/// Counts of latencies, bucketed by [`LATENCY_BUCKETS_MS`].
+#[derive(Debug, Clone, Serialize)]
+pub struct LatencyHistogram {
+    pub buckets: Vec<LatencyBucket>,
+    pub count: u64,
+    pub total_ms: u64,
+    pub max_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LatencyBucket {
+    /// The bucket holds latencies up to this, `None` for the one with everything slower.
+    pub le_ms: Option<u64>,
+    pub count: u64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        let bounds = LATENCY_BUCKETS_MS.into_iter().map(Some).chain([None]);
+        Self {
+            buckets: bounds.map(|le_ms| LatencyBucket { le_ms, count: 0 }).collect(),
+            count: 0,
+            total_ms: 0,
+            max_ms: 0,
+        }
+    }
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, latency_ms: u64) {
+        let bucket = self
+            .buckets
+            .iter_mut()
+            .find(|bucket| bucket.le_ms.is_none_or(|le_ms| latency_ms <= le_ms));
+        if let Some(bucket) = bucket {
+            bucket.count += 1;
+        }
+        self.count += 1;
+        self.total_ms += latency_ms;
+        self.max_ms = self.max_ms.max(latency_ms);
+    }
+
+    pub fn average_ms(&self) -> Option<f64> {
+        (self.count > 0).then(|| self.total_ms as f64 / self.count as f64)
+    }
+}
+
+impl SessionSummary {
+    pub fn write_json(&self, path: &Path) -> std::io::Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, json)
+    }
+}
+
+impl std::fmt::Display for SessionSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Session summary:")?;
+        writeln!(f, "  Uptime: {}", format_secs(self.uptime_secs))?;
+        writeln!(f, "  Airtime: {}", format_secs(self.airtime_secs))?;
+        writeln!(f, "  Files played: {}", self.files_played)?;
+        writeln!(f, "  Skips: {}", self.skips)?;
+        writeln!(f, "  Errors: {}", self.errors)?;
+        writeln!(f, "  Quarantined: {}", self.quarantined)?;
+        match self.average_switch_latency_ms {
+            Some(latency) => writeln!(f, "  Average switch latency: {latency:.0}ms")?,
+            None => writeln!(f, "  Average switch latency: n/a")?,
+        }
+        let latency = &self.first_buffer_latency;
+        if let Some(average) = latency.average_ms() {
+            writeln!(
+                f,
+                "  First frame after a switch: {average:.0}ms on average, {}ms at most",
+                latency.max_ms
+            )?;
+        }
+        if self.output_outages > 0 {
+            writeln!(
+                f,
+                "  Output outages: {} ({} in total)",
+                self.output_outages,
+                format_secs(self.output_downtime_secs)
+            )?;
+        }
+        match self.peak_memory_bytes {
+            Some(bytes) => write!(f, "  Peak memory: {:.1} MiB", bytes as f64 / 1024.0 / 1024.0),
+            None => write!(f, "  Peak memory: n/a"),
+        }
+    }
+}
+
+fn format_secs(secs: f64) -> String {
+    let secs = secs as u64;
+    format!("{:02}:{:02}:{:02}", secs / 3600, (secs / 60) % 60, secs % 60)
+}
+
+/// The high water mark of the resident set size, only available on Linux.
+fn peak_memory_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kib = line.trim_start_matches("VmHWM:").trim().trim_end_matches("kB").trim();
+    kib.parse::<u64>().ok().map(|kib| kib * 1024)
+}
@@ -0,0 +1,230 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+
+use parking_lot::Mutex;
+use serde::Serialize;
+
+use crate::media_type::MediaType;
+use crate::stream::{Event, SlateKind};
+
+/// Tracks what's playing from the stream [`Event`]s, for the API to report.
+#[derive(Debug, Clone)]
+pub struct StatusTracker {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug)]
+struct State {
+    started_at: Instant,
+    playing: Option<Playing>,
+    upcoming: Vec<PathBuf>,
+    live: Option<String>,
+    slate: Option<SlateKind>,
+    frozen: bool,
+    encoding: Option<Encoding>,
+    awaiting_approval: Vec<PathBuf>,
+    output_problem: Option<String>,
+    downloads: Vec<DownloadProgress>,
+    low_disk_space: Vec<PathBuf>,
+    unavailable_roots: Vec<PathBuf>,
+}
+
+#[derive(Debug)]
+struct Playing {
+    path: PathBuf,
+    media_type: MediaType,
+    duration: Option<gstreamer::ClockTime>,
+    started_at: Instant,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Status {
+    pub playing: Option<NowPlaying>,
+    #[serde(serialize_with = "crate::paths::serialize_all_lossy")]
+    pub upcoming: Vec<PathBuf>,
+    /// The live input that has taken over, if any.
+    pub live: Option<String>,
+    /// The slate being shown instead of files, if any.
+    pub slate: Option<SlateKind>,
+    /// Whether the video is held on the current frame.
+    pub frozen: bool,
+    /// What the output's encoder makes of the video, if it's encoded in this process.
+    pub encoding: Option<Encoding>,
+    /// Files that were picked, but can't play until they're approved.
+    #[serde(serialize_with = "crate::paths::serialize_all_lossy")]
+    pub awaiting_approval: Vec<PathBuf>,
+    /// Why the monitor finds the output unhealthy, if it does.
+    pub output_problem: Option<String>,
+    /// Web videos being downloaded, that play once they're done.
+    pub downloads: Vec<DownloadProgress>,
+    /// Directories the disk monitor finds to be running out of space.
+    #[serde(serialize_with = "crate::paths::serialize_all_lossy")]
+    pub low_disk_space: Vec<PathBuf>,
+    /// Root directories that can't be read, and aren't picked from until they can.
+    #[serde(serialize_with = "crate::paths::serialize_all_lossy")]
+    pub unavailable_roots: Vec<PathBuf>,
+    pub uptime_secs: f64,
+}
+
+#[derive(Debug, Copy, Clone, Serialize)]
+pub struct Encoding {
+    pub width: u32,
+    pub height: u32,
+    pub bitrate_kbps: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DownloadProgress {
+    pub url: String,
+    pub percent: u8,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NowPlaying {
+    #[serde(serialize_with = "crate::paths::serialize_lossy")]
+    pub path: PathBuf,
+    pub media_type: MediaType,
+    pub elapsed_secs: f64,
+    pub duration_secs: Option<f64>,
+}
+
+impl Default for StatusTracker {
+    fn default() -> Self {
+        let state = State {
+            started_at: Instant::now(),
+            playing: None,
+            upcoming: Vec::new(),
+            live: None,
+            slate: None,
+            frozen: false,
+            encoding: None,
+            awaiting_approval: Vec::new(),
+            output_problem: None,
+            downloads: Vec::new(),
+            low_disk_space: Vec::new(),
+            unavailable_roots: Vec::new(),
+        };
+        Self { state: Arc::new(Mutex::new(state)) }
+    }
+}
+
+impl StatusTracker {
+    pub fn handle_event(&self, event: &Event) {
+        let mut state = self.state.lock();
+        match event {
+            Event::Queued { path } => push_unique(&mut state.upcoming, path),
+            Event::Dequeued { path } => state.upcoming.retain(|p| p != path),
+            Event::Playing { path, media_type, duration } => {
+                if let Some(index) = state.upcoming.iter().position(|p| p == path) {
+                    state.upcoming.remove(index);
+                }
+                state.playing = Some(Playing {
+                    path: path.clone(),
+                    media_type: *media_type,
+                    duration: *duration,
+                    started_at: Instant::now(),
+                });
+            }
+            Event::Ended { path, .. } => {
+                if state.playing.as_ref().is_some_and(|playing| playing.path == *path) {
+                    state.playing = None;
+                }
+            }
+            Event::Quarantined { path, .. } => state.upcoming.retain(|p| p != path),
+            Event::LiveStarted { source } => state.live = Some(source.clone()),
+            Event::LiveEnded { .. } => state.live = None,
+            Event::AwaitingApproval { path } => push_unique(&mut state.awaiting_approval, path),
+            Event::Reviewed { path, .. } => state.awaiting_approval.retain(|p| p != path),
+            Event::SlateStarted { slate } => state.slate = Some(*slate),
+            Event::SlateEnded { .. } => state.slate = None,
+            Event::Frozen { .. } => state.frozen = true,
+            Event::Unfrozen => state.frozen = false,
+            Event::EncodingChanged { width, height, bitrate_kbps } => {
+                let (width, height, bitrate_kbps) = (*width, *height, *bitrate_kbps);
+                state.encoding = Some(Encoding { width, height, bitrate_kbps });
+            }
+            Event::OutputUnhealthy { reason } => state.output_problem = Some(reason.clone()),
+            Event::OutputHealthy => state.output_problem = None,
+            Event::Switched { .. } | Event::PrerollFailed { .. } => (),
+            Event::DownloadProgress { url, percent } => {
+                match state.downloads.iter_mut().find(|download| download.url == *url) {
+                    Some(download) => download.percent = *percent,
+                    None => {
+                        let download = DownloadProgress { url: url.clone(), percent: *percent };
+                        state.downloads.push(download);
+                    }
+                }
+            }
+            Event::Downloaded { url, .. } | Event::DownloadFailed { url, .. } => {
+                state.downloads.retain(|download| download.url != *url)
+            }
+            Event::DiskSpaceLow { dir, .. } => push_unique(&mut state.low_disk_space, dir),
+            Event::DiskSpaceOk { dir } => state.low_disk_space.retain(|d| d != dir),
+            Event::RootUnavailable { root, .. } => push_unique(&mut state.unavailable_roots, root),
+            Event::RootAvailable { root } => state.unavailable_roots.retain(|r| r != root),
+        }
+    }
+
+    pub fn status(&self) -> Status {
+        let state = self.state.lock();
+        let playing = state.playing.as_ref().map(|playing| NowPlaying {
+            path: playing.path.clone(),
+            media_type: playing.media_type,
+            elapsed_secs: playing.started_at.elapsed().as_secs_f64(),
+            duration_secs: playing.duration.map(|duration| duration.seconds_f64()),
+        });
+        Status {
+            playing,
+            upcoming: state.upcoming.clone(),
+            live: state.live.clone(),
+            slate: state.slate,
+            frozen: state.frozen,
+            encoding: state.encoding,
+            awaiting_approval: state.awaiting_approval.clone(),
+            output_problem: state.output_problem.clone(),
+            downloads: state.downloads.clone(),
+            low_disk_space: state.low_disk_space.clone(),
+            unavailable_roots: state.unavailable_roots.clone(),
+            uptime_secs: state.started_at.elapsed().as_secs_f64(),
+        }
+    }
+}
+
+/// Adds `path` to the end of `list` unless it's already there, so a repeated event can't leave a
+/// path behind once its end event has removed it.
+fn push_unique(list: &mut Vec<PathBuf>, path: &Path) {
+    if !list.iter().any(|p| p == path) {
+        list.push(path.to_path_buf());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeated_events_dont_duplicate_entries() {
+        let tracker = StatusTracker::default();
+        let path = PathBuf::from("/media/a.mkv");
+        let root = PathBuf::from("/media");
+        for _ in 0..2 {
+            tracker.handle_event(&Event::Queued { path: path.clone() });
+            tracker.handle_event(&Event::AwaitingApproval { path: path.clone() });
+            let reason = "unmounted".to_string();
+            tracker.handle_event(&Event::RootUnavailable { root: root.clone(), reason });
+        }
+        let status = tracker.status();
+        assert_eq!(status.upcoming, [path.clone()]);
+        assert_eq!(status.awaiting_approval, [path.clone()]);
+        assert_eq!(status.unavailable_roots, [root.clone()]);
+
+        tracker.handle_event(&Event::Dequeued { path: path.clone() });
+        tracker.handle_event(&Event::Reviewed { path, approved: true });
+        tracker.handle_event(&Event::RootAvailable { root });
+        let status = tracker.status();
+        assert!(status.upcoming.is_empty());
+        assert!(status.awaiting_approval.is_empty());
+        assert!(status.unavailable_roots.is_empty());
+    }
+}
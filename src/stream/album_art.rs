@@ -0,0 +1,164 @@
+use std::path::{Path, PathBuf};
+
+use gstreamer::prelude::*;
+use gstreamer_pbutils::prelude::*;
+
+use super::Error;
+use super::feeder::create_file_source;
+use super::selection::pad_stream_type;
+use crate::media_info::DEFAULT_DISCOVERY_TIMEOUT;
+
+/// Pictures next to an audio file that are taken as its cover when it has none embedded, best
+/// first. Matched case-insensitively.
+const FOLDER_ART: &[&str] = &[
+    "folder.jpg",
+    "folder.png",
+    "cover.jpg",
+    "cover.png",
+    "front.jpg",
+    "front.png",
+    "album.jpg",
+    "album.png",
+];
+
+pub(crate) enum CoverArt {
+    /// A picture in the file's tags, e.g. an ID3 `APIC` frame.
+    Embedded(gstreamer::Sample),
+    /// A picture in the file's directory.
+    Folder(PathBuf),
+}
+
+/// What's shown while an audio file plays: its cover art, and what the track is.
+pub(crate) struct AudioCover {
+    pub(crate) art: Option<CoverArt>,
+    /// `Artist - Title`, or whichever of the two is tagged.
+    pub(crate) caption: Option<String>,
+}
+
+impl AudioCover {
+    pub(crate) fn find(path: &Path) -> Self {
+        let tags = read_tags(path).unwrap_or_else(|error| {
+            eprintln!("Failed to read the tags of {}: {error}", path.display());
+            None
+        });
+        let embedded = tags.as_ref().and_then(|tags| {
+            let image = tags.get::<gstreamer::tags::Image>();
+            image
+                .or_else(|| tags.get::<gstreamer::tags::PreviewImage>())
+                .map(|image| image.get())
+        });
+        let art = embedded
+            .map(CoverArt::Embedded)
+            .or_else(|| folder_art(path).map(CoverArt::Folder));
+        let caption = tags.as_ref().and_then(caption);
+        Self { art, caption }
+    }
+}
+
+/// The tags of all of `path`'s streams. Discovery results are cached without them, so this
+/// discovers the file again.
+fn read_tags(path: &Path) -> Result<Option<gstreamer::TagList>, Error> {
+    let uri = glib::filename_to_uri(path, None)?;
+    let discoverer = gstreamer_pbutils::Discoverer::new(DEFAULT_DISCOVERY_TIMEOUT)?;
+    let info = discoverer.discover_uri(&uri)?;
+    let tags = info
+        .stream_list()
+        .iter()
+        .filter_map(|stream| stream.tags())
+        .reduce(|tags, more| tags.merge(&more, gstreamer::TagMergeMode::Append));
+    Ok(tags)
+}
+
+fn caption(tags: &gstreamer::TagList) -> Option<String> {
+    let artist = tags.get::<gstreamer::tags::Artist>().map(|artist| artist.get().to_string());
+    let title = tags.get::<gstreamer::tags::Title>().map(|title| title.get().to_string());
+    match (artist, title) {
+        (Some(artist), Some(title)) => Some(format!("{artist} - {title}")),
+        (artist, title) => artist.or(title),
+    }
+}
+
+fn folder_art(path: &Path) -> Option<PathBuf> {
+    let entries = std::fs::read_dir(path.parent()?).ok()?;
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_lowercase();
+            let rank = FOLDER_ART.iter().position(|art| *art == name)?;
+            Some((rank, entry.path()))
+        })
+        .min_by_key(|(rank, _)| *rank)
+        .map(|(_, path)| path)
+}
+
+/// Adds the start of a video chain showing `art` to `pipeline`: a source, decodebin3 and an
+/// imagefreeze that ends by itself once it's made `duration` worth of frames at `framerate`.
+/// Returns the element to link the rest of the chain to.
+pub(crate) fn add_cover_art(
+    pipeline: &gstreamer::Pipeline,
+    art: &CoverArt,
+    duration: gstreamer::ClockTime,
+    framerate: gstreamer::Fraction,
+) -> Result<gstreamer::Element, Error> {
+    let source = match art {
+        CoverArt::Embedded(sample) => {
+            // No caps, decodebin3 finds out what the picture is
+            let appsrc = gstreamer_app::AppSrc::builder().name("cover_art_src").build();
+            if let Some(buffer) = sample.buffer_owned()
+                && let Err(error) = appsrc.push_buffer(buffer)
+            {
+                eprintln!("Failed to push the cover art: {error}");
+            }
+            _ = appsrc.end_of_stream();
+            appsrc.upcast()
+        }
+        CoverArt::Folder(path) => create_file_source(path)?,
+    };
+    let decodebin = gstreamer::ElementFactory::make("decodebin3")
+        .name("cover_art_decodebin")
+        .build()?;
+
+    let frames =
+        duration.seconds_f64() * f64::from(framerate.numer()) / f64::from(framerate.denom());
+    let imagefreeze = gstreamer::ElementFactory::make("imagefreeze")
+        .property("num-buffers", frames.ceil() as i32)
+        .build()?;
+    // So imagefreeze makes its frames at the output's rate, and `num-buffers` comes out right
+    let capsfilter = gstreamer::ElementFactory::make("capsfilter")
+        .property(
+            "caps",
+            gstreamer::Caps::builder("video/x-raw").field("framerate", framerate).build(),
+        )
+        .build()?;
+
+    pipeline.add_many([&source, &decodebin, &imagefreeze, &capsfilter])?;
+    source.link(&decodebin)?;
+    imagefreeze.link(&capsfilter)?;
+
+    let imagefreeze_sink_pad = imagefreeze.static_pad("sink").unwrap();
+    decodebin.connect_pad_added(move |_, pad| {
+        if pad_stream_type(pad).contains(gstreamer::StreamType::VIDEO)
+            && !imagefreeze_sink_pad.is_linked()
+            && let Err(error) = pad.link(&imagefreeze_sink_pad)
+        {
+            eprintln!("Failed to link the cover art: {error}");
+        }
+    });
+
+    Ok(capsfilter)
+}
+
+/// The track's artist and title, bigger than the file name and above it.
+pub(crate) fn create_caption_overlay(caption: &str) -> Result<gstreamer::Element, Error> {
+    let overlay = gstreamer::ElementFactory::make("textoverlay")
+        .name("caption_overlay")
+        .property("text", caption)
+        .property("use-markup", false)
+        .property_from_str("valignment", "bottom")
+        .property_from_str("halignment", "center")
+        .property_from_str("font-desc", "Sans, 16")
+        .property("ypad", 40i32)
+        .property("shaded-background", true)
+        .build()?;
+    Ok(overlay)
+}
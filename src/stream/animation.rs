@@ -0,0 +1,124 @@
+use std::path::Path;
+
+use gstreamer::prelude::*;
+
+use super::Error;
+use super::feeder::create_file_source;
+use super::selection::pad_stream_type;
+
+/// Most an animation may take up once decoded, bigger ones are shown as stills.
+const MAX_DECODED_BYTES: usize = 256 * 1024 * 1024;
+
+/// How long decoding waits for the next frame before giving up on the file.
+const FRAME_TIMEOUT: gstreamer::ClockTime = gstreamer::ClockTime::from_seconds(5);
+
+/// The frames of an animated image (GIF, APNG), decoded up front so they can be looped for as
+/// long as the image is shown.
+pub(crate) struct Animation {
+    caps: gstreamer::Caps,
+    frames: Vec<gstreamer::Buffer>,
+    /// When the first frame is shown.
+    start: gstreamer::ClockTime,
+    /// How long one loop takes.
+    length: gstreamer::ClockTime,
+}
+
+impl Animation {
+    /// `None` if it's too big to keep decoded, or doesn't have the timestamps to loop by.
+    pub(crate) fn decode(path: &Path) -> Result<Option<Self>, Error> {
+        let pipeline = gstreamer::Pipeline::builder().name("animation-decoder").build();
+        let filesrc = create_file_source(path)?;
+        let decodebin = gstreamer::ElementFactory::make("decodebin3").build()?;
+        let videoconvert = gstreamer::ElementFactory::make("videoconvert").build()?;
+        let appsink = gstreamer_app::AppSink::builder().sync(false).build();
+        pipeline.add_many([&filesrc, &decodebin, &videoconvert, appsink.upcast_ref()])?;
+        filesrc.link(&decodebin)?;
+        videoconvert.link(&appsink)?;
+
+        let videoconvert_sink_pad = videoconvert.static_pad("sink").unwrap();
+        decodebin.connect_pad_added(move |_, pad| {
+            if pad_stream_type(pad).contains(gstreamer::StreamType::VIDEO)
+                && !videoconvert_sink_pad.is_linked()
+                && let Err(error) = pad.link(&videoconvert_sink_pad)
+            {
+                eprintln!("Failed to link the animation: {error}");
+            }
+        });
+
+        pipeline.set_state(gstreamer::State::Playing)?;
+        let result = pull_frames(&pipeline, &appsink);
+        _ = pipeline.set_state(gstreamer::State::Null);
+        result
+    }
+
+    /// An appsrc that plays the frames over and over, each loop's timestamps carrying on from
+    /// the last one's. It never ends, the feeder loop stops it like a still.
+    pub(crate) fn into_source(self) -> gstreamer_app::AppSrc {
+        let appsrc = gstreamer_app::AppSrc::builder()
+            .name("animation_src")
+            .caps(&self.caps)
+            .format(gstreamer::Format::Time)
+            .build();
+        let mut next = 0;
+        appsrc.set_callbacks(
+            gstreamer_app::AppSrcCallbacks::builder()
+                .need_data(move |appsrc, _| {
+                    let frame = &self.frames[next % self.frames.len()];
+                    let offset = self.length * (next / self.frames.len()) as u64;
+                    next += 1;
+                    let mut buffer = frame.copy();
+                    let pts = frame.pts().map(|pts| pts.saturating_sub(self.start) + offset);
+                    buffer.make_mut().set_pts(pts);
+                    _ = appsrc.push_buffer(buffer);
+                })
+                .build(),
+        );
+        appsrc
+    }
+}
+
+fn pull_frames(
+    pipeline: &gstreamer::Pipeline,
+    appsink: &gstreamer_app::AppSink,
+) -> Result<Option<Animation>, Error> {
+    let mut caps = None;
+    let mut frames = Vec::new();
+    let mut decoded_bytes = 0;
+    let mut end = gstreamer::ClockTime::ZERO;
+    while let Some(sample) = appsink.try_pull_sample(FRAME_TIMEOUT) {
+        let Some(buffer) = sample.buffer_owned() else { continue };
+        let Some(pts) = buffer.pts() else { return Ok(None) };
+        decoded_bytes += buffer.size();
+        if decoded_bytes > MAX_DECODED_BYTES {
+            return Ok(None);
+        }
+        end = end.max(pts + buffer.duration().unwrap_or(gstreamer::ClockTime::ZERO));
+        if caps.is_none() {
+            caps = sample.caps_owned();
+        }
+        frames.push(buffer);
+    }
+
+    let bus = pipeline.bus().unwrap();
+    if let Some(msg) = bus.pop_filtered(&[gstreamer::MessageType::Error])
+        && let gstreamer::MessageView::Error(err) = msg.view()
+    {
+        return Err(Error::Glib(err.error()));
+    }
+    // Stopped coming without an error
+    if !appsink.is_eos() {
+        return Ok(None);
+    }
+
+    let start = frames
+        .first()
+        .and_then(|frame| frame.pts())
+        .unwrap_or(gstreamer::ClockTime::ZERO);
+    let length = end.saturating_sub(start);
+    match caps {
+        Some(caps) if frames.len() > 1 && length > gstreamer::ClockTime::ZERO => {
+            Ok(Some(Animation { caps, frames, start, length }))
+        }
+        _ => Ok(None),
+    }
+}
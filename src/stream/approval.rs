@@ -0,0 +1,73 @@
+use std::collections::{HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+/// How many files can wait for review at once, further picks are skipped without asking.
+const MAX_PENDING: usize = 50;
+
+/// Moderation of picked files: when enabled, a file only airs once it has been approved.
+///
+/// Files nobody has reviewed yet are skipped and put up for review. Once approved they're played
+/// next, and can be picked again later without asking. Rejected files are never played.
+#[derive(Debug, Clone, Default)]
+pub struct Approvals {
+    enabled: bool,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    pending: Vec<PathBuf>,
+    approved: HashSet<PathBuf>,
+    rejected: HashSet<PathBuf>,
+    /// Approved files that haven't aired since.
+    ready: VecDeque<PathBuf>,
+}
+
+impl Approvals {
+    pub fn new(enabled: bool) -> Self {
+        Self { enabled, state: Arc::default() }
+    }
+
+    /// Whether `path` can air.
+    pub fn allows(&self, path: &Path) -> bool {
+        !self.enabled || self.state.lock().approved.contains(path)
+    }
+
+    /// Puts `path` up for review. Returns `false` if it's already waiting, has been rejected, or
+    /// there are too many files waiting already.
+    pub fn request(&self, path: &Path) -> bool {
+        let mut state = self.state.lock();
+        if state.rejected.contains(path)
+            || state.pending.len() >= MAX_PENDING
+            || state.pending.iter().any(|pending| pending == path)
+        {
+            return false;
+        }
+        state.pending.push(path.to_path_buf());
+        true
+    }
+
+    /// Records the review of a waiting file. Returns `false` if it wasn't waiting for one.
+    pub fn review(&self, path: &Path, approved: bool) -> bool {
+        let mut state = self.state.lock();
+        let Some(index) = state.pending.iter().position(|pending| pending == path) else {
+            return false;
+        };
+        let path = state.pending.remove(index);
+        if approved {
+            state.approved.insert(path.clone());
+            state.ready.push_back(path);
+        } else {
+            state.rejected.insert(path);
+        }
+        true
+    }
+
+    /// The next file that was approved and hasn't aired since.
+    pub fn take_ready(&self) -> Option<PathBuf> {
+        self.state.lock().ready.pop_front()
+    }
+}
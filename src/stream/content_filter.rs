@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use crate::media_cache::MediaInfoCache;
+use crate::thumbnail::thumbnail;
+
+/// Width of the thumbnails handed to the classifier.
+const THUMBNAIL_WIDTH: u32 = 320;
+
+/// An external classifier that decides whether a file may air, e.g. to keep NSFW content off.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum ContentClassifier {
+    /// Runs through the shell with the file's path as `$1` and a JPEG thumbnail as `$2` (empty for
+    /// files without video). Exiting with 0 allows the file, 1 vetoes it.
+    Command(String),
+    /// `POST`s the JPEG thumbnail (an empty body for files without video) to this URL, with the
+    /// file's path in the `path` query parameter. Expects `{"allow": bool}` back.
+    Http(String),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ClassifyError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Http(#[from] ureq::Error),
+    #[error("Classifier exited with {0}")]
+    Exit(std::process::ExitStatus),
+}
+
+/// Runs candidate files past a [`ContentClassifier`] before they're picked.
+///
+/// Verdicts are remembered in the media cache database if there is one (for as long as the file
+/// doesn't change), otherwise for the rest of the session. Files that can't be classified are
+/// skipped for now and tried again the next time they come up.
+#[derive(Debug, Clone)]
+pub struct ContentFilter {
+    classifier: ContentClassifier,
+    cache: Option<MediaInfoCache>,
+    verdicts: Arc<Mutex<HashMap<PathBuf, bool>>>,
+}
+
+impl ContentFilter {
+    pub fn new(classifier: ContentClassifier, cache: Option<MediaInfoCache>) -> Self {
+        Self { classifier, cache, verdicts: Arc::default() }
+    }
+
+    /// Whether `path` may air, asking the classifier unless there's a verdict already.
+    pub fn allows(&self, path: &Path) -> bool {
+        if let Some(allowed) = self.cached(path) {
+            return allowed;
+        }
+
+        let allowed = match self.classify(path) {
+            Ok(allowed) => allowed,
+            Err(error) => {
+                eprintln!("Failed to classify {}: {error}", path.display());
+                return false;
+            }
+        };
+        self.verdicts.lock().insert(path.to_path_buf(), allowed);
+        if let Some(cache) = &self.cache
+            && let Err(error) = cache.set_classification(path, allowed)
+        {
+            eprintln!("Failed to cache classification of {}: {error}", path.display());
+        }
+        allowed
+    }
+
+    /// The verdict on `path`, if it has been classified already.
+    pub fn cached(&self, path: &Path) -> Option<bool> {
+        if let Some(&allowed) = self.verdicts.lock().get(path) {
+            return Some(allowed);
+        }
+        self.cache.as_ref()?.classification(path)
+    }
+
+    fn classify(&self, path: &Path) -> Result<bool, ClassifyError> {
+        // Audio files have nothing to look at, the classifier still gets their path
+        let jpeg = thumbnail(path, THUMBNAIL_WIDTH).unwrap_or_default();
+        match &self.classifier {
+            ContentClassifier::Command(command) => {
+                let mut thumbnail_file = tempfile::Builder::new().suffix(".jpg").tempfile()?;
+                thumbnail_file.write_all(&jpeg)?;
+                let thumbnail_arg =
+                    if jpeg.is_empty() { Path::new("") } else { thumbnail_file.path() };
+                let status = Command::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .arg("z-stream")
+                    .arg(path)
+                    .arg(thumbnail_arg)
+                    .status()?;
+                match status.code() {
+                    Some(0) => Ok(true),
+                    Some(1) => Ok(false),
+                    _ => Err(ClassifyError::Exit(status)),
+                }
+            }
+            ContentClassifier::Http(url) => {
+                #[derive(serde::Deserialize)]
+                struct Verdict {
+                    allow: bool,
+                }
+
+                let verdict: Verdict = ureq::post(url)
+                    .query("path", path.to_string_lossy())
+                    .content_type("image/jpeg")
+                    .send(&jpeg[..])?
+                    .body_mut()
+                    .read_json()?;
+                Ok(verdict.allow)
+            }
+        }
+    }
+}
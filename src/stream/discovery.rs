@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use crate::media_cache::MediaInfoCache;
+use crate::media_info::{Error, MediaInfo};
+
+/// How many files can be discovered ahead of time at once.
+const PREFETCH_THREADS: usize = 2;
+
+type PendingResult = flume::Receiver<Result<MediaInfo, Error>>;
+
+/// Finds out what files contain, going through the cache if there is one.
+///
+/// Queued files can be discovered ahead of time on a small thread pool of its own, so a file
+/// that's slow to probe doesn't hold up the switch to it.
+#[derive(Debug, Clone)]
+pub struct Discovery {
+    cache: Option<MediaInfoCache>,
+    timeout: gstreamer::ClockTime,
+    pool: Arc<rayon::ThreadPool>,
+    pending: Arc<Mutex<HashMap<PathBuf, PendingResult>>>,
+}
+
+impl Discovery {
+    pub fn new(cache: Option<MediaInfoCache>, timeout: gstreamer::ClockTime) -> Self {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(PREFETCH_THREADS)
+            .thread_name(|index| format!("discovery-{index}"))
+            .start_handler(|_| crate::resources::limit_scan_thread())
+            .build()
+            .expect("Failed to start discovery threads");
+        Self { cache, timeout, pool: Arc::new(pool), pending: Arc::default() }
+    }
+
+    pub fn cache(&self) -> Option<&MediaInfoCache> {
+        self.cache.as_ref()
+    }
+
+    /// Starts discovering `path` in the background, unless that's already happening.
+    pub fn prefetch(&self, path: &Path) {
+        let mut pending = self.pending.lock();
+        if pending.contains_key(path) {
+            return;
+        }
+        let (result_tx, result_rx) = flume::bounded(1);
+        pending.insert(path.to_path_buf(), result_rx);
+
+        let this = self.clone();
+        let path = path.to_path_buf();
+        self.pool.spawn(move || _ = result_tx.send(this.detect_now(&path)));
+    }
+
+    /// Drops a prefetched result that won't be needed, e.g. because the file was dequeued.
+    pub fn forget(&self, path: &Path) {
+        self.pending.lock().remove(path);
+    }
+
+    /// Uses the prefetched result if there is one (waiting for it if it's still running),
+    /// otherwise discovers the file now.
+    pub fn detect(&self, path: &Path) -> Result<MediaInfo, Error> {
+        let pending = self.pending.lock().remove(path);
+        if let Some(result) = pending.and_then(|result_rx| result_rx.recv().ok()) {
+            return result;
+        }
+        self.detect_now(path)
+    }
+
+    fn detect_now(&self, path: &Path) -> Result<MediaInfo, Error> {
+        match &self.cache {
+            Some(cache) => cache.detect(path, self.timeout),
+            None => MediaInfo::detect_with_timeout(path, self.timeout),
+        }
+    }
+}
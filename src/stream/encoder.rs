@@ -1,40 +1,122 @@
 use glib::object::ObjectExt;
+use glib::types::StaticType;
 use gstreamer::gobject::GObjectExtManualGst;
 
-use super::Error;
+use super::{AudioOptions, ElementRole, Error, OutputProfile, RateControl, VideoOptions};
 
-pub fn create_video_encoder() -> Result<gstreamer::Element, Error> {
-    if let Ok(encoder) = create_video_encoder_inner("nvh264enc") {
-        eprintln!("Using nvh264enc");
-        return Ok(encoder);
-    }
+/// H.264 encoders in order of preference. Only the hardware encoders for hardware that's actually
+/// there are registered, x264 is the fallback if none of them are.
+const DEFAULT_H264_ENCODERS: &[&str] = &[
+    "nvh264enc",  // NVIDIA
+    "qsvh264enc", // Intel Quick Sync, Linux and Windows
+    "vah264enc",  // VA-API, Intel and AMD on Linux
+    "vtenc_h264", // VideoToolbox, macOS
+    "x264enc",
+];
+
+/// Properties set on specific encoders, before the ones that apply to all of them. Rate control
+/// and the GOP are set from [`VideoOptions`].
+const DEFAULT_H264_PROPERTIES: &[(&str, &str, &str)] = &[
+    // Use preset for a better quality/latency balance than "tune"
+    ("nvh264enc", "preset", "low-latency-hq"),
+    ("nvh264enc", "zerolatency", "true"),
+    ("qsvh264enc", "cabac", "on"),
+    ("qsvh264enc", "b-frames", "2"),
+    ("vtenc_h264", "realtime", "true"),
+    // B-frames, which would add latency
+    ("vtenc_h264", "allow-frame-reordering", "false"),
+    ("x264enc", "profile", "high"),
+];
+
+/// Which H.264 encoders are tried, and properties to set on them that override the defaults.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct EncoderOptions {
+    /// Element factory names, the first one that can be made is used.
+    pub priority: Vec<String>,
+    /// Set after everything else, so they win over the defaults.
+    pub properties: Vec<EncoderProperty>,
+    /// Threads each software encoder may use, hardware encoders don't have a say.
+    pub threads: Option<u32>,
+}
 
-    if let Ok(encoder) = create_video_encoder_inner("vah264enc") {
-        eprintln!("Using vah264enc");
-        return Ok(encoder);
+impl Default for EncoderOptions {
+    fn default() -> Self {
+        Self {
+            priority: DEFAULT_H264_ENCODERS.iter().map(|factory| factory.to_string()).collect(),
+            properties: Vec::new(),
+            threads: None,
+        }
     }
+}
 
-    create_video_encoder_inner("x264enc")
+/// A property to set on one encoder, if it's the one that ends up being used.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct EncoderProperty {
+    pub factory: String,
+    pub name: String,
+    /// Parsed the way `gst-launch-1.0` parses property values.
+    pub value: String,
 }
 
-fn create_video_encoder_inner(factory: &str) -> Result<gstreamer::Element, Error> {
-    let encoder = gstreamer::ElementFactory::make(factory).name("v_encode").build()?;
+/// Parses `FACTORY.PROPERTY=VALUE`, e.g. `x264enc.speed-preset=veryfast`.
+impl std::str::FromStr for EncoderProperty {
+    type Err = String;
 
-    match factory {
-        "nvh264enc" => {
-            // Use preset for a better quality/latency balance than "tune"
-            encoder.set_property_from_str("preset", "low-latency-hq");
-            // Use Constant Bitrate (CBR) for streaming
-            encoder.set_property_from_str("rc-mode", "cbr");
-            encoder.set_property("zerolatency", true);
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid encoder property {s:?}, expected FACTORY.PROPERTY=VALUE");
+        let (key, value) = s.split_once('=').ok_or_else(invalid)?;
+        let (factory, name) = key.split_once('.').ok_or_else(invalid)?;
+        if factory.is_empty() || name.is_empty() {
+            return Err(invalid());
         }
-        "vah264enc" => {
-            encoder.set_property_from_str("rate-control", "cbr");
-        }
-        "x264enc" => {
-            encoder.set_property("profile", "high");
+        Ok(Self {
+            factory: factory.to_string(),
+            name: name.to_string(),
+            value: value.to_string(),
+        })
+    }
+}
+
+pub fn create_video_encoder(
+    video: VideoOptions,
+    options: &EncoderOptions,
+) -> Result<gstreamer::Element, Error> {
+    let mut last_error = None;
+    for factory in &options.priority {
+        match create_video_encoder_inner(factory, video, options) {
+            Ok(encoder) => {
+                eprintln!("Using {factory}");
+                return Ok(encoder);
+            }
+            Err(error) => last_error = Some(error),
         }
-        _ => (),
+    }
+    Err(last_error.unwrap_or(Error::NoVideoEncoder))
+}
+
+/// Sets a property from its string form, without panicking on properties the encoder (or its
+/// version) doesn't have.
+fn set_encoder_property(encoder: &gstreamer::Element, name: &str, value: &str) {
+    if !encoder.has_property(name) {
+        eprintln!("Encoder has no property {name:?}, not setting it to {value:?}");
+        return;
+    }
+    if let Err(error) = encoder.try_set_property_from_str(name, value) {
+        eprintln!("Failed to set encoder property {name:?} to {value:?}: {error}");
+    }
+}
+
+fn create_video_encoder_inner(
+    factory: &str,
+    video: VideoOptions,
+    options: &EncoderOptions,
+) -> Result<gstreamer::Element, Error> {
+    let encoder = gstreamer::ElementFactory::make(factory)
+        .name(ElementRole::VideoEncoder.element_name())
+        .build()?;
+
+    for &(_, name, value) in DEFAULT_H264_PROPERTIES.iter().filter(|(f, ..)| *f == factory) {
+        set_encoder_property(&encoder, name, value);
     }
 
     if encoder.has_property("tune") && factory != "nvh264enc" {
@@ -46,21 +128,167 @@ fn create_video_encoder_inner(factory: &str) -> Result<gstreamer::Element, Error
     }
 
     if encoder.has_property("bitrate") {
-        // Set a target bitrate (e.g., 4 Mbps for 720p)
-        encoder.set_property("bitrate", 6000u32);
-    }
-
-    if encoder.has_property("key-int-max") {
-        encoder.set_property("key-int-max", 60u32);
+        // In kbit/s for all of them
+        encoder.set_property("bitrate", video.bitrate_kbps);
     }
 
     if encoder.has_property("bframes") {
         encoder.set_property("bframes", 2u32);
     }
 
-    if encoder.has_property("cabac") {
+    // An enum on some encoders rather than a flag
+    if encoder.has_property_with_type("cabac", bool::static_type()) {
         encoder.set_property("cabac", true);
     }
 
+    for (name, value) in rate_control_properties(factory, video) {
+        set_encoder_property(&encoder, name, &value);
+    }
+    for (name, value) in keyframe_properties(factory, video) {
+        set_encoder_property(&encoder, name, &value);
+    }
+
+    if let Some(threads) = options.threads
+        && encoder.has_property("threads")
+    {
+        set_encoder_property(&encoder, "threads", &threads.to_string());
+    }
+
+    for property in options.properties.iter().filter(|property| property.factory == factory) {
+        set_encoder_property(&encoder, &property.name, &property.value);
+    }
+
+    Ok(encoder)
+}
+
+/// The properties that put `factory` in `video`'s rate control mode. Set after the bitrate, which
+/// some modes clear.
+fn rate_control_properties(factory: &str, video: VideoOptions) -> Vec<(&'static str, String)> {
+    let bitrate = video.bitrate_kbps.to_string();
+    match (factory, video.rate_control) {
+        // x264enc's "cbr" pass is really ABR, the VBV buffer is what keeps it constant
+        ("x264enc", RateControl::Cbr) => {
+            vec![("pass", "cbr".into()), ("vbv-buf-capacity", "1000".into())]
+        }
+        ("x264enc", RateControl::Vbr) => {
+            vec![("pass", "cbr".into()), ("vbv-buf-capacity", "0".into())]
+        }
+        ("x264enc", RateControl::Cqp { qp }) => {
+            vec![("pass", "quant".into()), ("quantizer", qp.to_string())]
+        }
+        ("x264enc", RateControl::Crf { quality }) => {
+            vec![("pass", "qual".into()), ("quantizer", quality.to_string())]
+        }
+        ("nvh264enc", RateControl::Cbr) => vec![("rc-mode", "cbr".into())],
+        ("nvh264enc", RateControl::Vbr) => vec![("rc-mode", "vbr".into())],
+        ("nvh264enc", RateControl::Cqp { qp }) => {
+            vec![("rc-mode", "cqp".into()), ("qp-const", qp.to_string())]
+        }
+        // Quality targeted VBR, with the bitrate as the cap
+        ("nvh264enc", RateControl::Crf { quality }) => vec![
+            ("rc-mode", "vbr".into()),
+            ("const-quality", quality.to_string()),
+            ("max-bitrate", bitrate),
+            ("bitrate", "0".into()),
+        ],
+        ("qsvh264enc", RateControl::Cbr) => vec![("rate-control", "cbr".into())],
+        ("qsvh264enc", RateControl::Vbr) => vec![("rate-control", "vbr".into())],
+        ("qsvh264enc", RateControl::Cqp { qp }) => vec![
+            ("rate-control", "cqp".into()),
+            ("qp-i", qp.to_string()),
+            ("qp-p", qp.to_string()),
+            ("qp-b", qp.to_string()),
+        ],
+        ("qsvh264enc", RateControl::Crf { quality }) => {
+            vec![("rate-control", "icq".into()), ("icq-quality", quality.to_string())]
+        }
+        ("vah264enc", RateControl::Cbr) => vec![("rate-control", "cbr".into())],
+        ("vah264enc", RateControl::Vbr) => vec![("rate-control", "vbr".into())],
+        // Which quality modes VA-API has depends on the driver, constant QP always works
+        ("vah264enc", RateControl::Cqp { qp: quality } | RateControl::Crf { quality }) => vec![
+            ("rate-control", "cqp".into()),
+            ("qpi", quality.to_string()),
+            ("qpp", quality.to_string()),
+            ("qpb", quality.to_string()),
+        ],
+        ("vtenc_h264", RateControl::Cbr) => vec![("rate-control", "cbr".into())],
+        ("vtenc_h264", RateControl::Vbr) => vec![("rate-control", "abr".into())],
+        // VideoToolbox only has a 0-1 quality, used when there's no bitrate
+        ("vtenc_h264", RateControl::Cqp { qp: quality } | RateControl::Crf { quality }) => {
+            let quality = 1.0 - f64::from(quality.min(51)) / 51.0;
+            vec![("bitrate", "0".into()), ("quality", quality.to_string())]
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// The properties that give `factory` a fixed GOP of `video`'s keyframe interval. Adaptive and
+/// scene cut keyframes are turned off where they'd move the next GOP.
+fn keyframe_properties(factory: &str, video: VideoOptions) -> Vec<(&'static str, String)> {
+    let frames = video.keyframe_frames().to_string();
+    match factory {
+        "x264enc" => vec![
+            ("key-int-max", frames.clone()),
+            ("option-string", format!("min-keyint={frames}:scenecut=0")),
+        ],
+        "nvh264enc" => vec![("gop-size", frames), ("i-adapt", "false".into())],
+        "qsvh264enc" => vec![("gop-size", frames)],
+        "vah264enc" => vec![("key-int-max", frames)],
+        "vtenc_h264" => vec![("max-keyframe-interval", frames)],
+        "mpeg2enc" => vec![
+            ("min-gop-size", frames.clone()),
+            ("max-gop-size", frames),
+            ("closed-gop", "true".into()),
+        ],
+        "avenc_mpeg2video" => vec![("gop-size", frames)],
+        _ => Vec::new(),
+    }
+}
+
+/// For [`OutputProfile::Mpeg2Ts`], mjpegtools' encoder if it's there, otherwise libav's.
+pub fn create_mpeg2_video_encoder(video: VideoOptions) -> Result<gstreamer::Element, Error> {
+    let name = ElementRole::VideoEncoder.element_name();
+    let encoder = match gstreamer::ElementFactory::make("mpeg2enc").name(name.as_str()).build() {
+        Ok(encoder) => {
+            // Generic MPEG-2 (rather than DVD/SVCD flavoured), the bitrate is in kbit/s
+            encoder.set_property_from_str("format", "3");
+            encoder.set_property_from_str("bitrate", &video.bitrate_kbps.to_string());
+            encoder
+        }
+        Err(_) => {
+            let encoder = gstreamer::ElementFactory::make("avenc_mpeg2video").name(name).build()?;
+            // In bit/s
+            let bitrate = u64::from(video.bitrate_kbps) * 1000;
+            encoder.set_property_from_str("bitrate", &bitrate.to_string());
+            encoder
+        }
+    };
+    let factory = encoder.factory().map(|f| f.name()).unwrap_or_default();
+    for (name, value) in keyframe_properties(&factory, video) {
+        set_encoder_property(&encoder, name, &value);
+    }
+    eprintln!("Using {factory}");
+    Ok(encoder)
+}
+
+/// AAC, or MPEG-1 Layer II (Layer III if that's all there is) for [`OutputProfile::Mpeg2Ts`].
+/// `program` is 0 for the primary audio.
+pub fn create_audio_encoder(
+    profile: OutputProfile,
+    audio: AudioOptions,
+    program: usize,
+) -> Result<gstreamer::Element, Error> {
+    let name = ElementRole::AudioEncoder(program).element_name();
+    let make = |factory: &str| gstreamer::ElementFactory::make(factory).name(name.as_str()).build();
+    let encoder = match profile {
+        OutputProfile::H264Aac => make("avenc_aac")?,
+        OutputProfile::Mpeg2Ts => make("avenc_mp2").or_else(|_| make("lamemp3enc"))?,
+    };
+    // lamemp3enc takes kbit/s, libav's encoders bit/s
+    let bitrate = match encoder.factory().map(|f| f.name()).as_deref() {
+        Some("lamemp3enc") => u64::from(audio.bitrate_kbps),
+        _ => u64::from(audio.bitrate_kbps) * 1000,
+    };
+    encoder.set_property_from_str("bitrate", &bitrate.to_string());
     Ok(encoder)
 }
@@ -1,25 +1,113 @@
+use std::collections::VecDeque;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use glib::prelude::*;
 use gstreamer::prelude::*;
 use parking_lot::Mutex;
 
-use super::{AppSources, AppSrcStorage, Command, Error, Event};
-use crate::media_info::MediaInfo;
-use crate::media_type::MediaType;
-use crate::random_files::RandomFiles;
+use super::album_art::{AudioCover, add_cover_art, create_caption_overlay};
+use super::animation::Animation;
+use super::pool::create_video_appsink;
+use super::selection::{StreamSelection, pad_stream_id, pad_stream_type};
+use super::visualizer::add_visualizer;
+use super::{
+    AppSources, AppSrcStorage, Approvals, AspectPolicy, AudioOptions, Command, ContentFilter,
+    DeinterlaceMode, Discovery, EncoderSwitch, EndReason, Error, Event, FileSource, Freeze,
+    GainOverrides, ItemOverrides, LiveInput, LiveTransition, LoudnessOptions, OverlaySlot,
+    PeerFiles, Probes, RatingPolicy, SlateKind, StreamOptions, StreamServices, TitleTemplate,
+    VideoOptions, attach_title_template, create_ken_burns, create_loudness_elements,
+    create_slate_pipeline, create_subtitle_overlay, db_to_linear, link_sidecar, play_sting,
+};
+use crate::media_info::{Error as MediaInfoError, MediaInfo};
+use crate::media_type::{MediaType, TypeFinder};
+use crate::random_files::LibraryRoots;
+
+/// How long to wait after a pick that can't play and can't be put up for approval either.
+const APPROVAL_BACKOFF: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// How long picks can keep being turned down, e.g. because nothing in the library is allowed in
+/// the current slot or peers are playing all of it, before the standby slate goes up instead.
+const MAX_PICKING_TIME: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// How long the standby slate stays up before looking for something to play again.
+const STANDBY_RETRY: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// How long to wait before trying again after a slate failed.
+const SLATE_RETRY: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Longest a graceful skip waits for a keyframe once its delay is up, for files that rarely or
+/// never have one.
+const MAX_KEYFRAME_WAIT: std::time::Duration = std::time::Duration::from_secs(10);
 
 /// Blocks until the AppSrc is available in the shared storage.
-fn get_app_sources(storage: AppSrcStorage) -> AppSources {
-    loop {
-        let appsrc_opt = storage.lock().clone();
-        if let Some(appsrc) = appsrc_opt {
-            println!("Feeder thread connected to appsrc.");
-            return appsrc;
+fn get_app_sources(storage: &AppSrcStorage) -> (u64, AppSources) {
+    let (version, appsrcs) = storage.wait();
+    println!("Feeder thread connected to appsrc.");
+    (version, appsrcs)
+}
+
+/// A deinterlacer for the video of a file, if `mode` calls for one.
+fn create_deinterlace(
+    mode: DeinterlaceMode,
+    media_info: &MediaInfo,
+) -> Result<Option<gstreamer::Element>, Error> {
+    // In auto mode it leaves progressive frames alone, which covers files that only have some
+    let element_mode = match mode {
+        DeinterlaceMode::Auto if media_info.interlaced => "auto",
+        DeinterlaceMode::Always => "interlaced",
+        DeinterlaceMode::Auto | DeinterlaceMode::Never => return Ok(None),
+    };
+    let deinterlace = gstreamer::ElementFactory::make("deinterlace")
+        .name("deinterlace_vid")
+        .property_from_str("mode", element_mode)
+        .build()?;
+    Ok(Some(deinterlace))
+}
+
+/// A filesrc reading `path`, set through its URI so paths that aren't UTF-8 work too.
+pub(crate) fn create_file_source(path: &Path) -> Result<gstreamer::Element, Error> {
+    let uri = glib::filename_to_uri(path, None)?;
+    let filesrc = gstreamer::ElementFactory::make("filesrc").build()?;
+    filesrc.dynamic_cast_ref::<gstreamer::URIHandler>().unwrap().set_uri(&uri)?;
+    Ok(filesrc)
+}
+
+/// Turns the picture upright before it's scaled, going by its orientation tag, e.g. for videos
+/// recorded in portrait on a phone.
+///
+/// It passes everything else through, and is always added as media infos cached before
+/// [`MediaInfo::rotation`] existed don't know the orientation.
+fn create_videoflip() -> Result<gstreamer::Element, Error> {
+    let videoflip = gstreamer::ElementFactory::make("videoflip")
+        .name("videoflip_vid")
+        .property_from_str("method", "automatic")
+        .build()?;
+    Ok(videoflip)
+}
+
+/// Fits the picture to the output's size as `policy` says. The crop, if there is one, goes
+/// before the videoscale, and the capsfilter after them sets the size.
+fn create_fit(
+    policy: AspectPolicy,
+    video: VideoOptions,
+) -> Result<(Option<gstreamer::Element>, gstreamer::Element), Error> {
+    let crop = match policy {
+        AspectPolicy::Crop => {
+            let aspect_ratio = gstreamer::Fraction::new(video.width as i32, video.height as i32);
+            let crop = gstreamer::ElementFactory::make("aspectratiocrop")
+                .property("aspect-ratio", aspect_ratio)
+                .build()?;
+            Some(crop)
         }
-        std::thread::sleep(std::time::Duration::from_millis(100));
-    }
+        AspectPolicy::Letterbox | AspectPolicy::Stretch => None,
+    };
+    // Without borders it scales to whatever the capsfilter asks for, stretching if it has to
+    let videoscale = gstreamer::ElementFactory::make("videoscale")
+        .property("add-borders", policy == AspectPolicy::Letterbox)
+        .build()?;
+    Ok((crop, videoscale))
 }
 
 fn create_title_overlay(path: &Path) -> Result<gstreamer::Element, Error> {
@@ -27,6 +115,8 @@ fn create_title_overlay(path: &Path) -> Result<gstreamer::Element, Error> {
     let element = gstreamer::ElementFactory::make("textoverlay")
         .name("textoverlay")
         .property("text", name.as_ref())
+        // Not markup, a name can have `&` or `<` in it
+        .property("use-markup", false)
         .property_from_str("valignment", "bottom") // top, center, bottom
         .property_from_str("halignment", "left") // left, center, right
         .property_from_str("font-desc", "Sans, 6")
@@ -36,6 +126,7 @@ fn create_title_overlay(path: &Path) -> Result<gstreamer::Element, Error> {
 }
 
 fn create_counter_overlay(
+    probes: &Probes,
     duration: Option<gstreamer::ClockTime>,
 ) -> Result<gstreamer::Element, Error> {
     let duration_str = duration.map(|duration| {
@@ -61,7 +152,7 @@ fn create_counter_overlay(
     let last_updated_second = Arc::new(Mutex::new(None));
     let sink_pad = counter_overlay.static_pad("video_sink").unwrap();
     let counter_overlay_weak = counter_overlay.downgrade();
-    sink_pad.add_probe(gstreamer::PadProbeType::BUFFER, move |_pad, info| {
+    probes.add(&sink_pad, gstreamer::PadProbeType::BUFFER, move |_pad, info| {
         if let Some(buffer) = info.buffer()
             && let Some(pts) = buffer.pts()
             && let Some(counter_overlay) = counter_overlay_weak.upgrade()
@@ -91,87 +182,187 @@ fn create_counter_overlay(
     Ok(counter_overlay)
 }
 
-fn create_silent_audio(pipeline: &gstreamer::Pipeline) -> Result<gstreamer_app::AppSink, Error> {
-    // --- Audio Chain (audiotestsrc -> ...) ---
+/// Shows when and where a photo was taken, as far as its (EXIF) tags say.
+fn create_photo_info_overlay(
+    probes: &Probes,
+    slot: OverlaySlot,
+) -> Result<gstreamer::Element, Error> {
+    let (valignment, halignment) = slot.alignment();
+    let photo_info_overlay = gstreamer::ElementFactory::make("textoverlay")
+        .name("photo_info_overlay")
+        .property_from_str("valignment", valignment)
+        .property_from_str("halignment", halignment)
+        .property_from_str("font-desc", "Sans, 12")
+        .property("shaded-background", true)
+        .property("use-markup", false)
+        .build()?;
+
+    let sink_pad = photo_info_overlay.static_pad("video_sink").unwrap();
+    let photo_info_overlay_weak = photo_info_overlay.downgrade();
+    probes.add(&sink_pad, gstreamer::PadProbeType::EVENT_DOWNSTREAM, move |_pad, info| {
+        if let Some(event) = info.event()
+            && let gstreamer::EventView::Tag(tag) = event.view()
+            && let Some(text) = photo_info(tag.tag())
+            && let Some(photo_info_overlay) = photo_info_overlay_weak.upgrade()
+        {
+            photo_info_overlay.set_property("text", &text);
+        }
+        gstreamer::PadProbeReturn::Ok
+    });
+
+    Ok(photo_info_overlay)
+}
+
+/// The capture date and location in `tags`, one per line, if there are any.
+fn photo_info(tags: &gstreamer::TagListRef) -> Option<String> {
+    let date = tags.get::<gstreamer::tags::DateTime>().map(|date_time| {
+        let date_time = date_time.get();
+        // Only fully defined dates convert, EXIF dates always are
+        date_time
+            .to_g_date_time()
+            .ok()
+            .and_then(|date_time| date_time.format("%-d %B %Y").ok())
+            .map_or_else(|| date_time.year().to_string(), |date| date.to_string())
+    });
+
+    let city = tags
+        .get::<gstreamer::tags::GeoLocationCity>()
+        .map(|city| city.get().to_string());
+    let country = tags
+        .get::<gstreamer::tags::GeoLocationCountry>()
+        .map(|country| country.get().to_string());
+    let latitude = tags.get::<gstreamer::tags::GeoLocationLatitude>().map(|v| v.get());
+    let longitude = tags.get::<gstreamer::tags::GeoLocationLongitude>().map(|v| v.get());
+    let location = match (city, country, latitude, longitude) {
+        (Some(city), Some(country), _, _) => Some(format!("{city}, {country}")),
+        (Some(place), None, _, _) | (None, Some(place), _, _) => Some(place),
+        (None, None, Some(latitude), Some(longitude)) => {
+            let north_south = if latitude < 0.0 { 'S' } else { 'N' };
+            let east_west = if longitude < 0.0 { 'W' } else { 'E' };
+            Some(format!("{:.4}°{north_south}, {:.4}°{east_west}", latitude.abs(), longitude.abs()))
+        }
+        _ => None,
+    };
+
+    let lines: Vec<String> = date.into_iter().chain(location).collect();
+    (!lines.is_empty()).then(|| lines.join("\n"))
+}
+
+/// Pushes every sample from `appsink` into `appsrc`.
+pub(super) fn forward_samples(appsink: &gstreamer_app::AppSink, appsrc: &gstreamer_app::AppSrc) {
+    let appsrc_weak = appsrc.downgrade();
+    appsink.set_callbacks(
+        gstreamer_app::AppSinkCallbacks::builder()
+            .new_sample(move |sink| {
+                let Some(appsrc) = appsrc_weak.upgrade() else {
+                    return Err(gstreamer::FlowError::Error);
+                };
+                let sample = sink.pull_sample().map_err(|_| gstreamer::FlowError::Eos)?;
+                appsrc.push_sample(&sample).map_err(|_| gstreamer::FlowError::Error)
+            })
+            .build(),
+    );
+}
+
+/// `name_suffix` keeps element names unique when there's more than one audio program.
+pub(super) fn create_silent_audio(
+    pipeline: &gstreamer::Pipeline,
+    name_suffix: &str,
+    audio: AudioOptions,
+) -> Result<gstreamer_app::AppSink, Error> {
     let audiotestsrc = gstreamer::ElementFactory::make("audiotestsrc")
-        .name("audiotestsrc")
+        .name(format!("audiotestsrc{name_suffix}"))
         // Generate silence
         .property_from_str("wave", "silence")
         .build()?;
+    create_test_audio(pipeline, audiotestsrc, name_suffix, audio)
+}
 
+/// Like [`create_silent_audio`], but with a quiet 1 kHz line-up tone.
+pub(super) fn create_tone_audio(
+    pipeline: &gstreamer::Pipeline,
+    name_suffix: &str,
+    audio: AudioOptions,
+) -> Result<gstreamer_app::AppSink, Error> {
+    let audiotestsrc = gstreamer::ElementFactory::make("audiotestsrc")
+        .name(format!("audiotestsrc{name_suffix}"))
+        .property_from_str("wave", "sine")
+        .property("freq", 1000.0_f64)
+        .property("volume", 0.1_f64)
+        .build()?;
+    create_test_audio(pipeline, audiotestsrc, name_suffix, audio)
+}
+
+fn create_test_audio(
+    pipeline: &gstreamer::Pipeline,
+    audiotestsrc: gstreamer::Element,
+    name_suffix: &str,
+    audio: AudioOptions,
+) -> Result<gstreamer_app::AppSink, Error> {
+    // --- Audio Chain (audiotestsrc -> ...) ---
     let audioconvert_aud = gstreamer::ElementFactory::make("audioconvert").build()?;
     let audiorate_aud = gstreamer::ElementFactory::make("audiorate").build()?;
     let capsfilter_aud = gstreamer::ElementFactory::make("capsfilter")
-        .property(
-            "caps",
-            gstreamer::Caps::builder("audio/x-raw")
-                .field("format", "S16LE")
-                .field("layout", "interleaved")
-                .field("rate", 48000)
-                .field("channels", 2)
-                .build(),
-        )
+        .property("caps", audio.caps())
         .build()?;
-    let appsink_audio = gstreamer_app::AppSink::builder().name("appsink_audio").build();
+    let batcher_aud = audio.create_batcher()?;
+    let appsink_audio = gstreamer_app::AppSink::builder()
+        .name(format!("appsink_audio{name_suffix}"))
+        .build();
 
-    pipeline.add_many([
-        &audiotestsrc,
-        &audioconvert_aud,
-        &audiorate_aud,
-        &capsfilter_aud,
-        appsink_audio.upcast_ref(),
-    ])?;
-
-    gstreamer::Element::link_many([
-        &audiotestsrc,
-        &audioconvert_aud,
-        &audiorate_aud,
-        &capsfilter_aud,
-        appsink_audio.upcast_ref(),
-    ])?;
+    let mut audio_chain = vec![&audiotestsrc, &audioconvert_aud, &audiorate_aud, &capsfilter_aud];
+    audio_chain.extend(&batcher_aud);
+    audio_chain.push(appsink_audio.upcast_ref());
+    pipeline.add_many(audio_chain.iter().copied())?;
+    gstreamer::Element::link_many(audio_chain.iter().copied())?;
 
     Ok(appsink_audio)
 }
 
-fn create_audio_chain(pipeline: &gstreamer::Pipeline) -> Result<gstreamer_app::AppSink, Error> {
+fn create_audio_chain(
+    pipeline: &gstreamer::Pipeline,
+    name_suffix: &str,
+    gain_db: Option<f64>,
+    audio: AudioOptions,
+    loudness: Option<LoudnessOptions>,
+) -> Result<gstreamer_app::AppSink, Error> {
     // --- Audio Chain ---
     let audioconvert_aud = gstreamer::ElementFactory::make("audioconvert")
-        .name("audioconvert_aud") // Unique name
+        .name(format!("audioconvert_aud{name_suffix}")) // Unique name
+        .build()?;
+    // Before the volume, so gain overrides still apply on top
+    let loudness = match loudness {
+        Some(loudness) => create_loudness_elements(loudness, name_suffix)?,
+        None => Vec::new(),
+    };
+    let volume_aud = gstreamer::ElementFactory::make("volume")
+        .name(format!("volume_aud{name_suffix}"))
+        .property("volume", gain_db.map(db_to_linear).unwrap_or(1.0))
         .build()?;
     let audio_resample = gstreamer::ElementFactory::make("audioresample")
-        .name("audio_resample")
+        .name(format!("audio_resample{name_suffix}"))
         .build()?;
-    // These caps MUST match the caps in media_factory.rs
+    // These caps MUST match the caps of the appsrcs in media_factory.rs
     let capsfilter_aud = gstreamer::ElementFactory::make("capsfilter")
-        .property(
-            "caps",
-            gstreamer::Caps::builder("audio/x-raw")
-                .field("format", "S16LE")
-                .field("layout", "interleaved")
-                .field("rate", 48000)
-                .field("channels", 2)
-                .build(),
-        )
+        .property("caps", audio.caps())
         .build()?;
-    let queue_audio = gstreamer::ElementFactory::make("queue").name("a_queue").build()?;
-    let appsink_audio = gstreamer_app::AppSink::builder().name("appsink_audio").build();
-
-    pipeline.add_many([
-        &audioconvert_aud,
-        &audio_resample,
-        &capsfilter_aud,
-        &queue_audio,
-        appsink_audio.upcast_ref(),
-    ])?;
+    let queue_audio = gstreamer::ElementFactory::make("queue")
+        .name(format!("a_queue{name_suffix}"))
+        .build()?;
+    let batcher_aud = audio.create_batcher()?;
+    let appsink_audio = gstreamer_app::AppSink::builder()
+        .name(format!("appsink_audio{name_suffix}"))
+        .build();
+
+    let mut audio_chain = vec![&audioconvert_aud];
+    audio_chain.extend(&loudness);
+    audio_chain.extend([&volume_aud, &audio_resample, &capsfilter_aud, &queue_audio]);
+    audio_chain.extend(&batcher_aud);
+    audio_chain.push(appsink_audio.upcast_ref());
+    pipeline.add_many(audio_chain.iter().copied())?;
 
     // Pre-link the audio chain
-    gstreamer::Element::link_many([
-        &audioconvert_aud,
-        &audio_resample,
-        &capsfilter_aud,
-        &queue_audio,
-        appsink_audio.upcast_ref(),
-    ])?;
+    gstreamer::Element::link_many(audio_chain.iter().copied())?;
 
     Ok(appsink_audio)
 }
@@ -179,54 +370,65 @@ fn create_audio_chain(pipeline: &gstreamer::Pipeline) -> Result<gstreamer_app::A
 fn create_video_pipeline(
     path: &Path,
     app_sources: &AppSources,
-    has_audio: bool,
-    duration: Option<gstreamer::ClockTime>,
+    options: &StreamOptions,
+    media_info: &MediaInfo,
+    gain_db: Option<f64>,
+    probes: &Probes,
 ) -> Result<gstreamer::Pipeline, Error> {
     // filesrc -> decodebin -> videoconvert -> capsfilter -> appsink
+    let audio_streams = media_info.audio_streams;
+    let duration = media_info.known_duration();
     let pipeline = gstreamer::Pipeline::builder().name("decoder-pipeline").build();
 
     // --- Core Pipeline Elements ---
-    let filesrc = gstreamer::ElementFactory::make("filesrc")
-        .property("location", path.to_str().unwrap())
-        .build()?;
+    let filesrc = create_file_source(path)?;
 
     // Remove `no-audio=true` to let decodebin find audio
-    let decodebin = gstreamer::ElementFactory::make("decodebin3").build()?;
+    let decodebin = gstreamer::ElementFactory::make("decodebin3").name("decodebin").build()?;
 
     // --- Video Chain ---
     let videoconvert_vid = gstreamer::ElementFactory::make("videoconvert")
         .name("videoconvert_vid") // Unique name
         .build()?;
-
-    let videoscale_vid = gstreamer::ElementFactory::make("videoscale")
-        .name("videoscale_vid")
-        .property("add-borders", true)
-        .build()?;
-
+    let deinterlace = create_deinterlace(options.deinterlace, media_info)?;
+    let videoflip_vid = create_videoflip()?;
+    let (crop_vid, videoscale_vid) = create_fit(options.aspect, options.video)?;
+
+    // At the output size, so the text is the same size whatever the file's resolution
+    let sidecar = options.subtitles.as_ref().and_then(|subtitles| subtitles.find_sidecar(path));
+    let burn_subtitles =
+        options.subtitles.is_some() && (media_info.subtitle_streams > 0 || sidecar.is_some());
+    let subtitle_overlay = burn_subtitles.then(create_subtitle_overlay).transpose()?;
     let title_overlay = create_title_overlay(path)?;
-    let counter_overlay = create_counter_overlay(duration)?;
+    let counter_overlay = create_counter_overlay(probes, duration)?;
 
     let capsfilter_vid = gstreamer::ElementFactory::make("capsfilter")
         .property(
             "caps",
             gstreamer::Caps::builder("video/x-raw")
                 .field("format", gstreamer_video::VideoFormat::I420.to_string())
-                .field("width", 1280)
-                .field("height", 720)
+                .field("width", options.video.width as i32)
+                .field("height", options.video.height as i32)
                 .field("pixel-aspect-ratio", gstreamer::Fraction::new(1, 1))
                 .build(),
         )
         .build()?;
 
     let queue_video = gstreamer::ElementFactory::make("queue").name("v_queue").build()?;
-    let appsink_video = gstreamer_app::AppSink::builder().name("appsink_video").build();
+    let appsink_video = create_video_appsink(probes);
 
     // --- Add all elements to pipeline ---
+    pipeline.add_many([&filesrc, &decodebin, &videoconvert_vid])?;
+    pipeline.add_many(&deinterlace)?;
+    pipeline.add(&videoflip_vid)?;
+    pipeline.add_many(&crop_vid)?;
+    pipeline.add(&videoscale_vid)?;
+    pipeline.add_many(&subtitle_overlay)?;
+    if let (Some(sidecar), Some(subtitle_overlay)) = (&sidecar, &subtitle_overlay) {
+        println!("Decoder: Burning in {}", sidecar.display());
+        link_sidecar(&pipeline, sidecar, subtitle_overlay)?;
+    }
     pipeline.add_many([
-        &filesrc,
-        &decodebin,
-        &videoconvert_vid,
-        &videoscale_vid,
         &title_overlay,
         &counter_overlay,
         &capsfilter_vid,
@@ -238,9 +440,22 @@ fn create_video_pipeline(
     gstreamer::Element::link_many([&filesrc, &decodebin])?;
 
     // Pre-link the video chain
+    let mut video_chain = vec![&videoconvert_vid];
+    video_chain.extend(&deinterlace);
+    video_chain.push(&videoflip_vid);
+    video_chain.extend(&crop_vid);
+    video_chain.push(&videoscale_vid);
+    gstreamer::Element::link_many(video_chain)?;
+    // By pad name, as the overlay's subtitle pad would take video too
+    let overlays_start = match &subtitle_overlay {
+        Some(subtitle_overlay) => {
+            videoscale_vid.link_pads(None, subtitle_overlay, Some("video_sink"))?;
+            subtitle_overlay
+        }
+        None => &videoscale_vid,
+    };
     gstreamer::Element::link_many([
-        &videoconvert_vid,
-        &videoscale_vid,
+        overlays_start,
         &title_overlay,
         &counter_overlay,
         &capsfilter_vid,
@@ -248,42 +463,93 @@ fn create_video_pipeline(
         appsink_video.upcast_ref(),
     ])?;
 
-    let appsink_audio = if has_audio {
-        create_audio_chain(&pipeline)?
+    let appsink_audio = if audio_streams > 0 {
+        create_audio_chain(&pipeline, "", gain_db, options.audio, options.loudness)?
     } else {
-        create_silent_audio(&pipeline)?
+        create_silent_audio(&pipeline, "", options.audio)?
     };
 
+    // The second program is the file's second audio track, or silence if it only has one.
+    let use_second_track = app_sources.audio2.is_some() && audio_streams > 1;
+    if let Some(appsrc_audio2) = &app_sources.audio2 {
+        let appsink_audio2 = if use_second_track {
+            create_audio_chain(&pipeline, "2", gain_db, options.audio, options.loudness)?
+        } else {
+            create_silent_audio(&pipeline, "2", options.audio)?
+        };
+        forward_samples(&appsink_audio2, appsrc_audio2);
+    }
+
+    // --- Stream Selection ---
+    // Pick the streams explicitly rather than trusting decodebin3's defaults or pad names, which
+    // differ between demuxers.
+    let selection = Arc::new(Mutex::new(None::<StreamSelection>));
+    let selection_clone = selection.clone();
+    let audio_languages = options.audio_languages.clone();
+    // Only picks a subtitle track if there's somewhere to draw it, and no sidecar already is
+    let subtitle_languages = options
+        .subtitles
+        .as_ref()
+        .filter(|_| burn_subtitles && sidecar.is_none())
+        .map(|subtitles| subtitles.languages.clone());
+    let decodebin_weak = decodebin.downgrade();
+    pipeline.bus().unwrap().set_sync_handler(move |_, msg| {
+        if let gstreamer::MessageView::StreamCollection(msg) = msg.view()
+            && let Some(decodebin) = decodebin_weak.upgrade()
+            && msg.src() == Some(decodebin.upcast_ref::<gstreamer::Object>())
+        {
+            let collection = msg.stream_collection();
+            let stream_selection = StreamSelection::select(
+                &collection,
+                &audio_languages,
+                use_second_track,
+                subtitle_languages.as_deref(),
+            );
+            println!("Decoder: Selecting streams {stream_selection:?}");
+            *selection_clone.lock() = Some(stream_selection.clone());
+            if !stream_selection.apply(&decodebin) {
+                eprintln!("Decoder: Failed to select streams");
+            }
+        }
+        gstreamer::BusSyncReply::Pass
+    });
+
     // --- Dynamic Pad Linking ---
     let pipeline_weak = pipeline.downgrade();
     decodebin.connect_pad_added(move |_, pad| {
         let Some(pipeline) = pipeline_weak.upgrade() else { return };
 
         let pad_name = pad.name();
-        println!("Decoder: New pad added: {pad_name}");
-
-        if pad_name.starts_with("video_") {
-            let sink_pad =
-                pipeline.by_name("videoconvert_vid").unwrap().static_pad("sink").unwrap();
-            if sink_pad.is_linked() {
-                eprintln!("Video sink already linked, ignoring.");
-                return;
-            }
-            if let Err(err) = pad.link(&sink_pad) {
-                eprintln!("Failed to link video pad: {}", err);
-            }
-        } else if pad_name.starts_with("audio_") {
-            let sink_pad =
-                pipeline.by_name("audioconvert_aud").unwrap().static_pad("sink").unwrap();
-            if sink_pad.is_linked() {
-                eprintln!("Audio sink already linked, ignoring.");
-                return;
-            }
-            if let Err(err) = pad.link(&sink_pad) {
-                eprintln!("Failed to link audio pad: {}", err);
-            }
+        let stream_type = pad_stream_type(pad);
+        println!("Decoder: New pad added: {pad_name} ({stream_type:?})");
+
+        let (sink_name, sink_pad_name) = if stream_type.contains(gstreamer::StreamType::VIDEO) {
+            ("videoconvert_vid", "sink")
+        } else if stream_type.contains(gstreamer::StreamType::AUDIO) {
+            let stream_id = pad_stream_id(pad);
+            let is_audio2 = selection
+                .lock()
+                .as_ref()
+                .is_some_and(|s| s.audio2.is_some() && s.audio2 == stream_id);
+            (if is_audio2 { "audioconvert_aud2" } else { "audioconvert_aud" }, "sink")
+        } else if stream_type.contains(gstreamer::StreamType::TEXT) {
+            ("subtitleoverlay", "subtitle_sink")
         } else {
             println!("Unknown pad type: {pad_name}");
+            return;
+        };
+
+        let Some(sink_pad) = pipeline.by_name(sink_name).and_then(|e| e.static_pad(sink_pad_name))
+        else {
+            eprintln!("No {sink_name} for {pad_name}, ignoring.");
+            return;
+        };
+        if sink_pad.is_linked() {
+            eprintln!("{sink_name} already linked, ignoring.");
+            return;
+        }
+        if let Err(err) = pad.link(&sink_pad) {
+            eprintln!("Failed to link {pad_name}: {}", err);
         }
     });
 
@@ -322,94 +588,75 @@ fn create_video_pipeline(
 fn create_image_pipeline(
     path: &Path,
     app_sources: &AppSources,
+    options: &StreamOptions,
     duration: gstreamer::ClockTime,
+    probes: &Probes,
 ) -> Result<gstreamer::Pipeline, Error> {
     let pipeline = gstreamer::Pipeline::builder().name("image-pipeline").build();
 
     // --- Video Chain (filesrc -> decodebin -> imagefreeze -> ...) ---
-    let filesrc = gstreamer::ElementFactory::make("filesrc")
-        .property("location", path.to_str().unwrap())
-        .build()?;
+    let filesrc = create_file_source(path)?;
 
     // Remove `no-audio=true` to let decodebin find audio
-    let decodebin = gstreamer::ElementFactory::make("decodebin3").build()?;
+    let decodebin = gstreamer::ElementFactory::make("decodebin3").name("decodebin").build()?;
 
     let imagefreeze = gstreamer::ElementFactory::make("imagefreeze").build()?;
 
     let videoconvert_vid = gstreamer::ElementFactory::make("videoconvert").build()?;
-
-    let videoscale_vid = gstreamer::ElementFactory::make("videoscale")
-        .property("add-borders", true)
-        .build()?;
+    let videoflip_vid = create_videoflip()?;
+    let ken_burns = options
+        .ken_burns
+        .map(|ken_burns| create_ken_burns(ken_burns, duration, probes))
+        .transpose()?;
+    let (crop_vid, videoscale_vid) = create_fit(options.aspect, options.video)?;
     let videorate_vid = gstreamer::ElementFactory::make("videorate").build()?;
 
     let title_overlay = create_title_overlay(path)?;
-    let counter_overlay = create_counter_overlay(Some(duration))?;
+    let counter_overlay = create_counter_overlay(probes, Some(duration))?;
+    let photo_info_overlay = options
+        .photo_info
+        .map(|slot| create_photo_info_overlay(probes, slot))
+        .transpose()?;
 
     let capsfilter_vid = gstreamer::ElementFactory::make("capsfilter")
         .property(
             "caps",
             gstreamer::Caps::builder("video/x-raw")
                 .field("format", gstreamer_video::VideoFormat::I420.to_string())
-                .field("width", 1280)
-                .field("height", 720)
+                .field("width", options.video.width as i32)
+                .field("height", options.video.height as i32)
                 .field("pixel-aspect-ratio", gstreamer::Fraction::new(1, 1))
-                .field("framerate", gstreamer::Fraction::new(30, 1))
+                .field("framerate", options.video.framerate())
                 .build(),
         )
         .build()?;
 
     let queue_video = gstreamer::ElementFactory::make("queue").name("v_queue").build()?;
-    let appsink_video = gstreamer_app::AppSink::builder().name("appsink_video").build();
+    let appsink_video = create_video_appsink(probes);
+
+    let mut video_chain = vec![&imagefreeze, &videoconvert_vid, &videoflip_vid];
+    video_chain.extend(&ken_burns);
+    video_chain.extend(&crop_vid);
+    video_chain.extend([&videoscale_vid, &videorate_vid, &title_overlay, &counter_overlay]);
+    video_chain.extend(&photo_info_overlay);
+    video_chain.extend([&capsfilter_vid, &queue_video, appsink_video.upcast_ref()]);
 
     // Add all elements
-    pipeline.add_many([
-        &filesrc,
-        &decodebin,
-        &imagefreeze,
-        &videoconvert_vid,
-        &videoscale_vid,
-        &videorate_vid,
-        &title_overlay,
-        &counter_overlay,
-        &capsfilter_vid,
-        &queue_video,
-        appsink_video.upcast_ref(),
-    ])?;
+    pipeline.add_many([&filesrc, &decodebin])?;
+    pipeline.add_many(video_chain.iter().copied())?;
 
     filesrc.link(&decodebin)?;
 
     // Link static chains
-    gstreamer::Element::link_many([
-        &imagefreeze,
-        &videoconvert_vid,
-        &videoscale_vid,
-        &videorate_vid,
-        &title_overlay,
-        &counter_overlay,
-        &capsfilter_vid,
-        &queue_video,
-        appsink_video.upcast_ref(),
-    ])?;
+    gstreamer::Element::link_many(video_chain.iter().copied())?;
 
-    let appsink_audio = create_silent_audio(&pipeline)?;
+    let appsink_audio = create_silent_audio(&pipeline, "", options.audio)?;
+    if let Some(appsrc_audio2) = &app_sources.audio2 {
+        let appsink_audio2 = create_silent_audio(&pipeline, "2", options.audio)?;
+        forward_samples(&appsink_audio2, appsrc_audio2);
+    }
 
-    let imagefreeze_src_pad = imagefreeze.static_pad("src").unwrap();
-    let audio_src_pad_weak =
-        pipeline.by_name("audiotestsrc").unwrap().static_pad("src").unwrap().downgrade();
-    imagefreeze_src_pad.add_probe(gstreamer::PadProbeType::BUFFER, move |pad, info| {
-        if let Some(buffer) = info.buffer()
-            && let Some(pts) = buffer.pts()
-            && pts > duration
-        {
-            pad.push_event(gstreamer::event::Eos::new());
-            if let Some(pad) = audio_src_pad_weak.upgrade() {
-                pad.push_event(gstreamer::event::Eos::new());
-            }
-            return gstreamer::PadProbeReturn::Remove;
-        }
-        gstreamer::PadProbeReturn::Ok
-    });
+    // imagefreeze never ends by itself, the feeder loop stops it once `duration` has elapsed
 
     // --- Dynamic linking for decodebin ---
     let imagefreeze_sink_pad = imagefreeze.static_pad("sink").unwrap();
@@ -417,7 +664,7 @@ fn create_image_pipeline(
         let pad_name = pad.name();
         println!("Decoder: New pad added: {pad_name}");
 
-        if pad_name.starts_with("video_") {
+        if pad_stream_type(pad).contains(gstreamer::StreamType::VIDEO) {
             if imagefreeze_sink_pad.is_linked() {
                 eprintln!("Image sink already linked, ignoring.");
                 return;
@@ -454,41 +701,335 @@ fn create_image_pipeline(
     Ok(pipeline)
 }
 
+/// Loops an animated image for `duration`, or shows its first frame like a still if it's too big
+/// to keep decoded.
+fn create_animation_pipeline(
+    path: &Path,
+    app_sources: &AppSources,
+    options: &StreamOptions,
+    duration: gstreamer::ClockTime,
+    probes: &Probes,
+) -> Result<gstreamer::Pipeline, Error> {
+    let Some(animation) = Animation::decode(path)? else {
+        println!("Can't loop {}, showing it as a still", path.display());
+        return create_image_pipeline(path, app_sources, options, duration, probes);
+    };
+    let pipeline = gstreamer::Pipeline::builder().name("animation-pipeline").build();
+
+    // --- Video Chain (appsrc -> ...) ---
+    let appsrc_vid = animation.into_source();
+    let videoconvert_vid = gstreamer::ElementFactory::make("videoconvert").build()?;
+    let (crop_vid, videoscale_vid) = create_fit(options.aspect, options.video)?;
+    let videorate_vid = gstreamer::ElementFactory::make("videorate").build()?;
+
+    let title_overlay = create_title_overlay(path)?;
+    let counter_overlay = create_counter_overlay(probes, Some(duration))?;
+
+    let capsfilter_vid = gstreamer::ElementFactory::make("capsfilter")
+        .property(
+            "caps",
+            gstreamer::Caps::builder("video/x-raw")
+                .field("format", gstreamer_video::VideoFormat::I420.to_string())
+                .field("width", options.video.width as i32)
+                .field("height", options.video.height as i32)
+                .field("pixel-aspect-ratio", gstreamer::Fraction::new(1, 1))
+                .field("framerate", options.video.framerate())
+                .build(),
+        )
+        .build()?;
+
+    let queue_video = gstreamer::ElementFactory::make("queue").name("v_queue").build()?;
+    let appsink_video = create_video_appsink(probes);
+
+    let mut video_chain = vec![appsrc_vid.upcast_ref(), &videoconvert_vid];
+    video_chain.extend(&crop_vid);
+    video_chain.extend([&videoscale_vid, &videorate_vid, &title_overlay, &counter_overlay]);
+    video_chain.extend([&capsfilter_vid, &queue_video, appsink_video.upcast_ref()]);
+
+    pipeline.add_many(video_chain.iter().copied())?;
+    gstreamer::Element::link_many(video_chain.iter().copied())?;
+
+    let appsink_audio = create_silent_audio(&pipeline, "", options.audio)?;
+    if let Some(appsrc_audio2) = &app_sources.audio2 {
+        let appsink_audio2 = create_silent_audio(&pipeline, "2", options.audio)?;
+        forward_samples(&appsink_audio2, appsrc_audio2);
+    }
+
+    // The animation never ends by itself, the feeder loop stops it once `duration` has elapsed
+    forward_samples(&appsink_video, &app_sources.video);
+    forward_samples(&appsink_audio, &app_sources.audio);
+
+    Ok(pipeline)
+}
+
+fn create_audio_only_pipeline(
+    path: &Path,
+    app_sources: &AppSources,
+    options: &StreamOptions,
+    duration: Option<gstreamer::ClockTime>,
+    gain_db: Option<f64>,
+    probes: &Probes,
+) -> Result<gstreamer::Pipeline, Error> {
+    // filesrc -> decodebin -> audio chain, with a visualizer, the cover art or a black frame as
+    // the video
+    let pipeline = gstreamer::Pipeline::builder().name("audio-pipeline").build();
+
+    let filesrc = create_file_source(path)?;
+    let decodebin = gstreamer::ElementFactory::make("decodebin3").name("decodebin").build()?;
+    pipeline.add_many([&filesrc, &decodebin])?;
+    filesrc.link(&decodebin)?;
+
+    let appsink_audio =
+        create_audio_chain(&pipeline, "", gain_db, options.audio, options.loudness)?;
+    if let Some(appsrc_audio2) = &app_sources.audio2 {
+        let appsink_audio2 = create_silent_audio(&pipeline, "2", options.audio)?;
+        forward_samples(&appsink_audio2, appsrc_audio2);
+    }
+    let mut audio_sink_pad =
+        pipeline.by_name("audioconvert_aud").unwrap().static_pad("sink").unwrap();
+
+    // --- Video Chain (visualizer, cover art or videotestsrc -> ...) ---
+    let cover = AudioCover::find(path);
+    let (video_source, fit) = match (options.visualizer, &cover.art, duration) {
+        (Some(visualizer), ..) => {
+            let (tee, scope) = add_visualizer(&pipeline, visualizer, &audio_sink_pad)?;
+            audio_sink_pad = tee.static_pad("sink").unwrap();
+            (scope, None)
+        }
+        // Cover art needs the duration, imagefreeze only ends by itself after so many frames
+        (None, Some(art), Some(duration)) => {
+            let video_source = add_cover_art(&pipeline, art, duration, options.video.framerate())?;
+            let videoflip_vid = create_videoflip()?;
+            let (crop_vid, videoscale_vid) = create_fit(options.aspect, options.video)?;
+            let videorate_vid = gstreamer::ElementFactory::make("videorate").build()?;
+            (video_source, Some((videoflip_vid, crop_vid, videoscale_vid, videorate_vid)))
+        }
+        _ => {
+            let videotestsrc = gstreamer::ElementFactory::make("videotestsrc")
+                .name("videotestsrc")
+                .property_from_str("pattern", "black")
+                .build()?;
+            pipeline.add(&videotestsrc)?;
+            (videotestsrc, None)
+        }
+    };
+    let videoconvert_vid = gstreamer::ElementFactory::make("videoconvert").build()?;
+
+    let title_overlay = create_title_overlay(path)?;
+    let caption_overlay = cover.caption.as_deref().map(create_caption_overlay).transpose()?;
+    let counter_overlay = create_counter_overlay(probes, duration)?;
+
+    let capsfilter_vid = gstreamer::ElementFactory::make("capsfilter")
+        .property(
+            "caps",
+            gstreamer::Caps::builder("video/x-raw")
+                .field("format", gstreamer_video::VideoFormat::I420.to_string())
+                .field("width", options.video.width as i32)
+                .field("height", options.video.height as i32)
+                .field("pixel-aspect-ratio", gstreamer::Fraction::new(1, 1))
+                .field("framerate", options.video.framerate())
+                .build(),
+        )
+        .build()?;
+
+    let queue_video = gstreamer::ElementFactory::make("queue").name("v_queue").build()?;
+    let appsink_video = create_video_appsink(probes);
+
+    let mut video_chain = vec![&videoconvert_vid];
+    if let Some((videoflip_vid, crop_vid, videoscale_vid, videorate_vid)) = &fit {
+        video_chain.push(videoflip_vid);
+        video_chain.extend(crop_vid);
+        video_chain.extend([videoscale_vid, videorate_vid]);
+    }
+    video_chain.push(&title_overlay);
+    video_chain.extend(&caption_overlay);
+    video_chain.extend([&counter_overlay, &capsfilter_vid]);
+    video_chain.extend([&queue_video, appsink_video.upcast_ref()]);
+
+    // The source is already in the pipeline
+    pipeline.add_many(video_chain.iter().copied())?;
+    video_chain.insert(0, &video_source);
+    gstreamer::Element::link_many(video_chain.iter().copied())?;
+
+    // --- Dynamic linking for decodebin ---
+    decodebin.connect_pad_added(move |_, pad| {
+        let pad_name = pad.name();
+        println!("Decoder: New pad added: {pad_name}");
+
+        if pad_stream_type(pad).contains(gstreamer::StreamType::AUDIO) {
+            if audio_sink_pad.is_linked() {
+                eprintln!("Audio sink already linked, ignoring.");
+                return;
+            }
+            if let Err(err) = pad.link(&audio_sink_pad) {
+                eprintln!("Failed to link audio pad: {}", err);
+            }
+        } else {
+            println!("Unknown pad type: {pad_name}");
+        }
+    });
+
+    // --- AppSink Callbacks ---
+    forward_samples(&appsink_video, &app_sources.video);
+
+    // The black frame never ends by itself, so end it along with the audio
+    let appsrc_audio_weak = app_sources.audio.downgrade();
+    let videotestsrc_weak =
+        pipeline.by_name("videotestsrc").map(|videotestsrc| videotestsrc.downgrade());
+    appsink_audio.set_callbacks(
+        gstreamer_app::AppSinkCallbacks::builder()
+            .new_sample(move |sink| {
+                let Some(appsrc_audio) = appsrc_audio_weak.upgrade() else {
+                    return Err(gstreamer::FlowError::Error);
+                };
+                let sample = sink.pull_sample().map_err(|_| gstreamer::FlowError::Eos)?;
+                appsrc_audio.push_sample(&sample).map_err(|_| gstreamer::FlowError::Error)
+            })
+            .eos(move |_| {
+                let videotestsrc = videotestsrc_weak.as_ref().and_then(|weak| weak.upgrade());
+                if let Some(videotestsrc) = videotestsrc {
+                    videotestsrc.send_event(gstreamer::event::Eos::new());
+                }
+            })
+            .build(),
+    );
+
+    Ok(pipeline)
+}
+
+enum PrepareFailure {
+    /// The file can't be played, but might be worth trying again later.
+    Skipped,
+    /// The file is broken or too slow to prepare, and shouldn't be picked again.
+    Quarantined(String),
+}
+
+/// A pipeline ready to be played, along with what the feeder loop needs to know about it.
+struct PreparedItem {
+    media_type: MediaType,
+    pipeline: gstreamer::Pipeline,
+    duration: Option<gstreamer::ClockTime>,
+    /// Running time after which the item is ended, for sources that never reach EOS.
+    play_limit: Option<gstreamer::ClockTime>,
+    /// Where to seek to once it's prerolled, and where to stop, see [`ItemOverrides::trim`].
+    trim: Option<(gstreamer::ClockTime, Option<gstreamer::ClockTime>)>,
+    /// From the file's sidecar, if it has one.
+    overrides: ItemOverrides,
+    /// Added by the pipeline's overlays and appsink, removed with it.
+    probes: Probes,
+}
+
+impl PreparedItem {
+    /// Stops the pipeline and removes its probes, so nothing they hold on to outlives the item.
+    fn tear_down(&self) {
+        _ = self.pipeline.set_state(gstreamer::State::Null);
+        self.probes.remove_all();
+    }
+}
+
 fn create_pipeline(
     path: &Path,
     app_sources: &AppSources,
-) -> Option<(MediaType, gstreamer::Pipeline)> {
-    let media_info = match MediaInfo::detect(path) {
-        Ok(media_info) if !media_info.is_empty() => media_info,
-        Ok(_) => return None,
+    options: &StreamOptions,
+    gains: &GainOverrides,
+    title_template: &TitleTemplate,
+    type_finder: &mut TypeFinder,
+    discovery: &Discovery,
+) -> Result<PreparedItem, PrepareFailure> {
+    // Typefind is much cheaper than discovery, so use it to throw out the obvious junk first
+    match type_finder.is_probably_media(path) {
+        Ok(true) => (),
+        Ok(false) => {
+            println!("Skipping non-media file {}", path.display());
+            return Err(PrepareFailure::Skipped);
+        }
+        Err(error) => {
+            eprintln!("Failed to typefind {}: {error}", path.display());
+            return Err(PrepareFailure::Skipped);
+        }
+    }
+
+    let media_info = match discovery.detect(path) {
+        Ok(media_info) => media_info,
+        Err(error @ MediaInfoError::Timeout) => {
+            return Err(PrepareFailure::Quarantined(error.to_string()));
+        }
         Err(error) => {
             eprintln!("Failed to get media info: {error}");
-            return None;
+            return Err(PrepareFailure::Quarantined(error.to_string()));
         }
     };
 
-    let media_type = media_info.media_type();
-    let duration = media_info.duration;
+    // The discoverer decides between image and video from the decoded streams, not the duration,
+    // so unknown-duration streams stay videos. Typefind only helps when it found nothing at all.
+    let media_type = match media_info.media_type() {
+        // A live source keeps producing frames, even if the discoverer only saw the first one
+        MediaType::Image if media_info.is_live => {
+            if media_info.audio.is_some() {
+                MediaType::VideoWithAudio
+            } else {
+                MediaType::VideoWithoutAudio
+            }
+        }
+        MediaType::Unknown if media_info.is_live => return Err(PrepareFailure::Skipped),
+        MediaType::Unknown => match type_finder.find(path) {
+            Ok(Some(caps)) if MediaType::from_caps(&caps) == MediaType::Image => MediaType::Image,
+            Ok(_) => return Err(PrepareFailure::Skipped),
+            Err(error) => {
+                eprintln!("Failed to get media type: {error}");
+                return Err(PrepareFailure::Skipped);
+            }
+        },
+        media_type => media_type,
+    };
+    // Animations loop for as long as stills are shown, their own duration is just one loop
+    let media_type = if media_info.animated && !media_info.is_live {
+        MediaType::Image
+    } else {
+        media_type
+    };
+    let mut duration = media_info.known_duration().filter(|_| !media_info.animated);
+    let overrides = ItemOverrides::load(path);
+    let gain_db = gains.get(path).or(overrides.gain);
+    // Stills have nothing to seek in
+    let trim = overrides.trim().filter(|_| media_type != MediaType::Image);
+    if let Some((start, end)) = trim {
+        let end = [end, duration].into_iter().flatten().min();
+        duration = end.map(|end| end.saturating_sub(start));
+    }
+    let max_duration = overrides.max_duration();
+    if media_type != MediaType::Image {
+        duration = duration.map(|duration| max_duration.map_or(duration, |max| duration.min(max)));
+    }
+    let play_limit = [options.play_duration.play_limit(media_type, duration), max_duration]
+        .into_iter()
+        .flatten()
+        .min();
+    let probes = Probes::default();
 
     let pipeline_result = match media_type {
-        MediaType::VideoWithAudio => create_video_pipeline(path, app_sources, true, duration),
-        MediaType::VideoWithoutAudio => create_video_pipeline(path, app_sources, false, duration),
+        MediaType::VideoWithAudio | MediaType::VideoWithoutAudio => {
+            create_video_pipeline(path, app_sources, options, &media_info, gain_db, &probes)
+        }
         MediaType::Image => {
-            let duration = if let Some(duration) = duration
-                && duration != gstreamer::ClockTime::ZERO
-            {
-                duration
+            // Images only end when the limit is reached, so that's their duration
+            let image_duration = play_limit.unwrap_or(options.play_duration.image_hold);
+            duration = Some(image_duration);
+            if media_info.animated {
+                create_animation_pipeline(path, app_sources, options, image_duration, &probes)
             } else {
-                5 * gstreamer::ClockTime::SECOND
-            };
-            create_image_pipeline(path, app_sources, duration)
+                create_image_pipeline(path, app_sources, options, image_duration, &probes)
+            }
+        }
+        MediaType::AudioOnly => {
+            create_audio_only_pipeline(path, app_sources, options, duration, gain_db, &probes)
         }
         MediaType::Unknown => {
             eprintln!(
                 "File feeder received unknown media type {} - {media_info:?}",
                 path.display()
             );
-            return None;
+            return Err(PrepareFailure::Skipped);
         }
     };
 
@@ -496,26 +1037,324 @@ fn create_pipeline(
         Ok(pipeline) => pipeline,
         Err(error) => {
             eprintln!("Failed to create pipeline: {error}");
-            return None;
+            probes.remove_all();
+            return Err(PrepareFailure::Skipped);
         }
     };
+    overrides.apply_to_overlays(&pipeline);
+    attach_title_template(
+        &pipeline,
+        path,
+        title_template,
+        overrides.title.as_deref(),
+        duration,
+        &probes,
+    );
 
-    Some((media_type, pipeline))
+    Ok(PreparedItem { media_type, pipeline, duration, play_limit, trim, overrides, probes })
+}
+
+/// Seeks a prerolled `pipeline` to play from `start` to `end`, and waits for it to preroll there.
+fn seek_to_trim(
+    pipeline: &gstreamer::Pipeline,
+    start: gstreamer::ClockTime,
+    end: Option<gstreamer::ClockTime>,
+    timeout: Option<gstreamer::ClockTime>,
+) -> Result<(), Error> {
+    let stop_type =
+        if end.is_some() { gstreamer::SeekType::Set } else { gstreamer::SeekType::None };
+    pipeline.seek(
+        1.0,
+        gstreamer::SeekFlags::FLUSH | gstreamer::SeekFlags::ACCURATE,
+        gstreamer::SeekType::Set,
+        Some(start),
+        stop_type,
+        end,
+    )?;
+    pipeline.state(timeout).0?;
+    Ok(())
+}
+
+/// Whether `path` may air right now, going by the rating in its sidecar if it has one.
+fn rating_allows_now(ratings: &RatingPolicy, path: &Path) -> bool {
+    if ratings.slots.is_empty() {
+        return true;
+    }
+    match ItemOverrides::load(path).rating {
+        Some(rating) => ratings.allows_rating_now(&rating),
+        None => ratings.allows_now(path),
+    }
+}
+
+/// Moves the files sent to `Command::Enqueue` to the end of `enqueued`. A random file that was
+/// already announced as next gives way to them, it's announced again once it's its turn.
+fn take_enqueued(
+    enqueue_rx: &flume::Receiver<PathBuf>,
+    enqueued: &mut VecDeque<PathBuf>,
+    announced_random: &mut Option<PathBuf>,
+    event_tx: &flume::Sender<Event>,
+) {
+    for path in enqueue_rx.try_iter() {
+        // The status only lists each file once
+        if enqueued.contains(&path) {
+            println!("{} is already queued", path.display());
+            continue;
+        }
+        if let Some(random) = announced_random.take() {
+            _ = event_tx.send(Event::Dequeued { path: random });
+        }
+        _ = event_tx.send(Event::Queued { path: path.clone() });
+        enqueued.push_back(path);
+    }
+}
+
+/// Sends what `encoder` makes of the video now, for the status.
+fn announce_encoding(encoder: &EncoderSwitch, event_tx: &flume::Sender<Event>) {
+    let video = encoder.video();
+    let (width, height, bitrate_kbps) = (video.width, video.height, video.bitrate_kbps);
+    _ = event_tx.send(Event::EncodingChanged { width, height, bitrate_kbps });
+}
+
+/// Drops whatever the output still has queued from the last item, and starts the next one on a
+/// fresh keyframe so the cut is clean for new and old viewers.
+fn restart_output(appsrcs: &AppSources) {
+    for appsrc in [Some(&appsrcs.video), Some(&appsrcs.audio), appsrcs.audio2.as_ref()]
+        .into_iter()
+        .flatten()
+    {
+        appsrc.send_event(gstreamer::event::FlushStart::new());
+        appsrc.send_event(gstreamer::event::FlushStop::new(true));
+    }
+    let force_key_unit = gstreamer_video::DownstreamForceKeyUnitEvent::builder()
+        .all_headers(true)
+        .build();
+    appsrcs.video.send_event(force_key_unit);
+}
+
+/// Sends [`Event::Switched`] once the first frame of `item` is through, which is when viewers see
+/// the switch from whatever was on before `started_at`.
+fn measure_switch(
+    item: &PreparedItem,
+    started_at: std::time::Instant,
+    event_tx: &flume::Sender<Event>,
+) {
+    let Some(sink_pad) = item.pipeline.by_name("appsink_video").and_then(|e| e.static_pad("sink"))
+    else {
+        return;
+    };
+    let started_at = Mutex::new(Some(started_at));
+    let event_tx = event_tx.clone();
+    item.probes.add(&sink_pad, gstreamer::PadProbeType::BUFFER, move |_, _| {
+        if let Some(started_at) = started_at.lock().take() {
+            let latency_ms = started_at.elapsed().as_millis() as u64;
+            _ = event_tx.try_send(Event::Switched { latency_ms });
+        }
+        gstreamer::PadProbeReturn::Ok
+    });
+}
+
+/// Where a graceful skip cuts: the first keyframe of the item's video once it's armed. The
+/// keyframe and everything after it are held back, so the item ends on the frame before it.
+#[derive(Debug, Default)]
+struct KeyframeCut {
+    armed: AtomicBool,
+    reached: AtomicBool,
+}
+
+/// Watches the video of `item` for keyframes, `None` if it has no video. Decoders mark every
+/// frame that isn't a keyframe as a delta unit, and the conversions after them keep the flag.
+fn watch_keyframes(item: &PreparedItem) -> Option<Arc<KeyframeCut>> {
+    let sink_pad = item.pipeline.by_name("appsink_video")?.static_pad("sink")?;
+    let cut = Arc::new(KeyframeCut::default());
+    let cut_clone = cut.clone();
+    item.probes.add(&sink_pad, gstreamer::PadProbeType::BUFFER, move |_, info| {
+        let Some(gstreamer::PadProbeData::Buffer(buffer)) = &info.data else {
+            return gstreamer::PadProbeReturn::Ok;
+        };
+        if cut_clone.armed.load(Ordering::Acquire)
+            && !buffer.flags().contains(gstreamer::BufferFlags::DELTA_UNIT)
+        {
+            cut_clone.reached.store(true, Ordering::Release);
+        }
+        if cut_clone.reached.load(Ordering::Acquire) {
+            gstreamer::PadProbeReturn::Drop
+        } else {
+            gstreamer::PadProbeReturn::Ok
+        }
+    });
+    Some(cut)
+}
+
+/// Keeps the live input on air until its caller goes away.
+fn play_live(
+    live: &LiveInput,
+    appsrcs: &AppSources,
+    abort_rx: &flume::Receiver<()>,
+    soft_skip_rx: &flume::Receiver<std::time::Duration>,
+    event_tx: &flume::Sender<Event>,
+) {
+    let source = live.source().to_string();
+    println!("Live input {source} is taking over");
+    _ = event_tx.send(Event::LiveStarted { source: source.clone() });
+    live.set_on_air(true);
+
+    while live.is_connected() {
+        // Skipping doesn't apply to a live input, so don't let it cut the next file short either
+        _ = abort_rx.recv_timeout(std::time::Duration::from_millis(100));
+        _ = soft_skip_rx.drain();
+    }
+
+    live.set_on_air(false);
+    restart_output(appsrcs);
+    println!("Live input {source} ended, back to files");
+    _ = event_tx.send(Event::LiveEnded { source });
+}
+
+/// Shows a slate while `keep_showing` returns `true`, or until it's skipped. `text` is checked
+/// for changes while it's up, so it can show a clock or a countdown.
+fn play_slate(
+    slate: SlateKind,
+    options: &StreamOptions,
+    appsrcs: &AppSources,
+    abort_rx: &flume::Receiver<()>,
+    event_tx: &flume::Sender<Event>,
+    text: impl Fn() -> String,
+    keep_showing: impl Fn() -> bool,
+) {
+    let mut shown_text = text();
+    let probes = Probes::default();
+    let pipeline = create_slate_pipeline(
+        &shown_text,
+        &options.slates,
+        appsrcs,
+        options.video,
+        options.audio,
+        &probes,
+    );
+    let pipeline = match pipeline {
+        Ok(pipeline) => pipeline,
+        Err(error) => {
+            eprintln!("Failed to create the {slate} slate: {error}");
+            probes.remove_all();
+            std::thread::sleep(SLATE_RETRY);
+            return;
+        }
+    };
+    if let Err(error) = pipeline.set_state(gstreamer::State::Playing) {
+        eprintln!("Failed to start the {slate} slate: {error}");
+        _ = pipeline.set_state(gstreamer::State::Null);
+        probes.remove_all();
+        std::thread::sleep(SLATE_RETRY);
+        return;
+    }
+    println!("Showing the {slate} slate");
+    _ = event_tx.send(Event::SlateStarted { slate });
+
+    let bus = pipeline.bus().unwrap();
+    let mut failed = false;
+    'slate: while keep_showing() {
+        if abort_rx.recv_timeout(std::time::Duration::from_millis(100)).is_ok() {
+            break;
+        }
+        let new_text = text();
+        if new_text != shown_text
+            && let Some(text_overlay) = pipeline.by_name("slate_text")
+        {
+            text_overlay.set_property("text", &new_text);
+            shown_text = new_text;
+        }
+        for msg in bus.iter_timed(gstreamer::ClockTime::ZERO) {
+            if let gstreamer::MessageView::Error(err) = msg.view() {
+                eprintln!("Error on the {slate} slate: {}", err.error());
+                failed = true;
+                break 'slate;
+            }
+        }
+    }
+
+    restart_output(appsrcs);
+    _ = pipeline.set_state(gstreamer::State::Null);
+    probes.remove_all();
+    _ = event_tx.send(Event::SlateEnded { slate });
+    if failed {
+        std::thread::sleep(SLATE_RETRY);
+    }
 }
 
 /// Task for the thread that feeds the RTSP stream.
 /// It waits for file paths from the channel and runs a pipeline for each.
 pub fn file_feeder_task(
-    root_dirs: Vec<PathBuf>,
+    files: FileSource,
     command_rx: flume::Receiver<Command>,
     event_tx: flume::Sender<Event>,
     storage: AppSrcStorage,
+    options: StreamOptions,
+    services: StreamServices,
+    roots: LibraryRoots,
 ) {
+    let StreamServices { media_cache, quarantine, history, .. } = services;
     // First, wait for the RTSP client to connect and create the appsrc
-    let appsrcs = get_app_sources(storage);
+    let (mut appsrcs_version, mut appsrcs) = get_app_sources(&storage);
+    if let Some(encoder) = &appsrcs.encoder {
+        announce_encoding(encoder, &event_tx);
+    }
+
+    let gains = GainOverrides::default();
+    let title_template = TitleTemplate::new(options.title_template.clone());
+    let freeze = Freeze::default();
+    freeze.attach(&appsrcs);
+    let approvals = Approvals::new(options.require_approval);
+    let content_filter = options
+        .classifier
+        .clone()
+        .map(|classifier| ContentFilter::new(classifier, media_cache.clone()));
+    let shared_history = history.zip(options.shared_repeat_window);
+    let peer_files = PeerFiles::start(&options.peers, shared_history);
+    let live = options
+        .live_input
+        .clone()
+        .map(|live_input| LiveInput::start(live_input, &storage, options.video, options.audio));
+    let mut type_finder = TypeFinder::default();
+
+    // Only failures that are the file's own fault count, not ones of files the operator chose to
+    // play, or of files whose root went away (e.g. a NAS dropping out)
+    let quarantine_file = |path: &Path, reason: String, chosen: bool| {
+        if chosen {
+            eprintln!("Not quarantining {}, it was chosen to play: {reason}", path.display());
+            return;
+        }
+        if !roots.root_is_readable(path) {
+            eprintln!("Not quarantining {}, its root can't be read: {reason}", path.display());
+            return;
+        }
+        eprintln!("Quarantining {}: {reason}", path.display());
+        if quarantine.add(path.to_path_buf(), &reason) {
+            _ = event_tx.send(Event::Quarantined { path: path.to_path_buf(), reason });
+        }
+    };
+
+    let preroll_failed = |path: &Path, reason: String| {
+        eprintln!("Failed to preroll {}: {reason}", path.display());
+        _ = event_tx.send(Event::PrerollFailed { path: path.to_path_buf(), reason });
+    };
 
     let (abort_tx, abort_rx) = flume::bounded(1);
+    let (gain_tx, gain_rx) = flume::unbounded::<PathBuf>();
+    let (next_tx, next_rx) = flume::unbounded::<PathBuf>();
+    let (enqueue_tx, enqueue_rx) = flume::unbounded::<PathBuf>();
+    let (soft_skip_tx, soft_skip_rx) = flume::unbounded::<std::time::Duration>();
     let abort_tx_clone = abort_tx.clone();
+    let gains_clone = gains.clone();
+    let discovery = Discovery::new(media_cache, options.prepare.discovery_timeout);
+    let discovery_clone = discovery.clone();
+    let approvals_clone = approvals.clone();
+    let event_tx_clone = event_tx.clone();
+    // Set through `Command::Hold`, the slate to show instead of files
+    let hold = Arc::new(Mutex::new(None::<SlateKind>));
+    let hold_clone = hold.clone();
+    let storage_clone = storage.clone();
+    let freeze_clone = freeze.clone();
+    let title_template_clone = title_template.clone();
     std::thread::spawn(move || {
         while let Ok(command) = command_rx.recv() {
             match command {
@@ -525,53 +1364,457 @@ pub fn file_feeder_task(
                         break;
                     }
                 }
+                Command::SoftSkip { delay } => {
+                    println!("Skipping file in {delay:?}");
+                    if soft_skip_tx.send(delay).is_err() {
+                        break;
+                    }
+                }
+                Command::SetGain { path, gain_db } => {
+                    println!("Setting gain for {}: {gain_db:?} dB", path.display());
+                    match gain_db {
+                        Some(gain_db) => gains_clone.set(path.clone(), gain_db),
+                        None => gains_clone.remove(&path),
+                    }
+                    if gain_tx.send(path).is_err() {
+                        break;
+                    }
+                }
+                Command::Freeze { mute_audio } => {
+                    println!("Freezing video (mute audio: {mute_audio})");
+                    freeze_clone.freeze(mute_audio);
+                    _ = event_tx_clone.send(Event::Frozen { mute_audio });
+                }
+                Command::Unfreeze => {
+                    println!("Unfreezing video");
+                    freeze_clone.unfreeze();
+                    _ = event_tx_clone.send(Event::Unfrozen);
+                }
+                Command::InvalidateMediaCache { path } => {
+                    let Some(media_cache) = discovery_clone.cache() else { continue };
+                    match media_cache.invalidate(path.as_deref()) {
+                        Ok(count) => println!("Removed {count} media info cache entries"),
+                        Err(error) => eprintln!("Failed to invalidate media info cache: {error}"),
+                    }
+                }
+                Command::PlayNext { path } => {
+                    println!("Playing next: {}", path.display());
+                    if next_tx.send(path).is_err() {
+                        break;
+                    }
+                }
+                Command::Enqueue { path } => {
+                    println!("Enqueued: {}", path.display());
+                    if enqueue_tx.send(path).is_err() {
+                        break;
+                    }
+                }
+                Command::Review { path, approved } => {
+                    if !approvals_clone.review(&path, approved) {
+                        eprintln!("{} isn't waiting for approval", path.display());
+                        continue;
+                    }
+                    let verdict = if approved { "Approved" } else { "Rejected" };
+                    println!("{verdict} {}", path.display());
+                    _ = event_tx_clone.send(Event::Reviewed { path, approved });
+                }
+                Command::Hold { slate } => {
+                    println!("Holding on the {slate} slate");
+                    *hold_clone.lock() = Some(slate);
+                    if abort_tx_clone.send(()).is_err() {
+                        break;
+                    }
+                }
+                Command::Resume => {
+                    println!("Resuming");
+                    *hold_clone.lock() = None;
+                }
+                Command::SetVideoProfile { width, height, bitrate_kbps } => {
+                    let encoder = storage_clone.get().and_then(|(_, appsrcs)| appsrcs.encoder);
+                    let Some(encoder) = &encoder else {
+                        eprintln!("The output profile can't change encoding while running");
+                        continue;
+                    };
+                    let current = encoder.video();
+                    let video = VideoOptions {
+                        width: width.unwrap_or(current.width),
+                        height: height.unwrap_or(current.height),
+                        bitrate_kbps: bitrate_kbps.unwrap_or(current.bitrate_kbps),
+                        ..current
+                    };
+                    println!("Switching the encoder to {video} at {} kbit/s", video.bitrate_kbps);
+                    match encoder.switch(video) {
+                        Ok(()) => announce_encoding(encoder, &event_tx_clone),
+                        Err(error) => eprintln!("{error}"),
+                    }
+                }
+                Command::SetTitleTemplate { template } => {
+                    println!("Setting the title template to {template:?}");
+                    title_template_clone.set(template);
+                }
             }
         }
     });
 
-    for path in RandomFiles::new(root_dirs) {
-        let Some((media_type, pipeline)) = create_pipeline(&path, &appsrcs) else { continue };
+    let mut files = files.peekable();
+    // Set through `Command::PlayNext`, takes the place of the next random file
+    let mut next_override: Option<PathBuf> = None;
+    // Set through `Command::Enqueue`, played in order after `next_override`
+    let mut enqueued: VecDeque<PathBuf> = VecDeque::new();
+    // The random file last announced as next, it's taken back if files are enqueued before it
+    let mut announced_random: Option<PathBuf> = None;
+    // Starting up in the middle of a slot doesn't count as it beginning
+    let mut current_slot = options.ratings.slot_now();
+    let mut jingle: Option<PathBuf> = None;
+    // When whatever was on air last ended, until the next item's first frame is through
+    let mut switch_started_at: Option<std::time::Instant> = None;
+    // When picks started being turned down, see `MAX_PICKING_TIME`
+    let mut rejecting_since: Option<std::time::Instant> = None;
+    loop {
+        // The output was built again, everything from here on goes to the new appsrcs
+        if let Some((version, new_appsrcs)) = storage.newer_than(appsrcs_version) {
+            println!("Feeder thread reconnected to the rebuilt output.");
+            appsrcs_version = version;
+            appsrcs = new_appsrcs;
+            freeze.attach(&appsrcs);
+            if let Some(encoder) = &appsrcs.encoder {
+                announce_encoding(encoder, &event_tx);
+            }
+        }
+        let output_current = || storage.version() == appsrcs_version;
+
+        if let Some(live) = &live
+            && live.is_connected()
+        {
+            play_live(live, &appsrcs, &abort_rx, &soft_skip_rx, &event_tx);
+            switch_started_at = Some(std::time::Instant::now());
+        }
+        let held_slate = *hold.lock();
+        if let Some(slate) = held_slate {
+            take_enqueued(&enqueue_rx, &mut enqueued, &mut announced_random, &event_tx);
+            let next = next_override.clone().or_else(|| enqueued.front().cloned());
+            let next = next.or_else(|| files.peek().cloned());
+            let text = || options.slates.text(slate, next.as_deref());
+            let keep_showing = || *hold.lock() == Some(slate) && output_current();
+            play_slate(slate, &options, &appsrcs, &abort_rx, &event_tx, text, keep_showing);
+            switch_started_at = Some(std::time::Instant::now());
+            continue;
+        }
+        // Count down to the next show once it's close enough, rather than starting another file
+        if let Some(lead) = options.slates.countdown_lead
+            && let Some((show, until)) = options.ratings.next_show_now()
+            && until <= lead
+        {
+            let show = show.to_string();
+            let starts_at = std::time::Instant::now() + until;
+            let text = || {
+                let remaining = starts_at.saturating_duration_since(std::time::Instant::now());
+                options.slates.countdown_text(&show, remaining)
+            };
+            let keep_showing = || {
+                std::time::Instant::now() < starts_at && hold.lock().is_none() && output_current()
+            };
+            play_slate(
+                SlateKind::Countdown,
+                &options,
+                &appsrcs,
+                &abort_rx,
+                &event_tx,
+                text,
+                keep_showing,
+            );
+            switch_started_at = Some(std::time::Instant::now());
+            continue;
+        }
+
+        let slot = options.ratings.slot_now();
+        if slot != current_slot {
+            current_slot = slot;
+            jingle = slot.and_then(|index| options.ratings.slots[index].jingle.clone());
+        }
+
+        take_enqueued(&enqueue_rx, &mut enqueued, &mut announced_random, &event_tx);
+        let override_path = jingle.take().or_else(|| next_override.take());
+        let override_path = override_path.or_else(|| enqueued.pop_front());
+        // Jingles and files sent to `PlayNext` or `Enqueue` play even if they're quarantined
+        let chosen = override_path.is_some();
+        // Whether to try another pick after one was turned down, rather than stand by
+        let mut keep_picking = || {
+            let since = *rejecting_since.get_or_insert_with(std::time::Instant::now);
+            since.elapsed() < MAX_PICKING_TIME
+        };
+        let picked = match override_path.or_else(|| approvals.take_ready()) {
+            Some(path) => Some(path),
+            None => match files.next() {
+                // Not logged, with a small library peers can hold most of it
+                Some(path) if peer_files.is_in_use(&path) => {
+                    if keep_picking() {
+                        continue;
+                    }
+                    println!(
+                        "Peers are playing or just played everything there is to pick, standing by"
+                    );
+                    None
+                }
+                // Not logged, outside the allowed slots most of the library can be skipped
+                Some(path) if !rating_allows_now(&options.ratings, &path) => {
+                    if keep_picking() {
+                        continue;
+                    }
+                    println!("Nothing in the library is allowed right now, standing by");
+                    None
+                }
+                Some(path)
+                    if content_filter.as_ref().is_some_and(|filter| !filter.allows(&path)) =>
+                {
+                    println!("Skipping {}, the classifier vetoed it", path.display());
+                    continue;
+                }
+                Some(path) if !approvals.allows(&path) => {
+                    if approvals.request(&path) {
+                        println!("Skipping {} until it's approved", path.display());
+                        _ = event_tx.send(Event::AwaitingApproval { path });
+                    } else {
+                        // Don't rescan the library as fast as possible while nothing can play
+                        std::thread::sleep(APPROVAL_BACKOFF);
+                    }
+                    continue;
+                }
+                picked => picked,
+            },
+        };
+        rejecting_since = None;
+        let Some(path) = picked else {
+            // Nothing to play (yet), e.g. an empty library
+            let until = std::time::Instant::now() + STANDBY_RETRY;
+            let keep_showing =
+                || std::time::Instant::now() < until && hold.lock().is_none() && output_current();
+            let text = || options.slates.text(SlateKind::Standby, None);
+            play_slate(
+                SlateKind::Standby,
+                &options,
+                &appsrcs,
+                &abort_rx,
+                &event_tx,
+                text,
+                keep_showing,
+            );
+            switch_started_at = Some(std::time::Instant::now());
+            continue;
+        };
+        if !chosen && quarantine.contains(&path) {
+            discovery.forget(&path);
+            continue;
+        }
+
+        let prepare_started_at = std::time::Instant::now();
+        let item = create_pipeline(
+            &path,
+            &appsrcs,
+            &options,
+            &gains,
+            &title_template,
+            &mut type_finder,
+            &discovery,
+        );
+        let item = match item {
+            Ok(item) => item,
+            Err(PrepareFailure::Skipped) => continue,
+            Err(PrepareFailure::Quarantined(reason)) => {
+                quarantine_file(&path, reason, chosen);
+                continue;
+            }
+        };
+        let PreparedItem { media_type, ref pipeline, duration, play_limit, .. } = item;
 
         println!("File feeder received {media_type:?} file: {}", path.display());
+        // Kept until the item is ready, a pick that fails to preroll is part of the switch
+        if let Some(started_at) = switch_started_at {
+            measure_switch(&item, started_at, &event_tx);
+        }
+
+        // Preroll within whatever is left of the budget
+        let budget = std::time::Duration::from(options.prepare.budget);
+        let remaining = budget.saturating_sub(prepare_started_at.elapsed());
+        if let Err(error) = pipeline.set_state(gstreamer::State::Paused) {
+            preroll_failed(&path, error.to_string());
+            item.tear_down();
+            continue;
+        }
+        let (preroll_result, _, _) = pipeline.state(gstreamer::ClockTime::try_from(remaining).ok());
+        match preroll_result {
+            Ok(gstreamer::StateChangeSuccess::Async) => {
+                item.tear_down();
+                let reason = format!("Not ready within {}", options.prepare.budget);
+                preroll_failed(&path, reason.clone());
+                quarantine_file(&path, reason, chosen);
+                continue;
+            }
+            Ok(_) => (),
+            Err(error) => {
+                preroll_failed(&path, error.to_string());
+                item.tear_down();
+                continue;
+            }
+        }
+        if let Some((start, end)) = item.trim {
+            let remaining = budget.saturating_sub(prepare_started_at.elapsed());
+            let timeout = gstreamer::ClockTime::try_from(remaining).ok();
+            if let Err(error) = seek_to_trim(pipeline, start, end, timeout) {
+                // Better the whole file than none of it
+                eprintln!("Failed to trim {}: {error}", path.display());
+            }
+        }
 
         println!("Playing file: {:?}", path);
-        _ = event_tx.try_send(Event::Playing { path: path.clone() });
+        switch_started_at = None;
+        _ = event_tx.send(Event::Playing { path: path.clone(), media_type, duration });
 
         // Start the file decoding pipeline
         pipeline.set_state(gstreamer::State::Playing).expect("Failed to start pipeline");
+        if let Some(sting) = &options.sting
+            && let Some(sting_input) = &appsrcs.sting
+        {
+            play_sting(sting, sting_input, options.audio);
+        }
+
+        // Pick the next file while this one plays, so it can be announced
+        let queued = next_override.as_ref().or(enqueued.front());
+        let next_path = queued.or_else(|| {
+            let vetoed = |path: &Path| {
+                content_filter.as_ref().is_some_and(|filter| filter.cached(path) == Some(false))
+            };
+            files.peek().filter(|path| {
+                rating_allows_now(&options.ratings, path) && approvals.allows(path) && !vetoed(path)
+            })
+        });
+        announced_random = None;
+        if let Some(next_path) = next_path {
+            discovery.prefetch(next_path);
+            // Enqueued files were announced when they were enqueued
+            if next_override.is_some() || enqueued.is_empty() {
+                _ = event_tx.send(Event::Queued { path: next_path.clone() });
+            }
+            if next_override.is_none() && enqueued.is_empty() {
+                announced_random = Some(next_path.clone());
+            }
+        }
 
         // --- Bus Message Handling ---
         let bus = pipeline.bus().unwrap();
 
-        'main: loop {
+        let keyframe_cut = watch_keyframes(&item);
+        let mut soft_skip_at: Option<gstreamer::ClockTime> = None;
+        let mut keyframe_wait_since: Option<std::time::Instant> = None;
+        let end_reason = 'main: loop {
             if let Ok(()) = abort_rx.recv_timeout(std::time::Duration::from_millis(10)) {
-                break 'main;
+                break 'main EndReason::Skipped;
+            }
+            if let Some(live) = &live
+                && live.transition() == LiveTransition::Cut
+                && live.is_connected()
+            {
+                break 'main EndReason::Interrupted;
+            }
+            // Don't push into appsrcs nobody is reading from any more
+            if !output_current() {
+                break 'main EndReason::Interrupted;
+            }
+
+            let running_time = pipeline.current_running_time();
+            for delay in soft_skip_rx.try_iter() {
+                let Ok(delay) = gstreamer::ClockTime::try_from(delay) else { continue };
+                let at = running_time.unwrap_or(gstreamer::ClockTime::ZERO) + delay;
+                soft_skip_at = Some(soft_skip_at.map_or(at, |current| current.min(at)));
+            }
+            if let Some(soft_skip_at) = soft_skip_at
+                && running_time.is_some_and(|time| time >= soft_skip_at)
+            {
+                // The delay is up, cut on the next keyframe
+                let waiting_since = *keyframe_wait_since.get_or_insert_with(|| {
+                    if let Some(cut) = &keyframe_cut {
+                        cut.armed.store(true, Ordering::Release);
+                    }
+                    std::time::Instant::now()
+                });
+                let reached =
+                    keyframe_cut.as_ref().is_none_or(|cut| cut.reached.load(Ordering::Acquire));
+                if reached || waiting_since.elapsed() > MAX_KEYFRAME_WAIT {
+                    break 'main EndReason::Skipped;
+                }
+            }
+
+            if let Some(play_limit) = play_limit
+                && running_time.is_some_and(|time| time >= play_limit)
+            {
+                break 'main EndReason::Finished;
+            }
+
+            for next_path in next_rx.try_iter() {
+                // Drop whatever was queued, a random file is consumed so it doesn't play later.
+                // Enqueued files stay, they play after this one
+                let replaced = match next_override.replace(next_path.clone()) {
+                    Some(previous) => Some(previous),
+                    None if enqueued.is_empty() => files.next(),
+                    None => None,
+                };
+                announced_random = None;
+                if let Some(replaced) = replaced {
+                    discovery.forget(&replaced);
+                    _ = event_tx.send(Event::Dequeued { path: replaced });
+                }
+                discovery.prefetch(&next_path);
+                _ = event_tx.send(Event::Queued { path: next_path });
+            }
+            take_enqueued(&enqueue_rx, &mut enqueued, &mut announced_random, &event_tx);
+
+            // Apply gain changes to the active item straight away
+            for changed_path in gain_rx.try_iter() {
+                if changed_path != path {
+                    continue;
+                }
+                let gain_db = gains.get(&path).or(item.overrides.gain);
+                let volume = gain_db.map(db_to_linear).unwrap_or(1.0);
+                for name in ["volume_aud", "volume_aud2"] {
+                    if let Some(element) = pipeline.by_name(name) {
+                        element.set_property("volume", volume);
+                    }
+                }
             }
 
             for msg in bus.iter_timed(gstreamer::ClockTime::from_mseconds(10)) {
                 use gstreamer::MessageView;
                 match msg.view() {
                     MessageView::Eos(..) => {
-                        break 'main;
+                        break 'main EndReason::Finished;
                     }
                     MessageView::Error(err) => {
                         eprintln!("Error on pipeline: {} (debug: {:?})", err.error(), err.debug());
-                        break 'main;
+                        // The old output going away can fail the push, that's not the file's fault
+                        if !output_current() {
+                            break 'main EndReason::Interrupted;
+                        }
+                        break 'main EndReason::Error(err.error().to_string());
                     }
                     _ => (),
                 }
             }
-        }
+        };
 
-        for appsrc in [&appsrcs.video, &appsrcs.audio] {
-            appsrc.send_event(gstreamer::event::FlushStart::new());
-            appsrc.send_event(gstreamer::event::FlushStop::new(true));
-        }
+        restart_output(&appsrcs);
+        switch_started_at = Some(std::time::Instant::now());
 
         pipeline.send_event(gstreamer::event::FlushStart::new());
 
-        _ = pipeline.set_state(gstreamer::State::Null);
-        _ = event_tx.try_send(Event::Ended { path: path.clone() });
+        item.tear_down();
+        // Once nothing of the item can come through any more, its last frame is what's held
+        if let Some(transition) = &appsrcs.transition {
+            transition.hold();
+        }
+        if let EndReason::Error(error) = &end_reason {
+            quarantine_file(&path, error.clone(), chosen);
+        }
+        _ = event_tx.send(Event::Ended { path: path.clone(), media_type, reason: end_reason });
     }
     println!("Feeder thread shutting down.");
 }
@@ -0,0 +1,78 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use gstreamer::prelude::*;
+use parking_lot::Mutex;
+
+use super::{AppSources, Probes};
+
+/// Holds the output on the last video frame while files keep playing (and switching) underneath.
+///
+/// This works on the output appsrcs rather than the per-file pipelines, so a freeze lasts across
+/// item switches until it's lifted.
+#[derive(Debug, Clone, Default)]
+pub struct Freeze {
+    frozen: Arc<AtomicBool>,
+    mute_audio: Arc<AtomicBool>,
+    last_frame: Arc<Mutex<Option<gstreamer::Buffer>>>,
+    probes: Probes,
+}
+
+impl Freeze {
+    /// Starts watching `app_sources`, in place of whatever it was attached to before.
+    pub fn attach(&self, app_sources: &AppSources) {
+        self.probes.remove_all();
+        let this = self.clone();
+        let video_pad = app_sources.video.static_pad("src").unwrap();
+        self.probes.add(&video_pad, gstreamer::PadProbeType::BUFFER, move |_, info| {
+            let Some(gstreamer::PadProbeData::Buffer(buffer)) = &mut info.data else {
+                return gstreamer::PadProbeReturn::Ok;
+            };
+            let mut last_frame = this.last_frame.lock();
+            if !this.frozen.load(Ordering::Relaxed) {
+                *last_frame = Some(buffer.clone());
+                return gstreamer::PadProbeReturn::Ok;
+            }
+            // Keep the new buffer's timing, but show the held picture
+            if let Some(frame) = last_frame.as_ref() {
+                let mut frame = frame.copy();
+                let frame_mut = frame.make_mut();
+                frame_mut.set_pts(buffer.pts());
+                frame_mut.set_dts(buffer.dts());
+                frame_mut.set_duration(buffer.duration());
+                *buffer = frame;
+            }
+            gstreamer::PadProbeReturn::Ok
+        });
+
+        let audio_sources = [Some(&app_sources.audio), app_sources.audio2.as_ref()];
+        for appsrc in audio_sources.into_iter().flatten() {
+            let this = self.clone();
+            let audio_pad = appsrc.static_pad("src").unwrap();
+            self.probes.add(&audio_pad, gstreamer::PadProbeType::BUFFER, move |_, info| {
+                let muted =
+                    this.frozen.load(Ordering::Relaxed) && this.mute_audio.load(Ordering::Relaxed);
+                if !muted {
+                    return gstreamer::PadProbeReturn::Ok;
+                }
+                // The output audio is always S16LE, so zeroes are silence
+                if let Some(gstreamer::PadProbeData::Buffer(buffer)) = &mut info.data
+                    && let Ok(mut map) = buffer.make_mut().map_writable()
+                {
+                    map.as_mut_slice().fill(0);
+                }
+                gstreamer::PadProbeReturn::Ok
+            });
+        }
+    }
+
+    /// Freezes the video, and silences the audio too if `mute_audio` is set.
+    pub fn freeze(&self, mute_audio: bool) {
+        self.mute_audio.store(mute_audio, Ordering::Relaxed);
+        self.frozen.store(true, Ordering::Relaxed);
+    }
+
+    pub fn unfreeze(&self) {
+        self.frozen.store(false, Ordering::Relaxed);
+    }
+}
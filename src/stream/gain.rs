@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+/// Extension of the sidecar file holding a gain override, e.g. `movie.mkv.gain`.
+const SIDECAR_EXTENSION: &str = "gain";
+
+/// The `volume` element doesn't accept anything above 10x.
+const MAX_LINEAR_GAIN: f64 = 10.0;
+
+/// Per-file gain adjustments in dB.
+/// Overrides set at runtime (via the API) take priority over sidecar files.
+#[derive(Debug, Clone, Default)]
+pub struct GainOverrides {
+    overrides: Arc<Mutex<HashMap<PathBuf, f64>>>,
+}
+
+impl GainOverrides {
+    pub fn set(&self, path: PathBuf, gain_db: f64) {
+        self.overrides.lock().insert(path, gain_db);
+    }
+
+    pub fn remove(&self, path: &Path) {
+        self.overrides.lock().remove(path);
+    }
+
+    /// Returns the gain for `path` in dB, if any.
+    pub fn get(&self, path: &Path) -> Option<f64> {
+        if let Some(gain_db) = self.overrides.lock().get(path) {
+            return Some(*gain_db);
+        }
+        read_sidecar(path)
+    }
+}
+
+fn read_sidecar(path: &Path) -> Option<f64> {
+    let mut sidecar_path = path.as_os_str().to_owned();
+    sidecar_path.push(".");
+    sidecar_path.push(SIDECAR_EXTENSION);
+
+    let contents = std::fs::read_to_string(PathBuf::from(sidecar_path)).ok()?;
+    match parse_gain(&contents) {
+        Some(gain_db) => Some(gain_db),
+        None => {
+            eprintln!("Invalid gain sidecar for {}: {contents:?}", path.display());
+            None
+        }
+    }
+}
+
+/// Parses a gain value such as `-3.5`, `+2 dB` or `6db`.
+pub fn parse_gain(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let value = value
+        .strip_suffix("dB")
+        .or_else(|| value.strip_suffix("db"))
+        .or_else(|| value.strip_suffix("DB"))
+        .unwrap_or(value);
+    value.trim().parse::<f64>().ok().filter(|v| v.is_finite())
+}
+
+/// Converts a gain in dB to the linear multiplier used by the `volume` element.
+pub fn db_to_linear(gain_db: f64) -> f64 {
+    10f64.powf(gain_db / 20.0).clamp(0.0, MAX_LINEAR_GAIN)
+}
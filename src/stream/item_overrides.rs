@@ -0,0 +1,100 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use gstreamer::prelude::*;
+use serde::Deserialize;
+
+/// Appended to a file's name for its settings sidecar, e.g. `movie.mkv.zstream.toml`.
+const SIDECAR_SUFFIX: &str = ".zstream.toml";
+
+/// Settings for a single file, from the `<file>.zstream.toml` next to it. Everything is optional:
+///
+/// ```toml
+/// title = "The Movie (1987)"
+/// start = 12.5          # seconds in, skipping an intro
+/// end = 5400            # seconds in, skipping the credits
+/// max-duration = 600    # play at most this many seconds
+/// gain = -3.0           # dB, unless the API or a `.gain` sidecar sets one
+/// rating = "family"     # instead of the one from `--rating`
+/// show-title = false
+/// show-counter = false
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ItemOverrides {
+    /// The `{title}` of the title overlay, which it shows instead of the file's path unless
+    /// there's a [`TitleTemplate`](super::TitleTemplate).
+    pub title: Option<String>,
+    /// Seconds into the file to start playing at.
+    pub start: Option<f64>,
+    /// Seconds into the file to stop playing at.
+    pub end: Option<f64>,
+    /// Longest the file is played for, in seconds.
+    pub max_duration: Option<f64>,
+    /// Gain in dB.
+    pub gain: Option<f64>,
+    pub rating: Option<String>,
+    pub show_title: Option<bool>,
+    pub show_counter: Option<bool>,
+}
+
+impl ItemOverrides {
+    /// The sidecar of `path`, which may not exist.
+    pub fn sidecar_path(path: &Path) -> PathBuf {
+        let mut sidecar_path = OsString::from(path.as_os_str());
+        sidecar_path.push(SIDECAR_SUFFIX);
+        PathBuf::from(sidecar_path)
+    }
+
+    /// The overrides for `path`, none if it doesn't have a sidecar or it's invalid.
+    pub fn load(path: &Path) -> Self {
+        let Ok(contents) = std::fs::read_to_string(Self::sidecar_path(path)) else {
+            return Self::default();
+        };
+        match toml::from_str(&contents) {
+            Ok(overrides) => overrides,
+            Err(error) => {
+                eprintln!("Invalid settings sidecar for {}: {error}", path.display());
+                Self::default()
+            }
+        }
+    }
+
+    /// Where to start and stop playing, if the file is trimmed. The end is left to the file if
+    /// it isn't set.
+    pub(crate) fn trim(&self) -> Option<(gstreamer::ClockTime, Option<gstreamer::ClockTime>)> {
+        let start = clock_time(self.start);
+        let end = clock_time(self.end).filter(|end| start.is_none_or(|start| *end > start));
+        if start.is_none() && end.is_none() {
+            return None;
+        }
+        Some((start.unwrap_or(gstreamer::ClockTime::ZERO), end))
+    }
+
+    pub(crate) fn max_duration(&self) -> Option<gstreamer::ClockTime> {
+        clock_time(self.max_duration)
+            .filter(|max_duration| *max_duration > gstreamer::ClockTime::ZERO)
+    }
+
+    /// Hides the overlays of `pipeline` as they say. The title is filled in by
+    /// [`attach_title_template`](super::attach_title_template).
+    pub(crate) fn apply_to_overlays(&self, pipeline: &gstreamer::Pipeline) {
+        if let Some(show_title) = self.show_title
+            && let Some(title_overlay) = pipeline.by_name("textoverlay")
+        {
+            title_overlay.set_property("silent", !show_title);
+        }
+        if let Some(show_counter) = self.show_counter
+            && let Some(counter_overlay) = pipeline.by_name("counter_overlay")
+        {
+            counter_overlay.set_property("silent", !show_counter);
+        }
+    }
+}
+
+/// `seconds` as a clock time, if it's a valid one.
+fn clock_time(seconds: Option<f64>) -> Option<gstreamer::ClockTime> {
+    let duration = Duration::try_from_secs_f64(seconds?).ok()?;
+    gstreamer::ClockTime::try_from(duration).ok()
+}
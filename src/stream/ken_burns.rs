@@ -0,0 +1,105 @@
+use gstreamer::prelude::*;
+use parking_lot::Mutex;
+use rand::Rng;
+
+use super::{Error, Probes};
+
+/// A slow zoom and pan over photos, instead of showing them still.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct KenBurnsOptions {
+    /// How far it zooms in, e.g. 20 shows 1/1.2 of the photo at the closest.
+    pub zoom_percent: u32,
+}
+
+impl Default for KenBurnsOptions {
+    fn default() -> Self {
+        Self { zoom_percent: 15 }
+    }
+}
+
+/// The part of the picture that's shown, in fractions of its size.
+#[derive(Debug, Copy, Clone)]
+struct Rect {
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+}
+
+impl Rect {
+    const FULL: Self = Self { x: 0.0, y: 0.0, width: 1.0, height: 1.0 };
+
+    fn lerp(self, other: Self, t: f64) -> Self {
+        let lerp = |a: f64, b: f64| a + (b - a) * t;
+        Self {
+            x: lerp(self.x, other.x),
+            y: lerp(self.y, other.y),
+            width: lerp(self.width, other.width),
+            height: lerp(self.height, other.height),
+        }
+    }
+}
+
+/// A videocrop that moves over the picture during `duration`, for the videoscale after it to
+/// blow back up. It zooms in on (or out from) a random corner, edge or the middle.
+pub(crate) fn create_ken_burns(
+    options: KenBurnsOptions,
+    duration: gstreamer::ClockTime,
+    probes: &Probes,
+) -> Result<gstreamer::Element, Error> {
+    let videocrop = gstreamer::ElementFactory::make("videocrop").name("ken_burns").build()?;
+    let (from, to) = random_move(options);
+
+    // Timestamps don't have to start at zero
+    let first_pts = Mutex::new(None::<gstreamer::ClockTime>);
+    let videocrop_weak = videocrop.downgrade();
+    let sink_pad = videocrop.static_pad("sink").unwrap();
+    probes.add(&sink_pad, gstreamer::PadProbeType::BUFFER, move |pad, info| {
+        let Some(pts) = info.buffer().and_then(|buffer| buffer.pts()) else {
+            return gstreamer::PadProbeReturn::Ok;
+        };
+        let Some(video_info) = pad
+            .current_caps()
+            .and_then(|caps| gstreamer_video::VideoInfo::from_caps(&caps).ok())
+        else {
+            return gstreamer::PadProbeReturn::Ok;
+        };
+        let Some(videocrop) = videocrop_weak.upgrade() else {
+            return gstreamer::PadProbeReturn::Ok;
+        };
+
+        let first_pts = *first_pts.lock().get_or_insert(pts);
+        let elapsed = pts.saturating_sub(first_pts).nseconds() as f64;
+        let progress = (elapsed / duration.nseconds().max(1) as f64).clamp(0.0, 1.0);
+        // Eased, so it doesn't start or stop with a jolt
+        let rect = from.lerp(to, progress * progress * (3.0 - 2.0 * progress));
+
+        let (width, height) = (video_info.width() as f64, video_info.height() as f64);
+        let left = rect.x * width;
+        let top = rect.y * height;
+        let right = (1.0 - rect.x - rect.width) * width;
+        let bottom = (1.0 - rect.y - rect.height) * height;
+        videocrop.set_property("left", left.round() as i32);
+        videocrop.set_property("top", top.round() as i32);
+        videocrop.set_property("right", right.round() as i32);
+        videocrop.set_property("bottom", bottom.round() as i32);
+        gstreamer::PadProbeReturn::Ok
+    });
+    Ok(videocrop)
+}
+
+fn random_move(options: KenBurnsOptions) -> (Rect, Rect) {
+    let mut rng = rand::rng();
+    let size = 100.0 / (100 + options.zoom_percent) as f64;
+    // Left or top, the middle, or right or bottom
+    let anchors = [0.0, 0.5, 1.0];
+    let anchor_x = anchors[rng.random_range(0..anchors.len())];
+    let anchor_y = anchors[rng.random_range(0..anchors.len())];
+    let zoomed = Rect {
+        x: (1.0 - size) * anchor_x,
+        y: (1.0 - size) * anchor_y,
+        width: size,
+        height: size,
+    };
+    if rng.random_bool(0.5) { (Rect::FULL, zoomed) } else { (zoomed, Rect::FULL) }
+}
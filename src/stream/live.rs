@@ -0,0 +1,424 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use gstreamer::prelude::*;
+
+use super::pool::create_video_appsink;
+use super::selection::pad_stream_type;
+use super::{AppSources, AppSrcStorage, AudioOptions, Error, Probes, VideoOptions};
+
+/// How long to wait before listening again after the live pipeline failed.
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Where a live input comes from.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum LiveSource {
+    /// Waits for an SRT caller (e.g. OBS or a hardware encoder) on this port, which has to use
+    /// the same passphrase if `encryption` is set.
+    Srt { port: u16, encryption: Option<SrtEncryption> },
+    /// A stream published over RTMP to this path of the bundled mediamtx, e.g.
+    /// `rtmp://host/live`. It's pulled back from mediamtx's RTSP port, polling until something is
+    /// published there.
+    Rtmp { path: String, mediamtx_port: u16 },
+}
+
+impl LiveSource {
+    fn uri(&self) -> String {
+        match self {
+            Self::Srt { port, .. } => format!("srt://:{port}?mode=listener"),
+            Self::Rtmp { path, mediamtx_port } => {
+                format!("rtsp://127.0.0.1:{mediamtx_port}/{path}")
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for LiveSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Srt { port, .. } => write!(f, "srt://:{port}"),
+            Self::Rtmp { path, .. } => write!(f, "rtmp://*/{path}"),
+        }
+    }
+}
+
+/// AES encryption of an SRT connection.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct SrtEncryption {
+    /// Shared with the other end, 10 to 79 characters.
+    pub passphrase: String,
+    /// Left to the caller if not set.
+    pub key_length: Option<SrtKeyLength>,
+}
+
+impl SrtEncryption {
+    /// Shortest and longest passphrases SRT accepts.
+    pub const PASSPHRASE_LENGTH: std::ops::RangeInclusive<usize> = 10..=79;
+
+    /// Sets up an `srtsrc` to use this.
+    fn configure(&self, element: &gstreamer::Element) {
+        element.set_property("passphrase", &self.passphrase);
+        if let Some(key_length) = self.key_length {
+            element.set_property_from_str("pbkeylen", key_length.nick());
+        }
+    }
+}
+
+/// Length of the key SRT encrypts with (`pbkeylen`).
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum SrtKeyLength {
+    Aes128,
+    Aes192,
+    Aes256,
+}
+
+impl SrtKeyLength {
+    fn nick(self) -> &'static str {
+        match self {
+            Self::Aes128 => "aes-128",
+            Self::Aes192 => "aes-192",
+            Self::Aes256 => "aes-256",
+        }
+    }
+}
+
+impl std::str::FromStr for SrtKeyLength {
+    type Err = String;
+
+    /// Takes the length in bytes like `pbkeylen` does, or the AES variant.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "16" | "aes-128" => Ok(Self::Aes128),
+            "24" | "aes-192" => Ok(Self::Aes192),
+            "32" | "aes-256" => Ok(Self::Aes256),
+            _ => Err(format!("Unknown SRT key length {s:?}, expected 16, 24 or 32")),
+        }
+    }
+}
+
+/// When a live input gets on air once it has connected.
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum LiveTransition {
+    /// Straight away, cutting off the current file.
+    #[default]
+    Cut,
+    /// Once the current file has ended by itself.
+    AfterCurrent,
+}
+
+impl std::str::FromStr for LiveTransition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cut" => Ok(Self::Cut),
+            "after-current" => Ok(Self::AfterCurrent),
+            _ => Err(format!("Unknown live transition {s:?}, expected cut or after-current")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct LiveInputOptions {
+    pub source: LiveSource,
+    pub transition: LiveTransition,
+}
+
+/// A live input that takes over the output while something is connected to it.
+///
+/// Its pipeline runs the whole time, so a source is picked up while files are playing. Nothing is
+/// pushed to the output until the feeder puts it on air, which it does once it has ended the
+/// current file.
+#[derive(Debug, Clone)]
+pub struct LiveInput {
+    source: LiveSource,
+    transition: LiveTransition,
+    connected: Arc<AtomicBool>,
+    on_air: Arc<AtomicBool>,
+}
+
+impl LiveInput {
+    /// Starts listening on a thread of its own.
+    pub fn start(
+        options: LiveInputOptions,
+        storage: &AppSrcStorage,
+        video: VideoOptions,
+        audio: AudioOptions,
+    ) -> Self {
+        let this = Self {
+            source: options.source,
+            transition: options.transition,
+            connected: Arc::new(AtomicBool::new(false)),
+            on_air: Arc::new(AtomicBool::new(false)),
+        };
+        let this_clone = this.clone();
+        let storage = storage.clone();
+        std::thread::spawn(move || {
+            println!("Live input waiting on {}", this_clone.source);
+            // Polled sources fail the same way until something is published, only log changes
+            let mut last_error = None;
+            loop {
+                match this_clone.run(&storage, video, audio) {
+                    Ok(()) => last_error = None,
+                    Err(error) => {
+                        let error = error.to_string();
+                        if last_error.as_ref() != Some(&error) {
+                            eprintln!("Live input {} failed: {error}", this_clone.source);
+                        }
+                        last_error = Some(error);
+                        std::thread::sleep(RETRY_INTERVAL);
+                    }
+                }
+                this_clone.connected.store(false, Ordering::Relaxed);
+            }
+        });
+        this
+    }
+
+    pub fn source(&self) -> &LiveSource {
+        &self.source
+    }
+
+    pub fn transition(&self) -> LiveTransition {
+        self.transition
+    }
+
+    /// Whether something is connected and its video is coming through.
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    /// Starts or stops pushing the live input to the output.
+    pub fn set_on_air(&self, on_air: bool) {
+        self.on_air.store(on_air, Ordering::Relaxed);
+    }
+
+    /// Runs the pipeline until the source goes away, or the output is built again and the
+    /// pipeline has to be set up for the new appsrcs.
+    fn run(
+        &self,
+        storage: &AppSrcStorage,
+        video: VideoOptions,
+        audio: AudioOptions,
+    ) -> Result<(), Error> {
+        let (version, app_sources) = storage.wait();
+        let probes = Probes::default();
+        let pipeline = match self.create_pipeline(&app_sources, video, audio, &probes) {
+            Ok(pipeline) => pipeline,
+            Err(error) => {
+                probes.remove_all();
+                return Err(error);
+            }
+        };
+        if let Err(error) = pipeline.set_state(gstreamer::State::Playing) {
+            _ = pipeline.set_state(gstreamer::State::Null);
+            probes.remove_all();
+            return Err(error.into());
+        }
+
+        let bus = pipeline.bus().unwrap();
+        let mut result = Ok(());
+        loop {
+            use gstreamer::MessageView;
+            if storage.version() != version {
+                println!("Output rebuilt, restarting live input {}", self.source);
+                break;
+            }
+            let Some(msg) = bus.timed_pop(gstreamer::ClockTime::from_mseconds(100)) else {
+                continue;
+            };
+            match msg.view() {
+                MessageView::Eos(..) => {
+                    println!("Live input {} disconnected", self.source);
+                    break;
+                }
+                MessageView::Error(err) => {
+                    result = Err(Error::Glib(err.error()));
+                    break;
+                }
+                _ => (),
+            }
+        }
+        _ = pipeline.set_state(gstreamer::State::Null);
+        probes.remove_all();
+        result
+    }
+
+    fn create_pipeline(
+        &self,
+        app_sources: &AppSources,
+        video: VideoOptions,
+        audio: AudioOptions,
+        probes: &Probes,
+    ) -> Result<gstreamer::Pipeline, Error> {
+        let pipeline = gstreamer::Pipeline::builder().name("live-pipeline").build();
+
+        let decodebin = gstreamer::ElementFactory::make("uridecodebin3")
+            .name("decodebin")
+            .property("uri", self.source.uri())
+            .build()?;
+        // The passphrase isn't put into the URI, where it would have to be escaped (and logged)
+        if let LiveSource::Srt { encryption: Some(encryption), .. } = &self.source {
+            let encryption = encryption.clone();
+            decodebin.connect("source-setup", false, move |args| {
+                if let Ok(source) = args[1].get::<gstreamer::Element>() {
+                    encryption.configure(&source);
+                }
+                None
+            });
+        }
+
+        // --- Video Chain ---
+        let videoconvert_vid = gstreamer::ElementFactory::make("videoconvert")
+            .name("videoconvert_vid")
+            .build()?;
+        let videoscale_vid = gstreamer::ElementFactory::make("videoscale")
+            .property("add-borders", true)
+            .build()?;
+        let videorate_vid = gstreamer::ElementFactory::make("videorate").build()?;
+        let capsfilter_vid = gstreamer::ElementFactory::make("capsfilter")
+            .property(
+                "caps",
+                gstreamer::Caps::builder("video/x-raw")
+                    .field("format", gstreamer_video::VideoFormat::I420.to_string())
+                    .field("width", video.width as i32)
+                    .field("height", video.height as i32)
+                    .field("pixel-aspect-ratio", gstreamer::Fraction::new(1, 1))
+                    .field("framerate", video.framerate())
+                    .build(),
+            )
+            .build()?;
+        let queue_video = gstreamer::ElementFactory::make("queue").build()?;
+        let appsink_video = create_video_appsink(probes);
+
+        // --- Audio Chain ---
+        // Mixed with silence, so there's audio even if the source doesn't send any
+        let audiotestsrc = gstreamer::ElementFactory::make("audiotestsrc")
+            .property_from_str("wave", "silence")
+            .property("is-live", true)
+            .build()?;
+        let audioconvert_aud = gstreamer::ElementFactory::make("audioconvert")
+            .name("audioconvert_aud")
+            .build()?;
+        let audioresample_aud = gstreamer::ElementFactory::make("audioresample").build()?;
+        let audiomixer = gstreamer::ElementFactory::make("audiomixer")
+            .property("ignore-inactive-pads", true)
+            .build()?;
+        // These caps MUST match the caps in media_factory.rs
+        let capsfilter_aud = gstreamer::ElementFactory::make("capsfilter")
+            .property("caps", audio.caps())
+            .build()?;
+        let batcher_aud = audio.create_batcher()?;
+        let appsink_audio = gstreamer_app::AppSink::builder().name("appsink_audio").build();
+
+        pipeline.add_many([
+            &decodebin,
+            &videoconvert_vid,
+            &videoscale_vid,
+            &videorate_vid,
+            &capsfilter_vid,
+            &queue_video,
+            appsink_video.upcast_ref(),
+            &audiotestsrc,
+            &audioconvert_aud,
+            &audioresample_aud,
+            &audiomixer,
+            &capsfilter_aud,
+            appsink_audio.upcast_ref(),
+        ])?;
+        if let Some(batcher_aud) = &batcher_aud {
+            pipeline.add(batcher_aud)?;
+        }
+
+        gstreamer::Element::link_many([
+            &videoconvert_vid,
+            &videoscale_vid,
+            &videorate_vid,
+            &capsfilter_vid,
+            &queue_video,
+            appsink_video.upcast_ref(),
+        ])?;
+        let mut audio_chain = vec![&audiotestsrc, &audiomixer, &capsfilter_aud];
+        audio_chain.extend(&batcher_aud);
+        audio_chain.push(appsink_audio.upcast_ref());
+        gstreamer::Element::link_many(audio_chain)?;
+        gstreamer::Element::link_many([&audioconvert_aud, &audioresample_aud, &audiomixer])?;
+
+        // --- Dynamic Pad Linking ---
+        let pipeline_weak = pipeline.downgrade();
+        decodebin.connect_pad_added(move |_, pad| {
+            let Some(pipeline) = pipeline_weak.upgrade() else { return };
+
+            let stream_type = pad_stream_type(pad);
+            let sink_name = if stream_type.contains(gstreamer::StreamType::VIDEO) {
+                "videoconvert_vid"
+            } else if stream_type.contains(gstreamer::StreamType::AUDIO) {
+                "audioconvert_aud"
+            } else {
+                println!("Live input: Unknown pad type: {}", pad.name());
+                return;
+            };
+
+            let Some(sink_pad) = pipeline.by_name(sink_name).and_then(|e| e.static_pad("sink"))
+            else {
+                return;
+            };
+            if sink_pad.is_linked() {
+                eprintln!("Live input: {sink_name} already linked, ignoring {}", pad.name());
+                return;
+            }
+            if let Err(err) = pad.link(&sink_pad) {
+                eprintln!("Live input: Failed to link {}: {err}", pad.name());
+            }
+        });
+
+        // --- AppSink Callbacks ---
+        // The first decoded frame means the source is connected and ready to take over
+        let connected = self.connected.clone();
+        let on_air = self.on_air.clone();
+        let appsrc_video_weak = app_sources.video.downgrade();
+        appsink_video.set_callbacks(
+            gstreamer_app::AppSinkCallbacks::builder()
+                .new_sample(move |sink| {
+                    let sample = sink.pull_sample().map_err(|_| gstreamer::FlowError::Eos)?;
+                    connected.store(true, Ordering::Relaxed);
+                    if !on_air.load(Ordering::Relaxed) {
+                        return Ok(gstreamer::FlowSuccess::Ok);
+                    }
+                    let Some(appsrc_video) = appsrc_video_weak.upgrade() else {
+                        return Err(gstreamer::FlowError::Error);
+                    };
+                    appsrc_video.push_sample(&sample).map_err(|_| gstreamer::FlowError::Error)
+                })
+                .build(),
+        );
+
+        // The secondary audio program carries the same audio
+        let on_air = self.on_air.clone();
+        let audio_sources = [Some(&app_sources.audio), app_sources.audio2.as_ref()];
+        let appsrcs_audio: Vec<_> =
+            audio_sources.into_iter().flatten().map(|a| a.downgrade()).collect();
+        appsink_audio.set_callbacks(
+            gstreamer_app::AppSinkCallbacks::builder()
+                .new_sample(move |sink| {
+                    let sample = sink.pull_sample().map_err(|_| gstreamer::FlowError::Eos)?;
+                    if !on_air.load(Ordering::Relaxed) {
+                        return Ok(gstreamer::FlowSuccess::Ok);
+                    }
+                    for appsrc_audio in &appsrcs_audio {
+                        let Some(appsrc_audio) = appsrc_audio.upgrade() else {
+                            return Err(gstreamer::FlowError::Error);
+                        };
+                        appsrc_audio
+                            .push_sample(&sample)
+                            .map_err(|_| gstreamer::FlowError::Error)?;
+                    }
+                    Ok(gstreamer::FlowSuccess::Ok)
+                })
+                .build(),
+        );
+
+        Ok(pipeline)
+    }
+}
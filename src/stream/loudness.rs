@@ -0,0 +1,54 @@
+use super::Error;
+
+/// ReplayGain tags are relative to this loudness, in LUFS.
+const REPLAY_GAIN_REFERENCE: i32 = -18;
+
+/// Brings every file to about the same loudness, so quiet home videos and loud movie rips play
+/// at a consistent volume.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct LoudnessOptions {
+    /// The loudness to aim for, in LUFS.
+    pub target_lufs: i32,
+}
+
+impl Default for LoudnessOptions {
+    fn default() -> Self {
+        // EBU R128
+        Self { target_lufs: -23 }
+    }
+}
+
+/// The elements normalizing the audio, to go between an audioconvert and the rest of the chain.
+///
+/// Uses `audioloudnorm` (a loudnorm-style filter measuring the audio as it plays) when it's
+/// installed. Otherwise falls back to `rgvolume`, which only helps files with ReplayGain tags,
+/// followed by `rglimiter` so the pre-amp can't clip.
+pub(crate) fn create_loudness_elements(
+    options: LoudnessOptions,
+    name_suffix: &str,
+) -> Result<Vec<gstreamer::Element>, Error> {
+    if gstreamer::ElementFactory::find("audioloudnorm").is_some() {
+        // audioloudnorm only takes 192kHz
+        let resample = gstreamer::ElementFactory::make("audioresample")
+            .name(format!("loudness_resample{name_suffix}"))
+            .build()?;
+        let loudnorm = gstreamer::ElementFactory::make("audioloudnorm")
+            .name(format!("loudnorm{name_suffix}"))
+            .property("loudness-target", f64::from(options.target_lufs))
+            .build()?;
+        let convert = gstreamer::ElementFactory::make("audioconvert")
+            .name(format!("loudness_convert{name_suffix}"))
+            .build()?;
+        return Ok(vec![resample, loudnorm, convert]);
+    }
+
+    let rgvolume = gstreamer::ElementFactory::make("rgvolume")
+        .name(format!("rgvolume{name_suffix}"))
+        .property("pre-amp", f64::from(options.target_lufs - REPLAY_GAIN_REFERENCE))
+        .property("album-mode", false)
+        .build()?;
+    let rglimiter = gstreamer::ElementFactory::make("rglimiter")
+        .name(format!("rglimiter{name_suffix}"))
+        .build()?;
+    Ok(vec![rgvolume, rglimiter])
+}
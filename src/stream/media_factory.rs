@@ -1,17 +1,159 @@
 use std::sync::Arc;
 
 use gstreamer_rtsp_server::subclass::prelude::*;
-use parking_lot::Mutex;
+use parking_lot::{Condvar, Mutex};
 
 #[derive(Clone)]
 pub struct AppSources {
     pub video: gstreamer_app::AppSrc,
     pub audio: gstreamer_app::AppSrc,
+    /// The secondary audio program, if enabled.
+    pub audio2: Option<gstreamer_app::AppSrc>,
+    /// Mixed over the program audio, if transition stings are enabled.
+    pub sting: Option<super::StingInput>,
+    /// Draws transitions over the program video, if enabled.
+    pub transition: Option<super::TransitionInput>,
+    /// Changes the video encoding on the fly, if the output profile allows it.
+    pub encoder: Option<super::EncoderSwitch>,
+    /// The output pipeline's elements that can be changed while it runs.
+    pub elements: super::ElementRegistry,
 }
 
-/// Shared storage for the AppSrc element.
-/// This allows the feeder thread to find the AppSrc created by the RTSP factory.
-pub type AppSrcStorage = Arc<Mutex<Option<AppSources>>>;
+/// How the output's appsrcs treat what's pushed into them. Direct RTSP clients are happy with the
+/// defaults, a restreamer like mediamtx can do better with e.g. the inputs' own timestamps.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct AppSrcPolicy {
+    /// Stamp buffers with the time they're pushed at, rather than keeping the inputs' timestamps.
+    pub do_timestamp: bool,
+    pub format: AppSrcFormat,
+    pub is_live: bool,
+    /// Latency the appsrcs report on top of their own, if any.
+    pub min_latency: Option<gstreamer::ClockTime>,
+}
+
+impl Default for AppSrcPolicy {
+    fn default() -> Self {
+        Self {
+            do_timestamp: true,
+            format: AppSrcFormat::Time,
+            is_live: true,
+            min_latency: None,
+        }
+    }
+}
+
+impl AppSrcPolicy {
+    /// An appsrc for the output pipeline, carrying `caps`.
+    pub(super) fn create_appsrc(
+        &self,
+        name: &str,
+        caps: &gstreamer::Caps,
+    ) -> gstreamer_app::AppSrc {
+        let min_latency = self.min_latency.map_or(-1, |latency| latency.nseconds() as i64);
+        gstreamer_app::AppSrc::builder()
+            .name(name)
+            .caps(caps)
+            .is_live(self.is_live)
+            .stream_type(gstreamer_app::AppStreamType::Stream)
+            .format(self.format.into())
+            .do_timestamp(self.do_timestamp)
+            .min_latency(min_latency)
+            .build()
+    }
+}
+
+/// The format of the segments the appsrcs start, see the appsrc `format` property.
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum AppSrcFormat {
+    #[default]
+    Time,
+    Bytes,
+    /// Buffer counts.
+    Default,
+}
+
+impl From<AppSrcFormat> for gstreamer::Format {
+    fn from(format: AppSrcFormat) -> Self {
+        match format {
+            AppSrcFormat::Time => Self::Time,
+            AppSrcFormat::Bytes => Self::Bytes,
+            AppSrcFormat::Default => Self::Default,
+        }
+    }
+}
+
+impl std::str::FromStr for AppSrcFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "time" => Ok(Self::Time),
+            "bytes" => Ok(Self::Bytes),
+            "default" | "buffers" => Ok(Self::Default),
+            _ => Err(format!("Unknown appsrc format {s:?}, expected time, bytes or default")),
+        }
+    }
+}
+
+impl std::fmt::Display for AppSrcFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Time => "time",
+            Self::Bytes => "bytes",
+            Self::Default => "default",
+        })
+    }
+}
+
+/// Shared storage for the AppSrc elements.
+/// This allows the feeder thread to find the AppSrcs created by the RTSP factory.
+///
+/// The factory makes a new output pipeline (with new appsrcs) whenever the media has to be built
+/// again, e.g. after every viewer left. Each one gets a new version, so whoever pushes into the
+/// appsrcs can tell theirs have been replaced.
+#[derive(Clone, Default)]
+pub struct AppSrcStorage {
+    inner: Arc<(Mutex<(u64, Option<AppSources>)>, Condvar)>,
+}
+
+impl AppSrcStorage {
+    /// Replaces the appsrcs with those of a new output pipeline.
+    pub fn set(&self, app_sources: AppSources) {
+        let (state, changed) = &*self.inner;
+        let mut state = state.lock();
+        state.0 += 1;
+        state.1 = Some(app_sources);
+        changed.notify_all();
+    }
+
+    /// Increases every time the appsrcs are replaced, 0 until there are any.
+    pub fn version(&self) -> u64 {
+        self.inner.0.lock().0
+    }
+
+    /// The current appsrcs and their version, if there are any yet.
+    pub fn get(&self) -> Option<(u64, AppSources)> {
+        let state = self.inner.0.lock();
+        state.1.clone().map(|app_sources| (state.0, app_sources))
+    }
+
+    /// The current appsrcs, if they've been replaced since `version`.
+    pub fn newer_than(&self, version: u64) -> Option<(u64, AppSources)> {
+        self.get().filter(|(current, _)| *current != version)
+    }
+
+    /// Blocks until there are appsrcs.
+    pub fn wait(&self) -> (u64, AppSources) {
+        let (state, changed) = &*self.inner;
+        let mut state = state.lock();
+        loop {
+            if let Some(app_sources) = &state.1 {
+                return (state.0, app_sources.clone());
+            }
+            changed.wait(&mut state);
+        }
+    }
+}
 
 // GObject Subclass Implementation
 mod imp {
@@ -20,12 +162,21 @@ mod imp {
     use gstreamer_rtsp_server::subclass::prelude::*;
     use parking_lot::Mutex;
 
-    use super::*;
-    use crate::stream::encoder::create_video_encoder; // This pulls in AppSrcStorage, etc.
+    use super::*; // This pulls in AppSrcStorage, etc.
+    use crate::stream::output::link_output_branch;
+    use crate::stream::whep::link_whep_branch;
+    use crate::stream::{
+        DataOverlays, ElementRegistry, ElementRole, MjpegFeed, Rendition, SecondaryAudio,
+        StingInput, StreamOptions, TransitionInput,
+    };
 
     #[derive(Default)]
     pub struct MyMediaFactory {
         pub(super) storage: Mutex<Option<AppSrcStorage>>,
+        pub(super) options: Mutex<StreamOptions>,
+        pub(super) data_overlays: Mutex<DataOverlays>,
+        pub(super) mjpeg: Mutex<Option<MjpegFeed>>,
+        pub(super) renditions: Mutex<Vec<Rendition>>,
     }
 
     #[glib::object_subclass]
@@ -39,6 +190,15 @@ mod imp {
     impl GstObjectImpl for MyMediaFactory {}
 
     impl RTSPMediaFactoryImpl for MyMediaFactory {
+        /// Every mount path (the stream key and its aliases) gets the same media, there's only
+        /// one set of appsrcs for the feeder to push into.
+        fn gen_key(
+            &self,
+            _url: &gstreamer_rtsp_server::gst_rtsp::RTSPUrl,
+        ) -> Option<glib::GString> {
+            Some("z-stream".into())
+        }
+
         /// This function is called once per client connection.
         /// Since we set `set_shared(true)`, the pipeline created here
         /// will be shared among all clients.
@@ -49,107 +209,177 @@ mod imp {
             println!("RTSP CLIENT CONNECTED: Building shared pipeline...");
             let storage = self.storage.lock();
             let storage = storage.as_ref().expect("Storage not set");
+            let options = self.options.lock();
+            let video_options = options.video;
+            let audio_options = options.audio;
+            let appsrc_policy = options.appsrc;
 
             // This is the pipeline that will be served via RTSP
             let bin = gstreamer::Bin::builder().name("rtsp-pipeline").build();
+            let elements = ElementRegistry::default();
 
             // --- 1. Video Branch ---
-            let appsrc_video = gstreamer_app::AppSrc::builder()
-                .name("videosrc")
-                .is_live(true)
-                .stream_type(gstreamer_app::AppStreamType::Stream)
-                .format(gstreamer::Format::Time)
-                .do_timestamp(true)
-                .build();
-
             let video_caps = gstreamer::Caps::builder("video/x-raw")
-                // .field("format", gstreamer_video::VideoFormat::I420)
-                .field("width", 1280)
-                .field("height", 720)
-                .field("framerate", gstreamer::Fraction::new(30, 1))
+                // Every input is converted to I420, so the conversion here can pass it through
+                .field("format", gstreamer_video::VideoFormat::I420.to_string())
+                .field("width", video_options.width as i32)
+                .field("height", video_options.height as i32)
+                .field("framerate", video_options.framerate())
                 .build();
-            appsrc_video.set_caps(Some(&video_caps));
+            let appsrc_video = appsrc_policy.create_appsrc("videosrc", &video_caps);
 
             let videoconvert = gstreamer::ElementFactory::make("videoconvert").build().ok()?;
+            // On top of everything, files, slates and live inputs alike
+            let data_overlays = self.data_overlays.lock().create_element().ok()?;
             let videorate = gstreamer::ElementFactory::make("videorate").build().ok()?;
             // let timestamper = gstreamer::ElementFactory::make("timecodestamper").build().ok()?;
 
-            let x264enc = create_video_encoder().ok()?;
-            let pay_vid = gstreamer::ElementFactory::make("rtph264pay")
-                .property("name", "pay0") // MUST be "pay0"
-                .property("pt", 96_u32)
-                .property("config-interval", 1)
-                .build()
-                .ok()?;
-
             // --- 2. Audio Branch ---
-            let appsrc_audio = gstreamer_app::AppSrc::builder()
-                .name("audiosrc")
-                .is_live(true)
-                .stream_type(gstreamer_app::AppStreamType::Stream)
-                .format(gstreamer::Format::Time)
-                .do_timestamp(true)
-                .build();
-
-            // This caps MUST match the caps in feeder.rs
-            let audio_caps = gstreamer::Caps::builder("audio/x-raw")
-                .field("format", "S16LE")
-                .field("layout", "interleaved")
-                .field("rate", 48000)
-                .field("channels", 2)
-                .build();
-            appsrc_audio.set_caps(Some(&audio_caps));
+            // Every input is converted to these caps before it's pushed
+            let audio_caps = audio_options.caps();
+            let appsrc_audio = appsrc_policy.create_appsrc("audiosrc", &audio_caps);
 
             let audioconvert = gstreamer::ElementFactory::make("audioconvert").build().ok()?;
-            let audiorate = gstreamer::ElementFactory::make("audiorate").build().ok()?;
-            let avenc_aac = gstreamer::ElementFactory::make("avenc_aac").build().ok()?;
-            let pay_aud = gstreamer::ElementFactory::make("rtpmp4apay")
-                .property("name", "pay1") // MUST be "pay1"
-                .property("pt", 97_u32)
+            // Idle until a sting plays, so it mustn't hold up the program audio
+            let audiomixer = gstreamer::ElementFactory::make("audiomixer")
+                .name(ElementRole::AudioMixer.element_name())
+                .property("ignore-inactive-pads", true)
                 .build()
                 .ok()?;
+            let audiorate = gstreamer::ElementFactory::make("audiorate").build().ok()?;
 
             // --- 3. Add to Bin and Link ---
             bin.add_many([
                 // Video elements
                 appsrc_video.upcast_ref(),
                 &videoconvert,
+                &data_overlays,
                 &videorate,
                 // &timestamper,
-                &x264enc,
-                &pay_vid,
                 // Audio elements
                 appsrc_audio.upcast_ref(),
                 &audioconvert,
+                &audiomixer,
                 &audiorate,
-                &avenc_aac,
-                &pay_aud,
             ])
             .ok()?;
 
             // Link video branch
+            gstreamer::Element::link_many([appsrc_video.upcast_ref(), &videoconvert]).ok()?;
+            // Transitions go under the data overlays, which stay put from one item to the next
+            let transition = match options.transition {
+                Some(transition) => Some(
+                    TransitionInput::insert(
+                        &bin,
+                        transition,
+                        &video_caps,
+                        appsrc_policy,
+                        &videoconvert,
+                        &data_overlays,
+                    )
+                    .ok()?,
+                ),
+                None => {
+                    videoconvert.link(&data_overlays).ok()?;
+                    None
+                }
+            };
             gstreamer::Element::link_many([
-                appsrc_video.upcast_ref(),
-                &videoconvert,
+                &data_overlays,
                 &videorate,
                 // &timestamper,
-                &x264enc,
-                &pay_vid,
             ])
             .ok()?;
 
+            elements.register(ElementRole::DataOverlays, &data_overlays);
+            elements.register(ElementRole::AudioMixer, &audiomixer);
+
             // Link audio branch
-            gstreamer::Element::link_many([
-                appsrc_audio.upcast_ref(),
-                &audioconvert,
-                &audiorate,
-                &avenc_aac,
-                &pay_aud,
-            ])
-            .ok()?;
+            gstreamer::Element::link_many([appsrc_audio.upcast_ref(), &audioconvert]).ok()?;
+            let program_pad = audiomixer.request_pad_simple("sink_%u")?;
+            audioconvert.static_pad("src")?.link(&program_pad).ok()?;
+            audiomixer.link(&audiorate).ok()?;
+
+            let sting = if options.sting.is_some() {
+                let appsrc_sting = appsrc_policy.create_appsrc("stingsrc", &audio_caps);
+                bin.add(&appsrc_sting).ok()?;
+                appsrc_sting.link(&audiomixer).ok()?;
+                Some(StingInput { appsrc: appsrc_sting, program_pad })
+            } else {
+                None
+            };
+
+            // --- 4. Secondary Audio Branch ---
+            // Players pick the first audio track by default, so this one stays optional
+            let appsrc_audio2 = match options.secondary_audio {
+                SecondaryAudio::Disabled => None,
+                SecondaryAudio::SecondTrack => {
+                    let appsrc_audio2 = appsrc_policy.create_appsrc("audiosrc2", &audio_caps);
+
+                    let audioconvert2 =
+                        gstreamer::ElementFactory::make("audioconvert").build().ok()?;
+                    let audiorate2 = gstreamer::ElementFactory::make("audiorate").build().ok()?;
+
+                    let elements = [appsrc_audio2.upcast_ref(), &audioconvert2, &audiorate2];
+                    bin.add_many(elements).ok()?;
+                    gstreamer::Element::link_many(elements).ok()?;
+                    Some((appsrc_audio2, audiorate2))
+                }
+            };
+
+            // --- 5. MJPEG, Rendition and WHEP Branches ---
+            // Split off before encoding, they make their own JPEGs and encodings from the raw
+            // program
+            let mjpeg = self.mjpeg.lock();
+            let renditions = self.renditions.lock();
+            let whep = options.whep;
+            let split = |src: &gstreamer::Element| -> Option<_> {
+                let tee = gstreamer::ElementFactory::make("tee").build().ok()?;
+                let queue = gstreamer::ElementFactory::make("queue").build().ok()?;
+                bin.add_many([&tee, &queue]).ok()?;
+                gstreamer::Element::link_many([src, &tee, &queue]).ok()?;
+                Some((tee, queue))
+            };
+            let branches = mjpeg.is_some() || !renditions.is_empty() || whep.is_some();
+            let (video_output, audio_output) = if branches {
+                let (video_tee, video_queue) = split(&videorate)?;
+                if let Some(mjpeg) = &*mjpeg {
+                    mjpeg.link_branch(&bin, &video_tee).ok()?;
+                }
+                if renditions.is_empty() && whep.is_none() {
+                    (video_queue, audiorate)
+                } else {
+                    let (audio_tee, audio_queue) = split(&audiorate)?;
+                    for rendition in renditions.iter() {
+                        rendition.link_taps(&bin, &video_tee, &audio_tee).ok()?;
+                    }
+                    if let Some(whep) = whep {
+                        link_whep_branch(&bin, whep, &video_tee, &audio_tee).ok()?;
+                    }
+                    (video_queue, audio_queue)
+                }
+            } else {
+                (videorate, audiorate)
+            };
+
+            // --- 6. Encoding and Payloading ---
+            // The secondary program goes on the mount as well, mediamtx only gets it from there
+            let mut audio_outputs = vec![&audio_output];
+            audio_outputs.extend(appsrc_audio2.as_ref().map(|(_, audiorate2)| audiorate2));
+            let encoder =
+                link_output_branch(&bin, &options, &video_output, &audio_outputs, &elements)
+                    .ok()?;
 
             // Save the appsrc to the shared storage so the feeder thread can find it
-            *storage.lock() = Some(AppSources { video: appsrc_video, audio: appsrc_audio });
+            storage.set(AppSources {
+                video: appsrc_video,
+                audio: appsrc_audio,
+                audio2: appsrc_audio2.map(|(appsrc_audio2, _)| appsrc_audio2),
+                sting,
+                transition,
+                encoder,
+                elements,
+            });
             println!("RTSP pipeline built.");
             Some(bin.upcast())
         }
@@ -164,10 +394,20 @@ glib::wrapper! {
 
 // Public constructor
 impl MyMediaFactory {
-    pub fn new(storage: AppSrcStorage) -> Self {
+    pub fn new(
+        storage: AppSrcStorage,
+        options: &super::StreamOptions,
+        data_overlays: super::DataOverlays,
+        mjpeg: Option<super::MjpegFeed>,
+        renditions: Vec<super::Rendition>,
+    ) -> Self {
         let factory: Self = glib::Object::new();
         // Store the AppSrcStorage handle in our factory's implementation struct
         *factory.imp().storage.lock() = Some(storage);
+        *factory.imp().options.lock() = options.clone();
+        *factory.imp().data_overlays.lock() = data_overlays;
+        *factory.imp().mjpeg.lock() = mjpeg;
+        *factory.imp().renditions.lock() = renditions;
         factory
     }
 }
@@ -0,0 +1,98 @@
+use std::sync::Arc;
+
+use gstreamer::prelude::*;
+
+use super::Error;
+
+/// A low framerate MJPEG copy of the program, for devices and dashboards that can't do anything
+/// better. Taken from the raw video before it's encoded.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct MjpegOptions {
+    pub width: u32,
+    pub height: u32,
+    pub framerate: u32,
+    /// JPEG quality, 0-100.
+    pub quality: u8,
+}
+
+impl Default for MjpegOptions {
+    fn default() -> Self {
+        Self { width: 640, height: 360, framerate: 2, quality: 70 }
+    }
+}
+
+/// The latest JPEG of the program, handed from the output pipeline to whoever is watching.
+#[derive(Debug, Clone)]
+pub struct MjpegFeed {
+    options: MjpegOptions,
+    frames: Arc<tokio::sync::watch::Sender<Option<Arc<[u8]>>>>,
+}
+
+impl MjpegFeed {
+    pub fn new(options: MjpegOptions) -> Self {
+        let (frames, _) = tokio::sync::watch::channel(None);
+        Self { options, frames: Arc::new(frames) }
+    }
+
+    /// Sees every new frame, starting with the latest one (if there is one yet).
+    pub fn subscribe(&self) -> tokio::sync::watch::Receiver<Option<Arc<[u8]>>> {
+        let mut frames = self.frames.subscribe();
+        frames.mark_changed();
+        frames
+    }
+
+    /// Adds the elements that make the JPEGs to `bin`, fed from `tee`.
+    pub(super) fn link_branch(
+        &self,
+        bin: &gstreamer::Bin,
+        tee: &gstreamer::Element,
+    ) -> Result<(), Error> {
+        // Never hold up the real output, old frames are worthless anyway
+        let queue = gstreamer::ElementFactory::make("queue")
+            .property_from_str("leaky", "downstream")
+            .property("max-size-buffers", 1u32)
+            .build()?;
+        let videorate = gstreamer::ElementFactory::make("videorate")
+            .property("drop-only", true)
+            .build()?;
+        let videoscale = gstreamer::ElementFactory::make("videoscale")
+            .property("add-borders", true)
+            .build()?;
+        let capsfilter = gstreamer::ElementFactory::make("capsfilter")
+            .property(
+                "caps",
+                gstreamer::Caps::builder("video/x-raw")
+                    .field("width", self.options.width as i32)
+                    .field("height", self.options.height as i32)
+                    .field("pixel-aspect-ratio", gstreamer::Fraction::new(1, 1))
+                    .field("framerate", gstreamer::Fraction::new(self.options.framerate as i32, 1))
+                    .build(),
+            )
+            .build()?;
+        let jpegenc = gstreamer::ElementFactory::make("jpegenc")
+            .property("quality", i32::from(self.options.quality.min(100)))
+            .build()?;
+        let appsink = gstreamer_app::AppSink::builder().name("appsink_mjpeg").sync(false).build();
+
+        let elements =
+            [&queue, &videorate, &videoscale, &capsfilter, &jpegenc, appsink.upcast_ref()];
+        bin.add_many(elements)?;
+        gstreamer::Element::link_many([tee].into_iter().chain(elements))?;
+
+        let frames = self.frames.clone();
+        appsink.set_callbacks(
+            gstreamer_app::AppSinkCallbacks::builder()
+                .new_sample(move |sink| {
+                    let sample = sink.pull_sample().map_err(|_| gstreamer::FlowError::Eos)?;
+                    let Some(buffer) = sample.buffer() else {
+                        return Ok(gstreamer::FlowSuccess::Ok);
+                    };
+                    let map = buffer.map_readable().map_err(|_| gstreamer::FlowError::Error)?;
+                    frames.send_replace(Some(Arc::from(map.as_slice())));
+                    Ok(gstreamer::FlowSuccess::Ok)
+                })
+                .build(),
+        );
+        Ok(())
+    }
+}
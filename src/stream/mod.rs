@@ -1,13 +1,75 @@
+mod album_art;
+mod animation;
+mod approval;
+mod content_filter;
+mod discovery;
 mod encoder;
 mod feeder;
+mod freeze;
+mod gain;
+mod item_overrides;
+mod ken_burns;
+mod live;
+mod loudness;
 mod media_factory;
+mod mjpeg;
+mod monitor;
+mod now_playing;
+mod output;
+mod overlay;
+mod peers;
+mod pool;
+mod probes;
+mod quarantine;
+mod ratings;
+mod registry;
+mod renditions;
+mod selection;
+mod slate;
+mod sting;
+mod subtitles;
+mod transition;
+mod visualizer;
+mod whep;
 
 use std::path::PathBuf;
+use std::str::FromStr;
 
 use gstreamer_rtsp_server::prelude::{RTSPMediaFactoryExt, RTSPMountPointsExt, RTSPServerExt};
 
+pub use self::approval::*;
+pub use self::content_filter::*;
+pub use self::discovery::*;
+pub use self::encoder::{EncoderOptions, EncoderProperty};
 pub use self::feeder::*;
+pub use self::freeze::*;
+pub use self::gain::*;
+pub use self::item_overrides::*;
+pub use self::ken_burns::*;
+pub use self::live::*;
+pub use self::loudness::*;
 pub use self::media_factory::*;
+pub use self::mjpeg::*;
+pub use self::monitor::*;
+pub use self::now_playing::*;
+pub use self::output::*;
+pub use self::overlay::*;
+pub use self::peers::*;
+pub use self::probes::*;
+pub use self::quarantine::*;
+pub use self::ratings::*;
+pub use self::registry::*;
+pub use self::renditions::*;
+pub use self::slate::*;
+pub use self::sting::*;
+pub use self::subtitles::*;
+pub use self::transition::*;
+pub use self::visualizer::*;
+pub use self::whep::WhepOptions;
+use crate::leader::RemoteCandidates;
+use crate::media_cache::MediaInfoCache;
+use crate::media_type::MediaType;
+use crate::random_files::{Cooldown, FileFilter, LibraryRoots, RandomFiles, ShuffledFiles};
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -19,39 +81,815 @@ pub enum Error {
 
     #[error("GStreamer state change error: {0}")]
     GstStateChange(#[from] gstreamer::StateChangeError),
+
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid video options: {0}")]
+    InvalidVideoOptions(String),
+
+    #[error("Invalid audio options: {0}")]
+    InvalidAudioOptions(String),
+
+    #[error("Invalid file pattern: {0}")]
+    InvalidPattern(#[from] globset::Error),
+
+    #[error("Database error: {0}")]
+    Database(#[from] rusqlite::Error),
+
+    #[error("Failed to switch encoders: {0}")]
+    EncoderSwitch(&'static str),
+
+    #[error("None of the video encoders could be created")]
+    NoVideoEncoder,
+}
+
+#[derive(Default, Debug, Clone, Eq, PartialEq, Hash)]
+pub struct StreamOptions {
+    pub video: VideoOptions,
+    pub audio: AudioOptions,
+    pub output_profile: OutputProfile,
+    /// How the output's appsrcs timestamp what the inputs push.
+    pub appsrc: AppSrcPolicy,
+    /// Which H.264 encoders are tried and how they're set up, for [`OutputProfile::H264Aac`].
+    pub encoders: EncoderOptions,
+    pub secondary_audio: SecondaryAudio,
+    pub deinterlace: DeinterlaceMode,
+    /// How files that don't have the output's aspect ratio are fitted to it.
+    pub aspect: AspectPolicy,
+    /// Preferred audio languages as ISO 639 codes, most preferred first.
+    pub audio_languages: Vec<String>,
+    pub play_duration: PlayDurationPolicy,
+    pub prepare: PreparePolicy,
+    /// Control API URLs of other instances sharing the library, whose files are avoided.
+    pub peers: Vec<String>,
+    /// Files any channel sharing the history database started within this long are avoided, see
+    /// [`History`](crate::history::History).
+    pub shared_repeat_window: Option<std::time::Duration>,
+    /// Get files from a leader process instead of scanning the root directories here.
+    pub leader: Option<String>,
+    pub files: FileFilter,
+    /// Weights of root directories, see [`RandomFiles::with_weights`].
+    pub root_weights: Vec<(PathBuf, u32)>,
+    /// Keeps recently picked files from coming up again, see [`RandomFiles::with_cooldown`].
+    pub cooldown: Option<Cooldown>,
+    pub shuffle: Shuffle,
+    /// A live input that takes over from the files while something is connected to it.
+    pub live_input: Option<LiveInputOptions>,
+    /// Only play picked files once they've been approved, see [`Approvals`].
+    pub require_approval: bool,
+    /// Lets this decide which files may air, see [`ContentFilter`].
+    pub classifier: Option<ContentClassifier>,
+    /// Which files may air at what time of day.
+    pub ratings: RatingPolicy,
+    pub slates: SlateOptions,
+    pub data_overlays: Vec<DataOverlayOptions>,
+    /// Where to show when and where photos were taken, if at all.
+    pub photo_info: Option<OverlaySlot>,
+    /// What the title overlay shows, see [`TitleTemplate`]. Can be changed while running.
+    pub title_template: Option<String>,
+    /// Zoom and pan slowly over photos, rather than showing them still.
+    pub ken_burns: Option<KenBurnsOptions>,
+    /// Shown as the video of audio-only files, instead of their cover art.
+    pub visualizer: Option<Visualizer>,
+    /// Burns a subtitle track into the picture, if set and the file has one.
+    pub subtitles: Option<SubtitleOptions>,
+    /// Normalizes the loudness of the files' audio, if set.
+    pub loudness: Option<LoudnessOptions>,
+    /// Played over the start of every item.
+    pub sting: Option<StingOptions>,
+    /// How the picture changes between items, if it doesn't just cut.
+    pub transition: Option<TransitionOptions>,
+    /// Also make an MJPEG copy of the program, see [`MjpegFeed`].
+    pub mjpeg: Option<MjpegOptions>,
+    /// Lower resolution copies of the output at mounts of their own, see [`Rendition`].
+    pub renditions: Vec<RenditionOptions>,
+    /// Play the output over RTSP to check it's healthy, see [`start_output_monitor`].
+    pub monitor: Option<MonitorOptions>,
+    /// Also serve the program over WHEP, see [`WhepOptions`].
+    pub whep: Option<WhepOptions>,
+}
+
+/// Limits on getting a file ready to play, so slow (e.g. network) files can't stall the stream.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct PreparePolicy {
+    pub discovery_timeout: gstreamer::ClockTime,
+    /// Total time allowed for discovery and preroll, after which the file is quarantined.
+    pub budget: gstreamer::ClockTime,
+}
+
+impl Default for PreparePolicy {
+    fn default() -> Self {
+        Self {
+            discovery_timeout: crate::media_info::DEFAULT_DISCOVERY_TIMEOUT,
+            budget: 15 * gstreamer::ClockTime::SECOND,
+        }
+    }
+}
+
+/// How long items without a (known) end are played for.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct PlayDurationPolicy {
+    /// How long images are shown, unless they have a duration of their own.
+    pub image_hold: gstreamer::ClockTime,
+    /// Cut-off for videos and audio whose duration isn't known (e.g. live sources).
+    /// `None` plays them until they end by themselves.
+    pub unknown_max: Option<gstreamer::ClockTime>,
+}
+
+impl Default for PlayDurationPolicy {
+    fn default() -> Self {
+        Self { image_hold: 5 * gstreamer::ClockTime::SECOND, unknown_max: None }
+    }
+}
+
+impl PlayDurationPolicy {
+    /// The running time after which an item should be ended, if it won't end by itself.
+    pub fn play_limit(
+        &self,
+        media_type: MediaType,
+        duration: Option<gstreamer::ClockTime>,
+    ) -> Option<gstreamer::ClockTime> {
+        match media_type {
+            MediaType::Image => Some(duration.unwrap_or(self.image_hold)),
+            _ if duration.is_none() => self.unknown_max,
+            _ => None,
+        }
+    }
+}
+
+/// Geometry of the output canvas, and the bitrate it's encoded at. Every input is scaled (with
+/// borders) to fit this.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct VideoOptions {
+    pub width: u32,
+    pub height: u32,
+    pub framerate: u32,
+    /// Target bitrate of the encoder, in kbit/s.
+    pub bitrate_kbps: u32,
+    pub rate_control: RateControl,
+    pub keyframe_interval: KeyframeInterval,
+}
+
+impl Default for VideoOptions {
+    fn default() -> Self {
+        Self {
+            width: 1280,
+            height: 720,
+            framerate: 30,
+            bitrate_kbps: 6000,
+            rate_control: RateControl::default(),
+            keyframe_interval: KeyframeInterval::default(),
+        }
+    }
+}
+
+impl VideoOptions {
+    /// I420 needs even dimensions, and H.264 can't go beyond level 6.2.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.width == 0 || self.height == 0 || self.framerate == 0 || self.bitrate_kbps == 0 {
+            return Err(Error::InvalidVideoOptions(format!("{self} has a zero component")));
+        }
+        if self.width % 2 != 0 || self.height % 2 != 0 {
+            return Err(Error::InvalidVideoOptions(format!("{self} must have even dimensions")));
+        }
+        if self.h264_level().is_none() {
+            return Err(Error::InvalidVideoOptions(format!("{self} exceeds H.264 level 6.2")));
+        }
+        if let RateControl::Cqp { qp: quality } | RateControl::Crf { quality } = self.rate_control
+            && quality > 51
+        {
+            return Err(Error::InvalidVideoOptions(format!(
+                "{} is beyond H.264's quantizer range of 0-51",
+                self.rate_control
+            )));
+        }
+        if self.keyframe_frames() == 0 {
+            return Err(Error::InvalidVideoOptions(format!(
+                "A keyframe interval of {} is less than a frame at {} fps",
+                self.keyframe_interval, self.framerate
+            )));
+        }
+        Ok(())
+    }
+
+    /// Frames from one keyframe to the next.
+    pub fn keyframe_frames(&self) -> u32 {
+        match self.keyframe_interval {
+            KeyframeInterval::Time(interval) => {
+                (interval.as_secs_f64() * f64::from(self.framerate)).round() as u32
+            }
+            KeyframeInterval::Frames(frames) => frames,
+        }
+    }
+
+    /// The lowest H.264 level that can carry this geometry and framerate.
+    pub fn h264_level(&self) -> Option<&'static str> {
+        // (level, max macroblocks per frame, max macroblocks per second)
+        const LEVELS: &[(&str, u64, u64)] = &[
+            ("3", 1620, 40500),
+            ("3.1", 3600, 108000),
+            ("3.2", 5120, 216000),
+            ("4", 8192, 245760),
+            ("4.2", 8704, 522240),
+            ("5", 22080, 589824),
+            ("5.1", 36864, 983040),
+            ("5.2", 36864, 2073600),
+            ("6", 139264, 4177920),
+            ("6.1", 139264, 8355840),
+            ("6.2", 139264, 16711680),
+        ];
+
+        let mb_width = u64::from(self.width).div_ceil(16);
+        let mb_height = u64::from(self.height).div_ceil(16);
+        let frame_size = mb_width * mb_height;
+        let rate = frame_size * u64::from(self.framerate);
+
+        // Each dimension is also limited to sqrt(8 * max frame size) macroblocks.
+        LEVELS
+            .iter()
+            .find(|(_, max_frame_size, max_rate)| {
+                let max_dimension = (8 * max_frame_size).isqrt();
+                frame_size <= *max_frame_size
+                    && rate <= *max_rate
+                    && mb_width <= max_dimension
+                    && mb_height <= max_dimension
+            })
+            .map(|(level, _, _)| *level)
+    }
+
+    pub fn framerate(&self) -> gstreamer::Fraction {
+        gstreamer::Fraction::new(self.framerate as i32, 1)
+    }
+}
+
+/// How the H.264 encoder spends bits. Quality based modes ignore the bitrate, or only use it as a
+/// cap where the encoder allows for one.
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum RateControl {
+    /// Constant bitrate, what live viewers and restreamers cope with best.
+    #[default]
+    Cbr,
+    /// Averages out at the bitrate, spending more on complex scenes.
+    Vbr,
+    /// The same quantizer for every frame.
+    Cqp { qp: u8 },
+    /// Constant quality (CRF in x264), for archival outputs.
+    Crf { quality: u8 },
+}
+
+/// How far apart the encoder puts keyframes. Every encoder gets a fixed GOP of this length, so
+/// HLS and DASH segmenters downstream (and the renditions) cut at the same points.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum KeyframeInterval {
+    Time(std::time::Duration),
+    Frames(u32),
+}
+
+impl Default for KeyframeInterval {
+    fn default() -> Self {
+        Self::Time(std::time::Duration::from_secs(2))
+    }
+}
+
+/// Parses `SECONDSs` or `FRAMESf`, e.g. `2s`, `0.5s` or `60f`.
+impl FromStr for KeyframeInterval {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid keyframe interval {s:?}, expected e.g. 2s or 60f");
+        if let Some(secs) = s.strip_suffix('s') {
+            let secs: f64 = secs.parse().map_err(|_| invalid())?;
+            let interval = std::time::Duration::try_from_secs_f64(secs).map_err(|_| invalid())?;
+            Ok(Self::Time(interval))
+        } else if let Some(frames) = s.strip_suffix('f') {
+            Ok(Self::Frames(frames.parse().map_err(|_| invalid())?))
+        } else {
+            Err(invalid())
+        }
+    }
+}
+
+impl std::fmt::Display for KeyframeInterval {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Time(interval) => write!(f, "{}s", interval.as_secs_f64()),
+            Self::Frames(frames) => write!(f, "{frames}f"),
+        }
+    }
+}
+
+/// Parses `cbr`, `vbr`, `cqp:QP` or `crf:QUALITY`, e.g. `crf:23`.
+impl FromStr for RateControl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid rate control {s:?}, expected cbr, vbr, cqp:QP or crf:N");
+        let (mode, value) = match s.split_once(':') {
+            Some((mode, value)) => (mode, Some(value.parse::<u8>().map_err(|_| invalid())?)),
+            None => (s, None),
+        };
+        match (mode, value) {
+            ("cbr", None) => Ok(Self::Cbr),
+            ("vbr", None) => Ok(Self::Vbr),
+            ("cqp", Some(qp)) => Ok(Self::Cqp { qp }),
+            ("crf", Some(quality)) => Ok(Self::Crf { quality }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl std::fmt::Display for RateControl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Cbr => f.write_str("cbr"),
+            Self::Vbr => f.write_str("vbr"),
+            Self::Cqp { qp } => write!(f, "cqp:{qp}"),
+            Self::Crf { quality } => write!(f, "crf:{quality}"),
+        }
+    }
+}
+
+/// How files are picked from the root directories.
+#[derive(Default, Debug, Clone, Eq, PartialEq, Hash)]
+pub enum Shuffle {
+    /// Every pick is independent, so a file can come up again straight away.
+    #[default]
+    Random,
+    /// Every file once before any of them repeats, see [`ShuffledFiles`]. Root weights don't
+    /// apply.
+    NoRepeat {
+        /// Remembers the round across restarts.
+        state_db: Option<PathBuf>,
+    },
+}
+
+/// Where the feeder gets the next file from.
+pub type FileSource = Box<dyn Iterator<Item = PathBuf> + Send>;
+
+/// Where the secondary audio program comes from.
+/// It is carried as a second audio track after the primary one, so players that only handle a
+/// single track keep playing the primary.
+///
+/// The track is on the RTSP mount too. mediamtx makes every output (SRT's transport stream, HLS
+/// and its own RTSP) from a pull of that mount, so anything missing from it can't reach them, and
+/// RTSP clients see both tracks.
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum SecondaryAudio {
+    #[default]
+    Disabled,
+    /// The second audio stream of each file (e.g. commentary), or silence if it has none.
+    SecondTrack,
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+/// How a picture with a different aspect ratio than the output is fitted to it.
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum AspectPolicy {
+    /// All of it, with black borders filling the rest.
+    #[default]
+    Letterbox,
+    /// Fill the output, cutting off the edges (around the centre) that don't fit.
+    Crop,
+    /// Fill the output by squashing or stretching the picture.
+    Stretch,
+}
+
+impl FromStr for AspectPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "letterbox" => Ok(Self::Letterbox),
+            "crop" => Ok(Self::Crop),
+            "stretch" => Ok(Self::Stretch),
+            _ => Err(format!("Unknown aspect policy {s:?}, expected letterbox, crop or stretch")),
+        }
+    }
+}
+
+impl std::fmt::Display for AspectPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Letterbox => "letterbox",
+            Self::Crop => "crop",
+            Self::Stretch => "stretch",
+        })
+    }
+}
+
+/// When video files are deinterlaced.
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum DeinterlaceMode {
+    /// Files discovery finds to be interlaced, and only their interlaced frames.
+    #[default]
+    Auto,
+    /// Every video file, every frame, for interlaced files that claim to be progressive.
+    Always,
+    Never,
+}
+
+impl FromStr for DeinterlaceMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "always" => Ok(Self::Always),
+            "never" => Ok(Self::Never),
+            _ => Err(format!("Unknown deinterlace mode {s:?}, expected auto, always or never")),
+        }
+    }
+}
+
+impl std::fmt::Display for DeinterlaceMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Auto => "auto",
+            Self::Always => "always",
+            Self::Never => "never",
+        })
+    }
+}
+
+impl std::fmt::Display for VideoOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{}@{}", self.width, self.height, self.framerate)
+    }
+}
+
+/// Parses `WIDTHxHEIGHT` or `WIDTHxHEIGHT@FPS`, e.g. `1080x1080` or `1920x360@25`, with the
+/// default bitrate.
+impl FromStr for VideoOptions {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidVideoOptions(s.to_string());
+
+        let (size, framerate) = match s.split_once('@') {
+            Some((size, framerate)) => (size, framerate.parse().map_err(|_| invalid())?),
+            None => (s, VideoOptions::default().framerate),
+        };
+        let (width, height) = size.split_once(['x', 'X']).ok_or_else(invalid)?;
+        let width = width.parse().map_err(|_| invalid())?;
+        let height = height.parse().map_err(|_| invalid())?;
+
+        let options = Self { width, height, framerate, ..Self::default() };
+        options.validate()?;
+        Ok(options)
+    }
+}
+
+/// Format of the program audio, and the bitrate it's encoded at. Every input is converted and
+/// resampled to this.
+///
+/// Samples are always S16LE, [`Freeze`] relies on silence being all zeroes.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct AudioOptions {
+    pub sample_rate: u32,
+    pub channels: u32,
+    /// Target bitrate of the encoder, in kbit/s.
+    pub bitrate_kbps: u32,
+    /// Hand audio to the output in chunks of this many milliseconds, rather than in whatever
+    /// (often tiny) buffers the inputs decode to.
+    pub chunk_ms: Option<u32>,
+}
+
+impl Default for AudioOptions {
+    fn default() -> Self {
+        Self { sample_rate: 48000, channels: 2, bitrate_kbps: 128, chunk_ms: None }
+    }
+}
+
+impl AudioOptions {
+    /// AAC (and the mixer) only go up to 8 channels.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.sample_rate == 0 || self.channels == 0 || self.bitrate_kbps == 0 {
+            return Err(Error::InvalidAudioOptions(format!("{self} has a zero component")));
+        }
+        if self.channels > 8 {
+            return Err(Error::InvalidAudioOptions(format!("{self} has more than 8 channels")));
+        }
+        if let Some(chunk_ms) = self.chunk_ms
+            && !(1..=1000).contains(&chunk_ms)
+        {
+            return Err(Error::InvalidAudioOptions(format!(
+                "Chunks of {chunk_ms} ms, expected 1-1000 ms"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Raw audio in this format, as every audio appsrc and appsink carries it.
+    pub fn caps(&self) -> gstreamer::Caps {
+        gstreamer::Caps::builder("audio/x-raw")
+            .field("format", "S16LE")
+            .field("layout", "interleaved")
+            .field("rate", self.sample_rate as i32)
+            .field("channels", self.channels as i32)
+            .build()
+    }
+
+    /// Collects audio into chunks before it's pushed to the output, if `chunk_ms` is set. Goes
+    /// right in front of the appsink.
+    pub(crate) fn create_batcher(&self) -> Result<Option<gstreamer::Element>, glib::BoolError> {
+        let Some(chunk_ms) = self.chunk_ms else { return Ok(None) };
+        gstreamer::ElementFactory::make("audiobuffersplit")
+            .property("output-buffer-duration", gstreamer::Fraction::new(chunk_ms as i32, 1000))
+            .build()
+            .map(Some)
+    }
+}
+
+impl std::fmt::Display for AudioOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} Hz, {} channels", self.sample_rate, self.channels)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Command {
     Skip,
+    /// Skips on the first keyframe after `delay` more of the current file has played, for a less
+    /// abrupt cut than `Skip`.
+    SoftSkip {
+        delay: std::time::Duration,
+    },
+    /// Sets (or clears, if `gain_db` is `None`) the gain override for a file.
+    SetGain {
+        path: PathBuf,
+        gain_db: Option<f64>,
+    },
+    /// Plays this file after the current one, instead of whatever was queued.
+    PlayNext {
+        path: PathBuf,
+    },
+    /// Adds this file to the end of the queue, it plays before any more random files.
+    Enqueue {
+        path: PathBuf,
+    },
+    /// Holds the video on the current frame, optionally muting the audio, until `Unfreeze`.
+    Freeze {
+        mute_audio: bool,
+    },
+    Unfreeze,
+    /// Forgets the cached media info of `path`, or of everything if `None`.
+    InvalidateMediaCache {
+        path: Option<PathBuf>,
+    },
+    /// Approves or rejects a file that's waiting for approval.
+    Review {
+        path: PathBuf,
+        approved: bool,
+    },
+    /// Cuts to a slate, which stays up until `Resume`.
+    Hold {
+        slate: SlateKind,
+    },
+    Resume,
+    /// Re-encodes the output at a different size and/or bitrate, see [`EncoderSwitch`]. `None`
+    /// keeps what's in use now.
+    SetVideoProfile {
+        width: Option<u32>,
+        height: Option<u32>,
+        bitrate_kbps: Option<u32>,
+    },
+    /// Changes what the title overlay shows, see [`TitleTemplate`]. `None` goes back to the
+    /// default.
+    SetTitleTemplate {
+        template: Option<String>,
+    },
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+/// What's happening on the stream.
+///
+/// The status, health check and history pair events up (e.g. `SlateStarted` with `SlateEnded`),
+/// so they're sent with a blocking `send` even though the channel is bounded. Only `Switched` and
+/// `DownloadProgress`, which nothing keeps state from, are dropped when it's full.
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, serde::Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum Event {
-    Playing { path: PathBuf },
-    Ended { path: PathBuf },
+    /// The next file has been picked, and will play after the current one.
+    Queued {
+        #[serde(serialize_with = "crate::paths::serialize_lossy")]
+        path: PathBuf,
+    },
+    /// A queued file was replaced before it got to play.
+    Dequeued {
+        #[serde(serialize_with = "crate::paths::serialize_lossy")]
+        path: PathBuf,
+    },
+    Playing {
+        #[serde(serialize_with = "crate::paths::serialize_lossy")]
+        path: PathBuf,
+        media_type: MediaType,
+        #[serde(rename = "duration_secs", serialize_with = "serialize_secs")]
+        duration: Option<gstreamer::ClockTime>,
+    },
+    Ended {
+        #[serde(serialize_with = "crate::paths::serialize_lossy")]
+        path: PathBuf,
+        media_type: MediaType,
+        reason: EndReason,
+    },
+    /// The file was picked, but its pipeline couldn't be made ready to play.
+    PrerollFailed {
+        #[serde(serialize_with = "crate::paths::serialize_lossy")]
+        path: PathBuf,
+        reason: String,
+    },
+    /// The file won't be picked again.
+    Quarantined {
+        #[serde(serialize_with = "crate::paths::serialize_lossy")]
+        path: PathBuf,
+        reason: String,
+    },
+    /// A live input has taken over, files resume after `LiveEnded`.
+    LiveStarted {
+        source: String,
+    },
+    LiveEnded {
+        source: String,
+    },
+    /// The file was picked, but has to be approved before it can play.
+    AwaitingApproval {
+        #[serde(serialize_with = "crate::paths::serialize_lossy")]
+        path: PathBuf,
+    },
+    Reviewed {
+        #[serde(serialize_with = "crate::paths::serialize_lossy")]
+        path: PathBuf,
+        approved: bool,
+    },
+    /// A slate is being shown instead of files, until `SlateEnded`.
+    SlateStarted {
+        slate: SlateKind,
+    },
+    SlateEnded {
+        slate: SlateKind,
+    },
+    /// The video is held on the current frame until `Unfrozen`.
+    Frozen {
+        mute_audio: bool,
+    },
+    Unfrozen,
+    /// The first frame of the item that's playing now reached the output, this long after the
+    /// previous one ended.
+    Switched {
+        latency_ms: u64,
+    },
+    /// The monitor stopped getting video or audio from the output, until `OutputHealthy`.
+    OutputUnhealthy {
+        reason: String,
+    },
+    OutputHealthy,
+    /// What the output's encoder makes of the video, sent once the output is up and whenever it
+    /// changes. Not sent for outputs encoded elsewhere.
+    EncodingChanged {
+        width: u32,
+        height: u32,
+        bitrate_kbps: u32,
+    },
+    /// A web video is being downloaded, see [`Downloader`](crate::download::Downloader).
+    DownloadProgress {
+        url: String,
+        percent: u8,
+    },
+    /// The download finished, and the file is queued to play next.
+    Downloaded {
+        url: String,
+        #[serde(serialize_with = "crate::paths::serialize_lossy")]
+        path: PathBuf,
+    },
+    DownloadFailed {
+        url: String,
+        reason: String,
+    },
+    /// Less free space than the disk monitor warns at, until `DiskSpaceOk`.
+    DiskSpaceLow {
+        #[serde(serialize_with = "crate::paths::serialize_lossy")]
+        dir: PathBuf,
+        free_bytes: u64,
+    },
+    DiskSpaceOk {
+        #[serde(serialize_with = "crate::paths::serialize_lossy")]
+        dir: PathBuf,
+    },
+    /// A root directory can't be read, e.g. because it's an unmounted network share, and isn't
+    /// picked from until `RootAvailable`.
+    RootUnavailable {
+        #[serde(serialize_with = "crate::paths::serialize_lossy")]
+        root: PathBuf,
+        reason: String,
+    },
+    RootAvailable {
+        #[serde(serialize_with = "crate::paths::serialize_lossy")]
+        root: PathBuf,
+    },
 }
 
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EndReason {
+    /// Reached the end, or the play limit.
+    Finished,
+    Skipped,
+    /// Cut off by a live input taking over, or the output being built again.
+    Interrupted,
+    Error(String),
+}
+
+fn serialize_secs<S: serde::Serializer>(
+    time: &Option<gstreamer::ClockTime>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serde::Serialize::serialize(&time.map(|time| time.seconds_f64()), serializer)
+}
+
+/// State the stream shares with the rest of the server, e.g. the API.
+#[derive(Debug, Clone, Default)]
+pub struct StreamServices {
+    pub media_cache: Option<MediaInfoCache>,
+    pub quarantine: Quarantine,
+    pub mjpeg: Option<MjpegFeed>,
+    /// Consulted for [`StreamOptions::shared_repeat_window`].
+    pub history: Option<crate::history::History>,
+}
+
+/// Serves the stream at every path in `stream_keys` (the stream key and its aliases).
 pub fn create_server(
-    root_dirs: Vec<PathBuf>,
+    roots: LibraryRoots,
     command_rx: flume::Receiver<Command>,
     event_tx: flume::Sender<Event>,
     rtsp_port: u16,
-    stream_key: &str,
+    stream_keys: &[String],
+    options: StreamOptions,
+    services: StreamServices,
 ) -> Result<gstreamer_rtsp_server::RTSPServer, Error> {
+    options.video.validate()?;
+    options.audio.validate()?;
+    for rendition in &options.renditions {
+        rendition.video(options.video).validate()?;
+    }
+    let matcher = options.files.compile()?;
+    let files: FileSource = match (&options.leader, &options.shuffle) {
+        (Some(leader_url), _) => Box::new(RemoteCandidates::new(leader_url)),
+        (None, Shuffle::Random) => {
+            let mut files = RandomFiles::from_roots(roots.clone())
+                .with_matcher(matcher)
+                .with_weights(options.root_weights.clone());
+            if let Some(cooldown) = options.cooldown {
+                files = files.with_cooldown(cooldown);
+            }
+            Box::new(files)
+        }
+        (None, Shuffle::NoRepeat { state_db }) => {
+            Box::new(ShuffledFiles::new(roots.clone(), matcher, state_db.as_deref())?)
+        }
+    };
+
     let appsrc_storage = AppSrcStorage::default();
 
     let server = gstreamer_rtsp_server::RTSPServer::new();
     server.set_service(&rtsp_port.to_string());
 
-    let factory = MyMediaFactory::new(appsrc_storage.clone());
+    let data_overlays = DataOverlays::start(&options.data_overlays);
+    let renditions: Vec<_> = options.renditions.iter().copied().map(Rendition::new).collect();
+    let factory = MyMediaFactory::new(
+        appsrc_storage.clone(),
+        &options,
+        data_overlays,
+        services.mjpeg.clone(),
+        renditions.clone(),
+    );
     factory.set_shared(true);
 
     let mounts = server.mount_points().unwrap();
-    let path = format!("/{stream_key}");
-    mounts.add_factory(&path, factory.clone());
+    // The factory hands out the same media for every path, so aliases share one pipeline
+    for key in stream_keys {
+        mounts.add_factory(&format!("/{key}"), factory.clone());
+    }
+    for rendition in renditions {
+        let suffix = rendition.options().mount_suffix();
+        let factory = RenditionMediaFactory::new(rendition, &options);
+        factory.set_shared(true);
+        for key in stream_keys {
+            mounts.add_factory(&format!("/{key}{suffix}"), factory.clone());
+        }
+    }
+
+    if let Some(stream_key) = stream_keys.first() {
+        let url = format!("rtsp://127.0.0.1:{rtsp_port}/{stream_key}");
+        match (options.monitor, options.whep) {
+            (Some(monitor), _) => start_output_monitor(url, monitor, event_tx.clone()),
+            // The output only runs while someone plays it over RTSP, WHEP viewers don't count
+            (None, Some(_)) => keep_output_running(url),
+            (None, None) => {}
+        }
+    }
 
-    std::thread::spawn(move || file_feeder_task(root_dirs, command_rx, event_tx, appsrc_storage));
+    std::thread::spawn(move || {
+        file_feeder_task(files, command_rx, event_tx, appsrc_storage, options, services, roots)
+    });
 
     Ok(server)
 }
@@ -0,0 +1,189 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use gstreamer::prelude::*;
+use parking_lot::Mutex;
+
+use super::{Error, Event, Probes};
+
+/// How often the monitor looks at what's arriving.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long to wait before connecting (again), also gives the server a moment to start listening.
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct MonitorOptions {
+    /// How long video or audio can stop arriving before the output counts as unhealthy.
+    pub stall_timeout: Duration,
+}
+
+impl Default for MonitorOptions {
+    fn default() -> Self {
+        Self { stall_timeout: Duration::from_secs(10) }
+    }
+}
+
+/// Watches the output from the outside, the way a viewer would see it.
+///
+/// The monitor plays `url` like any other RTSP client and checks that video and audio keep
+/// arriving, so a broken encoder or server shows up even while the feeder's pipelines look fine.
+/// [`Event::OutputUnhealthy`] is sent when that stops, and [`Event::OutputHealthy`] once it
+/// recovers. Being a client itself, it also keeps the output running while nobody else watches.
+pub fn start_output_monitor(url: String, options: MonitorOptions, event_tx: flume::Sender<Event>) {
+    spawn_watcher(url, options, Some(event_tx));
+}
+
+/// Plays `url` like the monitor does, only to keep the output running while nobody else watches
+/// over RTSP, without sending any events.
+pub(crate) fn keep_output_running(url: String) {
+    spawn_watcher(url, MonitorOptions::default(), None);
+}
+
+fn spawn_watcher(url: String, options: MonitorOptions, event_tx: Option<flume::Sender<Event>>) {
+    std::thread::spawn(move || {
+        let mut health = Health { event_tx, problem: None };
+        loop {
+            std::thread::sleep(RETRY_INTERVAL);
+            let problem = match watch(&url, options, &mut health) {
+                Ok(()) => "The output ended".to_string(),
+                Err(error) => format!("Can't play the output: {error}"),
+            };
+            health.report(Some(problem));
+        }
+    });
+}
+
+/// Plays `url` until it ends or fails, reporting whether video and audio keep arriving.
+fn watch(url: &str, options: MonitorOptions, health: &mut Health) -> Result<(), Error> {
+    let pipeline = gstreamer::Pipeline::builder().name("monitor-pipeline").build();
+    let rtspsrc = gstreamer::ElementFactory::make("rtspsrc")
+        .property("location", url)
+        .property_from_str("protocols", "tcp")
+        .build()?;
+    pipeline.add(&rtspsrc)?;
+
+    let probes = Probes::default();
+    let last_video = Arc::new(Mutex::new(None::<Instant>));
+    let last_audio = Arc::new(Mutex::new(None::<Instant>));
+    let pipeline_weak = pipeline.downgrade();
+    let probes_clone = probes.clone();
+    let last_video_clone = last_video.clone();
+    let last_audio_clone = last_audio.clone();
+    rtspsrc.connect_pad_added(move |_, pad| {
+        let Some(pipeline) = pipeline_weak.upgrade() else { return };
+        let media = pad.current_caps().and_then(|caps| {
+            caps.structure(0).and_then(|structure| structure.get::<String>("media").ok())
+        });
+        let last_seen = match media.as_deref() {
+            Some("video") => Some(last_video_clone.clone()),
+            Some("audio") => Some(last_audio_clone.clone()),
+            _ => None,
+        };
+
+        // Every stream has to go somewhere, or rtspsrc fails with not-linked
+        let Ok(fakesink) = gstreamer::ElementFactory::make("fakesink")
+            .property("sync", false)
+            .property("async", false)
+            .build()
+        else {
+            eprintln!("Failed to create a fakesink for the monitor");
+            return;
+        };
+        let sink_pad = fakesink.static_pad("sink").unwrap();
+        if let Some(last_seen) = last_seen {
+            let mask = gstreamer::PadProbeType::BUFFER | gstreamer::PadProbeType::BUFFER_LIST;
+            probes_clone.add(&sink_pad, mask, move |_, _| {
+                *last_seen.lock() = Some(Instant::now());
+                gstreamer::PadProbeReturn::Ok
+            });
+        }
+        if pipeline.add(&fakesink).is_err()
+            || fakesink.sync_state_with_parent().is_err()
+            || pad.link(&sink_pad).is_err()
+        {
+            eprintln!("Failed to link {} for the monitor", pad.name());
+        }
+    });
+
+    let result = check_flow(&pipeline, &last_video, &last_audio, options, health);
+    _ = pipeline.set_state(gstreamer::State::Null);
+    probes.remove_all();
+    result
+}
+
+/// Plays `pipeline`, checking every so often that both kinds of buffers are still arriving.
+fn check_flow(
+    pipeline: &gstreamer::Pipeline,
+    last_video: &Mutex<Option<Instant>>,
+    last_audio: &Mutex<Option<Instant>>,
+    options: MonitorOptions,
+    health: &mut Health,
+) -> Result<(), Error> {
+    pipeline.set_state(gstreamer::State::Playing)?;
+    let started_at = Instant::now();
+    let stalled = |last_seen: &Mutex<Option<Instant>>| {
+        last_seen.lock().unwrap_or(started_at).elapsed() > options.stall_timeout
+    };
+
+    let bus = pipeline.bus().unwrap();
+    loop {
+        use gstreamer::MessageView;
+        let msg = bus.timed_pop_filtered(
+            CHECK_INTERVAL,
+            &[gstreamer::MessageType::Eos, gstreamer::MessageType::Error],
+        );
+        if let Some(msg) = msg {
+            match msg.view() {
+                MessageView::Error(err) => return Err(Error::Glib(err.error())),
+                _ => return Ok(()),
+            }
+        }
+
+        let missing = match (stalled(last_video), stalled(last_audio)) {
+            (true, true) => Some("video or audio"),
+            (true, false) => Some("video"),
+            (false, true) => Some("audio"),
+            (false, false) => None,
+        };
+        // Not healthy (again) until both have arrived at least once
+        let connecting = last_video.lock().is_none() || last_audio.lock().is_none();
+        if missing.is_none() && connecting {
+            continue;
+        }
+        let timeout = options.stall_timeout.as_secs();
+        health.report(missing.map(|missing| format!("No {missing} for over {timeout}s")));
+    }
+}
+
+/// The last thing the monitor reported, so only changes are sent as events.
+struct Health {
+    /// Where to send changes to, if anywhere.
+    event_tx: Option<flume::Sender<Event>>,
+    /// Why the output is unhealthy, if it is.
+    problem: Option<String>,
+}
+
+impl Health {
+    /// Sends the changes without dropping them when the channel is full, `/healthz` goes by them.
+    fn report(&mut self, problem: Option<String>) {
+        if problem == self.problem {
+            return;
+        }
+        match &problem {
+            Some(reason) => {
+                eprintln!("Output unhealthy: {reason}");
+                if let Some(event_tx) = &self.event_tx {
+                    _ = event_tx.send(Event::OutputUnhealthy { reason: reason.clone() });
+                }
+            }
+            None => {
+                println!("Output healthy again");
+                if let Some(event_tx) = &self.event_tx {
+                    _ = event_tx.send(Event::OutputHealthy);
+                }
+            }
+        }
+        self.problem = problem;
+    }
+}
@@ -0,0 +1,183 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use gstreamer::prelude::*;
+use parking_lot::Mutex;
+
+use super::Probes;
+
+/// What the title overlay shows, e.g. `{title} ({year}) - {elapsed}/{duration}`.
+///
+/// It can use `{title}` (the sidecar's, the file's tags' or one made from its name), `{year}`,
+/// `{artist}`, `{file}` (the file name), `{path}`, `{elapsed}` and `{duration}`. Without one, the
+/// overlay shows the sidecar's title if there is one, else the path.
+///
+/// Changes show up on the item that's playing, as well as the ones after it.
+#[derive(Debug, Clone, Default)]
+pub struct TitleTemplate {
+    template: Arc<Mutex<Option<String>>>,
+}
+
+impl TitleTemplate {
+    pub fn new(template: Option<String>) -> Self {
+        Self { template: Arc::new(Mutex::new(template)) }
+    }
+
+    /// Replaces the template, `None` goes back to the default.
+    pub fn set(&self, template: Option<String>) {
+        *self.template.lock() = template;
+    }
+
+    fn get(&self) -> Option<String> {
+        self.template.lock().clone()
+    }
+}
+
+/// What the template is filled in with.
+#[derive(Debug)]
+struct TitleFields {
+    path: String,
+    file: String,
+    title: String,
+    year: Option<i32>,
+    artist: Option<String>,
+    /// Whether `title` is from the sidecar, which the default template shows.
+    sidecar_title: bool,
+    elapsed: Option<gstreamer::ClockTime>,
+    duration: Option<gstreamer::ClockTime>,
+}
+
+impl TitleFields {
+    fn render(&self, template: Option<&str>) -> String {
+        let template = match template {
+            Some(template) => template,
+            None if self.sidecar_title => "{title}",
+            None => "{path}",
+        };
+        template
+            .replace("{path}", &self.path)
+            .replace("{file}", &self.file)
+            .replace("{title}", &self.title)
+            .replace("{year}", &self.year.map(|year| year.to_string()).unwrap_or_default())
+            .replace("{artist}", self.artist.as_deref().unwrap_or_default())
+            .replace("{elapsed}", &format_time(self.elapsed.unwrap_or_default()))
+            .replace("{duration}", &self.duration.map(format_time).unwrap_or_default())
+    }
+
+    /// Takes the title, artist and year from the file's tags, where the sidecar didn't set them.
+    fn update_from_tags(&mut self, tags: &gstreamer::TagListRef) {
+        if !self.sidecar_title
+            && let Some(title) = tags.get::<gstreamer::tags::Title>()
+        {
+            self.title = title.get().to_string();
+        }
+        if let Some(artist) = tags.get::<gstreamer::tags::Artist>() {
+            self.artist = Some(artist.get().to_string());
+        }
+        let year = tags
+            .get::<gstreamer::tags::DateTime>()
+            .map(|date_time| date_time.get().year())
+            .or_else(|| tags.get::<gstreamer::tags::Date>().map(|date| date.get().year().into()));
+        if let Some(year) = year {
+            self.year = Some(year);
+        }
+    }
+}
+
+/// Fills in the title overlay of `pipeline` (the `textoverlay`) from `template`, and keeps it up
+/// to date with the file's tags, the time and changes to the template.
+pub(crate) fn attach_title_template(
+    pipeline: &gstreamer::Pipeline,
+    path: &Path,
+    template: &TitleTemplate,
+    sidecar_title: Option<&str>,
+    duration: Option<gstreamer::ClockTime>,
+    probes: &Probes,
+) {
+    let Some(title_overlay) = pipeline.by_name("textoverlay") else { return };
+    let Some(sink_pad) = title_overlay.static_pad("video_sink") else { return };
+
+    let (parsed_title, parsed_year) = parse_file_name(path);
+    let fields = TitleFields {
+        path: path.to_string_lossy().into_owned(),
+        file: path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        title: sidecar_title.map_or(parsed_title, str::to_string),
+        year: parsed_year,
+        artist: None,
+        sidecar_title: sidecar_title.is_some(),
+        elapsed: None,
+        duration,
+    };
+    let shown_text = fields.render(template.get().as_deref());
+    title_overlay.set_property("text", &shown_text);
+
+    let state = Mutex::new((fields, shown_text));
+    let template = template.clone();
+    let title_overlay_weak = title_overlay.downgrade();
+    let mask = gstreamer::PadProbeType::BUFFER | gstreamer::PadProbeType::EVENT_DOWNSTREAM;
+    probes.add(&sink_pad, mask, move |_, info| {
+        let mut state = state.lock();
+        let (fields, shown_text) = &mut *state;
+        match &info.data {
+            Some(gstreamer::PadProbeData::Buffer(buffer)) => fields.elapsed = buffer.pts(),
+            Some(gstreamer::PadProbeData::Event(event)) => {
+                let gstreamer::EventView::Tag(tag) = event.view() else {
+                    return gstreamer::PadProbeReturn::Ok;
+                };
+                fields.update_from_tags(tag.tag());
+            }
+            _ => return gstreamer::PadProbeReturn::Ok,
+        }
+        let text = fields.render(template.get().as_deref());
+        if text != *shown_text
+            && let Some(title_overlay) = title_overlay_weak.upgrade()
+        {
+            title_overlay.set_property("text", &text);
+            *shown_text = text;
+        }
+        gstreamer::PadProbeReturn::Ok
+    });
+}
+
+/// `mm:ss`, or `h:mm:ss` from an hour on.
+fn format_time(time: gstreamer::ClockTime) -> String {
+    let secs = time.seconds();
+    match secs / 3600 {
+        0 => format!("{:02}:{:02}", secs / 60, secs % 60),
+        hours => format!("{hours}:{:02}:{:02}", secs / 60 % 60, secs % 60),
+    }
+}
+
+/// A title and year from a file name like `The Movie (1987) 1080p.mkv` or
+/// `The.Movie.1987.1080p.mkv`. Everything from the year on is dropped from the title, it's
+/// usually release details.
+pub(crate) fn parse_file_name(path: &Path) -> (String, Option<i32>) {
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+    // Dots and underscores are spaces in names without any
+    let stem = if stem.contains(' ') {
+        stem.into_owned()
+    } else {
+        stem.replace(['.', '_'], " ")
+    };
+    let words: Vec<&str> = stem.split_whitespace().collect();
+
+    // Not the first word, that would be a title like `1917`
+    let year = words.iter().enumerate().skip(1).find_map(|(index, word)| {
+        let year = word.trim_matches(['(', ')', '[', ']']);
+        if year.len() != 4 || !year.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let year = year.parse::<i32>().ok().filter(|year| (1900..=2099).contains(year))?;
+        Some((index, year))
+    });
+    match year {
+        Some((index, year)) => {
+            let title = words[..index].join(" ");
+            (title.trim_end_matches([' ', '-']).to_string(), Some(year))
+        }
+        None => (words.join(" "), None),
+    }
+}
@@ -0,0 +1,303 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use gstreamer::prelude::*;
+use parking_lot::Mutex;
+
+use super::encoder::{create_audio_encoder, create_mpeg2_video_encoder, create_video_encoder};
+use super::{ElementRegistry, ElementRole, EncoderOptions, Error, StreamOptions, VideoOptions};
+
+/// How an output branch encodes the program and packs it into RTP.
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum OutputProfile {
+    /// H.264 video and AAC audio, each in its own RTP stream.
+    #[default]
+    H264Aac,
+    /// MPEG-2 video and MPEG-1 Layer II audio in an MPEG transport stream, for old set-top boxes
+    /// that can't decode H.264 or AAC.
+    Mpeg2Ts,
+}
+
+impl std::str::FromStr for OutputProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "h264-aac" => Ok(Self::H264Aac),
+            "mpeg2-ts" | "legacy" => Ok(Self::Mpeg2Ts),
+            _ => Err(format!("Unknown output profile {s:?}, expected h264-aac or mpeg2-ts")),
+        }
+    }
+}
+
+impl std::fmt::Display for OutputProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::H264Aac => "h264-aac",
+            Self::Mpeg2Ts => "mpeg2-ts",
+        })
+    }
+}
+
+/// How long a switch waits for a frame to cut on.
+const SWITCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Encodes the raw video from `video_src` and every audio program in `audio_srcs` (primary
+/// first) as `options` says, and adds payloaders for them to `bin`, named `pay0`, `pay1`, ... as
+/// the RTSP server expects.
+///
+/// The encoders are added to `elements`. Returns a handle to change the video encoding while it
+/// runs, if the profile supports it.
+pub(super) fn link_output_branch(
+    bin: &gstreamer::Bin,
+    options: &StreamOptions,
+    video_src: &gstreamer::Element,
+    audio_srcs: &[&gstreamer::Element],
+    elements: &ElementRegistry,
+) -> Result<Option<EncoderSwitch>, Error> {
+    let profile = options.output_profile;
+    let video_options = options.video;
+    let audio_options = options.audio;
+    let mut encoder_switch = None;
+    match profile {
+        OutputProfile::H264Aac => {
+            let (encoder, video_encoder) = create_h264_branch(video_options, &options.encoders)?;
+            elements.register(ElementRole::VideoEncoder, &video_encoder);
+            let pay_vid = gstreamer::ElementFactory::make("rtph264pay")
+                .property("name", "pay0") // MUST be "pay0"
+                .property("pt", 96_u32)
+                .property("config-interval", 1)
+                .build()?;
+            bin.add_many([encoder.upcast_ref(), &pay_vid])?;
+            gstreamer::Element::link_many([video_src, encoder.upcast_ref(), &pay_vid])?;
+            encoder_switch = Some(EncoderSwitch {
+                bin: bin.clone(),
+                upstream: video_src.static_pad("src").expect("video source has a src pad"),
+                downstream: pay_vid.static_pad("sink").expect("rtph264pay has a sink pad"),
+                elements: elements.clone(),
+                encoders: options.encoders.clone(),
+                state: Arc::new(Mutex::new(SwitchState { encoder, video: video_options })),
+            });
+
+            for (index, &audio_src) in audio_srcs.iter().enumerate() {
+                let audio_encoder = create_audio_encoder(profile, audio_options, index)?;
+                elements.register(ElementRole::AudioEncoder(index), &audio_encoder);
+                let pay_aud = gstreamer::ElementFactory::make("rtpmp4apay")
+                    .property("name", format!("pay{}", index + 1))
+                    .property("pt", 97_u32 + index as u32)
+                    .build()?;
+                bin.add_many([&audio_encoder, &pay_aud])?;
+                gstreamer::Element::link_many([audio_src, &audio_encoder, &pay_aud])?;
+            }
+        }
+        OutputProfile::Mpeg2Ts => {
+            // One transport stream carries everything, so it's the only payloader
+            let mux = gstreamer::ElementFactory::make("mpegtsmux").build()?;
+            let pay = gstreamer::ElementFactory::make("rtpmp2tpay")
+                .property("name", "pay0") // MUST be "pay0"
+                .property("pt", 33_u32)
+                .build()?;
+            bin.add_many([&mux, &pay])?;
+            mux.link(&pay)?;
+
+            let video_encoder = create_mpeg2_video_encoder(video_options)?;
+            elements.register(ElementRole::VideoEncoder, &video_encoder);
+            let video_parse = gstreamer::ElementFactory::make("mpegvideoparse").build()?;
+            bin.add_many([&video_encoder, &video_parse])?;
+            gstreamer::Element::link_many([video_src, &video_encoder, &video_parse, &mux])?;
+
+            for (index, &audio_src) in audio_srcs.iter().enumerate() {
+                let audio_encoder = create_audio_encoder(profile, audio_options, index)?;
+                elements.register(ElementRole::AudioEncoder(index), &audio_encoder);
+                let audio_parse = gstreamer::ElementFactory::make("mpegaudioparse").build()?;
+                bin.add_many([&audio_encoder, &audio_parse])?;
+                gstreamer::Element::link_many([audio_src, &audio_encoder, &audio_parse, &mux])?;
+            }
+        }
+    }
+    Ok(encoder_switch)
+}
+
+/// Scales the raw video to `video`'s size and encodes it, in a bin of its own so it can be
+/// swapped out as a whole. Also returns the encoder itself.
+fn create_h264_branch(
+    video: VideoOptions,
+    encoders: &EncoderOptions,
+) -> Result<(gstreamer::Bin, gstreamer::Element), Error> {
+    let videoscale = gstreamer::ElementFactory::make("videoscale")
+        .property("add-borders", true)
+        .build()?;
+    let scale_caps = gstreamer::Caps::builder("video/x-raw")
+        .field("width", video.width as i32)
+        .field("height", video.height as i32)
+        .field("pixel-aspect-ratio", gstreamer::Fraction::new(1, 1))
+        .build();
+    let scale_capsfilter = gstreamer::ElementFactory::make("capsfilter")
+        .property("caps", scale_caps)
+        .build()?;
+    let video_encoder = create_video_encoder(video, encoders)?;
+    // Make the encoder pick a level that can actually carry the size
+    let mut h264_caps = gstreamer::Caps::builder("video/x-h264");
+    if let Some(level) = video.h264_level() {
+        h264_caps = h264_caps.field("level", level);
+    }
+    let h264_capsfilter = gstreamer::ElementFactory::make("capsfilter")
+        .property("caps", h264_caps.build())
+        .build()?;
+
+    let bin = gstreamer::Bin::new();
+    let elements = [&videoscale, &scale_capsfilter, &video_encoder, &h264_capsfilter];
+    bin.add_many(elements)?;
+    gstreamer::Element::link_many(elements)?;
+    let sink_pad = videoscale.static_pad("sink").expect("videoscale has a sink pad");
+    let src_pad = h264_capsfilter.static_pad("src").expect("capsfilter has a src pad");
+    bin.add_pad(&gstreamer::GhostPad::with_target(&sink_pad)?)?;
+    bin.add_pad(&gstreamer::GhostPad::with_target(&src_pad)?)?;
+    Ok((bin, video_encoder))
+}
+
+/// Swaps the H.264 encoder of a running output for one with a different size or bitrate, without
+/// restarting the RTSP media. Viewers see at most a GOP's worth of glitching.
+///
+/// The new encoder is built and started before anything is cut over, then the raw video is
+/// moved to it between two frames, so the first thing it sends is a keyframe. The change lasts
+/// until the output is rebuilt, e.g. after every viewer has left.
+#[derive(Debug, Clone)]
+pub struct EncoderSwitch {
+    bin: gstreamer::Bin,
+    /// Where the raw video comes from.
+    upstream: gstreamer::Pad,
+    /// The payloader's sink.
+    downstream: gstreamer::Pad,
+    elements: ElementRegistry,
+    encoders: EncoderOptions,
+    state: Arc<Mutex<SwitchState>>,
+}
+
+#[derive(Debug)]
+struct SwitchState {
+    encoder: gstreamer::Bin,
+    video: VideoOptions,
+}
+
+impl EncoderSwitch {
+    /// What the video is being encoded with now.
+    pub fn video(&self) -> VideoOptions {
+        self.state.lock().video
+    }
+
+    /// Blocks until the new encoder has taken over, or it's given up.
+    pub fn switch(&self, video: VideoOptions) -> Result<(), Error> {
+        video.validate()?;
+        // One switch at a time
+        let mut state = self.state.lock();
+
+        // Most encoders can change bitrate on the fly, no need for a new one then
+        if (video.width, video.height) == (state.video.width, state.video.height)
+            && video.rate_control == state.video.rate_control
+            && video.keyframe_interval == state.video.keyframe_interval
+            && let Some(encoder) = self.elements.get(ElementRole::VideoEncoder)
+            && let Some(bitrate) = encoder.find_property("bitrate")
+            && bitrate.flags().contains(gstreamer::PARAM_FLAG_MUTABLE_PLAYING)
+        {
+            // In kbit/s for all of them, see `create_video_encoder`
+            encoder.set_property_from_str("bitrate", &video.bitrate_kbps.to_string());
+            state.video = video;
+            return Ok(());
+        }
+
+        // Warm the new encoder up before it's needed, so the cut itself is quick
+        let (encoder, video_encoder) = create_h264_branch(video, &self.encoders)?;
+        let discard = |encoder: &gstreamer::Bin| {
+            _ = encoder.set_state(gstreamer::State::Null);
+            _ = self.bin.remove(encoder);
+        };
+        self.bin.add(&encoder)?;
+        if let Err(error) = encoder.sync_state_with_parent() {
+            discard(&encoder);
+            return Err(error.into());
+        }
+
+        let old_sink = state.encoder.static_pad("sink").expect("encoder bin has a sink pad");
+        let old_src = state.encoder.static_pad("src").expect("encoder bin has a src pad");
+        let new_sink = encoder.static_pad("sink").expect("encoder bin has a sink pad");
+        let new_src = encoder.static_pad("src").expect("encoder bin has a src pad");
+        let downstream = self.downstream.clone();
+        let (done_tx, done_rx) = flume::bounded(1);
+        let probe = self.upstream.add_probe(
+            gstreamer::PadProbeType::BLOCK | gstreamer::PadProbeType::BUFFER,
+            move |upstream, _| {
+                // Whatever the old encoder still has in flight would only confuse players
+                let drop_probe = old_src
+                    .add_probe(gstreamer::PadProbeType::DATA_DOWNSTREAM, |_, _| {
+                        gstreamer::PadProbeReturn::Drop
+                    });
+                let relinked =
+                    relink(upstream, &old_sink, &new_sink, &old_src, &new_src, &downstream);
+                // Let the old encoder carry on
+                if !relinked && let Some(drop_probe) = drop_probe {
+                    old_src.remove_probe(drop_probe);
+                }
+                _ = done_tx.send(relinked);
+                gstreamer::PadProbeReturn::Remove
+            },
+        );
+
+        let relinked = done_rx.recv_timeout(SWITCH_TIMEOUT).ok().or_else(|| {
+            if let Some(probe) = probe {
+                self.upstream.remove_probe(probe);
+            }
+            // It may have gone through while the probe was being removed
+            done_rx.try_recv().ok()
+        });
+        match relinked {
+            Some(true) => {
+                let old = std::mem::replace(&mut state.encoder, encoder);
+                state.video = video;
+                self.elements.register(ElementRole::VideoEncoder, &video_encoder);
+                _ = old.set_state(gstreamer::State::Null);
+                _ = self.bin.remove(&old);
+                Ok(())
+            }
+            Some(false) => {
+                discard(&encoder);
+                Err(Error::EncoderSwitch("couldn't link the new encoder"))
+            }
+            None => {
+                discard(&encoder);
+                Err(Error::EncoderSwitch("no video to cut over on"))
+            }
+        }
+    }
+}
+
+/// Moves the raw video from `old_sink` to `new_sink`, and the payloader from `old_src` to
+/// `new_src`. If any step fails the ones before it are undone, so the old encoder stays linked.
+fn relink(
+    upstream: &gstreamer::Pad,
+    old_sink: &gstreamer::Pad,
+    new_sink: &gstreamer::Pad,
+    old_src: &gstreamer::Pad,
+    new_src: &gstreamer::Pad,
+    downstream: &gstreamer::Pad,
+) -> bool {
+    if old_src.unlink(downstream).is_err() {
+        return false;
+    }
+    if new_src.link(downstream).is_err() {
+        _ = old_src.link(downstream);
+        return false;
+    }
+    if upstream.unlink(old_sink).is_err() {
+        _ = new_src.unlink(downstream);
+        _ = old_src.link(downstream);
+        return false;
+    }
+    if upstream.link(new_sink).is_err() {
+        _ = upstream.link(old_sink);
+        _ = new_src.unlink(downstream);
+        _ = old_src.link(downstream);
+        return false;
+    }
+    true
+}
@@ -0,0 +1,197 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use gstreamer::prelude::*;
+use parking_lot::Mutex;
+
+use super::{ElementRole, Probes};
+
+/// Longest text a data overlay shows, anything after it is cut off.
+const MAX_TEXT_CHARS: usize = 100;
+
+/// Where on the frame an overlay goes.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum OverlaySlot {
+    TopLeft,
+    Top,
+    TopRight,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl OverlaySlot {
+    /// `valignment` and `halignment` of a textoverlay in this slot.
+    pub(super) fn alignment(self) -> (&'static str, &'static str) {
+        match self {
+            Self::TopLeft => ("top", "left"),
+            Self::Top => ("top", "center"),
+            Self::TopRight => ("top", "right"),
+            Self::BottomLeft => ("bottom", "left"),
+            Self::Bottom => ("bottom", "center"),
+            Self::BottomRight => ("bottom", "right"),
+        }
+    }
+}
+
+impl std::str::FromStr for OverlaySlot {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "top-left" => Ok(Self::TopLeft),
+            "top" => Ok(Self::Top),
+            "top-right" => Ok(Self::TopRight),
+            "bottom-left" => Ok(Self::BottomLeft),
+            "bottom" => Ok(Self::Bottom),
+            "bottom-right" => Ok(Self::BottomRight),
+            _ => Err(format!(
+                "Unknown overlay slot {s:?}, expected top-left, top, top-right, bottom-left, \
+                bottom or bottom-right"
+            )),
+        }
+    }
+}
+
+/// Where the text of a data overlay comes from.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum DataSource {
+    /// Runs through the shell, its output is the text.
+    Command(String),
+    /// `GET`s this URL, the response body is the text.
+    Url(String),
+}
+
+impl std::fmt::Display for DataSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Command(command) => write!(f, "`{command}`"),
+            Self::Url(url) => f.write_str(url),
+        }
+    }
+}
+
+/// Short text from somewhere else (e.g. the weather, or a follower count), refreshed every
+/// `interval` and shown over everything that goes out.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct DataOverlayOptions {
+    pub slot: OverlaySlot,
+    pub source: DataSource,
+    pub interval: Duration,
+}
+
+#[derive(Debug, thiserror::Error)]
+enum FetchError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Http(#[from] ureq::Error),
+    #[error("Exited with {0}")]
+    Exit(std::process::ExitStatus),
+}
+
+/// The data overlays, with their text kept up to date by a thread each.
+#[derive(Debug, Clone, Default)]
+pub struct DataOverlays {
+    overlays: Vec<(OverlaySlot, Arc<Mutex<String>>)>,
+    /// Those of the last element made, which is replaced when the output is rebuilt.
+    probes: Probes,
+}
+
+impl DataOverlays {
+    pub fn start(options: &[DataOverlayOptions]) -> Self {
+        let overlays = options
+            .iter()
+            .map(|options| {
+                let text = Arc::new(Mutex::new(String::new()));
+                let text_clone = text.clone();
+                let options = options.clone();
+                std::thread::spawn(move || refresh_task(&options, &text_clone));
+                (options.slot, text)
+            })
+            .collect();
+        Self { overlays, probes: Probes::default() }
+    }
+
+    /// An element that draws the overlays over raw video, or passes it through if there aren't
+    /// any. It replaces the last one made, which stops being updated.
+    pub fn create_element(&self) -> Result<gstreamer::Element, glib::BoolError> {
+        self.probes.remove_all();
+        let name = ElementRole::DataOverlays.element_name();
+        if self.overlays.is_empty() {
+            return gstreamer::ElementFactory::make("identity").name(name).build();
+        }
+
+        let bin = gstreamer::Bin::builder().name(name).build();
+        let mut text_overlays = Vec::new();
+        for (slot, text) in &self.overlays {
+            let (valignment, halignment) = slot.alignment();
+            let text_overlay = gstreamer::ElementFactory::make("textoverlay")
+                .property_from_str("valignment", valignment)
+                .property_from_str("halignment", halignment)
+                .property_from_str("font-desc", "Sans, 10")
+                .property("shaded-background", true)
+                .property("use-markup", false)
+                .build()?;
+
+            // Picks up new text with the next frame
+            let text = text.clone();
+            let text_overlay_weak = text_overlay.downgrade();
+            let mut shown = String::new();
+            let sink_pad = text_overlay.static_pad("video_sink").unwrap();
+            self.probes.add(&sink_pad, gstreamer::PadProbeType::BUFFER, move |_, _| {
+                let text = text.lock();
+                if *text != shown
+                    && let Some(text_overlay) = text_overlay_weak.upgrade()
+                {
+                    text_overlay.set_property("text", text.as_str());
+                    shown.clone_from(&text);
+                }
+                gstreamer::PadProbeReturn::Ok
+            });
+            text_overlays.push(text_overlay);
+        }
+
+        bin.add_many(&text_overlays)?;
+        gstreamer::Element::link_many(&text_overlays)?;
+        let first_sink = text_overlays[0].static_pad("video_sink").unwrap();
+        let last_src = text_overlays[text_overlays.len() - 1].static_pad("src").unwrap();
+        bin.add_pad(&gstreamer::GhostPad::with_target(&first_sink)?)?;
+        bin.add_pad(&gstreamer::GhostPad::with_target(&last_src)?)?;
+        Ok(bin.upcast())
+    }
+}
+
+fn refresh_task(options: &DataOverlayOptions, text: &Mutex<String>) {
+    // Sources that are down fail the same way every time, only log changes
+    let mut last_error = None;
+    loop {
+        match fetch(&options.source) {
+            Ok(fetched) => {
+                *text.lock() = fetched.trim().chars().take(MAX_TEXT_CHARS).collect();
+                last_error = None;
+            }
+            Err(error) => {
+                let error = error.to_string();
+                if last_error.as_ref() != Some(&error) {
+                    eprintln!("Failed to update data overlay from {}: {error}", options.source);
+                }
+                last_error = Some(error);
+            }
+        }
+        std::thread::sleep(options.interval);
+    }
+}
+
+fn fetch(source: &DataSource) -> Result<String, FetchError> {
+    match source {
+        DataSource::Command(command) => {
+            let output = std::process::Command::new("sh").arg("-c").arg(command).output()?;
+            if !output.status.success() {
+                return Err(FetchError::Exit(output.status));
+            }
+            Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+        }
+        DataSource::Url(url) => Ok(ureq::get(url).call()?.body_mut().read_to_string()?),
+    }
+}
@@ -0,0 +1,77 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+
+use crate::client::ApiClient;
+use crate::history::History;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The files other instances sharing the same library are playing, have queued or played
+/// recently, so this one can avoid putting them on again.
+///
+/// Peers are polled through their control API's `GET /status`, so it has to be reachable from
+/// here (it never needs a token). Recent plays come from a history database the instances share,
+/// see [`History`]. Either way, the library has to be mounted at the same path on every machine.
+#[derive(Debug, Clone, Default)]
+pub struct PeerFiles {
+    in_use: Arc<Mutex<HashSet<PathBuf>>>,
+    /// The shared history, and how far back a play counts.
+    history: Option<(History, Duration)>,
+}
+
+impl PeerFiles {
+    /// Starts polling `peer_urls` in the background. Peers that can't be reached are ignored.
+    pub fn start(peer_urls: &[String], history: Option<(History, Duration)>) -> Self {
+        let this = Self { in_use: Arc::default(), history };
+        if peer_urls.is_empty() {
+            return this;
+        }
+
+        let peers: Vec<_> =
+            peer_urls.iter().map(|url| (url.clone(), ApiClient::new(url, None))).collect();
+        let in_use = this.in_use.clone();
+        std::thread::spawn(move || {
+            // Only changes are logged, not every failed poll
+            let mut reachable = vec![true; peers.len()];
+            loop {
+                let mut paths = HashSet::new();
+                for ((url, peer), reachable) in peers.iter().zip(&mut reachable) {
+                    let status = match peer.status() {
+                        Ok(status) => status,
+                        Err(error) => {
+                            if std::mem::replace(reachable, false) {
+                                eprintln!("Failed to get status from peer {url}: {error}");
+                            }
+                            continue;
+                        }
+                    };
+                    if !std::mem::replace(reachable, true) {
+                        println!("Peer {url} is reachable again");
+                    }
+                    let playing = status["playing"]["path"].as_str();
+                    let upcoming = status["upcoming"].as_array().into_iter().flatten();
+                    let upcoming = upcoming.filter_map(|path| path.as_str());
+                    paths.extend(playing.into_iter().chain(upcoming).map(PathBuf::from));
+                }
+                *in_use.lock() = paths;
+                std::thread::sleep(POLL_INTERVAL);
+            }
+        });
+        this
+    }
+
+    pub fn is_in_use(&self, path: &Path) -> bool {
+        if self.in_use.lock().contains(path) {
+            return true;
+        }
+        let Some((history, window)) = &self.history else { return false };
+        history.played_since(path, *window).unwrap_or_else(|error| {
+            eprintln!("Failed to check the shared history: {error}");
+            false
+        })
+    }
+}
@@ -0,0 +1,59 @@
+use gstreamer::prelude::*;
+
+use super::Probes;
+
+/// The least number of frames a pool proposed to the normalization chain holds. Frames stay out
+/// of it while they're queued in front of the appsink, waiting in the output's appsrc and held
+/// for the encoder's lookahead, so it has to cover all of that to avoid allocating anyway.
+const MIN_VIDEO_BUFFERS: u32 = 8;
+
+/// An appsink for the normalized video of a pipeline that feeds the output.
+///
+/// It proposes a pool of output-sized frames to the videoconvert/videoscale chain in front of it,
+/// so frames are recycled once the output is done with them instead of allocated for every one.
+/// The last sample isn't kept either, which would leave every frame shared and make the overlays
+/// further down copy it before drawing.
+pub(super) fn create_video_appsink(probes: &Probes) -> gstreamer_app::AppSink {
+    let appsink = gstreamer_app::AppSink::builder()
+        .name("appsink_video")
+        .enable_last_sample(false)
+        .build();
+    let sink_pad = appsink.static_pad("sink").unwrap();
+    probes.add(&sink_pad, gstreamer::PadProbeType::QUERY_DOWNSTREAM, |_, info| {
+        let Some(query) = info.query_mut() else {
+            return gstreamer::PadProbeReturn::Ok;
+        };
+        let gstreamer::QueryViewMut::Allocation(allocation) = query.view_mut() else {
+            return gstreamer::PadProbeReturn::Ok;
+        };
+        match propose_video_pool(allocation) {
+            Ok(()) => gstreamer::PadProbeReturn::Handled,
+            Err(error) => {
+                eprintln!("Failed to propose a video buffer pool: {error}");
+                gstreamer::PadProbeReturn::Ok
+            }
+        }
+    });
+    appsink
+}
+
+fn propose_video_pool(
+    allocation: &mut gstreamer::query::Allocation,
+) -> Result<(), glib::BoolError> {
+    let (Some(caps), _) = allocation.get() else {
+        return Err(glib::bool_error!("Allocation query without caps"));
+    };
+    let caps = caps.to_owned();
+    let info = gstreamer_video::VideoInfo::from_caps(&caps)?;
+    let size = info.size() as u32;
+
+    let pool = gstreamer_video::VideoBufferPool::new();
+    let mut config = pool.config();
+    config.set_params(Some(&caps), size, MIN_VIDEO_BUFFERS, 0);
+    config.add_option(gstreamer_video::BUFFER_POOL_OPTION_VIDEO_META);
+    pool.set_config(config)?;
+
+    allocation.add_allocation_pool(Some(&pool), size, MIN_VIDEO_BUFFERS, 0);
+    allocation.add_allocation_meta::<gstreamer_video::VideoMeta>(None);
+    Ok(())
+}
@@ -0,0 +1,37 @@
+use std::sync::Arc;
+
+use gstreamer::prelude::*;
+use parking_lot::Mutex;
+
+/// Pad probes added for one pipeline (or bin), so they can all be removed when it's torn down.
+///
+/// A probe keeps everything its closure captured alive until it's removed, which with pads that
+/// outlive their pipeline (ghost pads, or an element that's still referenced) adds up over a day
+/// of file switches.
+#[derive(Debug, Clone, Default)]
+pub struct Probes {
+    probes: Arc<Mutex<Vec<(gstreamer::Pad, gstreamer::PadProbeId)>>>,
+}
+
+impl Probes {
+    /// Adds a probe to `pad`, to be removed with the rest.
+    pub fn add<F>(&self, pad: &gstreamer::Pad, mask: gstreamer::PadProbeType, func: F)
+    where
+        F: Fn(&gstreamer::Pad, &mut gstreamer::PadProbeInfo) -> gstreamer::PadProbeReturn
+            + Send
+            + Sync
+            + 'static,
+    {
+        if let Some(id) = pad.add_probe(mask, func) {
+            self.probes.lock().push((pad.clone(), id));
+        }
+    }
+
+    /// Removes every probe added so far. Best called once the pipeline is stopped, so no probe is
+    /// running while it's removed.
+    pub fn remove_all(&self) {
+        for (pad, id) in self.probes.lock().drain(..) {
+            pad.remove_probe(id);
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use parking_lot::Mutex;
+use serde::Serialize;
+
+/// Files that failed to play and shouldn't be picked again.
+///
+/// Kept in a SQLite database if opened with [`Quarantine::open`], so files stay out across
+/// restarts until they're removed, otherwise for the rest of the session.
+#[derive(Debug, Clone, Default)]
+pub struct Quarantine {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    connection: Option<rusqlite::Connection>,
+    entries: Vec<QuarantineEntry>,
+    /// Ids of entries when there's no database to hand them out.
+    next_id: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct QuarantineEntry {
+    pub id: i64,
+    #[serde(serialize_with = "crate::paths::serialize_lossy")]
+    pub path: PathBuf,
+    pub reason: String,
+    /// Unix timestamp, in seconds.
+    pub quarantined_at: f64,
+}
+
+impl Quarantine {
+    pub fn open(path: &Path) -> Result<Self, rusqlite::Error> {
+        let connection = rusqlite::Connection::open(path)?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS quarantine (
+                id INTEGER PRIMARY KEY,
+                path TEXT NOT NULL UNIQUE,
+                reason TEXT NOT NULL,
+                quarantined_at REAL NOT NULL
+            );",
+        )?;
+        let entries = connection
+            .prepare("SELECT id, path, reason, quarantined_at FROM quarantine ORDER BY id")?
+            .query_map([], |row| {
+                Ok(QuarantineEntry {
+                    id: row.get(0)?,
+                    path: PathBuf::from(row.get::<_, String>(1)?),
+                    reason: row.get(2)?,
+                    quarantined_at: row.get(3)?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+        let state = State { connection: Some(connection), entries, next_id: 0 };
+        Ok(Self { state: Arc::new(Mutex::new(state)) })
+    }
+
+    /// Returns `false` if the file was already quarantined.
+    pub fn add(&self, path: PathBuf, reason: &str) -> bool {
+        let mut state = self.state.lock();
+        let state = &mut *state;
+        if state.entries.iter().any(|entry| entry.path == path) {
+            return false;
+        }
+
+        let quarantined_at = unix_now();
+        let id = match &state.connection {
+            Some(connection) => {
+                let inserted = connection.execute(
+                    "INSERT OR IGNORE INTO quarantine (path, reason, quarantined_at)
+                    VALUES (?1, ?2, ?3)",
+                    rusqlite::params![path.to_string_lossy(), reason, quarantined_at],
+                );
+                match inserted {
+                    Ok(_) => connection.last_insert_rowid(),
+                    Err(error) => {
+                        // Still keep it out for this session
+                        eprintln!("Failed to record quarantine of {}: {error}", path.display());
+                        -1
+                    }
+                }
+            }
+            None => {
+                state.next_id += 1;
+                state.next_id
+            }
+        };
+        let reason = reason.to_string();
+        state.entries.push(QuarantineEntry { id, path, reason, quarantined_at });
+        true
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        self.state.lock().entries.iter().any(|entry| entry.path == path)
+    }
+
+    /// Every quarantined file, oldest first.
+    pub fn entries(&self) -> Vec<QuarantineEntry> {
+        self.state.lock().entries.clone()
+    }
+
+    /// Lets a file be picked again. Returns `false` if there's no entry with this id.
+    pub fn remove(&self, id: i64) -> Result<bool, rusqlite::Error> {
+        let mut state = self.state.lock();
+        let Some(index) = state.entries.iter().position(|entry| entry.id == id) else {
+            return Ok(false);
+        };
+        if let Some(connection) = &state.connection {
+            connection.execute("DELETE FROM quarantine WHERE id = ?1", [id])?;
+        }
+        state.entries.remove(index);
+        Ok(true)
+    }
+}
+
+fn unix_now() -> f64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64()
+}
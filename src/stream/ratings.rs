@@ -0,0 +1,222 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The rating of files that haven't been given one.
+pub const UNRATED: &str = "unrated";
+
+/// Content ratings of files, and which of them may air at what time of day.
+///
+/// A file has the rating of the most specific file or directory it was tagged through, or
+/// [`UNRATED`]. Outside of every slot, anything may air.
+#[derive(Default, Debug, Clone, Eq, PartialEq, Hash)]
+pub struct RatingPolicy {
+    /// Files or directories and their ratings.
+    pub ratings: Vec<(PathBuf, String)>,
+    /// If slots overlap, the first one applies.
+    pub slots: Vec<RatingSlot>,
+}
+
+impl RatingPolicy {
+    pub fn rating(&self, path: &Path) -> &str {
+        self.ratings
+            .iter()
+            .filter(|(tagged, _)| path.starts_with(tagged))
+            .max_by_key(|(tagged, _)| tagged.components().count())
+            .map_or(UNRATED, |(_, rating)| rating)
+    }
+
+    /// Whether `path` may air right now, in local time.
+    pub fn allows_now(&self, path: &Path) -> bool {
+        self.allows_rating_now(self.rating(path))
+    }
+
+    /// Whether files rated `rating` may air right now, in local time.
+    pub fn allows_rating_now(&self, rating: &str) -> bool {
+        if self.slots.is_empty() {
+            return true;
+        }
+        let Ok(now) = glib::DateTime::now_local() else { return true };
+        self.allows_rating(rating, (now.hour() * 60 + now.minute()) as u16)
+    }
+
+    /// Index of the slot that applies right now, in local time.
+    pub fn slot_now(&self) -> Option<usize> {
+        if self.slots.is_empty() {
+            return None;
+        }
+        let now = glib::DateTime::now_local().ok()?;
+        self.slot_at((now.hour() * 60 + now.minute()) as u16)
+    }
+
+    /// Index of the slot that applies at `minute` past midnight.
+    pub fn slot_at(&self, minute: u16) -> Option<usize> {
+        self.slots.iter().position(|slot| slot.contains(minute))
+    }
+
+    /// The next named slot to start, and how long until it does, in local time.
+    pub fn next_show_now(&self) -> Option<(&str, Duration)> {
+        let now = glib::DateTime::now_local().ok()?;
+        let second = now.hour() * 3600 + now.minute() * 60 + now.second();
+        self.next_show(second as u32)
+    }
+
+    /// The next named slot to start after `second` past midnight, and how long until it does.
+    pub fn next_show(&self, second: u32) -> Option<(&str, Duration)> {
+        const DAY: u32 = 24 * 60 * 60;
+
+        self.slots
+            .iter()
+            .filter_map(|slot| {
+                let name = slot.name.as_deref()?;
+                let until = (u32::from(slot.start) * 60 + DAY - second % DAY) % DAY;
+                // Already started
+                (until > 0).then_some((name, Duration::from_secs(until.into())))
+            })
+            .min_by_key(|(_, until)| *until)
+    }
+
+    /// Whether `path` may air at `minute` past midnight.
+    pub fn allows(&self, path: &Path, minute: u16) -> bool {
+        self.allows_rating(self.rating(path), minute)
+    }
+
+    /// Whether files rated `rating` may air at `minute` past midnight.
+    pub fn allows_rating(&self, rating: &str, minute: u16) -> bool {
+        let Some(index) = self.slot_at(minute) else { return true };
+        let slot = &self.slots[index];
+        slot.allowed.iter().any(|allowed| allowed == rating)
+    }
+}
+
+/// A daily time window and the ratings allowed during it, e.g. `06:00-21:00=family,unrated`.
+/// Windows ending before they start run past midnight.
+///
+/// Slots can be named, e.g. `Movie night@21:00-23:30=adult`, to count down to them with a slate.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct RatingSlot {
+    pub name: Option<String>,
+    /// Played first thing once the slot begins, whatever its rating.
+    pub jingle: Option<PathBuf>,
+    /// Minutes past midnight.
+    pub start: u16,
+    /// Minutes past midnight, exclusive.
+    pub end: u16,
+    pub allowed: Vec<String>,
+}
+
+impl RatingSlot {
+    fn contains(&self, minute: u16) -> bool {
+        if self.start <= self.end {
+            (self.start..self.end).contains(&minute)
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+}
+
+impl std::str::FromStr for RatingSlot {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, s) = match s.split_once('@') {
+            Some((name, s)) => (Some(name.trim().to_string()), s),
+            None => (None, s),
+        };
+        let (window, allowed) = s.split_once('=').ok_or("expected START-END=RATINGS")?;
+        let (start, end) = window.split_once('-').ok_or("expected START-END=RATINGS")?;
+        let allowed = allowed.split(',').map(|rating| rating.trim().to_string());
+        Ok(Self {
+            name: name.filter(|name| !name.is_empty()),
+            jingle: None,
+            start: parse_time_of_day(start)?,
+            end: parse_time_of_day(end)?,
+            allowed: allowed.filter(|rating| !rating.is_empty()).collect(),
+        })
+    }
+}
+
+/// Parses `HH:MM` into minutes past midnight.
+fn parse_time_of_day(s: &str) -> Result<u16, String> {
+    let invalid = || format!("invalid time of day {s:?}, expected HH:MM");
+    let (hours, minutes) = s.trim().split_once(':').ok_or_else(invalid)?;
+    let hours: u16 = hours.parse().map_err(|_| invalid())?;
+    let minutes: u16 = minutes.parse().map_err(|_| invalid())?;
+    if hours > 24 || minutes > 59 || (hours == 24 && minutes > 0) {
+        return Err(invalid());
+    }
+    Ok(hours * 60 + minutes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slot(s: &str) -> RatingSlot {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_slots() {
+        let parsed = slot(" Movie night @ 21:00-23:30 = adult, ,unrated");
+        assert_eq!(parsed.name.as_deref(), Some("Movie night"));
+        assert_eq!((parsed.start, parsed.end), (21 * 60, 23 * 60 + 30));
+        assert_eq!(parsed.allowed, ["adult", "unrated"]);
+        assert_eq!(slot("@06:00-24:00=family").name, None);
+
+        for invalid in ["06:00=family", "06:00-21:00", "6-21=family", "06:60-21:00=family"] {
+            assert!(invalid.parse::<RatingSlot>().is_err(), "{invalid:?}");
+        }
+        assert!("24:01-01:00=family".parse::<RatingSlot>().is_err());
+    }
+
+    #[test]
+    fn slots_run_past_midnight() {
+        let night = slot("22:00-06:00=adult");
+        assert!(night.contains(22 * 60));
+        assert!(night.contains(0));
+        assert!(night.contains(6 * 60 - 1));
+        assert!(!night.contains(6 * 60));
+        assert!(!night.contains(12 * 60));
+
+        let day = slot("06:00-22:00=family");
+        assert!(day.contains(6 * 60));
+        assert!(!day.contains(22 * 60));
+        assert!(!day.contains(0));
+    }
+
+    #[test]
+    fn first_slot_applies() {
+        let policy = RatingPolicy {
+            ratings: vec![("/media/films".into(), "adult".into())],
+            slots: vec![slot("06:00-21:00=family"), slot("00:00-24:00=adult,unrated")],
+        };
+        assert_eq!(policy.slot_at(12 * 60), Some(0));
+        assert_eq!(policy.slot_at(23 * 60), Some(1));
+        assert!(!policy.allows(Path::new("/media/films/a.mkv"), 12 * 60));
+        assert!(policy.allows(Path::new("/media/films/a.mkv"), 23 * 60));
+        assert!(policy.allows(Path::new("/media/music/a.mp3"), 23 * 60));
+    }
+
+    #[test]
+    fn next_show_wraps_around_midnight() {
+        let policy = RatingPolicy {
+            ratings: Vec::new(),
+            slots: vec![
+                slot("Late show@23:00-01:00=adult"),
+                slot("07:00-09:00=family"),
+                slot("Breakfast@06:00-09:00=family"),
+            ],
+        };
+        let hours = |hours: u64| Duration::from_secs(hours * 3600);
+        assert_eq!(policy.next_show(22 * 3600), Some(("Late show", hours(1))));
+        // Unnamed slots aren't shows
+        assert_eq!(policy.next_show(0), Some(("Breakfast", hours(6))));
+        assert_eq!(
+            policy.next_show(23 * 3600 + 30 * 60),
+            Some(("Breakfast", hours(6) + hours(1) / 2))
+        );
+        // One that has just started counts from tomorrow
+        assert_eq!(policy.next_show(6 * 3600), Some(("Late show", hours(17))));
+        assert_eq!(RatingPolicy::default().next_show(0), None);
+    }
+}
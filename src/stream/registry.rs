@@ -0,0 +1,50 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use gstreamer::prelude::*;
+use parking_lot::Mutex;
+
+/// What an element does in the output pipeline.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum ElementRole {
+    VideoEncoder,
+    /// The encoder of an audio program, 0 being the primary one.
+    AudioEncoder(usize),
+    /// Mixes stings into the program audio.
+    AudioMixer,
+    DataOverlays,
+}
+
+impl ElementRole {
+    /// The name the element is given, so it's the same in every pipeline and debug dump.
+    pub fn element_name(self) -> String {
+        match self {
+            Self::VideoEncoder => "video-encoder".to_string(),
+            Self::AudioEncoder(program) => format!("audio-encoder-{program}"),
+            Self::AudioMixer => "audio-mixer".to_string(),
+            Self::DataOverlays => "data-overlays".to_string(),
+        }
+    }
+}
+
+/// The live elements of a pipeline by role, so things that change them while it runs (bitrate
+/// changes, overlays, ...) don't have to go looking for them by name across bins.
+#[derive(Debug, Clone, Default)]
+pub struct ElementRegistry {
+    elements: Arc<Mutex<HashMap<ElementRole, gstreamer::Element>>>,
+}
+
+impl ElementRegistry {
+    /// Replaces whatever had this role before.
+    pub fn register(&self, role: ElementRole, element: &impl IsA<gstreamer::Element>) {
+        self.elements.lock().insert(role, element.clone().upcast());
+    }
+
+    pub fn get(&self, role: ElementRole) -> Option<gstreamer::Element> {
+        self.elements.lock().get(&role).cloned()
+    }
+
+    pub fn unregister(&self, role: ElementRole) -> Option<gstreamer::Element> {
+        self.elements.lock().remove(&role)
+    }
+}
@@ -0,0 +1,261 @@
+use std::str::FromStr;
+use std::sync::Arc;
+
+use gstreamer::prelude::*;
+use gstreamer_rtsp_server::subclass::prelude::*;
+use parking_lot::Mutex;
+
+use super::{Error, VideoOptions};
+
+/// A lower resolution (and bitrate) copy of the output, served at a mount of its own so a
+/// restreamer like mediamtx can offer an ABR ladder.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct RenditionOptions {
+    pub width: u32,
+    pub height: u32,
+    /// Target bitrate of the encoder, in kbit/s.
+    pub bitrate_kbps: u32,
+}
+
+impl RenditionOptions {
+    /// What the rendition is encoded as, everything but the size and bitrate comes from the
+    /// output's `video`.
+    pub fn video(&self, video: VideoOptions) -> VideoOptions {
+        VideoOptions {
+            width: self.width,
+            height: self.height,
+            bitrate_kbps: self.bitrate_kbps,
+            ..video
+        }
+    }
+
+    /// Appended to the stream key for the rendition's mount, e.g. `_720`.
+    pub fn mount_suffix(&self) -> String {
+        format!("_{}", self.height)
+    }
+}
+
+/// Parses `WIDTHxHEIGHT:KBPS`, e.g. `1280x720:3000`.
+impl FromStr for RenditionOptions {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidVideoOptions(format!("{s:?}, expected WIDTHxHEIGHT:KBPS"));
+        let (size, bitrate) = s.split_once(':').ok_or_else(invalid)?;
+        let (width, height) = size.split_once(['x', 'X']).ok_or_else(invalid)?;
+        Ok(Self {
+            width: width.parse().map_err(|_| invalid())?,
+            height: height.parse().map_err(|_| invalid())?,
+            bitrate_kbps: bitrate.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl std::fmt::Display for RenditionOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{} at {} kbit/s", self.width, self.height, self.bitrate_kbps)
+    }
+}
+
+/// The appsrcs of a rendition's pipeline, there while someone is watching it.
+#[derive(Debug, Clone)]
+struct RenditionSources {
+    video: gstreamer_app::AppSrc,
+    audio: gstreamer_app::AppSrc,
+}
+
+/// A rendition, fed the raw program from the output pipeline.
+///
+/// The output is only decoded and composited once, the output pipeline hands a copy of its raw
+/// video and primary audio to every rendition. That also means a rendition only has something to
+/// show while the main mount is being played.
+#[derive(Debug, Clone)]
+pub struct Rendition {
+    options: RenditionOptions,
+    sources: Arc<Mutex<Option<RenditionSources>>>,
+}
+
+impl Rendition {
+    pub fn new(options: RenditionOptions) -> Self {
+        Self { options, sources: Arc::default() }
+    }
+
+    pub fn options(&self) -> RenditionOptions {
+        self.options
+    }
+
+    /// Adds appsinks fed from `video_tee` and `audio_tee` to `bin`, which pass everything on to
+    /// the rendition's pipeline, if it's running.
+    pub(super) fn link_taps(
+        &self,
+        bin: &gstreamer::Bin,
+        video_tee: &gstreamer::Element,
+        audio_tee: &gstreamer::Element,
+    ) -> Result<(), Error> {
+        let suffix = self.options.mount_suffix();
+        for (tee, kind) in [(video_tee, "video"), (audio_tee, "audio")] {
+            // Never hold up the main output for a rendition
+            let queue = gstreamer::ElementFactory::make("queue")
+                .property_from_str("leaky", "downstream")
+                .build()?;
+            let appsink = gstreamer_app::AppSink::builder()
+                .name(format!("appsink_rendition{suffix}_{kind}"))
+                .sync(false)
+                .enable_last_sample(false)
+                .build();
+            bin.add_many([&queue, appsink.upcast_ref()])?;
+            gstreamer::Element::link_many([tee, &queue, appsink.upcast_ref()])?;
+
+            let sources = self.sources.clone();
+            appsink.set_callbacks(
+                gstreamer_app::AppSinkCallbacks::builder()
+                    .new_sample(move |sink| {
+                        let sample = sink.pull_sample().map_err(|_| gstreamer::FlowError::Eos)?;
+                        let appsrc = sources.lock().as_ref().map(|sources| match kind {
+                            "video" => sources.video.clone(),
+                            _ => sources.audio.clone(),
+                        });
+                        // Nobody watching the rendition (any more) isn't an error for the output
+                        if let Some(appsrc) = appsrc {
+                            _ = appsrc.push_sample(&sample);
+                        }
+                        Ok(gstreamer::FlowSuccess::Ok)
+                    })
+                    .build(),
+            );
+        }
+        Ok(())
+    }
+}
+
+mod imp {
+    use glib::subclass::prelude::*;
+    use gstreamer::prelude::*;
+    use gstreamer_rtsp_server::subclass::prelude::*;
+    use parking_lot::Mutex;
+
+    use super::{Rendition, RenditionSources};
+    use crate::stream::output::link_output_branch;
+    use crate::stream::{ElementRegistry, StreamOptions};
+
+    #[derive(Default)]
+    pub struct RenditionMediaFactory {
+        pub(super) rendition: Mutex<Option<Rendition>>,
+        /// The output's options, with the rendition's video.
+        pub(super) options: Mutex<StreamOptions>,
+        /// The size and framerate the output pipeline hands over.
+        pub(super) source_caps: Mutex<Option<gstreamer::Caps>>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for RenditionMediaFactory {
+        const NAME: &'static str = "RenditionMediaFactory";
+        type Type = super::RenditionMediaFactory;
+        type ParentType = gstreamer_rtsp_server::RTSPMediaFactory;
+    }
+
+    impl ObjectImpl for RenditionMediaFactory {}
+    impl GstObjectImpl for RenditionMediaFactory {}
+
+    impl RTSPMediaFactoryImpl for RenditionMediaFactory {
+        /// Like the main mount, every path of the rendition gets the same media.
+        fn gen_key(
+            &self,
+            _url: &gstreamer_rtsp_server::gst_rtsp::RTSPUrl,
+        ) -> Option<glib::GString> {
+            let rendition = self.rendition.lock();
+            let suffix = rendition.as_ref()?.options().mount_suffix();
+            Some(format!("z-stream{suffix}").into())
+        }
+
+        fn create_element(
+            &self,
+            _url: &gstreamer_rtsp_server::gst_rtsp::RTSPUrl,
+        ) -> Option<gstreamer::Element> {
+            let rendition = self.rendition.lock().clone()?;
+            let options = self.options.lock();
+            let source_caps = self.source_caps.lock().clone()?;
+            println!("RTSP CLIENT CONNECTED: Building {} rendition...", rendition.options());
+
+            let bin = gstreamer::Bin::builder().name("rendition-pipeline").build();
+            let appsrc_video = options.appsrc.create_appsrc("videosrc", &source_caps);
+            let appsrc_audio = options.appsrc.create_appsrc("audiosrc", &options.audio.caps());
+
+            // The H.264 encoder scales by itself, but the MPEG-2 one doesn't
+            let videoscale = gstreamer::ElementFactory::make("videoscale")
+                .property("add-borders", true)
+                .build()
+                .ok()?;
+            let capsfilter = gstreamer::ElementFactory::make("capsfilter")
+                .property(
+                    "caps",
+                    gstreamer::Caps::builder("video/x-raw")
+                        .field("width", options.video.width as i32)
+                        .field("height", options.video.height as i32)
+                        .field("pixel-aspect-ratio", gstreamer::Fraction::new(1, 1))
+                        .build(),
+                )
+                .build()
+                .ok()?;
+            let video_chain = [appsrc_video.upcast_ref(), &videoscale, &capsfilter];
+            bin.add_many(video_chain).ok()?;
+            bin.add(&appsrc_audio).ok()?;
+            gstreamer::Element::link_many(video_chain).ok()?;
+
+            // Not switchable at runtime, the main output is what's managed
+            let elements = ElementRegistry::default();
+            let audio_srcs = [appsrc_audio.upcast_ref()];
+            link_output_branch(&bin, &options, &capsfilter, &audio_srcs, &elements).ok()?;
+
+            *rendition.sources.lock() =
+                Some(RenditionSources { video: appsrc_video, audio: appsrc_audio });
+            println!("{} rendition pipeline built.", rendition.options());
+            Some(bin.upcast())
+        }
+    }
+}
+
+glib::wrapper! {
+    pub struct RenditionMediaFactory(ObjectSubclass<imp::RenditionMediaFactory>)
+        @extends gstreamer_rtsp_server::RTSPMediaFactory, gstreamer::Object;
+}
+
+impl RenditionMediaFactory {
+    /// Serves `rendition` of the output described by `options`.
+    pub fn new(rendition: Rendition, options: &super::StreamOptions) -> Self {
+        let source_caps = gstreamer::Caps::builder("video/x-raw")
+            .field("format", gstreamer_video::VideoFormat::I420.to_string())
+            .field("width", options.video.width as i32)
+            .field("height", options.video.height as i32)
+            .field("framerate", options.video.framerate())
+            .build();
+        let options = super::StreamOptions {
+            video: rendition.options().video(options.video),
+            ..options.clone()
+        };
+
+        let factory: Self = glib::Object::new();
+        *factory.imp().rendition.lock() = Some(rendition);
+        *factory.imp().options.lock() = options;
+        *factory.imp().source_caps.lock() = Some(source_caps);
+        factory
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_renditions() {
+        let rendition: RenditionOptions = "1280x720:3000".parse().unwrap();
+        assert_eq!(rendition, RenditionOptions { width: 1280, height: 720, bitrate_kbps: 3000 });
+        assert_eq!(rendition.mount_suffix(), "_720");
+        let rendition: RenditionOptions = "640X360:800".parse().unwrap();
+        assert_eq!((rendition.width, rendition.height), (640, 360));
+
+        for invalid in ["1280x720", "1280:3000", "1280x:3000", "x720:3000", "1280x720:fast", ""] {
+            assert!(invalid.parse::<RenditionOptions>().is_err(), "{invalid:?}");
+        }
+    }
+}
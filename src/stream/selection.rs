@@ -0,0 +1,96 @@
+use gstreamer::prelude::*;
+
+/// The streams picked out of a decoder's stream collection.
+#[derive(Default, Debug, Clone, Eq, PartialEq)]
+pub struct StreamSelection {
+    pub video: Option<String>,
+    pub audio: Option<String>,
+    /// Only picked when a secondary audio program is wanted.
+    pub audio2: Option<String>,
+    /// Only picked when subtitles are burned in.
+    pub subtitle: Option<String>,
+}
+
+impl StreamSelection {
+    /// Picks the first video stream and the best audio stream(s) by language preference.
+    /// `audio_languages` is a list of ISO 639 codes, most preferred first. A subtitle stream is
+    /// only picked if `subtitle_languages` is set, the best one in it or any if it's empty.
+    pub fn select(
+        collection: &gstreamer::StreamCollection,
+        audio_languages: &[String],
+        want_audio2: bool,
+        subtitle_languages: Option<&[String]>,
+    ) -> Self {
+        let stream_id = |stream: &gstreamer::Stream| stream.stream_id().map(|id| id.to_string());
+
+        let video = collection
+            .iter()
+            .find(|stream| stream.stream_type().contains(gstreamer::StreamType::VIDEO))
+            .and_then(|stream| stream_id(&stream));
+
+        let mut audio_streams = collection
+            .iter()
+            .filter(|stream| stream.stream_type().contains(gstreamer::StreamType::AUDIO))
+            .collect::<Vec<_>>();
+        // Stable, so streams with the same rank keep the container order
+        audio_streams.sort_by_key(|stream| language_rank(stream, audio_languages));
+
+        let mut audio_ids = audio_streams.iter().filter_map(stream_id);
+        let audio = audio_ids.next();
+        let audio2 = if want_audio2 { audio_ids.next() } else { None };
+
+        let subtitle = subtitle_languages.and_then(|languages| {
+            collection
+                .iter()
+                .filter(|stream| stream.stream_type().contains(gstreamer::StreamType::TEXT))
+                .map(|stream| (language_rank(&stream, languages), stream))
+                .filter(|(rank, _)| languages.is_empty() || *rank < languages.len())
+                .min_by_key(|(rank, _)| *rank)
+                .and_then(|(_, stream)| stream_id(&stream))
+        });
+
+        Self { video, audio, audio2, subtitle }
+    }
+
+    pub fn stream_ids(&self) -> impl Iterator<Item = &str> {
+        [&self.video, &self.audio, &self.audio2, &self.subtitle]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+    }
+
+    /// Sends a `select-streams` event for this selection to `decoder`.
+    pub fn apply(&self, decoder: &gstreamer::Element) -> bool {
+        decoder.send_event(gstreamer::event::SelectStreams::new(self.stream_ids()))
+    }
+}
+
+fn language_rank(stream: &gstreamer::Stream, languages: &[String]) -> usize {
+    let language = stream
+        .tags()
+        .and_then(|tags| tags.get::<gstreamer::tags::LanguageCode>().map(|v| v.get().to_string()));
+    language
+        .and_then(|language| {
+            languages.iter().position(|preferred| preferred.eq_ignore_ascii_case(&language))
+        })
+        .unwrap_or(languages.len())
+}
+
+/// The type of the stream a decoder pad carries, falling back to its caps.
+pub fn pad_stream_type(pad: &gstreamer::Pad) -> gstreamer::StreamType {
+    if let Some(stream) = pad.stream() {
+        return stream.stream_type();
+    }
+
+    let caps = pad.current_caps().unwrap_or_else(|| pad.query_caps(None));
+    match caps.structure(0).map(|s| s.name().as_str()) {
+        Some(name) if name.starts_with("video/") => gstreamer::StreamType::VIDEO,
+        Some(name) if name.starts_with("audio/") => gstreamer::StreamType::AUDIO,
+        Some(name) if name.starts_with("text/") => gstreamer::StreamType::TEXT,
+        _ => gstreamer::StreamType::UNKNOWN,
+    }
+}
+
+pub fn pad_stream_id(pad: &gstreamer::Pad) -> Option<String> {
+    pad.stream_id().map(|id| id.to_string())
+}
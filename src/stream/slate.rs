@@ -0,0 +1,208 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use gstreamer::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::feeder::{create_silent_audio, create_tone_audio, forward_samples};
+use super::pool::create_video_appsink;
+use super::{AppSources, AudioOptions, Error, Probes, VideoOptions};
+
+/// The screens the channel can show instead of files.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SlateKind {
+    /// Shown while there's nothing that can be played.
+    Standby,
+    SignOff,
+    Pause,
+    /// Counts down to the start of a show, only shown by the feeder.
+    #[serde(skip_deserializing)]
+    Countdown,
+}
+
+impl std::str::FromStr for SlateKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "standby" => Ok(Self::Standby),
+            "sign-off" => Ok(Self::SignOff),
+            "pause" => Ok(Self::Pause),
+            _ => Err(format!("Unknown slate {s:?}, expected standby, sign-off or pause")),
+        }
+    }
+}
+
+impl std::fmt::Display for SlateKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Standby => "standby",
+            Self::SignOff => "sign-off",
+            Self::Pause => "pause",
+            Self::Countdown => "countdown",
+        })
+    }
+}
+
+/// Text rendered over a background when a slate is shown, so they don't have to be made by hand.
+///
+/// Templates can use `{channel}`, `{time}` (the local time) and `{next}` (the file name of what's
+/// queued, if anything). The countdown can also use `{show}` and `{countdown}`.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct SlateOptions {
+    pub channel_name: String,
+    /// An image scaled to fill the frame, black if not set.
+    pub background: Option<PathBuf>,
+    /// Colour bars and a line-up tone instead of black and silence, under the background if
+    /// there's one.
+    pub test_card: bool,
+    /// A Pango font description.
+    pub font: String,
+    pub standby: String,
+    pub sign_off: String,
+    pub pause: String,
+    pub countdown: String,
+    /// Show the countdown slate between files once a named [`RatingSlot`](super::RatingSlot)
+    /// starts within this long.
+    pub countdown_lead: Option<Duration>,
+}
+
+impl Default for SlateOptions {
+    fn default() -> Self {
+        Self {
+            channel_name: "z-stream".to_string(),
+            background: None,
+            test_card: false,
+            font: "Sans Bold, 32".to_string(),
+            standby: "{channel}\nTechnical difficulties, please stand by".to_string(),
+            sign_off: "{channel}\nThat's all for now".to_string(),
+            pause: "{channel}\nWe'll be right back\n{next}".to_string(),
+            countdown: "{show} starts in {countdown}".to_string(),
+            countdown_lead: None,
+        }
+    }
+}
+
+impl SlateOptions {
+    pub fn text(&self, kind: SlateKind, next: Option<&Path>) -> String {
+        let template = match kind {
+            SlateKind::Standby => &self.standby,
+            SlateKind::SignOff => &self.sign_off,
+            SlateKind::Pause => &self.pause,
+            SlateKind::Countdown => &self.countdown,
+        };
+        self.fill(template, next)
+    }
+
+    /// The countdown slate's text, `remaining` until `show` starts.
+    pub fn countdown_text(&self, show: &str, remaining: Duration) -> String {
+        let secs = remaining.as_secs();
+        let countdown = match secs / 3600 {
+            0 => format!("{:02}:{:02}", secs / 60, secs % 60),
+            hours => format!("{hours}:{:02}:{:02}", secs / 60 % 60, secs % 60),
+        };
+        let template = self.countdown.replace("{show}", show).replace("{countdown}", &countdown);
+        self.fill(&template, None)
+    }
+
+    fn fill(&self, template: &str, next: Option<&Path>) -> String {
+        let time = glib::DateTime::now_local()
+            .and_then(|now| now.format("%H:%M"))
+            .map(|time| time.to_string())
+            .unwrap_or_default();
+        let next = next
+            .and_then(|next| next.file_stem())
+            .map(|next| glib::markup_escape_text(&next.to_string_lossy()).to_string())
+            .unwrap_or_default();
+        let text = template
+            .replace("{channel}", &self.channel_name)
+            .replace("{time}", &time)
+            .replace("{next}", &next);
+        text.trim().to_string()
+    }
+}
+
+/// A pipeline that shows a slate with `text` until it's stopped, with silent audio. Its probes
+/// are added to `probes`.
+pub fn create_slate_pipeline(
+    text: &str,
+    options: &SlateOptions,
+    app_sources: &AppSources,
+    video: VideoOptions,
+    audio: AudioOptions,
+    probes: &Probes,
+) -> Result<gstreamer::Pipeline, Error> {
+    let pipeline = gstreamer::Pipeline::builder().name("slate-pipeline").build();
+
+    // --- Video Chain (videotestsrc -> [gdkpixbufoverlay] -> textoverlay -> ...) ---
+    let pattern = if options.test_card { "smpte" } else { "black" };
+    let videotestsrc = gstreamer::ElementFactory::make("videotestsrc")
+        .property_from_str("pattern", pattern)
+        .build()?;
+    let capsfilter_size = gstreamer::ElementFactory::make("capsfilter")
+        .property(
+            "caps",
+            gstreamer::Caps::builder("video/x-raw")
+                .field("width", video.width as i32)
+                .field("height", video.height as i32)
+                .field("pixel-aspect-ratio", gstreamer::Fraction::new(1, 1))
+                .field("framerate", video.framerate())
+                .build(),
+        )
+        .build()?;
+    let background = options
+        .background
+        .as_ref()
+        .map(|background| {
+            gstreamer::ElementFactory::make("gdkpixbufoverlay")
+                .property("location", background.to_string_lossy().as_ref())
+                .property("overlay-width", video.width as i32)
+                .property("overlay-height", video.height as i32)
+                .build()
+        })
+        .transpose()?;
+    let text_overlay = gstreamer::ElementFactory::make("textoverlay")
+        .name("slate_text")
+        .property("text", text)
+        .property_from_str("halignment", "center")
+        .property_from_str("valignment", "center")
+        .property_from_str("line-alignment", "center")
+        .property("font-desc", options.font.as_str())
+        .property("shaded-background", options.background.is_some() || options.test_card)
+        .build()?;
+    let videoconvert_vid = gstreamer::ElementFactory::make("videoconvert").build()?;
+    let capsfilter_vid = gstreamer::ElementFactory::make("capsfilter")
+        .property(
+            "caps",
+            gstreamer::Caps::builder("video/x-raw")
+                .field("format", gstreamer_video::VideoFormat::I420.to_string())
+                .build(),
+        )
+        .build()?;
+    let queue_video = gstreamer::ElementFactory::make("queue").name("v_queue").build()?;
+    let appsink_video = create_video_appsink(probes);
+
+    let mut video_chain = vec![&videotestsrc, &capsfilter_size];
+    video_chain.extend(&background);
+    video_chain.extend([
+        &text_overlay,
+        &videoconvert_vid,
+        &capsfilter_vid,
+        &queue_video,
+        appsink_video.upcast_ref(),
+    ]);
+    pipeline.add_many(video_chain.iter().copied())?;
+    gstreamer::Element::link_many(video_chain.iter().copied())?;
+
+    let create_audio = if options.test_card { create_tone_audio } else { create_silent_audio };
+    let appsink_audio = create_audio(&pipeline, "", audio)?;
+    forward_samples(&appsink_video, &app_sources.video);
+    forward_samples(&appsink_audio, &app_sources.audio);
+    if let Some(appsrc_audio2) = &app_sources.audio2 {
+        let appsink_audio2 = create_audio(&pipeline, "2", audio)?;
+        forward_samples(&appsink_audio2, appsrc_audio2);
+    }
+
+    Ok(pipeline)
+}
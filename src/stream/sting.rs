@@ -0,0 +1,102 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use gstreamer::prelude::*;
+
+use super::feeder::{create_file_source, forward_samples};
+use super::selection::pad_stream_type;
+use super::{AudioOptions, Error};
+
+/// Longest a sting can play for, in case the file turns out to be longer than a sting should be.
+const MAX_STING_DURATION: Duration = Duration::from_secs(30);
+
+/// A short sound mixed over the change from one item to the next.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct StingOptions {
+    pub file: PathBuf,
+    /// Volume of the program audio while the sting plays, in percent.
+    pub duck_percent: u8,
+}
+
+/// Where stings go into the output's audio mixer, next to the program audio.
+#[derive(Debug, Clone)]
+pub struct StingInput {
+    pub appsrc: gstreamer_app::AppSrc,
+    /// The mixer pad of the program audio, which is ducked while a sting plays.
+    pub program_pad: gstreamer::Pad,
+}
+
+/// Plays the sting in the background, ducking the program audio until it's done.
+pub fn play_sting(options: &StingOptions, input: &StingInput, audio: AudioOptions) {
+    let pipeline = match create_sting_pipeline(options, input, audio) {
+        Ok(pipeline) => pipeline,
+        Err(error) => {
+            eprintln!("Failed to create sting pipeline: {error}");
+            return;
+        }
+    };
+    let duck = f64::from(options.duck_percent.min(100)) / 100.0;
+    let program_pad = input.program_pad.clone();
+    std::thread::spawn(move || {
+        program_pad.set_property("volume", duck);
+        if let Err(error) = pipeline.set_state(gstreamer::State::Playing) {
+            eprintln!("Failed to play sting: {error}");
+        } else {
+            let bus = pipeline.bus().unwrap();
+            let timeout = gstreamer::ClockTime::try_from(MAX_STING_DURATION).ok();
+            let message = bus.timed_pop_filtered(
+                timeout,
+                &[gstreamer::MessageType::Eos, gstreamer::MessageType::Error],
+            );
+            if let Some(message) = message
+                && let gstreamer::MessageView::Error(err) = message.view()
+            {
+                eprintln!("Sting failed: {}", err.error());
+            }
+        }
+        _ = pipeline.set_state(gstreamer::State::Null);
+        program_pad.set_property("volume", 1.0_f64);
+    });
+}
+
+fn create_sting_pipeline(
+    options: &StingOptions,
+    input: &StingInput,
+    audio: AudioOptions,
+) -> Result<gstreamer::Pipeline, Error> {
+    let pipeline = gstreamer::Pipeline::builder().name("sting-pipeline").build();
+
+    let filesrc = create_file_source(&options.file)?;
+    let decodebin = gstreamer::ElementFactory::make("decodebin3").build()?;
+    let audioconvert = gstreamer::ElementFactory::make("audioconvert").build()?;
+    let audioresample = gstreamer::ElementFactory::make("audioresample").build()?;
+    // These caps MUST match the caps in media_factory.rs
+    let capsfilter = gstreamer::ElementFactory::make("capsfilter")
+        .property("caps", audio.caps())
+        .build()?;
+    let batcher = audio.create_batcher()?;
+    let appsink = gstreamer_app::AppSink::builder().name("appsink_sting").build();
+
+    let mut audio_chain = vec![&audioconvert, &audioresample, &capsfilter];
+    audio_chain.extend(&batcher);
+    audio_chain.push(appsink.upcast_ref());
+    pipeline.add_many([&filesrc, &decodebin])?;
+    pipeline.add_many(audio_chain.iter().copied())?;
+    filesrc.link(&decodebin)?;
+    gstreamer::Element::link_many(audio_chain.iter().copied())?;
+
+    let audioconvert_sink_pad = audioconvert.static_pad("sink").unwrap();
+    decodebin.connect_pad_added(move |_, pad| {
+        if !pad_stream_type(pad).contains(gstreamer::StreamType::AUDIO)
+            || audioconvert_sink_pad.is_linked()
+        {
+            return;
+        }
+        if let Err(err) = pad.link(&audioconvert_sink_pad) {
+            eprintln!("Sting: Failed to link {}: {err}", pad.name());
+        }
+    });
+
+    forward_samples(&appsink, &input.appsrc);
+    Ok(pipeline)
+}
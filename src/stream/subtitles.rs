@@ -0,0 +1,73 @@
+use std::path::{Path, PathBuf};
+
+use gstreamer::prelude::*;
+
+use super::{Error, create_file_source};
+
+/// Burns a subtitle track of each file into the picture, for files in a language viewers don't
+/// speak.
+#[derive(Default, Debug, Clone, Eq, PartialEq, Hash)]
+pub struct SubtitleOptions {
+    /// Subtitle languages as ISO 639 codes, most preferred first. Files without any of them get
+    /// no subtitles, any track will do if it's empty.
+    pub languages: Vec<String>,
+    /// Use `.srt` files next to the files, e.g. `movie.srt` or `movie.en.srt` for `movie.mkv`,
+    /// over their embedded tracks.
+    pub sidecars: bool,
+}
+
+impl SubtitleOptions {
+    /// The `.srt` file next to `path` to use, if there's one. `movie.LANG.srt` files are looked
+    /// for in order of preference, then `movie.srt`, which is taken to be in any language.
+    pub(crate) fn find_sidecar(&self, path: &Path) -> Option<PathBuf> {
+        if !self.sidecars {
+            return None;
+        }
+        let stem = path.file_stem()?.to_str()?;
+        let names = self.languages.iter().map(|language| format!("{stem}.{language}.srt"));
+        names
+            .chain([format!("{stem}.srt")])
+            .map(|name| path.with_file_name(name))
+            .find(|sidecar| sidecar != path && sidecar.is_file())
+    }
+}
+
+/// A `subtitleoverlay` for the video chain, rendering whatever arrives on its `subtitle_sink`
+/// pad. Without subtitles it passes the video through.
+pub(crate) fn create_subtitle_overlay() -> Result<gstreamer::Element, Error> {
+    Ok(gstreamer::ElementFactory::make("subtitleoverlay")
+        .name("subtitleoverlay")
+        .property("font-desc", "Sans, 28")
+        .build()?)
+}
+
+/// Adds a parser for the `sidecar` subtitle file to `pipeline`, feeding `overlay`.
+pub(crate) fn link_sidecar(
+    pipeline: &gstreamer::Pipeline,
+    sidecar: &Path,
+    overlay: &gstreamer::Element,
+) -> Result<(), Error> {
+    let filesrc = create_file_source(sidecar)?;
+    let subparse = gstreamer::ElementFactory::make("subparse")
+        .name("subparse")
+        .property("subtitle-encoding", detect_encoding(sidecar))
+        .build()?;
+    pipeline.add_many([&filesrc, &subparse])?;
+    filesrc.link(&subparse)?;
+    subparse.link_pads(None, overlay, Some("subtitle_sink"))?;
+    Ok(())
+}
+
+/// The character set of a subtitle file, for `subparse`. Going by its byte order mark, then
+/// UTF-8 if it's valid, else Windows-1252, which most older `.srt` files in Western languages
+/// are in.
+fn detect_encoding(path: &Path) -> &'static str {
+    let Ok(bytes) = std::fs::read(path) else { return "UTF-8" };
+    match bytes.as_slice() {
+        [0xEF, 0xBB, 0xBF, ..] => "UTF-8",
+        [0xFF, 0xFE, ..] => "UTF-16LE",
+        [0xFE, 0xFF, ..] => "UTF-16BE",
+        bytes if std::str::from_utf8(bytes).is_ok() => "UTF-8",
+        _ => "WINDOWS-1252",
+    }
+}
@@ -0,0 +1,256 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use gstreamer::prelude::*;
+use parking_lot::Mutex;
+
+use super::{AppSrcPolicy, Error, Probes};
+
+/// Longest the last frame is held for while waiting for the next item, in case none comes
+/// (e.g. the output was rebuilt in the meantime).
+const MAX_HOLD: Duration = Duration::from_secs(30);
+
+/// How the picture changes from one item to the next.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum TransitionKind {
+    /// The old picture fades out over the new one.
+    Dissolve,
+    /// The old picture fades to black, then the new one fades in.
+    FadeBlack,
+    /// The old picture slides off to the left, uncovering the new one.
+    Wipe,
+}
+
+impl std::str::FromStr for TransitionKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dissolve" => Ok(Self::Dissolve),
+            "fade-black" => Ok(Self::FadeBlack),
+            "wipe" => Ok(Self::Wipe),
+            _ => Err(format!("Unknown transition {s:?}, expected dissolve, fade-black or wipe")),
+        }
+    }
+}
+
+impl std::fmt::Display for TransitionKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Dissolve => write!(f, "dissolve"),
+            Self::FadeBlack => write!(f, "fade-black"),
+            Self::Wipe => write!(f, "wipe"),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct TransitionOptions {
+    pub kind: TransitionKind,
+    pub duration: Duration,
+}
+
+impl Default for TransitionOptions {
+    fn default() -> Self {
+        Self { kind: TransitionKind::Dissolve, duration: Duration::from_secs(1) }
+    }
+}
+
+/// A compositor in the output, drawing the last frame of the outgoing item over the incoming one.
+///
+/// Items play one after the other, so the outgoing one is gone by the time the next starts. Its
+/// last frame is held on top instead, from when it ends until the next item's first frame
+/// arrives, and then taken away the way [`TransitionKind`] says.
+#[derive(Debug, Clone)]
+pub struct TransitionInput {
+    options: TransitionOptions,
+    width: i32,
+    frame_duration: gstreamer::ClockTime,
+    appsrc: gstreamer_app::AppSrc,
+    /// The compositor pad of the held frame, on top.
+    overlay_pad: gstreamer::Pad,
+    /// The compositor pad of the program, underneath.
+    program_pad: gstreamer::Pad,
+    state: Arc<Mutex<HoldState>>,
+    probes: Probes,
+}
+
+#[derive(Debug, Default)]
+struct HoldState {
+    last_frame: Option<gstreamer::Buffer>,
+    holding: bool,
+    /// When the incoming item's first frame arrived, which starts the transition.
+    started_at: Option<Instant>,
+}
+
+impl TransitionInput {
+    /// Adds the compositor to `bin` between `upstream`, whose frames are watched, and
+    /// `downstream`. The held frames get an appsrc of their own, carrying `caps`.
+    pub(super) fn insert(
+        bin: &gstreamer::Bin,
+        options: TransitionOptions,
+        caps: &gstreamer::Caps,
+        appsrc_policy: AppSrcPolicy,
+        upstream: &gstreamer::Element,
+        downstream: &gstreamer::Element,
+    ) -> Result<Self, Error> {
+        let compositor = gstreamer::ElementFactory::make("compositor")
+            .name("transition-compositor")
+            .property_from_str("background", "black")
+            // Idle until a transition plays, so it mustn't hold up the program
+            .property("ignore-inactive-pads", true)
+            .build()?;
+        let capsfilter =
+            gstreamer::ElementFactory::make("capsfilter").property("caps", caps).build()?;
+        // Held frames are stamped when they're pushed, whatever the program does
+        let policy = AppSrcPolicy { do_timestamp: true, ..appsrc_policy };
+        let appsrc = policy.create_appsrc("transitionsrc", caps);
+        bin.add_many([&compositor, &capsfilter, appsrc.upcast_ref()])?;
+
+        let program_pad = compositor
+            .request_pad_simple("sink_%u")
+            .ok_or(glib::bool_error!("The compositor has no sink pads"))?;
+        program_pad.set_property("zorder", 0u32);
+        upstream.link_pads(None, &compositor, Some(program_pad.name().as_str()))?;
+        let overlay_pad = compositor
+            .request_pad_simple("sink_%u")
+            .ok_or(glib::bool_error!("The compositor has no sink pads"))?;
+        overlay_pad.set_property("zorder", 1u32);
+        overlay_pad.set_property("alpha", 0.0_f64);
+        appsrc.link_pads(None, &compositor, Some(overlay_pad.name().as_str()))?;
+        gstreamer::Element::link_many([&compositor, &capsfilter, downstream])?;
+
+        let structure = caps.structure(0);
+        let width = structure.and_then(|s| s.get::<i32>("width").ok()).unwrap_or(0);
+        let framerate = structure
+            .and_then(|s| s.get::<gstreamer::Fraction>("framerate").ok())
+            .filter(|framerate| framerate.numer() > 0)
+            .unwrap_or(gstreamer::Fraction::new(30, 1));
+        let frame_duration = gstreamer::ClockTime::SECOND
+            .mul_div_floor(framerate.denom() as u64, framerate.numer() as u64)
+            .unwrap_or(gstreamer::ClockTime::from_mseconds(33));
+
+        let this = Self {
+            options,
+            width,
+            frame_duration,
+            appsrc,
+            overlay_pad,
+            program_pad,
+            state: Arc::default(),
+            probes: Probes::default(),
+        };
+        this.watch_program();
+        Ok(this)
+    }
+
+    /// Keeps the latest program frame, and notices the first one of the incoming item.
+    fn watch_program(&self) {
+        let state = self.state.clone();
+        let pad = &self.program_pad;
+        self.probes.add(pad, gstreamer::PadProbeType::BUFFER, move |_, info| {
+            let Some(gstreamer::PadProbeData::Buffer(buffer)) = &info.data else {
+                return gstreamer::PadProbeReturn::Ok;
+            };
+            let mut state = state.lock();
+            if !state.holding {
+                state.last_frame = Some(buffer.clone());
+            } else if state.started_at.is_none() {
+                state.started_at = Some(Instant::now());
+            }
+            gstreamer::PadProbeReturn::Ok
+        });
+    }
+
+    /// Holds the last frame on top of the program until the next item's first frame, then
+    /// transitions to it. Called once the outgoing item has ended.
+    pub fn hold(&self) {
+        let frame = {
+            let mut state = self.state.lock();
+            if state.holding {
+                return;
+            }
+            let Some(frame) = state.last_frame.clone() else { return };
+            state.holding = true;
+            state.started_at = None;
+            frame
+        };
+        let this = self.clone();
+        std::thread::spawn(move || {
+            this.apply(0.0);
+            let held_at = Instant::now();
+            loop {
+                let started_at = this.state.lock().started_at;
+                let progress = match started_at {
+                    Some(started_at) => {
+                        started_at.elapsed().as_secs_f64()
+                            / this.options.duration.as_secs_f64().max(f64::EPSILON)
+                    }
+                    None if held_at.elapsed() > MAX_HOLD => break,
+                    None => 0.0,
+                };
+                if progress >= 1.0 {
+                    break;
+                }
+                this.apply(progress);
+
+                // Without timestamps, so the appsrc stamps it with the time it's pushed at
+                let mut buffer = frame.copy();
+                let buffer_mut = buffer.make_mut();
+                buffer_mut.set_pts(None);
+                buffer_mut.set_dts(None);
+                buffer_mut.set_duration(this.frame_duration);
+                if this.appsrc.push_buffer(buffer).is_err() {
+                    break;
+                }
+                std::thread::sleep(Duration::from(this.frame_duration));
+            }
+            this.reset();
+        });
+    }
+
+    /// Shows the transition `progress` (0 to 1) of the way through.
+    fn apply(&self, progress: f64) {
+        let progress = progress.clamp(0.0, 1.0);
+        match self.options.kind {
+            TransitionKind::Dissolve => {
+                self.overlay_pad.set_property("alpha", 1.0 - progress);
+            }
+            TransitionKind::FadeBlack => {
+                // Out over the first half, the program back in over the second
+                self.overlay_pad.set_property("alpha", (1.0 - 2.0 * progress).max(0.0));
+                self.program_pad.set_property("alpha", (2.0 * progress - 1.0).max(0.0));
+            }
+            TransitionKind::Wipe => {
+                self.overlay_pad.set_property("alpha", 1.0_f64);
+                let xpos = -(f64::from(self.width) * progress).round() as i32;
+                self.overlay_pad.set_property("xpos", xpos);
+            }
+        }
+    }
+
+    /// Takes the held frame away and shows the program as it is.
+    fn reset(&self) {
+        self.overlay_pad.set_property("alpha", 0.0_f64);
+        self.overlay_pad.set_property("xpos", 0i32);
+        self.program_pad.set_property("alpha", 1.0_f64);
+        let mut state = self.state.lock();
+        state.holding = false;
+        state.started_at = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_what_it_displays() {
+        for kind in [TransitionKind::Dissolve, TransitionKind::FadeBlack, TransitionKind::Wipe] {
+            assert_eq!(kind.to_string().parse::<TransitionKind>(), Ok(kind));
+        }
+        for invalid in ["Dissolve", "fade", "fade_black", ""] {
+            assert!(invalid.parse::<TransitionKind>().is_err(), "{invalid:?}");
+        }
+    }
+}
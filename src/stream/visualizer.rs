@@ -0,0 +1,71 @@
+use std::str::FromStr;
+
+use gstreamer::prelude::*;
+
+use super::Error;
+
+/// What's drawn from the sound of audio-only files, instead of showing their cover art.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Visualizer {
+    /// The waveform, like an oscilloscope.
+    Wave,
+    /// The frequency spectrum.
+    Spectrum,
+}
+
+impl Visualizer {
+    fn factory(self) -> &'static str {
+        match self {
+            Self::Wave => "wavescope",
+            Self::Spectrum => "spectrascope",
+        }
+    }
+}
+
+impl FromStr for Visualizer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "wave" => Ok(Self::Wave),
+            "spectrum" => Ok(Self::Spectrum),
+            _ => Err(format!("Unknown visualizer {s:?}, expected wave or spectrum")),
+        }
+    }
+}
+
+impl std::fmt::Display for Visualizer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Wave => "wave",
+            Self::Spectrum => "spectrum",
+        })
+    }
+}
+
+/// Adds a tee to `pipeline` that splits the decoded audio between `audio_sink_pad` (the audio
+/// chain) and `visualizer` drawing it. Returns the tee to link the decoder to, and the scope to
+/// link the rest of the video chain to. The scope ends along with the audio.
+pub(crate) fn add_visualizer(
+    pipeline: &gstreamer::Pipeline,
+    visualizer: Visualizer,
+    audio_sink_pad: &gstreamer::Pad,
+) -> Result<(gstreamer::Element, gstreamer::Element), Error> {
+    let tee = gstreamer::ElementFactory::make("tee").name("visualizer_tee").build()?;
+    // A queue on each branch, so neither waits on the other
+    let queue_audio = gstreamer::ElementFactory::make("queue").build()?;
+    let queue_scope = gstreamer::ElementFactory::make("queue").build()?;
+    let audioconvert = gstreamer::ElementFactory::make("audioconvert").build()?;
+    let scope = gstreamer::ElementFactory::make(visualizer.factory())
+        .name("visualizer")
+        .build()?;
+    if visualizer == Visualizer::Wave {
+        scope.set_property_from_str("style", "color-lines");
+    }
+
+    pipeline.add_many([&tee, &queue_audio, &queue_scope, &audioconvert, &scope])?;
+    tee.link(&queue_audio)?;
+    queue_audio.static_pad("src").unwrap().link(audio_sink_pad)?;
+    gstreamer::Element::link_many([&tee, &queue_scope, &audioconvert, &scope])?;
+    Ok((tee, scope))
+}
@@ -0,0 +1,42 @@
+use gstreamer::prelude::*;
+
+use super::Error;
+
+/// WebRTC playback straight from the output, without going through mediamtx. Browsers play it
+/// with sub-second latency by POSTing an offer to `http://HOST:PORT/whep/endpoint`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct WhepOptions {
+    pub port: u16,
+}
+
+impl Default for WhepOptions {
+    fn default() -> Self {
+        Self { port: 9090 }
+    }
+}
+
+/// Adds a `whepserversink` to `bin`, fed from the raw program in `video_tee` and `audio_tee`.
+/// It encodes for each viewer itself, and serves WHEP on all interfaces.
+pub(super) fn link_whep_branch(
+    bin: &gstreamer::Bin,
+    options: WhepOptions,
+    video_tee: &gstreamer::Element,
+    audio_tee: &gstreamer::Element,
+) -> Result<(), Error> {
+    let sink = gstreamer::ElementFactory::make("whepserversink").name("whep_sink").build()?;
+    let signaller = sink.property::<glib::Object>("signaller");
+    signaller.set_property("host-addr", format!("http://0.0.0.0:{}", options.port));
+    bin.add(&sink)?;
+
+    for (tee, kind) in [(video_tee, "video"), (audio_tee, "audio")] {
+        // Never hold up the main output for WebRTC viewers
+        let queue = gstreamer::ElementFactory::make("queue")
+            .property_from_str("leaky", "downstream")
+            .build()?;
+        bin.add(&queue)?;
+        tee.link(&queue)?;
+        // Requests a new pad from the template, so video and audio can't get mixed up
+        queue.link_pads(None, &sink, Some(&format!("{kind}_%u")))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,213 @@
+//! JPEG thumbnails of files, e.g. to review them before they air, and short animated previews.
+
+use std::path::Path;
+
+use gstreamer::prelude::*;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Glib(#[from] glib::Error),
+    #[error(transparent)]
+    GlibBool(#[from] glib::BoolError),
+    #[error(transparent)]
+    StateChange(#[from] gstreamer::StateChangeError),
+    #[error("Not ready within {PREROLL_TIMEOUT}")]
+    Timeout,
+    #[error("The file has no video")]
+    NoVideo,
+    #[error("No {0} encoder is installed")]
+    Unsupported(PreviewFormat),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+const PREROLL_TIMEOUT: gstreamer::ClockTime = gstreamer::ClockTime::from_seconds(10);
+/// Previews are for a quick look, they don't need to be smooth.
+const PREVIEW_FRAMERATE: i32 = 10;
+
+/// Animated image formats for [`preview`].
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PreviewFormat {
+    #[default]
+    Gif,
+    WebP,
+}
+
+impl PreviewFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Gif => "image/gif",
+            Self::WebP => "image/webp",
+        }
+    }
+}
+
+impl std::fmt::Display for PreviewFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Gif => "GIF",
+            Self::WebP => "WebP",
+        })
+    }
+}
+
+/// Grabs a frame from 10% into `path` (or its only frame, for images) as a JPEG `width` pixels
+/// wide. GStreamer has to be initialised.
+pub fn thumbnail(path: &Path, width: u32) -> Result<Vec<u8>, Error> {
+    let uri = glib::filename_to_uri(path, None)?;
+    let playbin = gstreamer::ElementFactory::make("playbin")
+        .property("uri", uri)
+        .property("video-sink", gstreamer::ElementFactory::make("fakesink").build()?)
+        .property("audio-sink", gstreamer::ElementFactory::make("fakesink").build()?)
+        .build()?;
+
+    let result = grab_frame(&playbin, width);
+    _ = playbin.set_state(gstreamer::State::Null);
+    result
+}
+
+fn grab_frame(playbin: &gstreamer::Element, width: u32) -> Result<Vec<u8>, Error> {
+    preroll(playbin)?;
+    // The first frame is often black, or a title card
+    if let Some(duration) = playbin.query_duration::<gstreamer::ClockTime>()
+        && duration > gstreamer::ClockTime::ZERO
+    {
+        let flags = gstreamer::SeekFlags::FLUSH | gstreamer::SeekFlags::KEY_UNIT;
+        playbin.seek_simple(flags, duration / 10)?;
+        preroll(playbin)?;
+    }
+
+    let caps = gstreamer::Caps::builder("image/jpeg")
+        .field("width", width as i32)
+        .field("pixel-aspect-ratio", gstreamer::Fraction::new(1, 1))
+        .build();
+    let sample = playbin
+        .emit_by_name::<Option<gstreamer::Sample>>("convert-sample", &[&caps])
+        .ok_or(Error::NoVideo)?;
+    let buffer = sample.buffer().ok_or(Error::NoVideo)?;
+    let map = buffer.map_readable()?;
+    Ok(map.to_vec())
+}
+
+fn preroll(playbin: &gstreamer::Element) -> Result<(), Error> {
+    playbin.set_state(gstreamer::State::Paused)?;
+    match playbin.state(PREROLL_TIMEOUT).0? {
+        gstreamer::StateChangeSuccess::Async => Err(Error::Timeout),
+        _ => Ok(()),
+    }
+}
+
+/// Makes a looping animation of `length` of `path`, `width` pixels wide, starting from `start` (or
+/// 10% in if `None`). GStreamer has to be initialised.
+pub fn preview(
+    path: &Path,
+    start: Option<gstreamer::ClockTime>,
+    length: gstreamer::ClockTime,
+    width: u32,
+    format: PreviewFormat,
+) -> Result<Vec<u8>, Error> {
+    let output = tempfile::NamedTempFile::new()?;
+    let uri = glib::filename_to_uri(path, None)?;
+    let playbin = gstreamer::ElementFactory::make("playbin")
+        .property("uri", uri)
+        .property("video-sink", preview_sink(output.path(), width, format)?)
+        .property("audio-sink", gstreamer::ElementFactory::make("fakesink").build()?)
+        .build()?;
+
+    let result = record(&playbin, start, length);
+    _ = playbin.set_state(gstreamer::State::Null);
+    result?;
+    Ok(std::fs::read(output.path())?)
+}
+
+/// Scales, encodes and writes the video to `output`.
+fn preview_sink(
+    output: &Path,
+    width: u32,
+    format: PreviewFormat,
+) -> Result<gstreamer::Element, Error> {
+    let caps = gstreamer::Caps::builder("video/x-raw")
+        .field("width", width as i32)
+        .field("pixel-aspect-ratio", gstreamer::Fraction::new(1, 1))
+        .field("framerate", gstreamer::Fraction::new(PREVIEW_FRAMERATE, 1))
+        .build();
+    let mut elements = vec![
+        gstreamer::ElementFactory::make("videoconvert").build()?,
+        gstreamer::ElementFactory::make("videorate").build()?,
+        gstreamer::ElementFactory::make("videoscale").build()?,
+        gstreamer::ElementFactory::make("capsfilter").property("caps", caps).build()?,
+    ];
+    elements.extend(create_preview_encoder(format)?);
+    elements.push(
+        gstreamer::ElementFactory::make("filesink")
+            .property("location", output)
+            .build()?,
+    );
+
+    let bin = gstreamer::Bin::new();
+    bin.add_many(&elements)?;
+    gstreamer::Element::link_many(&elements)?;
+    let sink_pad = elements[0].static_pad("sink").expect("videoconvert has a sink pad");
+    bin.add_pad(&gstreamer::GhostPad::with_target(&sink_pad)?)?;
+    Ok(bin.upcast())
+}
+
+/// The encoder (and muxer, if it needs one) for `format`, preferring the Rust GIF encoder.
+fn create_preview_encoder(format: PreviewFormat) -> Result<Vec<gstreamer::Element>, Error> {
+    let make = |name: &str| gstreamer::ElementFactory::make(name).build();
+    let elements = match format {
+        PreviewFormat::Gif => match make("gifenc") {
+            Ok(gifenc) => {
+                // Loop forever, like people expect GIFs to
+                gifenc.set_property_from_str("repeat", "-1");
+                Ok(vec![gifenc])
+            }
+            Err(_) => make("avenc_gif").and_then(|encoder| Ok(vec![encoder, make("avmux_gif")?])),
+        },
+        PreviewFormat::WebP => {
+            make("avenc_libwebp_anim").and_then(|encoder| Ok(vec![encoder, make("avmux_webp")?]))
+        }
+    };
+    elements.map_err(|_| Error::Unsupported(format))
+}
+
+fn record(
+    playbin: &gstreamer::Element,
+    start: Option<gstreamer::ClockTime>,
+    length: gstreamer::ClockTime,
+) -> Result<(), Error> {
+    preroll(playbin)?;
+    // Stop at the end of the preview by itself where possible, so it encodes as fast as it can
+    if let Some(duration) = playbin.query_duration::<gstreamer::ClockTime>()
+        && duration > gstreamer::ClockTime::ZERO
+    {
+        let start = start.unwrap_or(duration / 10).min(duration.saturating_sub(length));
+        let flags = gstreamer::SeekFlags::FLUSH | gstreamer::SeekFlags::KEY_UNIT;
+        playbin.seek(
+            1.0,
+            flags,
+            gstreamer::SeekType::Set,
+            start,
+            gstreamer::SeekType::Set,
+            start + length,
+        )?;
+        preroll(playbin)?;
+    }
+    playbin.set_state(gstreamer::State::Playing)?;
+
+    let bus = playbin.bus().expect("playbin has a bus");
+    let types = [gstreamer::MessageType::Eos, gstreamer::MessageType::Error];
+    let mut message = bus.timed_pop_filtered(length + PREROLL_TIMEOUT, &types);
+    if message.is_none() {
+        // No known end (e.g. a live source), so finish the file off here
+        playbin.send_event(gstreamer::event::Eos::new());
+        message = bus.timed_pop_filtered(PREROLL_TIMEOUT, &types);
+    }
+    match message.as_ref().map(|message| message.view()) {
+        Some(gstreamer::MessageView::Eos(_)) => Ok(()),
+        Some(gstreamer::MessageView::Error(error)) => Err(Error::Glib(error.error())),
+        _ => Err(Error::Timeout),
+    }
+}
@@ -0,0 +1,223 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use z_stream::client::ApiClient;
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+const MAX_EVENTS: usize = 50;
+
+struct App {
+    client: ApiClient,
+    status: Option<serde_json::Value>,
+    stats: Option<serde_json::Value>,
+    events: VecDeque<String>,
+    message: String,
+}
+
+/// Monitors a running instance through its control API until `q` is pressed.
+pub fn run(client: ApiClient) -> std::io::Result<()> {
+    let (event_tx, event_rx) = flume::unbounded();
+    let events_client = client.clone();
+    std::thread::spawn(move || {
+        loop {
+            let result = events_client.follow_events(false, |event| {
+                _ = event_tx.send(event.to_string());
+            });
+            if let Err(error) = result {
+                _ = event_tx.send(format!("Event stream failed: {error}"));
+            }
+            if event_tx.is_disconnected() {
+                break;
+            }
+            std::thread::sleep(Duration::from_secs(3));
+        }
+    });
+
+    let app = App {
+        client,
+        status: None,
+        stats: None,
+        events: VecDeque::new(),
+        message: String::new(),
+    };
+    let mut terminal = ratatui::init();
+    let result = run_app(&mut terminal, app, event_rx);
+    ratatui::restore();
+    result
+}
+
+fn run_app(
+    terminal: &mut DefaultTerminal,
+    mut app: App,
+    event_rx: flume::Receiver<String>,
+) -> std::io::Result<()> {
+    let mut last_refresh: Option<Instant> = None;
+    loop {
+        if last_refresh.is_none_or(|at| at.elapsed() >= REFRESH_INTERVAL) {
+            app.refresh();
+            last_refresh = Some(Instant::now());
+        }
+        for event in event_rx.try_iter() {
+            if app.events.len() == MAX_EVENTS {
+                app.events.pop_back();
+            }
+            app.events.push_front(event);
+        }
+
+        terminal.draw(|frame| draw(frame, &app))?;
+
+        if !event::poll(Duration::from_millis(200))? {
+            continue;
+        }
+        let Event::Key(key) = event::read()? else { continue };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        let result = match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+            KeyCode::Char('s') => app.client.skip(false).map(|()| "Skipped"),
+            KeyCode::Char('g') => app.client.skip(true).map(|()| "Skipping gracefully"),
+            KeyCode::Char('f') if app.frozen() => app.client.unfreeze().map(|()| "Unfrozen"),
+            KeyCode::Char('f') => app.client.freeze(false).map(|()| "Frozen"),
+            _ => continue,
+        };
+        app.message = match result {
+            Ok(message) => {
+                // Don't wait for the next refresh to show what changed
+                app.refresh();
+                message.to_string()
+            }
+            Err(error) => format!("Failed: {error}"),
+        };
+    }
+}
+
+impl App {
+    fn refresh(&mut self) {
+        match self.client.status() {
+            Ok(status) => self.status = Some(status),
+            Err(error) => {
+                self.status = None;
+                self.message = format!("Failed to get status: {error}");
+            }
+        }
+        self.stats = self.client.stats().ok();
+    }
+
+    fn frozen(&self) -> bool {
+        self.status
+            .as_ref()
+            .and_then(|status| status["frozen"].as_bool())
+            .unwrap_or_default()
+    }
+}
+
+fn draw(frame: &mut Frame, app: &App) {
+    let [now_playing_area, stats_area, lists_area, help_area] = Layout::vertical([
+        Constraint::Length(5),
+        Constraint::Length(5),
+        Constraint::Min(5),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+    let [queue_area, events_area] =
+        Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)])
+            .areas(lists_area);
+
+    let playing = app.status.as_ref().map(|status| &status["playing"]);
+    let now_playing = match playing {
+        Some(playing) if !playing.is_null() => {
+            let elapsed = playing["elapsed_secs"].as_f64().unwrap_or_default();
+            let position = match playing["duration_secs"].as_f64() {
+                Some(duration) => format!("{} / {}", format_secs(elapsed), format_secs(duration)),
+                None => format_secs(elapsed),
+            };
+            vec![
+                Line::from(playing["path"].as_str().unwrap_or_default().to_string()),
+                Line::from(playing["media_type"].as_str().unwrap_or_default().to_string()),
+                Line::from(position),
+            ]
+        }
+        Some(_) => vec![Line::from("Nothing")],
+        None => vec![Line::from("Not connected")],
+    };
+    let live = app.status.as_ref().and_then(|status| status["live"].as_str());
+    let slate = app.status.as_ref().and_then(|status| status["slate"].as_str());
+    let title = match (live, slate) {
+        (Some(live), _) => format!("Live input {live}"),
+        (None, Some(slate)) => format!("Showing the {slate} slate"),
+        (None, None) if app.frozen() => "Now playing (frozen)".to_string(),
+        (None, None) => "Now playing".to_string(),
+    };
+    let now_playing = Paragraph::new(now_playing).block(Block::bordered().title(title));
+    frame.render_widget(now_playing, now_playing_area);
+
+    let stats = match &app.stats {
+        Some(stats) => {
+            let first_frame_ms = average_ms(&stats["first_buffer_latency"]);
+            vec![
+                Line::from(format!(
+                    "Uptime {}  Airtime {}  Played {}  Skips {}  Errors {}  Quarantined {}",
+                    format_secs(stats["uptime_secs"].as_f64().unwrap_or_default()),
+                    format_secs(stats["airtime_secs"].as_f64().unwrap_or_default()),
+                    stats["files_played"],
+                    stats["skips"],
+                    stats["errors"],
+                    stats["quarantined"],
+                )),
+                Line::from(format!(
+                    "Average switch latency {}  First frame after {} (max {})",
+                    format_ms(stats["average_switch_latency_ms"].as_f64()),
+                    format_ms(first_frame_ms),
+                    format_ms(first_frame_ms.and(stats["first_buffer_latency"]["max_ms"].as_f64())),
+                )),
+                Line::from(format_encoding(app.status.as_ref().map(|status| &status["encoding"]))),
+            ]
+        }
+        None => Vec::new(),
+    };
+    frame.render_widget(Paragraph::new(stats).block(Block::bordered().title("Stats")), stats_area);
+
+    let upcoming = app.status.as_ref().and_then(|status| status["upcoming"].as_array());
+    let queue = upcoming
+        .into_iter()
+        .flatten()
+        .filter_map(|path| path.as_str())
+        .map(|path| path.to_string());
+    frame.render_widget(List::new(queue).block(Block::bordered().title("Queue")), queue_area);
+
+    let events = app.events.iter().map(String::as_str);
+    frame.render_widget(List::new(events).block(Block::bordered().title("Events")), events_area);
+
+    let help = format!("q quit  s skip  g graceful skip  f freeze/unfreeze  {}", app.message);
+    frame.render_widget(Paragraph::new(help), help_area);
+}
+
+fn format_secs(secs: f64) -> String {
+    let secs = secs as u64;
+    format!("{:02}:{:02}:{:02}", secs / 3600, (secs / 60) % 60, secs % 60)
+}
+
+fn format_encoding(encoding: Option<&serde_json::Value>) -> String {
+    match encoding.filter(|encoding| !encoding.is_null()) {
+        Some(encoding) => format!(
+            "Encoding {}x{} at {} kbit/s",
+            encoding["width"], encoding["height"], encoding["bitrate_kbps"]
+        ),
+        None => "Encoding n/a".to_string(),
+    }
+}
+
+fn format_ms(ms: Option<f64>) -> String {
+    ms.map_or_else(|| "n/a".to_string(), |ms| format!("{ms:.0}ms"))
+}
+
+fn average_ms(histogram: &serde_json::Value) -> Option<f64> {
+    let count = histogram["count"].as_f64().filter(|count| *count > 0.0)?;
+    Some(histogram["total_ms"].as_f64()? / count)
+}
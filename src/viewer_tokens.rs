@@ -0,0 +1,153 @@
+//! Tokens that let viewers play the stream from mediamtx over HLS and WebRTC, minted and revoked
+//! through the API. mediamtx asks the API about every viewer, see [`ViewerTokens::allows`].
+//!
+//! Only mediamtx's HLS and WebRTC are protected. The same program is also served without any
+//! token check by the WHEP endpoint (`--whep-port`), the API's `GET /mjpeg`, and mediamtx's RTSP,
+//! RTMP and SRT, so leave those off or firewalled where viewers must have a token.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use parking_lot::Mutex;
+use rand::Rng;
+
+/// Protocols that need a token to read, the others are left open.
+const PROTECTED_PROTOCOLS: &[&str] = &["hls", "webrtc"];
+
+/// The tokens handed out to viewers. They're only kept in memory, so a restart revokes them all.
+#[derive(Debug, Clone, Default)]
+pub struct ViewerTokens {
+    tokens: Arc<Mutex<HashMap<String, ViewerToken>>>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ViewerToken {
+    /// Goes into the URL as `?token=...`.
+    pub token: String,
+    /// Who or what it was made for, to tell them apart when revoking.
+    pub label: Option<String>,
+    /// When it stops working, in seconds since the Unix epoch.
+    pub expires_at: u64,
+}
+
+/// What mediamtx sends to its `authHTTPAddress` for every client.
+#[derive(Debug, serde::Deserialize)]
+pub struct AuthRequest {
+    #[serde(default)]
+    pub action: String,
+    #[serde(default)]
+    pub protocol: String,
+    #[serde(default)]
+    pub password: String,
+    /// Set from an `Authorization: Bearer` header by newer versions of mediamtx.
+    #[serde(default)]
+    pub token: String,
+    /// The URL's query string, without the `?`.
+    #[serde(default)]
+    pub query: String,
+}
+
+impl ViewerTokens {
+    /// Makes a token that works for `ttl`.
+    pub fn mint(&self, ttl: Duration, label: Option<String>) -> ViewerToken {
+        let token = format!("{:032x}", rand::rng().random::<u128>());
+        let token = ViewerToken { token, label, expires_at: now().saturating_add(ttl.as_secs()) };
+        self.tokens.lock().insert(token.token.clone(), token.clone());
+        token
+    }
+
+    /// Stops `token` from working, returns whether there was such a token.
+    pub fn revoke(&self, token: &str) -> bool {
+        self.tokens.lock().remove(token).is_some()
+    }
+
+    /// The tokens that haven't expired yet, the ones that have are forgotten.
+    pub fn list(&self) -> Vec<ViewerToken> {
+        let mut tokens = self.tokens.lock();
+        let now = now();
+        tokens.retain(|_, token| token.expires_at > now);
+        let mut list: Vec<ViewerToken> = tokens.values().cloned().collect();
+        list.sort_by_key(|token| token.expires_at);
+        list
+    }
+
+    /// Whether `token` is one of these, and hasn't expired.
+    pub fn is_valid(&self, token: &str) -> bool {
+        self.tokens.lock().get(token).is_some_and(|token| token.expires_at > now())
+    }
+
+    /// Whether mediamtx should let `request` through. Reading over HLS or WebRTC needs a valid
+    /// token, in the query, the password or a bearer header. Everything else is allowed, the
+    /// other protocols and publishing the live input have their own protection.
+    pub fn allows(&self, request: &AuthRequest) -> bool {
+        if request.action != "read" || !PROTECTED_PROTOCOLS.contains(&request.protocol.as_str()) {
+            return true;
+        }
+        let query_token = request.query.split('&').find_map(|pair| pair.strip_prefix("token="));
+        [query_token, Some(request.password.as_str()), Some(request.token.as_str())]
+            .into_iter()
+            .flatten()
+            .any(|token| !token.is_empty() && self.is_valid(token))
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(protocol: &str) -> AuthRequest {
+        AuthRequest {
+            action: "read".to_string(),
+            protocol: protocol.to_string(),
+            password: String::new(),
+            token: String::new(),
+            query: String::new(),
+        }
+    }
+
+    #[test]
+    fn takes_the_token_from_the_query_password_or_bearer() {
+        let tokens = ViewerTokens::default();
+        let token = tokens.mint(Duration::from_secs(60), None).token;
+
+        let query = AuthRequest { query: format!("a=b&token={token}"), ..read("hls") };
+        assert!(tokens.allows(&query));
+        let password = AuthRequest { password: token.clone(), ..read("webrtc") };
+        assert!(tokens.allows(&password));
+        let bearer = AuthRequest { token: token.clone(), ..read("hls") };
+        assert!(tokens.allows(&bearer));
+
+        assert!(!tokens.allows(&read("hls")));
+        let wrong = AuthRequest { query: "token=wrong".to_string(), ..read("hls") };
+        assert!(!tokens.allows(&wrong));
+        let empty = AuthRequest { query: "token=".to_string(), ..read("webrtc") };
+        assert!(!tokens.allows(&empty));
+    }
+
+    #[test]
+    fn only_protects_reading_over_hls_and_webrtc() {
+        let tokens = ViewerTokens::default();
+        assert!(tokens.allows(&read("rtsp")));
+        assert!(tokens.allows(&read("srt")));
+        assert!(tokens.allows(&AuthRequest { action: "publish".to_string(), ..read("hls") }));
+    }
+
+    #[test]
+    fn expired_and_revoked_tokens_stop_working() {
+        let tokens = ViewerTokens::default();
+        let expired = tokens.mint(Duration::ZERO, None).token;
+        assert!(!tokens.is_valid(&expired));
+        assert!(!tokens.allows(&AuthRequest { token: expired, ..read("hls") }));
+        assert!(tokens.list().is_empty());
+
+        let revoked = tokens.mint(Duration::from_secs(60), None).token;
+        assert!(tokens.revoke(&revoked));
+        assert!(!tokens.revoke(&revoked));
+        assert!(!tokens.allows(&AuthRequest { token: revoked, ..read("hls") }));
+    }
+}